@@ -1,5 +1,6 @@
 #![feature(slice_take)]
 
+use anyhow::Result;
 use rand::SeedableRng;
 use rand_chacha::ChaCha20Rng;
 
@@ -10,20 +11,20 @@ use primitive_types::H256;
 use types::account::{get_address_from_pub_key, Account};
 use types::network::Network;
 
-pub fn create_account(network: Network) -> Account {
+pub fn create_account(network: Network) -> Result<Account> {
     let mut csprng = ChaCha20Rng::from_entropy();
     let keypair = Keypair::generate(&mut csprng);
     let secret = H256::from(keypair.secret.to_bytes());
-    let address = get_address_from_pub_key(keypair.public, network);
-    Account { address, secret }
+    let address = get_address_from_pub_key(keypair.public, network)?;
+    Ok(Account { address, secret })
 }
 
-pub fn create_account_from_uri(network: Network, uri: &str) -> Account {
+pub fn create_account_from_uri(network: Network, uri: &str) -> Result<Account> {
     let mut csprng = ChaCha20Rng::from_seed(*SHA256::digest(uri).as_fixed_bytes());
     let keypair = Keypair::generate(&mut csprng);
     let secret = H256::from(keypair.secret.to_bytes());
-    let address = get_address_from_pub_key(keypair.public, network);
-    Account { address, secret }
+    let address = get_address_from_pub_key(keypair.public, network)?;
+    Ok(Account { address, secret })
 }
 
 pub const ROOT: Address = Address([
@@ -41,8 +42,8 @@ mod tests {
 
     #[test]
     fn test_account_from_uri() {
-        let account0 = create_account_from_uri(Network::Testnet, "ama");
-        let account1 = create_account_from_uri(Network::Testnet, "ama");
+        let account0 = create_account_from_uri(Network::Testnet, "ama").unwrap();
+        let account1 = create_account_from_uri(Network::Testnet, "ama").unwrap();
         assert_eq!(account0, account1);
     }
 
@@ -2,44 +2,168 @@ use std::sync::Arc;
 
 use anyhow::Result;
 
-use primitive_types::H256;
-use storage::{KVStore, PersistentStorage, Schema, StorageIterator};
+use primitive_types::{H256, U256};
+use storage::{KVStore, PersistentStorage, Schema, StorageIterator, WriteBatch};
 use traits::{ChainHeadReader, ChainReader};
-use types::block::{Block, BlockHeader, BlockPrimaryKey, IndexedBlockHeader};
+use types::block::{Block, BlockHeader, BlockPrimaryKey, IndexedBlockHeader, TransactionLocation};
+use types::diff::StateDiff;
+use types::receipt::{Receipt, ReceiptList};
 use types::tx::{SignedTransaction, TransactionList};
 
+/// Writes go through a [`WriteBatch`] so a block and everything indexing it are stored at once,
+/// see [`BlockStorage::write`]
 pub struct BlockStorage {
+    persistent: Arc<PersistentStorage>,
     headers: Arc<BlockHeaderStorage>,
     transactions: Arc<BlockTransactionsStorage>,
+    receipts: Arc<BlockReceiptsStorage>,
+    state_diffs: Arc<BlockStateDiffStorage>,
     block_by_hash: Arc<BlockByHash>,
     block_by_level: Arc<BlockByLevel>,
+    transaction_index: Arc<TransactionIndex>,
+    total_work: Arc<BlockTotalWork>,
 }
 
 impl BlockStorage {
     pub fn new(persistent: Arc<PersistentStorage>) -> Self {
         Self {
+            persistent: persistent.clone(),
             headers: Arc::new(BlockHeaderStorage::new(persistent.database())),
             transactions: Arc::new(BlockTransactionsStorage::new(persistent.database())),
+            receipts: Arc::new(BlockReceiptsStorage::new(persistent.database())),
+            state_diffs: Arc::new(BlockStateDiffStorage::new(persistent.database())),
             block_by_hash: Arc::new(BlockByHash::new(persistent.database())),
             block_by_level: Arc::new(BlockByLevel::new(persistent.database())),
+            transaction_index: Arc::new(TransactionIndex::new(persistent.database())),
+            total_work: Arc::new(BlockTotalWork::new(persistent.database())),
         }
     }
 
-    pub fn put(&self, block: Block) -> Result<()> {
-        let block_key = self.headers.put(*block.header())?;
+    /// Applies the writes of `batch`, reads do not see them before
+    pub fn write(&self, batch: WriteBatch) -> Result<()> {
+        self.persistent.write_batch(batch)
+    }
+
+    /// Stores a block without indexing it by level or transaction, blocks only become
+    /// canonical through [`BlockStorage::set_canonical`]
+    pub fn put(&self, batch: &mut WriteBatch, block: Block) -> Result<()> {
+        let block_key = self.headers.put(batch, *block.header())?;
         self.transactions
-            .put(block_key, block.into_transactions())?;
-        self.block_by_hash.put(block_key.1, block_key)?;
-        self.block_by_level.put(block_key.0, block_key)?;
+            .put(batch, block_key, block.into_transactions())?;
+        self.block_by_hash.put(batch, block_key.1, block_key)?;
+        Ok(())
+    }
+
+    /// Makes `block` the canonical block at its level and indexes its transactions
+    pub fn set_canonical(&self, batch: &mut WriteBatch, block: &Block) -> Result<()> {
+        let block_key = BlockPrimaryKey(block.level(), block.hash());
+        self.block_by_level.put(batch, block_key.0, block_key)?;
+        let tx_hashes: Vec<_> = block.transactions().iter().map(|tx| tx.hash()).collect();
+        self.index_transactions(batch, block_key, tx_hashes)
+    }
+
+    fn index_transactions(
+        &self,
+        batch: &mut WriteBatch,
+        block_key: BlockPrimaryKey,
+        tx_hashes: Vec<H256>,
+    ) -> Result<()> {
+        for (index, tx_hash) in tx_hashes.into_iter().enumerate() {
+            self.transaction_index.put(
+                batch,
+                tx_hash,
+                TransactionLocation {
+                    block_hash: block_key.1,
+                    level: block_key.0,
+                    index: index as u32,
+                },
+            )?;
+        }
+        Ok(())
+    }
+
+    pub fn put_total_work(
+        &self,
+        batch: &mut WriteBatch,
+        hash: H256,
+        total_work: U256,
+    ) -> Result<()> {
+        self.total_work.put(batch, hash, total_work)
+    }
+
+    pub fn put_receipts(
+        &self,
+        batch: &mut WriteBatch,
+        header: &BlockHeader,
+        receipts: Vec<Receipt>,
+    ) -> Result<()> {
+        let block_key = BlockPrimaryKey(header.level, header.hash());
+        self.receipts.put(batch, block_key, receipts)
+    }
+
+    pub fn get_receipts(&self, hash: &H256, level: u32) -> Result<Option<Vec<Receipt>>> {
+        let block_key = BlockPrimaryKey(level, *hash);
+        self.receipts
+            .get_receipts(&block_key)
+            .map(|receipts| receipts.map(|receipts| receipts.into()))
+    }
+
+    pub fn put_state_diff(
+        &self,
+        batch: &mut WriteBatch,
+        header: &BlockHeader,
+        diff: StateDiff,
+    ) -> Result<()> {
+        let block_key = BlockPrimaryKey(header.level, header.hash());
+        self.state_diffs.put(batch, block_key, diff)
+    }
+
+    pub fn get_state_diff(&self, hash: &H256, level: u32) -> Result<Option<StateDiff>> {
+        self.state_diffs.get(&BlockPrimaryKey(level, *hash))
+    }
+
+    /// Removes a block from the canonical chain, its level and transactions are no longer
+    /// indexed. The block is kept and can still be read by hash.
+    pub fn unset_canonical(&self, batch: &mut WriteBatch, hash: &H256, level: u32) -> Result<()> {
+        let block_key = BlockPrimaryKey(level, *hash);
+        if self.block_by_level.get(level)? == Some(block_key) {
+            self.block_by_level.delete(batch, level)?;
+        }
+        if let Some(transactions) = self.transactions.get_transactions(&block_key)? {
+            for tx in transactions.as_ref() {
+                self.transaction_index
+                    .delete(batch, &tx.hash(), &block_key)?;
+            }
+        }
         Ok(())
     }
 
-    pub fn delete(&self, hash: &H256, level: u32) -> Result<()> {
+    /// Removes a block and every index pointing at it
+    pub fn delete(&self, batch: &mut WriteBatch, hash: &H256, level: u32) -> Result<()> {
         let block_key = BlockPrimaryKey(level, *hash);
-        self.block_by_level.delete(block_key.0)?;
+        self.unset_canonical(batch, hash, level)?;
+        self.block_by_hash.delete(batch, hash)?;
+        self.total_work.delete(batch, hash)?;
+        self.receipts.delete_block(batch, &block_key)?;
+        self.state_diffs.delete_block(batch, &block_key)?;
+        self.transactions.delete_block(batch, &block_key)?;
+        self.headers.delete_block(batch, &block_key)?;
         Ok(())
     }
 
+    /// Iterates over canonical and side chain headers ordered by level, starting at `level`
+    pub fn get_headers(
+        &self,
+        level: u32,
+    ) -> Result<Box<dyn '_ + Send + Iterator<Item = Result<BlockHeader>>>> {
+        let primary_key = BlockPrimaryKey(level, H256::zero());
+        Ok(Box::new(
+            self.headers
+                .get_blocks(&primary_key)?
+                .map(|(_, header)| header),
+        ))
+    }
+
     pub fn get_blocks<'a>(
         &'a self,
         hash: &'a H256,
@@ -113,6 +237,22 @@ impl ChainReader for BlockStorage {
         }
         Ok(None)
     }
+
+    fn get_transaction_location(&self, tx_hash: &H256) -> Result<Option<TransactionLocation>> {
+        self.transaction_index.get(tx_hash)
+    }
+
+    fn get_block_receipts(&self, hash: &H256, level: u32) -> Result<Option<Vec<Receipt>>> {
+        self.get_receipts(hash, level)
+    }
+
+    fn get_block_state_diff(&self, hash: &H256, level: u32) -> Result<Option<StateDiff>> {
+        self.get_state_diff(hash, level)
+    }
+
+    fn get_total_work(&self, hash: &H256) -> Result<Option<U256>> {
+        self.total_work.get(hash)
+    }
 }
 
 /// Primary block storage
@@ -136,22 +276,22 @@ impl BlockHeaderStorage {
     pub fn new(kv: Arc<BlockHeaderStorageKV>) -> Self {
         Self { kv }
     }
-    pub fn put(&self, blockheader: BlockHeader) -> Result<BlockPrimaryKey> {
+    pub fn put(&self, batch: &mut WriteBatch, blockheader: BlockHeader) -> Result<BlockPrimaryKey> {
         let hash = blockheader.hash();
         let level = blockheader.level;
         let block_key = BlockPrimaryKey(level, hash);
         if self.kv.contains(&block_key)? {
             return Ok(block_key);
         }
-        self.kv.put(block_key, blockheader)?;
+        batch.put::<Self>(block_key, blockheader)?;
         Ok(block_key)
     }
     pub fn get_blockheader(&self, block_key: &BlockPrimaryKey) -> Result<Option<BlockHeader>> {
         self.kv.get(block_key)
     }
 
-    pub fn delete_block(&self, block_key: &BlockPrimaryKey) -> Result<()> {
-        self.kv.delete(block_key)
+    pub fn delete_block(&self, batch: &mut WriteBatch, block_key: &BlockPrimaryKey) -> Result<()> {
+        batch.delete::<Self>(block_key)
     }
 
     pub fn has_block(&self, block_key: &BlockPrimaryKey) -> Result<bool> {
@@ -186,16 +326,20 @@ impl BlockTransactionsStorage {
     pub fn new(kv: Arc<BlockTransactionsStorageKV>) -> Self {
         Self { kv }
     }
-    pub fn put(&self, block_key: BlockPrimaryKey, txs: Vec<SignedTransaction>) -> Result<()> {
-        self.kv.put(block_key, TransactionList::from(txs))?;
-        Ok(())
+    pub fn put(
+        &self,
+        batch: &mut WriteBatch,
+        block_key: BlockPrimaryKey,
+        txs: Vec<SignedTransaction>,
+    ) -> Result<()> {
+        batch.put::<Self>(block_key, TransactionList::from(txs))
     }
     pub fn get_transactions(&self, block_key: &BlockPrimaryKey) -> Result<Option<TransactionList>> {
         self.kv.get(block_key)
     }
 
-    pub fn delete_block(&self, block_key: &BlockPrimaryKey) -> Result<()> {
-        self.kv.delete(block_key)
+    pub fn delete_block(&self, batch: &mut WriteBatch, block_key: &BlockPrimaryKey) -> Result<()> {
+        batch.delete::<Self>(block_key)
     }
 
     pub fn has_block(&self, block_key: &BlockPrimaryKey) -> Result<bool> {
@@ -210,6 +354,80 @@ impl BlockTransactionsStorage {
     }
 }
 
+pub type BlockReceiptsStorageKV = dyn KVStore<BlockReceiptsStorage> + Send + Sync;
+
+/// Transaction receipts of a block, in the same order as the block transactions
+pub struct BlockReceiptsStorage {
+    kv: Arc<BlockReceiptsStorageKV>,
+}
+
+impl Schema for BlockReceiptsStorage {
+    type Key = BlockPrimaryKey;
+    type Value = ReceiptList;
+
+    fn column() -> &'static str {
+        "block_receipts_storage"
+    }
+}
+
+impl BlockReceiptsStorage {
+    pub fn new(kv: Arc<BlockReceiptsStorageKV>) -> Self {
+        Self { kv }
+    }
+    pub fn put(
+        &self,
+        batch: &mut WriteBatch,
+        block_key: BlockPrimaryKey,
+        receipts: Vec<Receipt>,
+    ) -> Result<()> {
+        batch.put::<Self>(block_key, ReceiptList::from(receipts))
+    }
+    pub fn get_receipts(&self, block_key: &BlockPrimaryKey) -> Result<Option<ReceiptList>> {
+        self.kv.get(block_key)
+    }
+
+    pub fn delete_block(&self, batch: &mut WriteBatch, block_key: &BlockPrimaryKey) -> Result<()> {
+        batch.delete::<Self>(block_key)
+    }
+}
+
+pub type BlockStateDiffStorageKV = dyn KVStore<BlockStateDiffStorage> + Send + Sync;
+
+/// Accounts and app storage changed by a block
+pub struct BlockStateDiffStorage {
+    kv: Arc<BlockStateDiffStorageKV>,
+}
+
+impl Schema for BlockStateDiffStorage {
+    type Key = BlockPrimaryKey;
+    type Value = StateDiff;
+
+    fn column() -> &'static str {
+        "block_state_diff_storage"
+    }
+}
+
+impl BlockStateDiffStorage {
+    pub fn new(kv: Arc<BlockStateDiffStorageKV>) -> Self {
+        Self { kv }
+    }
+    pub fn put(
+        &self,
+        batch: &mut WriteBatch,
+        block_key: BlockPrimaryKey,
+        diff: StateDiff,
+    ) -> Result<()> {
+        batch.put::<Self>(block_key, diff)
+    }
+    pub fn get(&self, block_key: &BlockPrimaryKey) -> Result<Option<StateDiff>> {
+        self.kv.get(block_key)
+    }
+
+    pub fn delete_block(&self, batch: &mut WriteBatch, block_key: &BlockPrimaryKey) -> Result<()> {
+        batch.delete::<Self>(block_key)
+    }
+}
+
 /// Block by level index
 pub type BlockByLevelStorageKV = dyn KVStore<BlockByLevel> + Send + Sync;
 
@@ -230,18 +448,20 @@ impl BlockByLevel {
     pub fn new(kv: Arc<BlockByLevelStorageKV>) -> Self {
         Self { kv }
     }
-    pub fn put(&self, level: u32, primary_key: BlockPrimaryKey) -> Result<()> {
-        if !self.kv.contains(&level)? {
-            return self.kv.put(level, primary_key);
-        }
-        Ok(())
+    pub fn put(
+        &self,
+        batch: &mut WriteBatch,
+        level: u32,
+        primary_key: BlockPrimaryKey,
+    ) -> Result<()> {
+        batch.put::<Self>(level, primary_key)
     }
     pub fn get(&self, level: u32) -> Result<Option<BlockPrimaryKey>> {
         self.kv.get(&level)
     }
 
-    pub fn delete(&self, key: u32) -> Result<()> {
-        self.kv.delete(&key)
+    pub fn delete(&self, batch: &mut WriteBatch, key: u32) -> Result<()> {
+        batch.delete::<Self>(&key)
     }
 }
 
@@ -265,13 +485,201 @@ impl BlockByHash {
     pub fn new(kv: Arc<BlockByHashStorageKV>) -> Self {
         Self { kv }
     }
-    pub fn put(&self, hash: H256, primary_key: BlockPrimaryKey) -> Result<()> {
-        self.kv.put(hash, primary_key)
+    pub fn put(
+        &self,
+        batch: &mut WriteBatch,
+        hash: H256,
+        primary_key: BlockPrimaryKey,
+    ) -> Result<()> {
+        batch.put::<Self>(hash, primary_key)
     }
-    pub fn delete(&self, hash: &H256) -> Result<()> {
-        self.kv.delete(hash)
+    pub fn delete(&self, batch: &mut WriteBatch, hash: &H256) -> Result<()> {
+        batch.delete::<Self>(hash)
     }
     pub fn get(&self, hash: &H256) -> Result<Option<BlockPrimaryKey>> {
         self.kv.get(hash)
     }
 }
+
+/// Transaction hash index
+pub type TransactionIndexStorageKV = dyn KVStore<TransactionIndex> + Send + Sync;
+
+pub struct TransactionIndex {
+    kv: Arc<TransactionIndexStorageKV>,
+}
+
+impl Schema for TransactionIndex {
+    type Key = H256;
+    type Value = TransactionLocation;
+
+    fn column() -> &'static str {
+        "transaction_index_storage"
+    }
+}
+
+impl TransactionIndex {
+    pub fn new(kv: Arc<TransactionIndexStorageKV>) -> Self {
+        Self { kv }
+    }
+    pub fn put(
+        &self,
+        batch: &mut WriteBatch,
+        tx_hash: H256,
+        location: TransactionLocation,
+    ) -> Result<()> {
+        batch.put::<Self>(tx_hash, location)
+    }
+    pub fn get(&self, tx_hash: &H256) -> Result<Option<TransactionLocation>> {
+        self.kv.get(tx_hash)
+    }
+    /// Removes the index entry only if it still points into the given block
+    pub fn delete(
+        &self,
+        batch: &mut WriteBatch,
+        tx_hash: &H256,
+        block_key: &BlockPrimaryKey,
+    ) -> Result<()> {
+        match self.kv.get(tx_hash)? {
+            Some(location) if location.block_key().eq(block_key) => batch.delete::<Self>(tx_hash),
+            _ => Ok(()),
+        }
+    }
+}
+
+/// Cumulative chain work up to and including a block
+pub type BlockTotalWorkStorageKV = dyn KVStore<BlockTotalWork> + Send + Sync;
+
+pub struct BlockTotalWork {
+    kv: Arc<BlockTotalWorkStorageKV>,
+}
+
+impl Schema for BlockTotalWork {
+    type Key = H256;
+    type Value = U256;
+
+    fn column() -> &'static str {
+        "block_total_work_storage"
+    }
+}
+
+impl BlockTotalWork {
+    pub fn new(kv: Arc<BlockTotalWorkStorageKV>) -> Self {
+        Self { kv }
+    }
+    pub fn put(&self, batch: &mut WriteBatch, hash: H256, total_work: U256) -> Result<()> {
+        batch.put::<Self>(hash, total_work)
+    }
+    pub fn get(&self, hash: &H256) -> Result<Option<U256>> {
+        self.kv.get(hash)
+    }
+    pub fn delete(&self, batch: &mut WriteBatch, hash: &H256) -> Result<()> {
+        batch.delete::<Self>(hash)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use account::create_account_from_uri;
+    use storage::memstore::MemStore;
+    use storage::{PersistentStorage, PersistentStorageBackend, Schema, WriteBatch};
+    use traits::{ChainHeadReader, ChainReader};
+    use transaction::make_payment_sign_transaction;
+    use types::block::{Block, BlockHeader};
+    use types::network::Network;
+
+    use crate::block_storage::*;
+
+    fn block_storage() -> BlockStorage {
+        let columns = vec![
+            BlockHeaderStorage::column(),
+            BlockTransactionsStorage::column(),
+            BlockReceiptsStorage::column(),
+            BlockStateDiffStorage::column(),
+            BlockByHash::column(),
+            BlockByLevel::column(),
+            TransactionIndex::column(),
+            BlockTotalWork::column(),
+        ];
+        BlockStorage::new(Arc::new(PersistentStorage::new(
+            PersistentStorageBackend::InMemory(Arc::new(MemStore::new(columns))),
+        )))
+    }
+
+    fn child(parent: &Block, time: u32, nonce: u64) -> Block {
+        let alice = create_account_from_uri(Network::Testnet, "alice").unwrap();
+        let bob = create_account_from_uri(Network::Testnet, "bob").unwrap();
+        let tx = make_payment_sign_transaction(
+            alice.secret,
+            bob.address,
+            nonce,
+            10,
+            1,
+            Network::Testnet,
+        )
+        .unwrap();
+        let header = BlockHeader {
+            parent_hash: parent.hash(),
+            level: parent.level() + 1,
+            time,
+            ..Default::default()
+        };
+        Block::new(header, vec![tx])
+    }
+
+    #[test]
+    fn test_side_branch_above_head_is_not_indexed() {
+        let storage = block_storage();
+        let genesis = Block::new(BlockHeader::default(), vec![]);
+        let head = child(&genesis, 1, 1);
+        let mut batch = WriteBatch::new();
+        for block in [&genesis, &head] {
+            storage.put(&mut batch, block.clone()).unwrap();
+            storage.set_canonical(&mut batch, block).unwrap();
+        }
+        storage.write(batch).unwrap();
+
+        // A longer side branch that is stored without becoming canonical
+        let mut side = vec![child(&genesis, 2, 1)];
+        for nonce in 2..=3 {
+            side.push(child(&side[side.len() - 1], 2, nonce));
+        }
+        let mut batch = WriteBatch::new();
+        for block in side.iter() {
+            storage.put(&mut batch, block.clone()).unwrap();
+        }
+        storage.write(batch).unwrap();
+        assert_eq!(
+            storage.get_header_by_level(1).unwrap().unwrap().hash,
+            head.hash()
+        );
+        for level in 2..=3 {
+            assert!(storage.get_header_by_level(level).unwrap().is_none());
+        }
+        assert!(storage
+            .get_block_by_hash(&side[2].hash())
+            .unwrap()
+            .is_some());
+
+        // The canonical chain reaches the levels of the side branch
+        let next = child(&head, 3, 2);
+        let mut batch = WriteBatch::new();
+        storage.put(&mut batch, next.clone()).unwrap();
+        storage.set_canonical(&mut batch, &next).unwrap();
+        storage.write(batch).unwrap();
+        assert_eq!(
+            storage.get_header_by_level(2).unwrap().unwrap().hash,
+            next.hash()
+        );
+        let location = storage
+            .get_transaction_location(&next.transactions()[0].hash())
+            .unwrap()
+            .unwrap();
+        assert_eq!(location.block_hash, next.hash());
+        assert!(storage
+            .get_transaction_location(&side[2].transactions()[0].hash())
+            .unwrap()
+            .is_none());
+    }
+}
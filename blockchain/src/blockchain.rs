@@ -1,12 +1,19 @@
 use std::path::PathBuf;
 use std::sync::{Arc, RwLock};
 
-use anyhow::Result;
+use anyhow::{anyhow, ensure, Result};
 use tokio::sync::mpsc::UnboundedSender;
 
-use storage::PersistentStorage;
+use primitive_types::U256;
+use smt::TreeHasherKind;
+use state::snapshot::StateSnapshot;
+use state::State;
+use storage::{PersistentStorage, WriteBatch};
 use traits::Consensus;
 use txpool::TxPool;
+use types::block::{Block, BlockHeader};
+use types::chainspec::ChainSpec;
+use types::config::StatePruning;
 use types::events::LocalEventMessage;
 
 use crate::block_storage::BlockStorage;
@@ -23,7 +30,9 @@ impl Chain {
         consensus: Arc<dyn Consensus>,
         main_storage: Arc<PersistentStorage>,
         lmpsc: UnboundedSender<LocalEventMessage>,
-        built_in_apps: Vec<(&'static str, &[u8])>,
+        chainspec: &ChainSpec,
+        built_in_apps: Vec<(String, Vec<u8>)>,
+        pruning: StatePruning,
     ) -> Result<Self> {
         let chain_state_storage = Arc::new(ChainStateStorage::new(main_storage.database()));
         let block_storage = Arc::new(BlockStorage::new(main_storage));
@@ -31,9 +40,11 @@ impl Chain {
             dir.join("state"),
             consensus.clone(),
             block_storage,
+            chainspec,
             built_in_apps,
             chain_state_storage,
             lmpsc.clone(),
+            pruning,
         )?);
         let txpool = Arc::new(RwLock::new(TxPool::new(
             None,
@@ -48,6 +59,56 @@ impl Chain {
         })
     }
 
+    /// Seeds an empty data directory with a state snapshot and the blocks it was taken with.
+    /// `blocks` starts with genesis, followed by consecutive blocks ending at the block whose
+    /// state is in `snapshot`, each with its total work. The chain then continues from the last
+    /// block without replaying history. The trees of the snapshot are hashed with `tree_hasher`.
+    pub fn import_snapshot(
+        dir: PathBuf,
+        main_storage: Arc<PersistentStorage>,
+        blocks: Vec<(Block, U256)>,
+        snapshot: StateSnapshot,
+        tree_hasher: TreeHasherKind,
+    ) -> Result<BlockHeader> {
+        let chain_state_storage = ChainStateStorage::new(main_storage.database());
+        ensure!(
+            chain_state_storage.get_current_header()?.is_none(),
+            "chain is already initialized"
+        );
+        let (genesis, _) = blocks
+            .first()
+            .ok_or_else(|| anyhow!("snapshot contains no blocks"))?;
+        ensure!(genesis.level() == 0, "snapshot does not start at genesis");
+        for pair in blocks.windows(2).skip(1) {
+            ensure!(
+                pair[1].0.parent_hash() == &pair[0].0.hash(),
+                "snapshot block {:?} does not extend {:?}",
+                pair[1].0.hash(),
+                pair[0].0.hash()
+            );
+        }
+        let head = *blocks[blocks.len() - 1].0.header();
+        ensure!(
+            head.state_root == snapshot.root,
+            "snapshot state root {:?} does not match block {:?}",
+            snapshot.root,
+            head.hash()
+        );
+
+        State::from_snapshot(dir.join("state"), snapshot, tree_hasher)?;
+        let block_storage = BlockStorage::new(main_storage);
+        let mut batch = WriteBatch::new();
+        for (block, total_work) in blocks {
+            let hash = block.hash();
+            block_storage.put(&mut batch, block.clone())?;
+            block_storage.set_canonical(&mut batch, &block)?;
+            block_storage.put_total_work(&mut batch, hash, total_work)?;
+        }
+        chain_state_storage.set_current_header(&mut batch, head)?;
+        block_storage.write(batch)?;
+        Ok(head)
+    }
+
     pub fn chain_state(&self) -> Arc<ChainState> {
         self.chain.clone()
     }
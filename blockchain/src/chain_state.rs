@@ -1,25 +1,29 @@
-use std::collections::HashMap;
+use std::collections::{BTreeSet, HashMap};
 use std::path::PathBuf;
-use std::sync::{Arc, RwLock};
+use std::sync::{Arc, Mutex, RwLock};
 
 use account::ROOT;
-use anyhow::{anyhow, bail, Result};
+use anyhow::{anyhow, Result};
 use tokio::sync::mpsc::UnboundedSender;
 
-use primitive_types::address::Address;
-use primitive_types::H256;
+use primitive_types::{H256, U256};
 use rune_vm::WasmVM;
+use state::error::StateError;
 use state::State;
-use storage::{KVStore, Schema};
+use storage::{KVStore, Schema, WriteBatch};
 use tracing::{debug, info, trace, warn};
 use traits::{Blockchain, ChainHeadReader, ChainReader, Consensus, StateDB};
 use txpool::tx_lookup::AccountSet;
 use txpool::{ResetRequest, TxPool};
-use types::account::{get_address_from_package_name, AppState};
-use types::app::AppStateKey;
-use types::block::{Block, BlockHeader, IndexedBlockHeader};
+use types::account::{get_address_from_package_name, AppState, BlockReward};
+use types::block::{Block, BlockHeader, IndexedBlockHeader, TransactionLocation};
+use types::chainspec::ChainSpec;
+use types::config::StatePruning;
+use types::diff::StateDiff;
 use types::events::LocalEventMessage;
 use types::network::Network;
+use types::receipt::Receipt;
+use types::tx::SignedTransaction;
 use types::ChainStateValue;
 
 use crate::block_storage::BlockStorage;
@@ -28,6 +32,9 @@ use crate::errors::BlockChainError::FailedToVerifyHeader;
 
 pub type ChainStateStorageKV = dyn KVStore<ChainStateStorage> + Send + Sync;
 
+/// Number of blocks between state pruning runs when pruning is enabled
+const STATE_PRUNE_INTERVAL: u32 = 64;
+
 pub struct ChainStateStorage {
     kv: Arc<ChainStateStorageKV>,
 }
@@ -47,8 +54,8 @@ impl ChainStateStorage {
     pub fn new(kv: Arc<ChainStateStorageKV>) -> Self {
         Self { kv }
     }
-    pub fn set_current_header(&self, header: BlockHeader) -> Result<()> {
-        self.kv.put(
+    pub fn set_current_header(&self, batch: &mut WriteBatch, header: BlockHeader) -> Result<()> {
+        batch.put::<Self>(
             CURR_HEAD.to_string(),
             ChainStateValue::CurrentHeader(header),
         )
@@ -75,36 +82,57 @@ pub struct ChainState {
     chain_state: Arc<ChainStateStorage>,
     vm: Arc<WasmVM>,
     sender: UnboundedSender<LocalEventMessage>,
+    pruning: StatePruning,
+    prune_lock: Mutex<()>,
 }
 
 impl ChainState {
+    #[allow(clippy::too_many_arguments)]
     pub fn new(
         state_dir: PathBuf,
         consensus: Arc<dyn Consensus>,
         block_storage: Arc<BlockStorage>,
-        built_in: Vec<(&'static str, &[u8])>,
+        chainspec: &ChainSpec,
+        built_in: Vec<(String, Vec<u8>)>,
         chain_state_storage: Arc<ChainStateStorage>,
         sender: UnboundedSender<LocalEventMessage>,
+        pruning: StatePruning,
     ) -> Result<Self> {
-        let state = Arc::new(State::new(state_dir)?);
+        let state = Arc::new(State::with_hasher(state_dir, chainspec.tree_hasher)?);
         let vm = if let Some(current_head) = chain_state_storage.get_current_header()? {
+            // A state root committed for a block whose head update was never written is not
+            // referenced by the head, resetting to the head's root discards it
             state.reset(current_head.state_root)?;
             let vm = Arc::new(WasmVM::new(block_storage.clone())?);
             for (pkn, _) in built_in {
                 vm.load_application(
                     state.clone(),
-                    get_address_from_package_name(pkn, consensus.network())?,
+                    get_address_from_package_name(&pkn, consensus.network())?,
                 )?;
             }
             info!(blockhash = ?current_head.hash(), level = ?current_head.level, "restore from blockchain state");
             vm
         } else {
-            // TODO: Clean up genesis generation to use a config file or function
             let mut genesis = consensus.get_genesis_header();
-            state.credit_balance(&Address::default(), 1_000_000_000_000)?;
+            for account in chainspec.accounts.iter() {
+                let mut account_state = state.account_state(&account.address);
+                account_state.free_balance = account_state
+                    .free_balance
+                    .checked_add(account.free)
+                    .ok_or_else(|| {
+                        anyhow!("genesis balance of {} overflows", account.address)
+                    })?;
+                account_state.reserve_balance = account_state
+                    .reserve_balance
+                    .checked_add(account.reserved)
+                    .ok_or_else(|| {
+                        anyhow!("genesis reserve of {} overflows", account.address)
+                    })?;
+                state.set_account_state(account.address, account_state)?;
+            }
             let vm = Arc::new(WasmVM::new(block_storage.clone())?);
             let mut states = HashMap::new();
-            for (pkn, binary) in built_in {
+            for (pkn, binary) in built_in.iter() {
                 let app_address = get_address_from_package_name(pkn, consensus.network())?;
                 let (descriptor, changelist) =
                     vm.create_application(state.clone(), ROOT, app_address, 0, binary)?;
@@ -120,10 +148,7 @@ impl ChainState {
                 app_state.app_state =
                     Some(AppState::new(changelist.storage.root(), code_hash, ROOT, 1));
 
-                state.set_app_data(
-                    AppStateKey(app_address, changelist.storage.root()),
-                    changelist.storage,
-                )?;
+                state.set_app_data(&changelist.storage)?;
                 state.set_app_metadata(binary, descriptor)?;
             }
             for (addr, account_state) in states {
@@ -132,8 +157,16 @@ impl ChainState {
             state.commit()?;
             genesis.state_root = state.root();
             let block = Block::new(genesis, vec![]);
-            block_storage.put(block)?;
-            chain_state_storage.set_current_header(genesis)?;
+            let mut batch = WriteBatch::new();
+            block_storage.put(&mut batch, block.clone())?;
+            block_storage.set_canonical(&mut batch, &block)?;
+            block_storage.put_total_work(
+                &mut batch,
+                genesis.hash(),
+                consensus.block_proof(&genesis),
+            )?;
+            chain_state_storage.set_current_header(&mut batch, genesis)?;
+            block_storage.write(batch)?;
             info!(blockhash = ?genesis.hash(), level = ?genesis.level, "blockchain state started from genesis");
             vm
         };
@@ -146,6 +179,8 @@ impl ChainState {
             chain_state: chain_state_storage,
             vm,
             sender,
+            pruning,
+            prune_lock: Default::default(),
         })
     }
 
@@ -155,72 +190,37 @@ impl ChainState {
         blocks: Box<dyn Iterator<Item = Block>>,
         txpool: Arc<RwLock<TxPool>>,
     ) -> Result<()> {
-        let _lock = self.lock.write().map_err(|e| anyhow!("{}", e))?;
-        let mut blocks = blocks.peekable();
-        let current_head = self.current_header()?;
-        let current_head =
-            current_head.ok_or_else(|| anyhow!("failed to load current head, state invalid"))?;
-        let first_block = blocks.peek().unwrap();
-        if first_block.parent_hash().ne(&current_head.hash)
-            && current_head.raw.level > first_block.level() - 1
-        {
-            // Reset header to common head
-            let _header = first_block.header();
-            let block_storage = self.block_storage();
-            let parent_header = block_storage
-                .get_header_by_hash(first_block.parent_hash())?
-                .ok_or_else(|| anyhow!("error accepting block non commit"))?;
-
-            let parent_header_raw = &parent_header.raw;
-            let parent_state_root = parent_header_raw.state_root;
-            debug!(blockhash = ?parent_header_raw.hash(), level = parent_header_raw.level, "Resetting state to");
-            self.state.reset(parent_state_root)?;
-            self.chain_state.set_current_header(*parent_header_raw)?;
-            info!(blockhash = ?parent_header_raw.hash(), level = parent_header_raw.level, "Rolled back chain to previous");
-            debug!(chain_head = ?current_head.hash, chain_tail = ?parent_header.hash, level = current_head.raw.level, "Removing stale chain");
-            // Remove current chain
-            {
-                let block_storage = self.block_storage();
-                let mut head = current_head.raw.hash();
-                let mut remove_count = 0;
-                loop {
-                    let (next, level) = match block_storage.get_header_by_hash(&head) {
-                        Ok(Some(block)) => (block.raw.parent_hash, block.raw.level),
-                        _ => break,
-                    };
-
-                    // Delete Head from storage
-                    block_storage.delete(&head, level)?;
-                    remove_count += 1;
-                    debug!(blockhash = ?head,level = current_head.raw.level, "Deleting block");
-                    head = next;
-
-                    if next.ne(&parent_header.hash) {
-                        break;
-                    }
-                }
-
-                warn!( staled_blocks_count = ?remove_count, "Chain ReOrg");
-            }
+        let mut prune_due = false;
+        let result = self.import_chain(consensus, blocks, txpool, &mut prune_due);
+        // Pruning walks the whole state, it runs once the chain lock is released so imports and
+        // head reads are not stalled behind it
+        if prune_due {
+            self.maybe_prune_state();
         }
+        result
+    }
 
+    fn import_chain(
+        &self,
+        consensus: Arc<dyn Consensus>,
+        blocks: Box<dyn Iterator<Item = Block>>,
+        txpool: Arc<RwLock<TxPool>>,
+        prune_due: &mut bool,
+    ) -> Result<()> {
+        let _lock = self.lock.write().map_err(|e| anyhow!("{}", e))?;
         for block in blocks {
             let header = *block.header();
-            match self
-                .process_block(consensus.clone(), block)
-                .and_then(|block| self.accept_block(consensus.clone(), block))
-            {
-                Ok((repack, store_block, block)) => {
-                    if store_block {
-                        self.block_storage.put(block.clone())?;
-                    }
-
-                    if repack {
+            match self.process_block(consensus.clone(), block).and_then(
+                |(block, receipts, diff, reward)| {
+                    self.accept_block(consensus.clone(), block, receipts, diff, reward)
+                },
+            ) {
+                Ok(reset) => {
+                    if let Some(reset) = reset {
                         let mut txpool = txpool.write().map_err(|e| anyhow::anyhow!("{}", e))?;
-                        txpool.repack(
-                            AccountSet::new(),
-                            Some(ResetRequest::new(Some(current_head.raw), *block.header())),
-                        )?;
+                        txpool.repack(AccountSet::new(), Some(reset))?;
+                        drop(txpool);
+                        *prune_due |= self.prune_level_reached();
                     }
                 }
                 Err(e) => {
@@ -246,52 +246,91 @@ impl ChainState {
             .map(|header| header.map(|header| header.into()))
     }
 
-    fn process_block(&self, consensus: Arc<dyn Consensus>, block: Block) -> Result<Block> {
+    fn process_block(
+        &self,
+        consensus: Arc<dyn Consensus>,
+        block: Block,
+    ) -> Result<(Block, Vec<Receipt>, StateDiff, BlockReward)> {
         let mut header = *block.header();
         consensus.prepare_header(self.block_storage.clone(), &mut header)?;
         let block_storage = self.block_storage();
         let parent_header = block_storage
             .get_header_by_hash(block.parent_hash())?
             .ok_or_else(|| anyhow!("error processing block parent block not found"))?;
+        // The senders are recovered before executing so a bad signature rejects the block
+        for tx in block.transactions() {
+            tx.from().map_err(|_| BlockChainError::InvalidTransaction)?;
+        }
         let parent_state_root = parent_header.raw.state_root;
         let parent_state = self.state.get_sate_at(parent_state_root)?;
-        consensus.finalize(
-            self.block_storage.clone(),
-            &mut header,
-            self.vm.clone(),
-            parent_state,
-            block.transactions(),
-        )?;
+        let reward = consensus.block_reward(self.block_storage.clone(), &header)?;
+        let (receipts, diff) = consensus
+            .finalize(
+                &mut header,
+                self.vm.clone(),
+                parent_state,
+                &reward,
+                block.transactions(),
+            )
+            .map_err(|e| {
+                if let Some(StateError::InvalidNonce { expected, got }) = e.downcast_ref() {
+                    warn!(header = ?block.hash(), expected, got, "Rejected block with invalid transaction nonce");
+                }
+                e
+            })?;
+        if header.receipt_hash != block.header().receipt_hash {
+            warn!(header = ?block.hash(), expected_receipt_hash = ?header.receipt_hash, block_receipt_hash = ?block.header().receipt_hash, "Rejected block with invalid receipts");
+            return Err(BlockChainError::InvalidReceiptHash.into());
+        }
         consensus
             .verify_header(self.block_storage.clone(), &header)
             .map_err(|e| FailedToVerifyHeader(header.into(), (*block.header()).into(), e))?;
         if header.hash() != block.hash() {
             return Err(BlockChainError::InvalidBlock.into());
         }
-        Ok(block)
+        Ok((block, receipts, diff, reward))
     }
 
+    /// Stores a processed block with its receipts and state diff, and makes it the head if it
+    /// extends the current head or its branch has more work. The block and the head are written
+    /// in one batch, after the state root of the block was committed. If the batch can not be
+    /// written the state is reset to the current head.
     fn accept_block(
         &self,
         consensus: Arc<dyn Consensus>,
         block: Block,
-    ) -> Result<(bool, bool, Block)> {
+        receipts: Vec<Receipt>,
+        diff: StateDiff,
+        reward: BlockReward,
+    ) -> Result<Option<ResetRequest>> {
         let current_head = self.current_header()?;
         let current_head =
             current_head.ok_or_else(|| anyhow!("failed to load current head, state invalid"))?;
         let header = block.header();
-        let mut repack = false;
+        let mut reset = None;
+        let parent_total_work = self
+            .block_storage
+            .get_total_work(block.parent_hash())?
+            .unwrap_or_default();
+        let total_work = parent_total_work + consensus.block_proof(header);
+        let mut batch = WriteBatch::new();
+        self.block_storage.put(&mut batch, block.clone())?;
+        self.block_storage
+            .put_receipts(&mut batch, header, receipts)?;
+        self.block_storage
+            .put_state_diff(&mut batch, header, diff)?;
+        self.block_storage
+            .put_total_work(&mut batch, block.hash(), total_work)?;
         if block.parent_hash().eq(&current_head.hash) {
-            let state = self.state();
-            state.apply_txs(self.vm.clone(), block.transactions())?;
-            let _ = state.credit_balance(&header.coinbase, consensus.miner_reward(header.level))?;
-            state.commit()?;
-            self.chain_state.set_current_header(*header)?;
+            if let Err(error) = self.commit_block(batch, &block, &reward) {
+                self.state.reset(current_head.raw.state_root)?;
+                return Err(error);
+            }
             self.sender.send(LocalEventMessage::StateChanged {
                 current_head: self.current_header().unwrap().unwrap().raw,
             })?;
             info!(header = ?header.hash(), level = header.level, parent_hash = ?format!("{}", header.parent_hash), tx_count = block.transactions().len(), "Applied new block");
-            repack = true;
+            reset = Some(ResetRequest::new(Some(current_head.raw), *header));
         } else {
             let state = self.state();
             let block_storage = self.block_storage();
@@ -300,30 +339,184 @@ impl ChainState {
                 .get_header_by_hash(block.parent_hash())?
                 .ok_or_else(|| anyhow!("error accepting block non commit"))?;
             let parent_state_root = parent_header.raw.state_root;
-            let commit_state = state.apply_txs_no_commit(
+            let (commit_state, _) = state.apply_txs_no_commit(
                 self.vm.clone(),
                 parent_state_root,
-                consensus.miner_reward(block.level()),
-                block.header().coinbase,
+                &reward,
                 block.transactions(),
             )?;
             let commit_state = H256::from(commit_state);
             if commit_state.ne(&header.state_root) {
                 warn!(header = ?header.hash(), expected_state_root = ?commit_state , block_state_root = ?header.state_root, parent_hash = ?format!("{}", header.parent_hash), "Rejected block with invalid state");
-                bail!("Invalid or Corrupt Block")
+                return Err(BlockChainError::InvalidStateRoot.into());
             }
 
             info!(header = ?header.hash(), level = header.level, parent_hash = ?format!("{}", header.parent_hash), "Accepted block No Commit");
-            if block.level() > current_head.raw.level {
-                debug!(header = ?header.hash(), level = header.level, parent_hash = ?format!("{}", header.parent_hash), "Resetting state");
-                self.state.reset(header.state_root)?;
-                self.chain_state.set_current_header(*header)?;
+            let current_total_work = self
+                .block_storage
+                .get_total_work(&current_head.hash)?
+                .unwrap_or_default();
+            if total_work > current_total_work {
+                debug!(header = ?header.hash(), level = header.level, total_work = ?total_work, current_total_work = ?current_total_work, "Resetting state");
+                let discarded = self.switch_chain(batch, &current_head, &block)?;
                 info!(header = ?header.hash(), level = header.level, parent_hash = ?format!("{}", header.parent_hash), "Chain changed, network fork");
-                repack = true
+                reset = Some(ResetRequest::with_discarded(
+                    Some(current_head.raw),
+                    *header,
+                    discarded,
+                ));
+            } else {
+                self.block_storage.write(batch)?;
             }
         }
+        Ok(reset)
+    }
 
-        Ok((repack, true, block))
+    /// Applies the transactions of `block` to the current state, commits the state and writes
+    /// `batch` with `block` as the new head
+    fn commit_block(
+        &self,
+        mut batch: WriteBatch,
+        block: &Block,
+        reward: &BlockReward,
+    ) -> Result<()> {
+        let state = self.state();
+        state.apply_txs(self.vm.clone(), reward, block.transactions())?;
+        state.commit()?;
+        self.block_storage.set_canonical(&mut batch, block)?;
+        self.chain_state
+            .set_current_header(&mut batch, *block.header())?;
+        self.block_storage.write(batch)
+    }
+
+    /// Makes `new_head` the tip of the canonical chain. The current chain is unwound down to the
+    /// common ancestor, its blocks are kept as a side branch but no longer indexed by level or
+    /// transaction, and the new branch is indexed by level. The changes are written together
+    /// with `batch`, which stores `new_head`. Returns the transactions of the unwound blocks that
+    /// are not included on the new branch
+    fn switch_chain(
+        &self,
+        mut batch: WriteBatch,
+        current_head: &IndexedBlockHeader,
+        new_head: &Block,
+    ) -> Result<Vec<SignedTransaction>> {
+        let block_storage = self.block_storage();
+        let mut included: BTreeSet<H256> =
+            new_head.transactions().iter().map(|tx| tx.hash()).collect();
+
+        // Walk the new branch back to the canonical chain
+        let mut branch = Vec::new();
+        let mut parent_hash = *new_head.parent_hash();
+        let ancestor = loop {
+            let parent = block_storage
+                .get_block_by_hash(&parent_hash)?
+                .ok_or_else(|| anyhow!("missing ancestor {} of new chain head", parent_hash))?;
+            if let Some(canonical) = block_storage.get_header_by_level(parent.level())? {
+                if canonical.hash.eq(&parent.hash()) {
+                    break canonical;
+                }
+            }
+            included.extend(parent.transactions().iter().map(|tx| tx.hash()));
+            parent_hash = *parent.parent_hash();
+            branch.push(parent);
+        };
+
+        // Unwind the current chain down to the common ancestor
+        let mut discarded = Vec::new();
+        let mut stale = current_head.clone();
+        while stale.raw.level > ancestor.raw.level {
+            if let Some(block) = block_storage.get_block(&stale.hash, stale.raw.level)? {
+                discarded.extend(
+                    block
+                        .transactions()
+                        .iter()
+                        .filter(|tx| !included.contains(&tx.hash()))
+                        .cloned(),
+                );
+            }
+            let parent_hash = stale.raw.parent_hash;
+            debug!(blockhash = ?stale.hash, level = stale.raw.level, "Unindexing stale block");
+            block_storage.unset_canonical(&mut batch, &stale.hash, stale.raw.level)?;
+            stale = block_storage
+                .get_header_by_hash(&parent_hash)?
+                .ok_or_else(|| anyhow!("missing ancestor {} of current chain head", parent_hash))?;
+        }
+
+        for block in branch.iter().rev().chain(std::iter::once(new_head)) {
+            block_storage.set_canonical(&mut batch, block)?;
+        }
+        self.chain_state
+            .set_current_header(&mut batch, *new_head.header())?;
+        block_storage.write(batch)?;
+        self.state.reset(new_head.header().state_root)?;
+
+        let depth = current_head.raw.level - ancestor.raw.level;
+        warn!(old_tip = ?current_head.hash, new_tip = ?new_head.hash(), common_ancestor = ?ancestor.hash, depth = depth, discarded_txs = discarded.len(), "Chain ReOrg");
+        self.sender.send(LocalEventMessage::ChainReorg {
+            old_tip: current_head.raw,
+            new_tip: *new_head.header(),
+            depth,
+        })?;
+        Ok(discarded)
+    }
+
+    /// Whether pruning is enabled and the current head is at a multiple of
+    /// [`STATE_PRUNE_INTERVAL`].
+    fn prune_level_reached(&self) -> bool {
+        if !matches!(self.pruning, StatePruning::KeepRecent(_)) {
+            return false;
+        }
+        match self.current_header() {
+            Ok(Some(header)) => header.raw.level % STATE_PRUNE_INTERVAL == 0,
+            _ => false,
+        }
+    }
+
+    /// Prunes the state when an import reached a prune level. It runs after the import released
+    /// the chain lock, a failure is logged and does not fail the block import. A prune already in
+    /// progress makes this one a no-op.
+    fn maybe_prune_state(&self) {
+        let StatePruning::KeepRecent(keep_recent) = self.pruning else {
+            return;
+        };
+        if let Err(error) = self.prune_state(keep_recent) {
+            warn!(error = ?error, "Failed to prune state");
+        }
+    }
+
+    /// Deletes the state of blocks more than `keep_recent` levels below the current head. Side
+    /// chain states within the window are kept so recent forks can still be reorganized to.
+    ///
+    /// The live state is marked without the chain lock, only deleting what is no longer
+    /// reachable takes it, so block imports continue while the state is walked.
+    pub fn prune_state(&self, keep_recent: u32) -> Result<usize> {
+        let Ok(_prune_lock) = self.prune_lock.try_lock() else {
+            debug!("State prune already in progress");
+            return Ok(0);
+        };
+        let current_head = self
+            .current_header()?
+            .ok_or_else(|| anyhow!("failed to load current head, state invalid"))?;
+        let from = current_head
+            .raw
+            .level
+            .saturating_sub(keep_recent.saturating_sub(1));
+        let mut keep = BTreeSet::new();
+        for header in self.block_storage.get_headers(from)? {
+            keep.insert(header?.state_root);
+        }
+        let plan = self.state.plan_prune(&keep)?;
+        let pruned = {
+            let _lock = self.lock.write().map_err(|e| anyhow!("{}", e))?;
+            self.state.apply_prune(plan)?
+        };
+        info!(
+            level = current_head.raw.level,
+            kept = keep.len(),
+            pruned,
+            "Pruned state"
+        );
+        Ok(pruned)
     }
 
     pub fn block_storage(&self) -> Arc<BlockStorage> {
@@ -379,4 +572,355 @@ impl ChainReader for ChainState {
     fn get_block_by_level(&self, level: u32) -> Result<Option<Block>> {
         self.block_storage.get_block_by_level(level)
     }
+
+    fn get_transaction_location(&self, tx_hash: &H256) -> Result<Option<TransactionLocation>> {
+        self.block_storage.get_transaction_location(tx_hash)
+    }
+
+    fn get_block_receipts(&self, hash: &H256, level: u32) -> Result<Option<Vec<Receipt>>> {
+        self.block_storage.get_block_receipts(hash, level)
+    }
+
+    fn get_block_state_diff(&self, hash: &H256, level: u32) -> Result<Option<StateDiff>> {
+        self.block_storage.get_block_state_diff(hash, level)
+    }
+
+    fn get_total_work(&self, hash: &H256) -> Result<Option<U256>> {
+        self.block_storage.get_total_work(hash)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::{Arc, RwLock};
+
+    use anyhow::Result;
+    use tempdir::TempDir;
+    use tokio::sync::mpsc::{unbounded_channel, UnboundedReceiver};
+
+    use account::create_account_from_uri;
+    use primitive_types::{Compact, H256, U256};
+    use smt::SparseMerkleTree;
+    use storage::memstore::MemStore;
+    use storage::{PersistentStorage, PersistentStorageBackend, Schema};
+    use traits::{Blockchain, ChainHeadReader, ChainReader, Consensus, StateDB, WasmVMInstance};
+    use transaction::make_payment_sign_transaction;
+    use txpool::TxPool;
+    use types::account::BlockReward;
+    use types::block::{Block, BlockHeader};
+    use types::chainspec::{ChainSpec, GenesisAccount};
+    use types::config::StatePruning;
+    use types::diff::StateDiff;
+    use types::events::LocalEventMessage;
+    use types::network::Network;
+    use types::receipt::Receipt;
+    use types::tx::SignedTransaction;
+
+    use crate::block_storage::*;
+    use crate::chain_state::{ChainState, ChainStateStorage};
+
+    /// Accepts every header, the work of a block is its difficulty
+    struct TestConsensus;
+
+    impl Consensus for TestConsensus {
+        fn verify_header(&self, _: Arc<dyn ChainHeadReader>, _: &BlockHeader) -> Result<()> {
+            Ok(())
+        }
+
+        fn prepare_header(&self, _: Arc<dyn ChainHeadReader>, _: &mut BlockHeader) -> Result<()> {
+            Ok(())
+        }
+
+        fn finalize(
+            &self,
+            header: &mut BlockHeader,
+            vm: Arc<dyn WasmVMInstance>,
+            state: Arc<dyn StateDB>,
+            reward: &BlockReward,
+            txs: &[SignedTransaction],
+        ) -> Result<(Vec<Receipt>, StateDiff)> {
+            let mut merkle = SparseMerkleTree::default();
+            for tx in txs {
+                merkle.update(tx.hash(), tx.hash())?;
+            }
+            let (receipts, diff) = state.apply_txs(vm, reward, txs)?;
+            state.commit()?;
+            let mut receipts_merkle = SparseMerkleTree::default();
+            for receipt in receipts.iter() {
+                receipts_merkle.update(receipt.tx_hash(), receipt.hash())?;
+            }
+            header.state_root = state.root();
+            header.tx_root = merkle.root();
+            header.receipt_hash = receipts_merkle.root();
+            Ok((receipts, diff))
+        }
+
+        fn finalize_and_assemble(
+            &self,
+            chain: Arc<dyn ChainHeadReader>,
+            header: &mut BlockHeader,
+            vm: Arc<dyn WasmVMInstance>,
+            state: Arc<dyn StateDB>,
+            txs: &[SignedTransaction],
+        ) -> Result<Option<Block>> {
+            let reward = self.block_reward(chain, header)?;
+            self.finalize(header, vm, state, &reward, txs)?;
+            Ok(Some(Block::new(*header, txs.into())))
+        }
+
+        fn work_required(&self, _: Arc<dyn ChainHeadReader>, _: &H256, _: u32) -> Result<Compact> {
+            Ok(Compact::from(1u32))
+        }
+
+        fn is_genesis(&self, header: &BlockHeader) -> bool {
+            header.level == 0
+        }
+
+        fn miner_reward(&self, _: u32) -> u64 {
+            0
+        }
+
+        fn block_reward(
+            &self,
+            _: Arc<dyn ChainHeadReader>,
+            header: &BlockHeader,
+        ) -> Result<BlockReward> {
+            Ok(BlockReward {
+                level: header.level,
+                coinbase: header.coinbase,
+                amount: 0,
+                unlock_level: header.level + 100,
+                matured_coinbase: None,
+                fee_burn_percent: 0,
+                chain_id: Network::Testnet.chain_id(),
+            })
+        }
+
+        fn block_proof(&self, header: &BlockHeader) -> U256 {
+            U256::from(header.difficulty)
+        }
+
+        fn get_genesis_header(&self) -> BlockHeader {
+            BlockHeader {
+                chain_id: Network::Testnet.chain_id(),
+                difficulty: 1,
+                ..Default::default()
+            }
+        }
+
+        fn network(&self) -> Network {
+            Network::Testnet
+        }
+    }
+
+    struct TestChain {
+        _dir: TempDir,
+        chain: Arc<ChainState>,
+        txpool: Arc<RwLock<TxPool>>,
+        events: UnboundedReceiver<LocalEventMessage>,
+    }
+
+    impl TestChain {
+        fn new() -> Self {
+            Self::with_accounts(vec![GenesisAccount {
+                address: create_account_from_uri(Network::Testnet, "alice")
+                    .unwrap()
+                    .address,
+                free: 1_000,
+                reserved: 0,
+            }])
+            .unwrap()
+        }
+
+        fn with_accounts(accounts: Vec<GenesisAccount>) -> Result<Self> {
+            let dir = TempDir::new("chain_state").unwrap();
+            let columns = vec![
+                BlockHeaderStorage::column(),
+                BlockTransactionsStorage::column(),
+                BlockReceiptsStorage::column(),
+                BlockStateDiffStorage::column(),
+                BlockByHash::column(),
+                BlockByLevel::column(),
+                TransactionIndex::column(),
+                BlockTotalWork::column(),
+                ChainStateStorage::column(),
+            ];
+            let storage = Arc::new(PersistentStorage::new(PersistentStorageBackend::InMemory(
+                Arc::new(MemStore::new(columns)),
+            )));
+            let mut chainspec = ChainSpec::from_network(Network::Testnet).unwrap();
+            chainspec.accounts = accounts;
+            let (sender, events) = unbounded_channel();
+            let chain = Arc::new(ChainState::new(
+                dir.path().join("state"),
+                Arc::new(TestConsensus),
+                Arc::new(BlockStorage::new(storage.clone())),
+                &chainspec,
+                vec![],
+                Arc::new(ChainStateStorage::new(storage.database())),
+                sender.clone(),
+                StatePruning::Archive,
+            )?);
+            let txpool = Arc::new(RwLock::new(
+                TxPool::new(None, None, sender, chain.clone()).unwrap(),
+            ));
+            Ok(Self {
+                _dir: dir,
+                chain,
+                txpool,
+                events,
+            })
+        }
+
+        /// Builds a child of `parent` with `work` and imports it
+        fn mine(&self, parent: &BlockHeader, work: u32, txs: Vec<SignedTransaction>) -> Block {
+            let mut header = BlockHeader {
+                parent_hash: parent.hash(),
+                level: parent.level + 1,
+                time: parent.time + 1,
+                chain_id: Network::Testnet.chain_id(),
+                difficulty: work,
+                ..Default::default()
+            };
+            let state = self.chain.state().get_sate_at(parent.state_root).unwrap();
+            let reward = TestConsensus
+                .block_reward(self.chain.block_storage(), &header)
+                .unwrap();
+            TestConsensus
+                .finalize(&mut header, self.chain.vm(), state, &reward, &txs)
+                .unwrap();
+            let block = Block::new(header, txs);
+            self.chain
+                .put_chain(
+                    Arc::new(TestConsensus),
+                    Box::new(std::iter::once(block.clone())),
+                    self.txpool.clone(),
+                )
+                .unwrap();
+            block
+        }
+
+        fn head(&self) -> H256 {
+            self.chain.current_header().unwrap().unwrap().hash
+        }
+
+        fn reorgs(&mut self) -> Vec<(H256, H256, u32)> {
+            let mut reorgs = Vec::new();
+            while let Ok(event) = self.events.try_recv() {
+                if let LocalEventMessage::ChainReorg {
+                    old_tip,
+                    new_tip,
+                    depth,
+                } = event
+                {
+                    reorgs.push((old_tip.hash(), new_tip.hash(), depth));
+                }
+            }
+            reorgs
+        }
+    }
+
+    fn payment(nonce: u64) -> SignedTransaction {
+        let alice = create_account_from_uri(Network::Testnet, "alice").unwrap();
+        let bob = create_account_from_uri(Network::Testnet, "bob").unwrap();
+        make_payment_sign_transaction(alice.secret, bob.address, nonce, 10, 1, Network::Testnet)
+            .unwrap()
+    }
+
+    #[test]
+    fn test_deep_reorg_keeps_stale_blocks_and_reinjects_transactions() {
+        let mut chain = TestChain::new();
+        let genesis = chain.chain.genesis().raw;
+        let (first, second) = (payment(1), payment(2));
+
+        let a1 = chain.mine(&genesis, 1, vec![first.clone()]);
+        let a2 = chain.mine(a1.header(), 1, vec![second.clone()]);
+        let a3 = chain.mine(a2.header(), 1, vec![]);
+        assert_eq!(chain.head(), a3.hash());
+
+        // The fork includes the first payment again, it catches up on work at its third block
+        let b1 = chain.mine(&genesis, 1, vec![first.clone()]);
+        let b2 = chain.mine(b1.header(), 1, vec![]);
+        let b3 = chain.mine(b2.header(), 1, vec![]);
+        assert_eq!(chain.head(), a3.hash());
+        assert!(chain.reorgs().is_empty());
+        let b4 = chain.mine(b3.header(), 1, vec![]);
+        assert_eq!(chain.head(), b4.hash());
+        assert_eq!(chain.reorgs(), vec![(a3.hash(), b4.hash(), 3)]);
+
+        let storage = chain.chain.block_storage();
+        for (level, block) in [&b1, &b2, &b3, &b4].into_iter().enumerate() {
+            assert_eq!(
+                storage
+                    .get_header_by_level(level as u32 + 1)
+                    .unwrap()
+                    .unwrap()
+                    .hash,
+                block.hash()
+            );
+        }
+        // The stale branch is kept as a side branch
+        for block in [&a1, &a2, &a3] {
+            let stored = storage.get_block_by_hash(&block.hash()).unwrap().unwrap();
+            assert_eq!(stored.transactions().len(), block.transactions().len());
+        }
+        assert_eq!(
+            storage
+                .get_transaction_location(&first.hash())
+                .unwrap()
+                .unwrap()
+                .block_hash,
+            b1.hash()
+        );
+        assert!(storage
+            .get_transaction_location(&second.hash())
+            .unwrap()
+            .is_none());
+
+        // Only the payment missing from the new chain goes back to the pool
+        let txpool = chain.txpool.read().unwrap();
+        assert!(txpool.has(&second.hash()));
+        assert!(!txpool.has(&first.hash()));
+    }
+
+    #[test]
+    fn test_fork_choice_follows_total_work() {
+        let mut chain = TestChain::new();
+        let genesis = chain.chain.genesis().raw;
+        let a1 = chain.mine(&genesis, 5, vec![]);
+        let a2 = chain.mine(a1.header(), 5, vec![]);
+
+        // A longer fork with less work does not replace the head
+        let mut parent = genesis;
+        for _ in 0..3 {
+            parent = *chain.mine(&parent, 1, vec![]).header();
+        }
+        assert!(parent.level > a2.level());
+        assert_eq!(chain.head(), a2.hash());
+        assert!(chain.reorgs().is_empty());
+
+        // A shorter fork with more work does
+        let heavy = chain.mine(&genesis, 20, vec![]);
+        assert_eq!(chain.head(), heavy.hash());
+        assert_eq!(chain.reorgs(), vec![(a2.hash(), heavy.hash(), 2)]);
+        let storage = chain.chain.block_storage();
+        assert_eq!(
+            storage.get_header_by_level(1).unwrap().unwrap().hash,
+            heavy.hash()
+        );
+        assert!(storage.get_header_by_level(2).unwrap().is_none());
+    }
+
+    #[test]
+    fn test_genesis_balance_overflow_is_rejected() {
+        let alice = create_account_from_uri(Network::Testnet, "alice")
+            .unwrap()
+            .address;
+        let account = GenesisAccount {
+            address: alice,
+            free: u64::MAX,
+            reserved: 0,
+        };
+        assert!(TestChain::with_accounts(vec![account.clone(), account]).is_err());
+    }
 }
@@ -1,4 +1,5 @@
 use anyhow::Error;
+use state::error::StateError;
 use thiserror::Error;
 use types::block::BlockHeader;
 
@@ -30,8 +31,38 @@ pub enum BlockChainError {
     TransactionNotFound,
     #[error("InvalidBlock")]
     InvalidBlock,
+    #[error("InvalidReceiptHash")]
+    InvalidReceiptHash,
+    #[error("InvalidStateRoot")]
+    InvalidStateRoot,
     #[error("UnknownError")]
     UnknownError,
     #[error("Failed to verify header expected {0:#?} {1:#?} detail error {2}")]
     FailedToVerifyHeader(Box<BlockHeader>, Box<BlockHeader>, Error),
 }
+
+impl BlockChainError {
+    /// Whether `error` rejected a block because the block is invalid, as opposed to the block
+    /// not being processed, e.g. because of a storage error or a missing parent
+    pub fn is_invalid_block(error: &Error) -> bool {
+        if let Some(error) = error.downcast_ref::<BlockChainError>() {
+            return matches!(
+                error,
+                BlockChainError::InvalidBlock
+                    | BlockChainError::InvalidReceiptHash
+                    | BlockChainError::InvalidStateRoot
+                    | BlockChainError::InvalidTransaction
+                    | BlockChainError::FailedToVerifyHeader(..)
+            );
+        }
+        matches!(
+            error.downcast_ref::<StateError>(),
+            Some(
+                StateError::InvalidNonce { .. }
+                    | StateError::InvalidChainId { .. }
+                    | StateError::UnsupportedTransaction(_)
+                    | StateError::Overflow
+            )
+        )
+    }
+}
@@ -4,7 +4,8 @@
 use storage::Schema;
 
 use crate::block_storage::{
-    BlockByHash, BlockByLevel, BlockHeaderStorage, BlockTransactionsStorage,
+    BlockByHash, BlockByLevel, BlockHeaderStorage, BlockReceiptsStorage, BlockStateDiffStorage,
+    BlockTotalWork, BlockTransactionsStorage, TransactionIndex,
 };
 use crate::chain_state::ChainStateStorage;
 
@@ -17,8 +18,12 @@ pub fn column_family_names() -> Vec<&'static str> {
     vec![
         BlockHeaderStorage::column(),
         BlockTransactionsStorage::column(),
+        BlockReceiptsStorage::column(),
+        BlockStateDiffStorage::column(),
         BlockByLevel::column(),
         BlockByHash::column(),
+        TransactionIndex::column(),
+        BlockTotalWork::column(),
         ChainStateStorage::column(),
     ]
 }
@@ -1,4 +1,9 @@
-use crate::rpc::{GetAccountRequest, GetDescriptorRequest};
+use crate::proof::{verify_account, verify_storage};
+use crate::rpc::block_selector::Block;
+use crate::rpc::{
+    BlockSelector, GetAccountProofRequest, GetAccountRequest, GetDescriptorRequest,
+    GetStorageProofRequest, Query, TransactionHash,
+};
 use crate::util::{parse_cli_args_to_json, RpcMethod};
 use crate::Client;
 use clap::{Args, Subcommand};
@@ -16,6 +21,7 @@ use std::path::PathBuf;
 use std::str::FromStr;
 use transaction::{make_payment_sign_transaction, make_signed_transaction};
 use types::account::{get_address_from_package_name, get_address_from_secret_key};
+use types::chainspec::ChainSpec;
 use types::network::Network;
 use types::tx::{ApplicationCall, CreateApplication, TransactionData};
 
@@ -25,6 +31,10 @@ pub struct ClientArgsCommands {
     command: ClientCommands,
     #[clap(long, default_value_t = String::from("127.0.0.1:9121"))]
     rpc_addr: String,
+    /// Chainspec of a custom network, transactions are signed for it and dev accounts and app
+    /// ids are resolved on it. Defaults to testnet.
+    #[clap(long)]
+    chainspec: Option<PathBuf>,
 }
 
 #[derive(Subcommand, Debug)]
@@ -32,9 +42,14 @@ pub enum ClientCommands {
     GetBalance(AddressArg),
     GetNonce(AddressArg),
     GetAccountState(AddressArg),
+    /// Fetches the account state with a merkle proof and verifies it locally against the state
+    /// root of a block with valid proof of work, the block is not checked to be canonical
+    GetAccountProof(AddressArg),
     SendPayment(SendPaymentArgs),
     App(AppArgsCommands),
     GetTxpool,
+    GetTransaction(TxHashArg),
+    GetTransactionReceipt(TxHashArg),
 }
 
 #[derive(Args, Debug)]
@@ -50,7 +65,7 @@ pub struct SignerArgs {
     #[clap(long)]
     value: u64,
     #[clap(long, value_parser = parse_signer)]
-    signer: H256,
+    signer: SignerInput,
 }
 
 #[derive(Subcommand, Debug)]
@@ -58,24 +73,55 @@ pub enum AppCommands {
     Call(CallArgs),
     Create(AppCreateArgs),
     Query(AppQueryArgs),
+    /// Fetches an app storage value with merkle proofs and verifies it locally against the state
+    /// root of a block with valid proof of work, the block is not checked to be canonical
+    StorageProof(AppStorageProofArgs),
 }
 
 #[derive(Args, Debug)]
 pub struct AddressArg {
     #[clap(long, value_parser = parse_address)]
-    address: Address,
+    address: AddressInput,
+    #[clap(flatten)]
+    block: BlockArgs,
+}
+
+/// Reads state at a past block instead of the current head
+#[derive(Args, Debug)]
+pub struct BlockArgs {
+    #[clap(long, conflicts_with = "block-hash")]
+    block_level: Option<u32>,
+    #[clap(long, value_parser = parse_hash)]
+    block_hash: Option<H256>,
+}
+
+impl BlockArgs {
+    fn selector(&self) -> Option<BlockSelector> {
+        let block = match (self.block_level, self.block_hash) {
+            (Some(level), _) => Block::BlockLevel(level),
+            (None, Some(hash)) => Block::BlockHash(hash),
+            (None, None) => return None,
+        };
+        Some(BlockSelector { block: Some(block) })
+    }
+}
+
+#[derive(Args, Debug)]
+pub struct TxHashArg {
+    #[clap(long, value_parser = parse_hash)]
+    hash: H256,
 }
 
 #[derive(Args, Debug)]
 pub struct SendPaymentArgs {
     #[clap(long, value_parser = parse_address)]
-    to: Address,
+    to: AddressInput,
     #[clap(long)]
     amount: u64,
     #[clap(long)]
     fee: u64,
     #[clap(long, value_parser = parse_signer)]
-    signer: H256,
+    signer: SignerInput,
 }
 
 #[derive(Args, Debug)]
@@ -107,35 +153,89 @@ pub struct AppQueryArgs {
     call: String,
     #[clap(require_equals = true, multiple = true)]
     params: Vec<String>,
+    #[clap(flatten)]
+    block: BlockArgs,
+}
+
+#[derive(Args, Debug)]
+pub struct AppStorageProofArgs {
+    #[clap(long)]
+    app: String,
+    /// Hex encoded storage key
+    #[clap(long)]
+    key: String,
+    #[clap(flatten)]
+    block: BlockArgs,
+}
+
+/// An address, or the name of a dev account whose address depends on the network
+#[derive(Clone, Debug)]
+pub enum AddressInput {
+    Address(Address),
+    Dev(String),
+}
+
+impl AddressInput {
+    fn resolve(&self, network: Network) -> anyhow::Result<Address> {
+        match self {
+            AddressInput::Address(address) => Ok(*address),
+            AddressInput::Dev(name) => Ok(account::create_account_from_uri(network, name)?.address),
+        }
+    }
+}
+
+/// A secret key, or the name of a dev account
+#[derive(Clone, Debug)]
+pub enum SignerInput {
+    Secret(H256),
+    Dev(String),
 }
 
-pub(crate) fn parse_address(s: &str) -> Result<Address, String> {
-    if s.eq_ignore_ascii_case("ama")
+impl SignerInput {
+    fn secret(&self, network: Network) -> anyhow::Result<H256> {
+        match self {
+            SignerInput::Secret(secret) => Ok(*secret),
+            SignerInput::Dev(name) => Ok(account::create_account_from_uri(network, name)?.secret),
+        }
+    }
+}
+
+fn is_dev_account(s: &str) -> bool {
+    s.eq_ignore_ascii_case("ama")
         || s.eq_ignore_ascii_case("kofi")
         || s.eq_ignore_ascii_case("kwame")
-    {
-        return Ok(account::create_account_from_uri(Network::Testnet, s).address);
+}
+
+pub(crate) fn parse_address(s: &str) -> Result<AddressInput, String> {
+    if is_dev_account(s) {
+        return Ok(AddressInput::Dev(s.to_string()));
     }
     match Address::from_str(s) {
-        Ok(s) => Ok(s),
+        Ok(s) => Ok(AddressInput::Address(s)),
         Err(error) => Err(format!("{}", error)),
     }
 }
 
-pub(crate) fn parse_signer(s: &str) -> Result<H256, String> {
-    if s.eq_ignore_ascii_case("ama")
-        || s.eq_ignore_ascii_case("kofi")
-        || s.eq_ignore_ascii_case("kwame")
-    {
-        return Ok(account::create_account_from_uri(Network::Testnet, s).secret);
+pub(crate) fn parse_signer(s: &str) -> Result<SignerInput, String> {
+    if is_dev_account(s) {
+        return Ok(SignerInput::Dev(s.to_string()));
     }
     hex::decode(s)
         .map_err(|e| format!("{}", e))
-        .map(|decode_hex| H256::from_slice(&decode_hex))
+        .map(|decode_hex| SignerInput::Secret(H256::from_slice(&decode_hex)))
+}
+
+pub(crate) fn parse_hash(s: &str) -> Result<H256, String> {
+    let decoded = hex::decode(s.trim_start_matches("0x")).map_err(|e| format!("{}", e))?;
+    if decoded.len() != 32 {
+        return Err(format!("invalid hash length {}", decoded.len()));
+    }
+    Ok(H256::from_slice(&decoded))
 }
 
 pub async fn handle_app_command(
     rpc_client: &Client,
+    network: Network,
     command: &AppArgsCommands,
 ) -> anyhow::Result<Value> {
     let deserialize_options = DeserializeOptions::default().parse_string_to_primitives(true);
@@ -147,29 +247,31 @@ pub async fn handle_app_command(
             call,
             params,
         }) => {
-            let signer = sign_args.signer;
+            let signer = sign_args.signer.secret(network)?;
             let value = sign_args.value;
             let tip = sign_args.tip;
 
             let call = RpcMethod::parse(call)?;
-            let signer_address = get_address_from_secret_key(signer, Network::Testnet)?;
+            let signer_address = get_address_from_secret_key(signer, network)?;
 
             let nonce = rpc_client
                 .account_service()
                 .get_nonce(GetAccountRequest {
                     address: Some(signer_address),
+                    block: None,
                 })
                 .await?
                 .get_ref()
                 .nonce;
 
-            let app_id = get_address_from_package_name(app, Network::Testnet)?;
+            let app_id = get_address_from_package_name(app, network)?;
 
             //Get App Descriptor bytes
             let mut rt = rpc_client.runtime_api_service();
             let descriptor = rt
                 .get_descriptor(GetDescriptorRequest {
                     app_id: Some(app_id),
+                    block: None,
                 })
                 .await?
                 .into_inner()
@@ -199,8 +301,7 @@ pub async fn handle_app_command(
                 method: call.method_id(),
                 args: message.encode_to_vec(),
             });
-            let signed_tx =
-                make_signed_transaction(signer, nonce, value, tip, Network::Testnet, data)?;
+            let signed_tx = make_signed_transaction(signer, nonce, value, tip, network, data)?;
             let signed_tx_size = signed_tx.encoded_len();
             let response = rpc_client
                 .transaction_service()
@@ -218,16 +319,17 @@ pub async fn handle_app_command(
             sign_args,
             bin_path,
         }) => {
-            let signer = sign_args.signer;
+            let signer = sign_args.signer.secret(network)?;
             let value = sign_args.value;
             let tip = sign_args.tip;
 
-            let signer_address = get_address_from_secret_key(signer, Network::Testnet)?;
+            let signer_address = get_address_from_secret_key(signer, network)?;
 
             let nonce = rpc_client
                 .account_service()
                 .get_nonce(GetAccountRequest {
                     address: Some(signer_address),
+                    block: None,
                 })
                 .await?
                 .get_ref()
@@ -241,8 +343,7 @@ pub async fn handle_app_command(
                 package_name: package_name.to_owned(),
                 binary,
             });
-            let signed_tx =
-                make_signed_transaction(signer, nonce, value, tip, Network::Testnet, data)?;
+            let signed_tx = make_signed_transaction(signer, nonce, value, tip, network, data)?;
             let signed_tx_size = signed_tx.encoded_len();
             let response = rpc_client
                 .transaction_service()
@@ -254,14 +355,40 @@ pub async fn handle_app_command(
                 "tx_hash" : response.get_ref().hash,
             })
         }
-        AppCommands::Query(AppQueryArgs { app, call, params }) => {
+        AppCommands::StorageProof(AppStorageProofArgs { app, key, block }) => {
+            let app_id = get_address_from_package_name(app, network)?;
+            let key = hex::decode(key.trim_start_matches("0x"))?;
+            let response = rpc_client
+                .runtime_api_service()
+                .get_storage_proof(GetStorageProofRequest {
+                    app_id: Some(app_id),
+                    storage_key: key.clone(),
+                    block: block.selector(),
+                })
+                .await?
+                .into_inner();
+            let value = verify_storage(rpc_client, &app_id, &key, &response).await?;
+            json!({
+                "block_hash" : response.block_hash,
+                "state_root" : response.state_root,
+                "value" : hex::encode(&value, false),
+                "verified" : true,
+            })
+        }
+        AppCommands::Query(AppQueryArgs {
+            app,
+            call,
+            params,
+            block,
+        }) => {
             let call = RpcMethod::parse(call)?;
-            let app_id = get_address_from_package_name(app, Network::Testnet)?;
+            let app_id = get_address_from_package_name(app, network)?;
 
             let mut rt = rpc_client.runtime_api_service();
             let descriptor = rt
                 .get_descriptor(GetDescriptorRequest {
                     app_id: Some(app_id),
+                    block: block.selector(),
                 })
                 .await?
                 .into_inner()
@@ -285,11 +412,12 @@ pub async fn handle_app_command(
                 &deserialize_options,
             )?;
 
-            let query = ApplicationCall {
-                app_id,
+            let query = Query {
+                app_id: Some(app_id),
                 service: call.service_id(),
                 method: call.method_id(),
-                args: message_in.encode_to_vec(),
+                query: message_in.encode_to_vec(),
+                block: block.selector(),
             };
 
             let response = rpc_client
@@ -314,61 +442,91 @@ pub async fn handle_app_command(
 
 pub async fn handle_client_command(command: &ClientArgsCommands) -> anyhow::Result<Value> {
     let rpc_client = Client::connect(format!("http://{}", command.rpc_addr)).await?;
+    // Registers a custom network so the addresses of its accounts resolve to it, every command
+    // signs transactions and resolves addresses on this network
+    let network = match &command.chainspec {
+        Some(path) => ChainSpec::open(path)?.network()?,
+        None => Network::Testnet,
+    };
 
     let resp = match &command.command {
-        ClientCommands::GetBalance(AddressArg { address }) => {
+        ClientCommands::GetBalance(AddressArg { address, block }) => {
+            let address = address.resolve(network)?;
             let balance = rpc_client
                 .account_service()
                 .get_balance(GetAccountRequest {
-                    address: Some(*address),
+                    address: Some(address),
+                    block: block.selector(),
                 })
                 .await?;
             Value::Number(balance.get_ref().balance.into())
         }
-        ClientCommands::GetNonce(AddressArg { address }) => {
+        ClientCommands::GetNonce(AddressArg { address, block }) => {
+            let address = address.resolve(network)?;
             let nonce = rpc_client
                 .account_service()
                 .get_nonce(GetAccountRequest {
-                    address: Some(*address),
+                    address: Some(address),
+                    block: block.selector(),
                 })
                 .await?;
             Value::Number(nonce.get_ref().nonce.into())
         }
-        ClientCommands::GetAccountState(AddressArg { address }) => {
+        ClientCommands::GetAccountState(AddressArg { address, block }) => {
+            let address = address.resolve(network)?;
             let account_state = rpc_client
                 .account_service()
                 .get_account_state(GetAccountRequest {
-                    address: Some(*address),
+                    address: Some(address),
+                    block: block.selector(),
                 })
                 .await?;
             serde_json::to_value(account_state.get_ref())?
         }
+        ClientCommands::GetAccountProof(AddressArg { address, block }) => {
+            let address = address.resolve(network)?;
+            let response = rpc_client
+                .account_service()
+                .get_account_proof(GetAccountProofRequest {
+                    address: Some(address),
+                    block: block.selector(),
+                })
+                .await?
+                .into_inner();
+            let account_state = verify_account(&rpc_client, &address, &response).await?;
+            json!({
+                "block_hash" : response.block_hash,
+                "state_root" : response.state_root,
+                "account_state" : account_state,
+                "verified" : true,
+            })
+        }
         ClientCommands::SendPayment(SendPaymentArgs {
             to,
             amount,
             signer,
             fee,
         }) => {
-            // Todo get network
-
-            let signer_address = get_address_from_secret_key(*signer, Network::Testnet)?;
+            let signer = signer.secret(network)?;
+            let signer_address = get_address_from_secret_key(signer, network)?;
 
             let nonce = rpc_client
                 .account_service()
                 .get_nonce(GetAccountRequest {
                     address: Some(signer_address),
+                    block: None,
                 })
                 .await?
                 .get_ref()
                 .nonce;
 
             let signed_tx = make_payment_sign_transaction(
-                *signer,
-                *to,
+                signer,
+                to.resolve(network)?,
                 nonce,
                 *amount,
                 *fee,
-                Network::Testnet,
+                network,
             )?;
 
             let signed_tx_size = signed_tx.encoded_len();
@@ -406,7 +564,33 @@ pub async fn handle_client_command(command: &ClientArgsCommands) -> anyhow::Resu
                 "pending" : pending_txs,
             })
         }
-        ClientCommands::App(a) => handle_app_command(&rpc_client, a).await?,
+        ClientCommands::GetTransaction(TxHashArg { hash }) => {
+            let response = rpc_client
+                .transaction_service()
+                .get_transaction_by_hash(TransactionHash { hash: Some(*hash) })
+                .await?;
+            let response = response.get_ref();
+            json!({
+                "tx" : response.tx,
+                "block_hash" : response.block_hash,
+                "block_level" : response.block_level,
+                "index" : response.index,
+            })
+        }
+        ClientCommands::GetTransactionReceipt(TxHashArg { hash }) => {
+            let response = rpc_client
+                .transaction_service()
+                .get_transaction_receipt(TransactionHash { hash: Some(*hash) })
+                .await?;
+            let response = response.get_ref();
+            json!({
+                "receipt" : response.receipt,
+                "block_hash" : response.block_hash,
+                "block_level" : response.block_level,
+                "index" : response.index,
+            })
+        }
+        ClientCommands::App(a) => handle_app_command(&rpc_client, network, a).await?,
     };
     Ok(resp)
 }
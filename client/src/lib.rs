@@ -8,6 +8,7 @@ use tonic::codegen::StdError;
 
 pub mod commands;
 mod parser;
+mod proof;
 #[allow(clippy::all)]
 mod rpc {
     include!(concat!(env!("OUT_DIR"), "/rpc.rs"));
@@ -0,0 +1,145 @@
+//! Local verification of the state proofs served by a node.
+//!
+//! A proof is checked against the state root of the block it was read at. The header of that
+//! block is fetched from the same node, its hash and proof of work are checked but it is not
+//! linked to a trusted checkpoint or to the heaviest chain. A node willing to mine a block on a
+//! fork of its own can still serve a consistent proof of a state that is not canonical.
+
+use anyhow::{anyhow, ensure, Result};
+
+use codec::Decodable;
+use primitive_types::address::Address;
+use primitive_types::H256;
+use smt::proof::Proof;
+use types::account::AccountState;
+use types::network::Network;
+use types::proof::{verify_account_proof, verify_storage_proof};
+use types::Addressing;
+
+use crate::rpc::{GetAccountProofResponse, GetBlockByHashRequest, GetStorageProofResponse};
+use crate::Client;
+
+/// Network of the state `address` belongs to, a custom network is only known once its chainspec
+/// is loaded
+fn network_of(address: &Address) -> Result<Network> {
+    address.network().ok_or_else(|| {
+        anyhow!(
+            "unknown network of address {}, pass the chainspec of its network with --chainspec",
+            address
+        )
+    })
+}
+
+/// Fetches the header of `block_hash` and checks that it commits to `state_root`, the header hash
+/// is recomputed so a node can not pair a proof with a different state root. The header must
+/// belong to `network`, whose tree hasher the proofs are verified with, and meet the target it
+/// claims, which may not be easier than the network's minimum difficulty.
+async fn verify_state_root(
+    client: &Client,
+    network: Network,
+    block_hash: H256,
+    state_root: H256,
+) -> Result<()> {
+    let block = client
+        .blockchain_service()
+        .get_block_by_hash(GetBlockByHashRequest {
+            hash: Some(block_hash),
+        })
+        .await?
+        .into_inner();
+    let header = block.header();
+    ensure!(
+        header.hash() == block_hash,
+        "header does not match block hash {:?}",
+        block_hash
+    );
+    ensure!(
+        header.chain_id == network.chain_id(),
+        "block {:?} has chain id {} but the proven state belongs to chain id {}",
+        block_hash,
+        header.chain_id,
+        network.chain_id()
+    );
+    ensure!(
+        crypto::is_valid_proof_of_work(
+            network.max_difficulty_compact()?,
+            header.difficulty(),
+            &block_hash
+        ),
+        "block {:?} does not meet its proof of work target",
+        block_hash
+    );
+    ensure!(
+        header.state_root == state_root,
+        "block {:?} does not commit to state root {:?}",
+        block_hash,
+        state_root
+    );
+    Ok(())
+}
+
+/// Verifies a `GetAccountProof` response and returns the proven account state, `None` if the
+/// account does not exist
+pub(crate) async fn verify_account(
+    client: &Client,
+    address: &Address,
+    response: &GetAccountProofResponse,
+) -> Result<Option<AccountState>> {
+    let block_hash = response
+        .block_hash
+        .ok_or_else(|| anyhow!("proof response without block hash"))?;
+    let state_root = response
+        .state_root
+        .ok_or_else(|| anyhow!("proof response without state root"))?;
+    let network = network_of(address)?;
+    verify_state_root(client, network, block_hash, state_root).await?;
+    verify_account_proof(
+        network.tree_hasher()?,
+        state_root,
+        address,
+        response.account_state.as_ref(),
+        &Proof::decode(&response.proof)?,
+    )?;
+    Ok(response.account_state.clone())
+}
+
+/// Verifies a `GetStorageProof` response and returns the proven value, empty if the key is not set
+pub(crate) async fn verify_storage(
+    client: &Client,
+    app_id: &Address,
+    key: &[u8],
+    response: &GetStorageProofResponse,
+) -> Result<Vec<u8>> {
+    let block_hash = response
+        .block_hash
+        .ok_or_else(|| anyhow!("proof response without block hash"))?;
+    let state_root = response
+        .state_root
+        .ok_or_else(|| anyhow!("proof response without state root"))?;
+    let network = network_of(app_id)?;
+    verify_state_root(client, network, block_hash, state_root).await?;
+    let app_account_state = response
+        .app_account_state
+        .as_ref()
+        .ok_or_else(|| anyhow!("proof response without app account state"))?;
+    let hasher = network.tree_hasher()?;
+    verify_account_proof(
+        hasher,
+        state_root,
+        app_id,
+        Some(app_account_state),
+        &Proof::decode(&response.account_proof)?,
+    )?;
+    let app_state = app_account_state
+        .app_state
+        .as_ref()
+        .ok_or_else(|| anyhow!("account {} is not an app", app_id))?;
+    verify_storage_proof(
+        hasher,
+        app_state.root_hash,
+        key,
+        &response.value,
+        &Proof::decode(&response.storage_proof)?,
+    )?;
+    Ok(response.value.clone())
+}
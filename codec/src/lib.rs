@@ -7,7 +7,7 @@ use alloc::vec::Vec;
 use anyhow::Result;
 use bincode::config::{BigEndian, Fixint, NoLimit};
 use primitive_types::address::Address;
-use primitive_types::{H160, H256};
+use primitive_types::{H160, H256, U256};
 
 pub trait ConsensusCodec: Sized {
     fn consensus_encode(self) -> Vec<u8>;
@@ -129,6 +129,18 @@ impl Decodable for H256 {
     }
 }
 
+impl Encodable for U256 {
+    fn encode(&self) -> Result<Vec<u8>> {
+        Ok(self.to_be_bytes().to_vec())
+    }
+}
+
+impl Decodable for U256 {
+    fn decode(buf: &[u8]) -> Result<Self> {
+        Ok(U256::from_big_endian(buf))
+    }
+}
+
 impl Encodable for Address {
     fn encode(&self) -> Result<Vec<u8>> {
         Ok(self.0.to_vec())
@@ -3,13 +3,16 @@ use std::sync::Arc;
 use anyhow::Result;
 
 use primitive_types::address::Address;
-use primitive_types::{Compact, H160, H256};
-use smt::SparseMerkleTree;
-use types::account::AccountState;
-use types::block::{Block, BlockHeader, IndexedBlockHeader};
+use primitive_types::{Compact, H160, H256, U256};
+use smt::proof::Proof;
+use smt::TreeHasherKind;
+use types::account::{AccountState, BlockReward};
+use types::block::{Block, BlockHeader, IndexedBlockHeader, TransactionLocation};
+use types::diff::StateDiff;
 use types::network::Network;
+use types::receipt::Receipt;
 
-use types::app::AppStateKey;
+use types::app::AppStorage;
 use types::tx::{ApplicationCall, CreateApplication, SignedTransaction};
 use types::Changelist;
 
@@ -29,16 +32,25 @@ pub trait StateDB: Send + Sync {
     fn credit_balance(&self, address: &Address, amount: u64) -> Result<H256>;
     fn debit_balance(&self, address: &Address, amount: u64) -> Result<H256>;
     fn reset(&self, root: H256) -> Result<()>;
-    fn apply_txs(&self, vm: Arc<dyn WasmVMInstance>, txs: &[SignedTransaction]) -> Result<H256>;
+    fn apply_txs(
+        &self,
+        vm: Arc<dyn WasmVMInstance>,
+        reward: &BlockReward,
+        txs: &[SignedTransaction],
+    ) -> Result<(Vec<Receipt>, StateDiff)>;
     fn root(&self) -> H256;
     fn commit(&self) -> Result<()>;
     fn snapshot(&self) -> Result<Arc<dyn StateDB>>;
     fn state_at(&self, root: H256) -> Result<Arc<dyn StateDB>>;
-    fn get_app_data(&self, app_id: Address) -> Result<SparseMerkleTree>;
-    fn set_app_data(&self, app_state_key: AppStateKey, app_data: SparseMerkleTree) -> Result<()>;
+    fn account_state_with_proof(&self, address: &Address) -> Result<(Option<AccountState>, Proof)>;
+    fn get_app_data(&self, app_id: Address) -> Result<AppStorage>;
+    /// Persists the storage nodes and values written to `app_data`
+    fn set_app_data(&self, app_data: &AppStorage) -> Result<()>;
     fn get_app_source(&self, app_id: Address) -> Result<Vec<u8>>;
     fn get_app_descriptor(&self, app_id: Address) -> Result<Vec<u8>>;
     fn set_app_metadata(&self, binary: &[u8], descriptor: Vec<u8>) -> Result<()>;
+    /// Hasher of the state trie and app storage trees
+    fn tree_hasher(&self) -> TreeHasherKind;
 }
 
 pub trait AccountStateReader: Send + Sync {
@@ -87,6 +99,10 @@ pub trait ChainReader: Send + Sync {
     fn get_block(&self, hash: &H256, level: u32) -> Result<Option<Block>>;
     fn get_block_by_hash(&self, hash: &H256) -> Result<Option<Block>>;
     fn get_block_by_level(&self, level: u32) -> Result<Option<Block>>;
+    fn get_transaction_location(&self, tx_hash: &H256) -> Result<Option<TransactionLocation>>;
+    fn get_block_receipts(&self, hash: &H256, level: u32) -> Result<Option<Vec<Receipt>>>;
+    fn get_block_state_diff(&self, hash: &H256, level: u32) -> Result<Option<StateDiff>>;
+    fn get_total_work(&self, hash: &H256) -> Result<Option<U256>>;
 }
 
 pub trait Consensus: Send + Sync {
@@ -98,12 +114,12 @@ pub trait Consensus: Send + Sync {
     ) -> Result<()>;
     fn finalize(
         &self,
-        chain: Arc<dyn ChainHeadReader>,
         header: &mut BlockHeader,
         vm: Arc<dyn WasmVMInstance>,
         state: Arc<dyn StateDB>,
+        reward: &BlockReward,
         txs: &[SignedTransaction],
-    ) -> Result<()>;
+    ) -> Result<(Vec<Receipt>, StateDiff)>;
     fn finalize_and_assemble(
         &self,
         chain: Arc<dyn ChainHeadReader>,
@@ -120,6 +136,12 @@ pub trait Consensus: Send + Sync {
     ) -> Result<Compact>;
     fn is_genesis(&self, header: &BlockHeader) -> bool;
     fn miner_reward(&self, block_level: u32) -> u64;
+    fn block_reward(
+        &self,
+        chain: Arc<dyn ChainHeadReader>,
+        header: &BlockHeader,
+    ) -> Result<BlockReward>;
+    fn block_proof(&self, header: &BlockHeader) -> U256;
     fn get_genesis_header(&self) -> BlockHeader;
     fn network(&self) -> Network;
 }
@@ -47,6 +47,9 @@ pub struct AccountState {
     pub nonce: u64,
     #[prost(message, optional, tag = "4")]
     pub app_state: Option<AppState>,
+    /// Mining rewards that can not be spent until their unlock level
+    #[prost(message, repeated, tag = "5")]
+    pub locked_rewards: Vec<LockedReward>,
 }
 
 impl AccountState {
@@ -56,8 +59,67 @@ impl AccountState {
             reserve_balance: 0u64,
             nonce: 1u64,
             app_state: None,
+            locked_rewards: Vec::new(),
         }
     }
+
+    pub fn locked_balance(&self) -> u64 {
+        self.locked_rewards
+            .iter()
+            .fold(0, |total, reward| total.saturating_add(reward.amount))
+    }
+
+    pub fn lock_reward(&mut self, amount: u64, unlock_level: u32) {
+        self.locked_rewards.push(LockedReward {
+            amount,
+            unlock_level,
+        });
+    }
+
+    /// Moves the rewards unlocked at `level` to the free balance, returns the amount released or
+    /// `None` if the free balance would overflow
+    pub fn unlock_rewards(&mut self, level: u32) -> Option<u64> {
+        let released = self
+            .locked_rewards
+            .iter()
+            .filter(|reward| reward.unlock_level <= level)
+            .try_fold(0u64, |total, reward| total.checked_add(reward.amount))?;
+        self.free_balance = self.free_balance.checked_add(released)?;
+        self.locked_rewards
+            .retain(|reward| reward.unlock_level > level);
+        Some(released)
+    }
+}
+
+#[derive(Serialize, Deserialize, Clone, PartialEq, Eq, PartialOrd, Ord, prost::Message)]
+pub struct LockedReward {
+    #[prost(uint64, tag = "1")]
+    pub amount: u64,
+    #[prost(uint32, tag = "2")]
+    pub unlock_level: u32,
+}
+
+/// Balance changes applied to the state after the transactions of a block
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct BlockReward {
+    pub level: u32,
+    pub coinbase: Address,
+    pub amount: u64,
+    pub unlock_level: u32,
+    /// Coinbase of the ancestor whose reward unlocks at `level`
+    pub matured_coinbase: Option<Address>,
+    /// Percentage of the block's transaction fees that is burned instead of paid to the coinbase
+    pub fee_burn_percent: u8,
+    /// Chain id the transactions of the block must be signed for
+    pub chain_id: u32,
+}
+
+impl BlockReward {
+    /// Returns the amount locked for the coinbase once `fees` have been collected
+    pub fn total(&self, fees: u64) -> Option<u64> {
+        let burned = (fees as u128 * self.fee_burn_percent.min(100) as u128 / 100) as u64;
+        self.amount.checked_add(fees - burned)
+    }
 }
 
 impl Encodable for AccountState {
@@ -132,28 +194,23 @@ impl Addressing for Address {
     }
     fn is_valid(&self) -> bool {
         match bech32::decode(&String::from_utf8_lossy(&self.0)) {
-            Ok((hrp, _, _)) => ALPHA_HRP.eq(&hrp) || TESTNET_HRP.eq(&hrp) || MAINNET_HRP.eq(&hrp),
+            Ok((hrp, _, _)) => Network::from_hrp(&hrp).is_some(),
             Err(_) => false,
         }
     }
     fn network(&self) -> Option<Network> {
-        match self.hrp().as_str() {
-            MAINNET_HRP => Some(Network::Mainnet),
-            TESTNET_HRP => Some(Network::Testnet),
-            ALPHA_HRP => Some(Network::Alphanet),
-            _ => None,
-        }
+        Network::from_hrp(&self.hrp())
     }
 }
 
-pub fn get_address_from_pub_key(pub_key: PublicKey, network: Network) -> Address {
+pub fn get_address_from_pub_key(pub_key: PublicKey, network: Network) -> Result<Address> {
     let key = pub_key.hash();
     let checksum = &key[12..];
-    let address: String = bech32::encode(network.hrp(), checksum.to_base32(), Variant::Bech32m)
+    let address: String = bech32::encode(network.hrp()?, checksum.to_base32(), Variant::Bech32m)
         .expect("error creating account id");
     let mut raw_address = [0; ADDRESS_LEN];
     raw_address.copy_from_slice(address.as_bytes());
-    Address(raw_address)
+    Ok(Address(raw_address))
 }
 
 pub fn get_address_from_secret_key(sk: H256, network: Network) -> Result<Address> {
@@ -161,7 +218,7 @@ pub fn get_address_from_secret_key(sk: H256, network: Network) -> Result<Address
     let pk = sk.public();
     let key = pk.hash();
     let checksum = &key[12..];
-    let address: String = bech32::encode(network.hrp(), checksum.to_base32(), Variant::Bech32m)
+    let address: String = bech32::encode(network.hrp()?, checksum.to_base32(), Variant::Bech32m)
         .expect("error creating account id");
     let mut raw_address = [0; ADDRESS_LEN];
     raw_address.copy_from_slice(address.as_bytes());
@@ -171,7 +228,7 @@ pub fn get_address_from_secret_key(sk: H256, network: Network) -> Result<Address
 pub fn get_address_from_package_name(package_name: &str, network: Network) -> Result<Address> {
     let package = PackageName::parse(package_name)?;
     let checksum = &package.package_id[12..];
-    let address: String = bech32::encode(network.hrp(), checksum.to_base32(), Variant::Bech32m)
+    let address: String = bech32::encode(network.hrp()?, checksum.to_base32(), Variant::Bech32m)
         .expect("error creating account id");
     let mut raw_address = [0; ADDRESS_LEN];
     raw_address.copy_from_slice(&address.as_bytes()[0..ADDRESS_LEN]);
@@ -181,7 +238,7 @@ pub fn get_address_from_package_name(package_name: &str, network: Network) -> Re
 pub fn get_address_from_seed(seed: &[u8], network: Network) -> Result<Address> {
     let key = keccak256(seed);
     let checksum = &key[12..];
-    let address: String = bech32::encode(network.hrp(), checksum.to_base32(), Variant::Bech32m)
+    let address: String = bech32::encode(network.hrp()?, checksum.to_base32(), Variant::Bech32m)
         .expect("error creating account id");
     let mut raw_address = [0; ADDRESS_LEN];
     raw_address.copy_from_slice(&address.as_bytes()[0..ADDRESS_LEN]);
@@ -19,6 +19,11 @@ use bincode::{Decode, Encode};
 use codec::{Decodable, Encodable};
 use primitive_types::address::Address;
 use primitive_types::H256;
+use smt::overlay::OverlayStorage;
+use smt::{SparseMerkleTree, TreeHasherKind};
+
+/// Storage tree of an app, nodes and values are loaded from the state as they are touched
+pub type AppStorage = SparseMerkleTree<OverlayStorage, TreeHasherKind>;
 
 #[derive(Encode, Decode, Clone, Debug)]
 pub struct AppMetadata {
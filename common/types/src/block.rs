@@ -44,6 +44,45 @@ impl Decodable for BlockPrimaryKey {
     }
 }
 
+/// Position of a transaction in the canonical chain
+#[derive(Clone, Copy, PartialEq, Eq, Debug, Serialize, Deserialize)]
+pub struct TransactionLocation {
+    pub block_hash: H256,
+    pub level: u32,
+    pub index: u32,
+}
+
+impl TransactionLocation {
+    pub fn block_key(&self) -> BlockPrimaryKey {
+        BlockPrimaryKey(self.level, self.block_hash)
+    }
+}
+
+impl Encodable for TransactionLocation {
+    fn encode(&self) -> Result<Vec<u8>> {
+        let mut encoded = Vec::with_capacity(40);
+        encoded.extend_from_slice(&self.level.to_be_bytes());
+        encoded.extend_from_slice(self.block_hash.as_bytes());
+        encoded.extend_from_slice(&self.index.to_be_bytes());
+        Ok(encoded)
+    }
+}
+
+impl Decodable for TransactionLocation {
+    fn decode(buf: &[u8]) -> Result<Self> {
+        anyhow::ensure!(buf.len() == 40, "invalid transaction location length");
+        let mut level: [u8; 4] = [0; 4];
+        level.copy_from_slice(&buf[..4]);
+        let mut index: [u8; 4] = [0; 4];
+        index.copy_from_slice(&buf[36..]);
+        Ok(Self {
+            block_hash: H256::from_slice(&buf[4..36]),
+            level: u32::from_be_bytes(level),
+            index: u32::from_be_bytes(index),
+        })
+    }
+}
+
 #[derive(Serialize, Deserialize, Copy, Clone, prost::Message)]
 pub struct BlockHeader {
     #[prost(required, message, tag = "1")]
@@ -0,0 +1,261 @@
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use anyhow::{ensure, Result};
+use serde::{Deserialize, Serialize};
+
+use primitive_types::address::Address;
+use primitive_types::{Compact, H256, U256};
+use smt::TreeHasherKind;
+
+use crate::block::BlockHeader;
+use crate::network::{Network, NetworkParams};
+
+/// Balance credited to `Address::default()` by the built-in network chainspecs
+pub const DEFAULT_GENESIS_BALANCE: u64 = 1_000_000_000_000;
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub struct GenesisAccount {
+    pub address: Address,
+    #[serde(default)]
+    pub free: u64,
+    #[serde(default)]
+    pub reserved: u64,
+}
+
+/// Describes the genesis state and parameters of a chain
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub struct ChainSpec {
+    pub name: String,
+    pub chain_id: u32,
+    pub hrp: String,
+    pub max_difficulty: U256,
+    #[serde(default)]
+    pub genesis_timestamp: u32,
+    /// Gossip topic of a custom network, defaults to the network name
+    #[serde(default)]
+    pub p2p_topic: Option<String>,
+    #[serde(default)]
+    pub allow_min_difficulty_blocks: bool,
+    /// Percentage of transaction fees burned instead of being paid to the block producer
+    #[serde(default)]
+    pub fee_burn_percent: u8,
+    #[serde(default)]
+    pub accounts: Vec<GenesisAccount>,
+    /// Built-in app package names mapped to wasm binaries, relative paths are resolved against
+    /// the chainspec file. When empty the node's embedded built-in apps are used.
+    #[serde(default)]
+    pub built_in_apps: BTreeMap<String, PathBuf>,
+    /// Hasher of the state trie and app storage, `keccak256`, `blake2b256` or `sha256`. Built-in
+    /// networks use `keccak256`.
+    #[serde(default, with = "tree_hasher_name")]
+    pub tree_hasher: TreeHasherKind,
+}
+
+mod tree_hasher_name {
+    use serde::de::Error;
+    use serde::{Deserialize, Deserializer, Serializer};
+    use smt::TreeHasherKind;
+
+    pub fn serialize<S: Serializer>(
+        hasher: &TreeHasherKind,
+        serializer: S,
+    ) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(hasher.name())
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(
+        deserializer: D,
+    ) -> Result<TreeHasherKind, D::Error> {
+        let name = String::deserialize(deserializer)?;
+        TreeHasherKind::from_name(&name)
+            .ok_or_else(|| D::Error::custom(format!("unknown tree hasher {}", name)))
+    }
+}
+
+impl ChainSpec {
+    pub fn from_network(network: Network) -> Result<Self> {
+        Ok(Self {
+            name: network.into(),
+            chain_id: network.chain_id(),
+            hrp: network.hrp()?.to_string(),
+            max_difficulty: network.max_difficulty()?,
+            genesis_timestamp: 0,
+            p2p_topic: None,
+            allow_min_difficulty_blocks: network.allow_min_difficulty_blocks()?,
+            fee_burn_percent: 0,
+            accounts: vec![GenesisAccount {
+                address: Address::default(),
+                free: DEFAULT_GENESIS_BALANCE,
+                reserved: 0,
+            }],
+            built_in_apps: Default::default(),
+            tree_hasher: network.tree_hasher()?,
+        })
+    }
+
+    /// Loads a chainspec from a `.toml` or `.json` file
+    pub fn open<P: AsRef<Path>>(path: P) -> Result<Self> {
+        let path = path.as_ref();
+        let content = fs::read_to_string(path)?;
+        let mut spec: ChainSpec = match path.extension().and_then(|ext| ext.to_str()) {
+            Some("toml") => toml::from_str(&content)?,
+            _ => serde_json::from_str(&content)?,
+        };
+        if let Some(base_dir) = path.parent() {
+            for app_path in spec.built_in_apps.values_mut() {
+                if app_path.is_relative() {
+                    *app_path = base_dir.join(&app_path);
+                }
+            }
+        }
+        spec.validate()?;
+        Ok(spec)
+    }
+
+    pub fn validate(&self) -> Result<()> {
+        ensure!(!self.name.is_empty(), "chainspec name is empty");
+        ensure!(
+            !self.max_difficulty.is_zero(),
+            "chainspec max_difficulty is zero"
+        );
+        ensure!(!self.hrp.is_empty(), "chainspec hrp is empty");
+        ensure!(
+            self.fee_burn_percent <= 100,
+            "chainspec fee_burn_percent {} is above 100",
+            self.fee_burn_percent
+        );
+        if let Some(network) = self.built_in_network() {
+            let hrp = network.hrp()?;
+            ensure!(
+                hrp == self.hrp,
+                "chainspec hrp {} does not match {} hrp {}",
+                self.hrp,
+                String::from(network),
+                hrp
+            );
+            let tree_hasher = network.tree_hasher()?;
+            ensure!(
+                tree_hasher == self.tree_hasher,
+                "chainspec tree hasher {} does not match {} tree hasher {}",
+                self.tree_hasher.name(),
+                String::from(network),
+                tree_hasher.name()
+            );
+        }
+        Ok(())
+    }
+
+    fn built_in_network(&self) -> Option<Network> {
+        match Network::from_chain_id(self.chain_id) {
+            Ok(Network::Custom(_)) | Err(_) => None,
+            Ok(network) => Some(network),
+        }
+    }
+
+    /// Returns the network described by this chainspec, registering it if it is a custom network
+    pub fn network(&self) -> Result<Network> {
+        self.validate()?;
+        if let Some(network) = self.built_in_network() {
+            return Ok(network);
+        }
+        Network::register(NetworkParams {
+            name: self.name.clone(),
+            chain_id: self.chain_id,
+            hrp: self.hrp.clone(),
+            topic: self.p2p_topic.clone().unwrap_or_else(|| self.name.clone()),
+            max_difficulty: self.max_difficulty,
+            allow_min_difficulty_blocks: self.allow_min_difficulty_blocks,
+            tree_hasher: self.tree_hasher,
+        })
+    }
+
+    pub fn max_difficulty_compact(&self) -> Compact {
+        Compact::from_u256(self.max_difficulty)
+    }
+
+    /// Returns the genesis header without its state root, the state root is set once the
+    /// genesis accounts and built-in apps have been applied.
+    pub fn genesis_header(&self) -> BlockHeader {
+        BlockHeader::new(
+            H256::zero(),
+            H256::zero(),
+            H256::zero(),
+            H256::zero(),
+            U256::zero(),
+            Address::default(),
+            self.max_difficulty_compact().into(),
+            self.chain_id,
+            0,
+            self.genesis_timestamp,
+            0,
+        )
+    }
+
+    /// Reads the wasm binaries of the built-in apps
+    pub fn load_built_in_apps(&self) -> Result<Vec<(String, Vec<u8>)>> {
+        self.built_in_apps
+            .iter()
+            .map(|(package_name, path)| Ok((package_name.clone(), fs::read(path)?)))
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use smt::TreeHasherKind;
+
+    use crate::chainspec::ChainSpec;
+    use crate::network::Network;
+
+    #[test]
+    fn test_parse_chainspec() {
+        let json = r#"{
+            "name": "testnet",
+            "chain_id": 1,
+            "hrp": "odanx",
+            "max_difficulty": "0x377ae000000000000000000000000000000000000000000000000000000",
+            "genesis_timestamp": 1680000000,
+            "built_in_apps": { "network.odax.nameregistry": "nameregistry.wasm" }
+        }"#;
+        let spec: ChainSpec = serde_json::from_str(json).unwrap();
+        assert_eq!(spec.network().unwrap(), Network::Testnet);
+        assert_eq!(
+            spec.max_difficulty,
+            Network::Testnet.max_difficulty().unwrap()
+        );
+        assert!(spec.accounts.is_empty());
+        assert_eq!(spec.genesis_header().time, 1680000000);
+        assert_eq!(spec.genesis_header().hash(), spec.genesis_header().hash());
+
+        let mut other = spec.clone();
+        other.genesis_timestamp += 1;
+        assert_ne!(spec.genesis_header().hash(), other.genesis_header().hash());
+
+        let mut mismatched = spec.clone();
+        mismatched.hrp = "devnet".to_string();
+        assert!(mismatched.validate().is_err());
+        assert_eq!(spec.tree_hasher, TreeHasherKind::Keccak256);
+        let mut mismatched = spec.clone();
+        mismatched.tree_hasher = TreeHasherKind::Sha256;
+        assert!(mismatched.validate().is_err());
+
+        let mut custom = spec;
+        custom.name = "devnet".to_string();
+        custom.chain_id = 2001;
+        custom.hrp = "odadn".to_string();
+        custom.tree_hasher = TreeHasherKind::Sha256;
+        let network = custom.network().unwrap();
+        assert_eq!(network, Network::Custom(2001));
+        assert_eq!(network.hrp().unwrap(), "odadn");
+        assert_eq!(network.topic().unwrap(), "devnet");
+        assert_eq!(network.max_difficulty().unwrap(), custom.max_difficulty);
+        assert_eq!(network.tree_hasher().unwrap(), TreeHasherKind::Sha256);
+        let json = serde_json::to_string(&custom).unwrap();
+        assert!(json.contains(r#""tree_hasher":"sha256""#));
+        assert_eq!(serde_json::from_str::<ChainSpec>(&json).unwrap(), custom);
+    }
+}
@@ -5,6 +5,7 @@ use std::path::{Path, PathBuf};
 use anyhow::Result;
 use serde::{Deserialize, Serialize};
 
+use crate::chainspec::ChainSpec;
 use crate::network::Network;
 use directories::UserDirs;
 use primitive_types::address::Address;
@@ -27,6 +28,17 @@ impl NodeIdentityConfig {
     }
 }
 
+/// Which historical states are kept on disk
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, Default)]
+#[serde(rename_all = "snake_case")]
+pub enum StatePruning {
+    /// Keep the state of every block
+    #[default]
+    Archive,
+    /// Keep the state of blocks in the last `n` levels below the current head
+    KeepRecent(u32),
+}
+
 #[derive(Serialize, Deserialize, Debug, Clone)]
 #[serde(rename_all = "snake_case")]
 pub struct EnvironmentConfig {
@@ -48,6 +60,11 @@ pub struct EnvironmentConfig {
     #[serde(default)]
     pub datadir: PathBuf,
     pub network: Network,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    #[serde(default)]
+    pub chainspec: Option<PathBuf>,
+    #[serde(default)]
+    pub state_pruning: StatePruning,
 }
 
 impl EnvironmentConfig {
@@ -72,6 +89,13 @@ impl EnvironmentConfig {
     pub fn datadir(&self) -> &PathBuf {
         &self.datadir
     }
+    /// Loads the configured chainspec, or the default chainspec of the configured network
+    pub fn chainspec(&self) -> Result<ChainSpec> {
+        match &self.chainspec {
+            None => ChainSpec::from_network(self.network),
+            Some(path) => ChainSpec::open(path),
+        }
+    }
     pub fn sanitize(&mut self) {
         let default = Self::default();
         if !self.datadir.exists() {
@@ -101,6 +125,8 @@ impl Default for EnvironmentConfig {
             identity_file: None,
             datadir: default_datadir,
             network: Network::Testnet,
+            chainspec: None,
+            state_pruning: StatePruning::Archive,
         }
     }
 }
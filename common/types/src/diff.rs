@@ -0,0 +1,103 @@
+use std::collections::BTreeMap;
+
+use codec::{impl_codec_using_prost, Decodable, Encodable};
+use primitive_types::address::Address;
+use primitive_types::H256;
+use serde::{Deserialize, Serialize};
+
+use anyhow::Result;
+
+use crate::account::AccountState;
+
+/// Storage keys written by an app mapped to their last value, removed keys map to an empty value
+pub type StorageWrites = BTreeMap<Vec<u8>, Vec<u8>>;
+
+#[derive(Serialize, Deserialize, Clone, PartialEq, Eq, prost::Message)]
+pub struct AccountDiff {
+    #[prost(required, message, tag = "1")]
+    pub address: Address,
+    #[prost(message, optional, tag = "2")]
+    pub before: Option<AccountState>,
+    #[prost(message, optional, tag = "3")]
+    pub after: Option<AccountState>,
+}
+
+#[derive(Serialize, Deserialize, Clone, PartialEq, Eq, prost::Message)]
+pub struct StorageWrite {
+    #[prost(bytes, tag = "1")]
+    #[serde(with = "hex")]
+    pub key: Vec<u8>,
+    #[prost(bytes, tag = "2")]
+    #[serde(with = "hex")]
+    pub value: Vec<u8>,
+}
+
+#[derive(Serialize, Deserialize, Clone, PartialEq, Eq, prost::Message)]
+pub struct AppStorageDiff {
+    #[prost(required, message, tag = "1")]
+    pub app_id: Address,
+    #[prost(required, message, tag = "2")]
+    pub root_before: H256,
+    #[prost(required, message, tag = "3")]
+    pub root_after: H256,
+    #[prost(repeated, message, tag = "4")]
+    pub writes: Vec<StorageWrite>,
+}
+
+/// Changes a block made to the state, accounts and apps are sorted by address
+#[derive(Serialize, Deserialize, Clone, PartialEq, Eq, prost::Message)]
+pub struct StateDiff {
+    #[prost(repeated, message, tag = "1")]
+    pub accounts: Vec<AccountDiff>,
+    #[prost(repeated, message, tag = "2")]
+    pub app_storage: Vec<AppStorageDiff>,
+}
+
+impl StateDiff {
+    /// Diff between `before` and `after` of every account in `after`, accounts missing from
+    /// `before` did not exist. `storage_writes` are the keys written per app.
+    pub fn new(
+        before: &BTreeMap<Address, AccountState>,
+        after: &BTreeMap<Address, AccountState>,
+        mut storage_writes: BTreeMap<Address, StorageWrites>,
+    ) -> Self {
+        let app_root = |account: Option<&AccountState>| {
+            account
+                .and_then(|account| account.app_state.as_ref())
+                .map(|app_state| app_state.root_hash)
+                .unwrap_or_default()
+        };
+        let mut accounts = Vec::new();
+        let mut app_storage = Vec::new();
+        for (address, after) in after {
+            let before = before.get(address);
+            if before == Some(after) {
+                continue;
+            }
+            accounts.push(AccountDiff {
+                address: *address,
+                before: before.cloned(),
+                after: Some(after.clone()),
+            });
+            let writes = storage_writes.remove(address).unwrap_or_default();
+            let (root_before, root_after) = (app_root(before), app_root(Some(after)));
+            if root_before != root_after || !writes.is_empty() {
+                app_storage.push(AppStorageDiff {
+                    app_id: *address,
+                    root_before,
+                    root_after,
+                    writes: writes
+                        .into_iter()
+                        .map(|(key, value)| StorageWrite { key, value })
+                        .collect(),
+                })
+            }
+        }
+        Self {
+            accounts,
+            app_storage,
+        }
+    }
+}
+
+impl_codec_using_prost!(StateDiff);
@@ -9,6 +9,11 @@ pub enum LocalEventMessage {
     StateChanged {
         current_head: BlockHeader,
     },
+    ChainReorg {
+        old_tip: BlockHeader,
+        new_tip: BlockHeader,
+        depth: u32,
+    },
     NetworkHighestHeadChanged {
         peer_id: String,
         tip: Option<BlockHeader>,
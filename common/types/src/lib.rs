@@ -8,6 +8,8 @@ use std::sync::Arc;
 use std::time::Duration;
 
 use crate::account::AccountState;
+use crate::app::AppStorage;
+use crate::diff::StorageWrites;
 use bytes::{Buf, BufMut};
 use codec::{Decodable, Encodable};
 use parking_lot::RwLock;
@@ -15,7 +17,6 @@ use primitive_types::address::Address;
 use prost::encoding::{DecodeContext, WireType};
 use prost::{DecodeError, Message};
 use serde::{Deserialize, Serialize};
-use smt::SparseMerkleTree;
 
 use crate::block::BlockHeader;
 use crate::network::Network;
@@ -23,10 +24,13 @@ use crate::network::Network;
 pub mod account;
 pub mod app;
 pub mod block;
+pub mod chainspec;
 pub mod config;
+pub mod diff;
 pub mod events;
 pub mod misc;
 pub mod network;
+pub mod proof;
 pub mod receipt;
 pub mod tx;
 pub mod util;
@@ -152,15 +156,19 @@ pub trait Addressing {
 pub struct Changelist {
     pub account_changes: HashMap<Address, AccountState>,
     pub logs: Vec<(String, Vec<u8>)>,
-    pub storage: SparseMerkleTree,
+    pub storage: AppStorage,
+    pub storage_writes: StorageWrites,
+    pub fuel_used: u64,
 }
 
 pub mod prelude {
     pub use crate::account::*;
     pub use crate::block::*;
     pub use crate::config::*;
+    pub use crate::diff::*;
     pub use crate::events::*;
     pub use crate::network::*;
+    pub use crate::receipt::*;
     pub use crate::tx::*;
     pub use crate::Addressing;
 }
@@ -1,17 +1,37 @@
-use clap::ArgEnum;
+use std::collections::BTreeMap;
+
+use anyhow::{anyhow, bail, Result};
+use clap::{ArgEnum, PossibleValue};
+use parking_lot::RwLock;
 use serde::{Deserialize, Serialize};
 
 use primitive_types::{Compact, U256};
+use smt::TreeHasherKind;
 
-#[derive(
-    Copy, Clone, PartialEq, Eq, PartialOrd, Ord, Debug, Default, Serialize, Deserialize, ArgEnum,
-)]
+#[derive(Copy, Clone, PartialEq, Eq, PartialOrd, Ord, Debug, Default, Serialize, Deserialize)]
 #[serde(rename_all = "lowercase")]
 pub enum Network {
     Mainnet,
     #[default]
     Testnet,
     Alphanet,
+    /// User defined network identified by its chain id, see [`Network::register`]
+    Custom(u32),
+}
+
+impl ArgEnum for Network {
+    fn value_variants<'a>() -> &'a [Self] {
+        &[Network::Mainnet, Network::Testnet, Network::Alphanet]
+    }
+
+    fn to_possible_value<'a>(&self) -> Option<PossibleValue<'a>> {
+        match self {
+            Network::Mainnet => Some(PossibleValue::new("mainnet")),
+            Network::Testnet => Some(PossibleValue::new("testnet")),
+            Network::Alphanet => Some(PossibleValue::new("alphanet")),
+            Network::Custom(_) => None,
+        }
+    }
 }
 
 impl From<Network> for String {
@@ -20,10 +40,52 @@ impl From<Network> for String {
             Network::Testnet => "testnet".to_string(),
             Network::Alphanet => "aplhanet".to_string(),
             Network::Mainnet => "mainnet".to_string(),
+            Network::Custom(chain_id) => network
+                .custom()
+                .map(|custom| custom.name.to_string())
+                .unwrap_or_else(|| format!("custom-{}", chain_id)),
         }
     }
 }
 
+/// Parameters of a user defined network
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct NetworkParams {
+    pub name: String,
+    pub chain_id: u32,
+    pub hrp: String,
+    pub topic: String,
+    pub max_difficulty: U256,
+    pub allow_min_difficulty_blocks: bool,
+    pub tree_hasher: TreeHasherKind,
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+struct CustomNetwork {
+    name: &'static str,
+    hrp: &'static str,
+    topic: &'static str,
+    max_difficulty: U256,
+    allow_min_difficulty_blocks: bool,
+    tree_hasher: TreeHasherKind,
+}
+
+impl CustomNetwork {
+    fn matches(&self, params: &NetworkParams) -> bool {
+        self.name == params.name
+            && self.hrp == params.hrp
+            && self.topic == params.topic
+            && self.max_difficulty == params.max_difficulty
+            && self.allow_min_difficulty_blocks == params.allow_min_difficulty_blocks
+            && self.tree_hasher == params.tree_hasher
+    }
+}
+
+static CUSTOM_NETWORKS: RwLock<BTreeMap<u32, CustomNetwork>> =
+    parking_lot::const_rwlock(BTreeMap::new());
+
+const BUILT_IN_NETWORKS: [Network; 3] = [Network::Mainnet, Network::Testnet, Network::Alphanet];
+
 const TESTNET_MAX_DIFFICULTY: U256 = U256([
     0x0000000000000000u64,
     0x0000000000000000u64,
@@ -51,11 +113,74 @@ pub const ALPHA_HRP: &str = "odaxx";
 pub const MAINNET_HRP: &str = "odana";
 
 impl Network {
-    pub fn max_difficulty(&self) -> U256 {
+    /// Registers a user defined network, registering the same parameters twice is a no-op
+    pub fn register(params: NetworkParams) -> Result<Network> {
+        if BUILT_IN_NETWORKS.iter().any(|network| {
+            network.chain_id() == params.chain_id || network.hrp().ok() == Some(params.hrp.as_str())
+        }) {
+            bail!(
+                "network {} conflicts with a built-in network chain id or hrp",
+                params.name
+            )
+        }
+        // Addresses have a fixed length, so do their hrps
+        if params.hrp.len() != MAINNET_HRP.len() {
+            bail!(
+                "hrp {} must be {} characters long",
+                params.hrp,
+                MAINNET_HRP.len()
+            )
+        }
+        let mut networks = CUSTOM_NETWORKS.write();
+        if let Some(network) = networks.get(&params.chain_id) {
+            if network.matches(&params) {
+                return Ok(Network::Custom(params.chain_id));
+            }
+            bail!("chain id {} is already registered", params.chain_id)
+        }
+        if networks.values().any(|network| network.hrp == params.hrp) {
+            bail!("hrp {} is already registered", params.hrp)
+        }
+        networks.insert(
+            params.chain_id,
+            CustomNetwork {
+                name: Box::leak(params.name.into_boxed_str()),
+                hrp: Box::leak(params.hrp.into_boxed_str()),
+                topic: Box::leak(params.topic.into_boxed_str()),
+                max_difficulty: params.max_difficulty,
+                allow_min_difficulty_blocks: params.allow_min_difficulty_blocks,
+                tree_hasher: params.tree_hasher,
+            },
+        );
+        Ok(Network::Custom(params.chain_id))
+    }
+
+    pub fn is_registered(&self) -> bool {
         match self {
-            Network::Testnet => TESTNET_MAX_DIFFICULTY,
-            Network::Alphanet => ALPHA_MAX_DIFFICULTY,
-            Network::Mainnet => MAINNET_MAX_DIFFICULTY,
+            Network::Custom(chain_id) => CUSTOM_NETWORKS.read().contains_key(chain_id),
+            _ => true,
+        }
+    }
+
+    fn custom(&self) -> Option<CustomNetwork> {
+        match self {
+            Network::Custom(chain_id) => CUSTOM_NETWORKS.read().get(chain_id).copied(),
+            _ => None,
+        }
+    }
+
+    /// Parameters of a custom network, fails if it was not registered
+    fn registered(&self) -> Result<CustomNetwork> {
+        self.custom()
+            .ok_or_else(|| anyhow!("custom network {} is not registered", self.chain_id()))
+    }
+
+    pub fn max_difficulty(&self) -> Result<U256> {
+        match self {
+            Network::Testnet => Ok(TESTNET_MAX_DIFFICULTY),
+            Network::Alphanet => Ok(ALPHA_MAX_DIFFICULTY),
+            Network::Mainnet => Ok(MAINNET_MAX_DIFFICULTY),
+            Network::Custom(_) => Ok(self.registered()?.max_difficulty),
         }
     }
 
@@ -64,30 +189,124 @@ impl Network {
             Network::Mainnet => 0,
             Network::Testnet => 1,
             Network::Alphanet => 2,
+            Network::Custom(chain_id) => *chain_id,
         }
     }
 
-    pub fn from_chain_id(chain_id: u32) -> Self {
+    pub fn from_chain_id(chain_id: u32) -> Result<Self> {
         match chain_id {
-            0 => Network::Mainnet,
-            1 => Network::Testnet,
-            2 => Network::Alphanet,
-            _ => Network::Testnet,
+            0 => Ok(Network::Mainnet),
+            1 => Ok(Network::Testnet),
+            2 => Ok(Network::Alphanet),
+            _ if CUSTOM_NETWORKS.read().contains_key(&chain_id) => Ok(Network::Custom(chain_id)),
+            _ => bail!("unknown chain id {}", chain_id),
+        }
+    }
+
+    pub fn from_hrp(hrp: &str) -> Option<Self> {
+        match hrp {
+            MAINNET_HRP => Some(Network::Mainnet),
+            TESTNET_HRP => Some(Network::Testnet),
+            ALPHA_HRP => Some(Network::Alphanet),
+            _ => CUSTOM_NETWORKS
+                .read()
+                .iter()
+                .find(|(_, network)| network.hrp == hrp)
+                .map(|(chain_id, _)| Network::Custom(*chain_id)),
+        }
+    }
+
+    pub fn hrp(&self) -> Result<&'static str> {
+        match self {
+            Network::Mainnet => Ok(MAINNET_HRP),
+            Network::Testnet => Ok(TESTNET_HRP),
+            Network::Alphanet => Ok(ALPHA_HRP),
+            Network::Custom(_) => Ok(self.registered()?.hrp),
+        }
+    }
+
+    /// Gossip topic shared by the nodes of this network
+    pub fn topic(&self) -> Result<String> {
+        match self {
+            Network::Custom(_) => Ok(self.registered()?.topic.to_string()),
+            _ => Ok(String::from(*self)),
         }
     }
-    pub fn hrp(&self) -> &'static str {
+
+    /// Whether a block may fall back to the max difficulty when the previous block is late
+    pub fn allow_min_difficulty_blocks(&self) -> Result<bool> {
         match self {
-            Network::Mainnet => MAINNET_HRP,
-            Network::Testnet => TESTNET_HRP,
-            Network::Alphanet => ALPHA_HRP,
+            Network::Testnet => Ok(true),
+            Network::Custom(_) => Ok(self.registered()?.allow_min_difficulty_blocks),
+            _ => Ok(false),
         }
     }
 
-    pub fn max_difficulty_compact(&self) -> Compact {
+    pub fn max_difficulty_compact(&self) -> Result<Compact> {
+        self.max_difficulty().map(Compact::from_u256)
+    }
+
+    /// Hasher of the state trie and app storage trees
+    pub fn tree_hasher(&self) -> Result<TreeHasherKind> {
         match self {
-            Network::Testnet => Compact::from_u256(TESTNET_MAX_DIFFICULTY),
-            Network::Alphanet => Compact::from_u256(ALPHA_MAX_DIFFICULTY),
-            Network::Mainnet => Compact::from_u256(MAINNET_MAX_DIFFICULTY),
+            Network::Custom(_) => Ok(self.registered()?.tree_hasher),
+            _ => Ok(TreeHasherKind::Keccak256),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use primitive_types::U256;
+    use smt::TreeHasherKind;
+
+    use crate::network::{Network, NetworkParams};
+
+    fn devnet_params(chain_id: u32, hrp: &str) -> NetworkParams {
+        NetworkParams {
+            name: format!("devnet-{}", chain_id),
+            chain_id,
+            hrp: hrp.to_string(),
+            topic: format!("devnet-{}", chain_id),
+            max_difficulty: U256::max_value() >> 8,
+            allow_min_difficulty_blocks: true,
+            tree_hasher: TreeHasherKind::Blake2b256,
         }
     }
+
+    #[test]
+    fn test_register_custom_network() {
+        let network = Network::register(devnet_params(1001, "odadv")).unwrap();
+        assert_eq!(network, Network::Custom(1001));
+        assert_eq!(network.hrp().unwrap(), "odadv");
+        assert_eq!(network.topic().unwrap(), "devnet-1001");
+        assert_eq!(network.tree_hasher().unwrap(), TreeHasherKind::Blake2b256);
+        assert_eq!(
+            Network::Testnet.tree_hasher().unwrap(),
+            TreeHasherKind::Keccak256
+        );
+        assert_eq!(Network::from_chain_id(1001).unwrap(), network);
+        assert_eq!(Network::from_hrp("odadv"), Some(network));
+        assert_eq!(
+            Network::register(devnet_params(1001, "odadv")).unwrap(),
+            network
+        );
+        assert!(Network::register(devnet_params(1001, "odadw")).is_err());
+        assert!(Network::register(devnet_params(1002, "odadv")).is_err());
+        assert!(Network::register(devnet_params(1, "odady")).is_err());
+        assert!(Network::register(devnet_params(1003, "odadvx")).is_err());
+        assert!(Network::from_chain_id(1003).is_err());
+    }
+
+    #[test]
+    fn test_unregistered_custom_network() {
+        let network = Network::Custom(1004);
+        assert!(!network.is_registered());
+        assert!(network.hrp().is_err());
+        assert!(network.topic().is_err());
+        assert!(network.tree_hasher().is_err());
+        assert!(network.max_difficulty().is_err());
+        assert!(network.allow_min_difficulty_blocks().is_err());
+        assert_eq!(String::from(network), "custom-1004");
+    }
 }
@@ -0,0 +1,114 @@
+use anyhow::{ensure, Result};
+use bincode::{Decode, Encode};
+use serde::{Deserialize, Serialize};
+
+use codec::{Decodable, Encodable};
+use primitive_types::address::Address;
+use primitive_types::H256;
+use smt::proof::{verify_proof, Proof};
+use smt::TreeHasherKind;
+
+use crate::account::AccountState;
+
+/// Leaf value of the state trie, deleted keys are kept as tombstones
+#[derive(Serialize, Deserialize, Clone, Encode, Decode)]
+pub enum IValue {
+    Deleted,
+    Value(Vec<u8>),
+}
+
+impl Encodable for IValue {
+    fn encode(&self) -> Result<Vec<u8>> {
+        bincode::encode_to_vec(self, codec::config()).map_err(|e| e.into())
+    }
+}
+
+impl Decodable for IValue {
+    fn decode(buf: &[u8]) -> Result<Self> {
+        bincode::decode_from_slice(buf, codec::config())
+            .map(|(output, _)| output)
+            .map_err(|e| e.into())
+    }
+}
+
+/// Checks that `address` holds `account_state` in the state trie with root `state_root`, a
+/// `None` account state checks that the account does not exist
+pub fn verify_account_proof(
+    hasher: TreeHasherKind,
+    state_root: H256,
+    address: &Address,
+    account_state: Option<&AccountState>,
+    proof: &Proof,
+) -> Result<()> {
+    let value = match account_state {
+        Some(account_state) => IValue::Value(account_state.encode()?).encode()?,
+        None => Vec::new(),
+    };
+    ensure!(
+        verify_proof(&hasher, proof, state_root, &address.encode()?, &value),
+        "invalid account proof for {} at state root {:?}",
+        address,
+        state_root
+    );
+    Ok(())
+}
+
+/// Checks that `key` holds `value` in the storage of an app with storage root `root_hash`, an
+/// empty value checks that the key is not set
+pub fn verify_storage_proof(
+    hasher: TreeHasherKind,
+    root_hash: H256,
+    key: &[u8],
+    value: &[u8],
+    proof: &Proof,
+) -> Result<()> {
+    ensure!(
+        verify_proof(&hasher, proof, root_hash, key, value),
+        "invalid storage proof at storage root {:?}",
+        root_hash
+    );
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use codec::Encodable;
+    use primitive_types::address::Address;
+    use smt::{SparseMerkleTree, TreeHasherKind};
+
+    use crate::account::AccountState;
+    use crate::proof::{verify_account_proof, IValue};
+
+    #[test]
+    fn test_verify_account_proof() {
+        let address = Address::default();
+        let mut account_state = AccountState::new();
+        account_state.free_balance = 100;
+        let mut tree = SparseMerkleTree::default();
+        tree.update(
+            address.encode().unwrap(),
+            IValue::Value(account_state.encode().unwrap())
+                .encode()
+                .unwrap(),
+        )
+        .unwrap();
+        let proof = tree.proof(&address.encode().unwrap()).unwrap();
+        let hasher = TreeHasherKind::Keccak256;
+        verify_account_proof(hasher, tree.root(), &address, Some(&account_state), &proof).unwrap();
+
+        let mut forged = account_state.clone();
+        forged.free_balance = 1000;
+        assert!(
+            verify_account_proof(hasher, tree.root(), &address, Some(&forged), &proof).is_err()
+        );
+        assert!(verify_account_proof(hasher, tree.root(), &address, None, &proof).is_err());
+        assert!(verify_account_proof(
+            TreeHasherKind::Sha256,
+            tree.root(),
+            &address,
+            Some(&account_state),
+            &proof
+        )
+        .is_err());
+    }
+}
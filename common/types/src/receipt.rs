@@ -1,8 +1,11 @@
 use primitive_types::H256;
 
+use codec::{impl_codec_using_prost, Decodable, Encodable};
 use primitive_types::address::Address;
 use serde::{Deserialize, Serialize};
 
+use anyhow::Result;
+
 pub type Log = Vec<u8>;
 
 #[derive(Serialize, Deserialize, Clone, prost::Message)]
@@ -23,6 +26,24 @@ pub struct Receipt {
 }
 
 impl Receipt {
+    pub fn new(
+        app_id: Address,
+        tx_hash: H256,
+        logs: Vec<Log>,
+        fuel_used: u64,
+        post_state: H256,
+        status: bool,
+    ) -> Self {
+        Self {
+            app_id,
+            tx_hash,
+            logs,
+            fuel_used,
+            post_state,
+            status,
+        }
+    }
+
     pub fn app_id(&self) -> Address {
         self.app_id
     }
@@ -35,28 +56,35 @@ impl Receipt {
     pub fn fuel_used(&self) -> u64 {
         self.fuel_used
     }
+    pub fn post_state(&self) -> H256 {
+        self.post_state
+    }
+    pub fn status(&self) -> bool {
+        self.status
+    }
+
+    pub fn hash(&self) -> H256 {
+        crypto::keccak256(prost::Message::encode_to_vec(self))
+    }
+}
+
+#[derive(Serialize, Deserialize, Clone, prost::Message)]
+pub struct ReceiptList {
+    #[prost(repeated, message, tag = "1")]
+    pub receipts: Vec<Receipt>,
+}
+
+impl From<Vec<Receipt>> for ReceiptList {
+    fn from(receipts: Vec<Receipt>) -> Self {
+        Self { receipts }
+    }
+}
 
-    // pub fn new(
-    //     app_id: u32,
-    //     tx_hash: H256,
-    //     logs: Vec<Log>,
-    //     fuel_used: u64,
-    //     post_state: H256,
-    //     status: bool,
-    // ) -> Self {
-    //     Self {
-    //         app_id,
-    //         tx_hash,
-    //         logs,
-    //         fuel_used,
-    //         post_state: post_state,
-    //         status,
-    //     }
-    // }
-    // pub fn post_state(&self) -> H256 {
-    //     self.post_state
-    // }
-    // pub fn status(&self) -> bool {
-    //     self.status
-    // }
+impl From<ReceiptList> for Vec<Receipt> {
+    fn from(value: ReceiptList) -> Self {
+        value.receipts
+    }
 }
+
+impl_codec_using_prost!(Receipt);
+impl_codec_using_prost!(ReceiptList);
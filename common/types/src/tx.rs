@@ -408,7 +408,9 @@ impl SignedTransaction {
     pub fn nonce(&self) -> u64 {
         self.tx.nonce
     }
-    pub fn sender(&self) -> Address {
+    /// Sender of the transaction, see [`SignedTransaction::from`]. Fails if it can not be
+    /// recovered, the sender is only recovered once.
+    pub fn sender(&self) -> Result<Address> {
         self.from()
     }
 
@@ -424,7 +426,7 @@ impl SignedTransaction {
             TransactionData::Create(CreateApplication { package_name, .. }) => {
                 get_address_from_package_name(
                     package_name,
-                    self.from()
+                    self.from()?
                         .network()
                         .ok_or_else(|| anyhow!("network not specified on senders address"))?,
                 )
@@ -433,8 +435,8 @@ impl SignedTransaction {
         })
     }
 
-    pub fn origin(&self) -> Address {
-        self.from()
+    pub fn origin(&self) -> Result<Address> {
+        self.sender()
     }
 
     pub fn tx(&self) -> &Transaction {
@@ -451,17 +453,28 @@ impl SignedTransaction {
         Ok(pub_key)
     }
 
-    pub fn from(&self) -> Address {
-        cache(&self.from, || {
-            let signature = Signature::from_rsv((self.r, self.s, self.v as u8))?;
-            signature
-                .recover_public_key(self.sig_hash()?.as_slice())
-                .map_err(|e| anyhow::anyhow!(e))
-                .map(|key| get_address_from_pub_key(key, Network::from_chain_id(self.tx.chain_id)))
-        })
-    }
-    pub fn network(&self) -> Network {
-        self.from().network().unwrap_or_default()
+    /// Recovers the sender from the signature, fails if the chain id is not a known network or
+    /// the signature is invalid
+    pub fn from(&self) -> Result<Address> {
+        if let Some(from) = *self.from.read() {
+            return Ok(from);
+        }
+        let network = Network::from_chain_id(self.tx.chain_id)?;
+        let signature = Signature::from_rsv((self.r, self.s, self.v as u8))?;
+        let key = signature
+            .recover_public_key(self.sig_hash()?.as_slice())
+            .map_err(|e| anyhow::anyhow!(e))?;
+        let from = get_address_from_pub_key(key, network)?;
+        *self.from.write() = Some(from);
+        Ok(from)
+    }
+
+    pub fn chain_id(&self) -> u32 {
+        self.tx.chain_id
+    }
+    /// Network of the chain id the transaction was signed for, fails if it is not a known network
+    pub fn network(&self) -> Result<Network> {
+        Network::from_chain_id(self.tx.chain_id)
     }
     pub fn fees(&self) -> u64 {
         self.tx.fee
@@ -5,13 +5,17 @@ use crypto::is_valid_proof_of_work;
 use primitive_types::{Compact, H256, U256};
 use smt::SparseMerkleTree;
 use traits::{ChainHeadReader, Consensus, StateDB, WasmVMInstance};
+use types::account::BlockReward;
 use types::block::{Block, BlockHeader, IndexedBlockHeader};
+use types::chainspec::ChainSpec;
+use types::diff::StateDiff;
 use types::network::Network;
+use types::receipt::Receipt;
 use types::tx::SignedTransaction;
 
 use crate::constants::{
-    BLOCK_MAX_FUTURE, DOUBLE_SPACING_SECONDS, MAX_TIMESPAN, MIN_TIMESPAN, RETARGETING_INTERVAL,
-    TARGET_SPACING_SECONDS, TARGET_TIMESPAN_SECONDS,
+    BLOCK_MAX_FUTURE, COINBASE_MATURITY, DOUBLE_SPACING_SECONDS, MAX_TIMESPAN, MEDIAN_TIME_SPAN,
+    MIN_TIMESPAN, RETARGETING_INTERVAL, TARGET_SPACING_SECONDS, TARGET_TIMESPAN_SECONDS,
 };
 use crate::error::Error;
 use crate::miner_reward;
@@ -23,17 +27,98 @@ pub const NODE_POW_TARGET: U256 = U256([
     0x00000fffff000000u64,
 ]);
 
+/// Get block proof, the expected amount of work needed to produce a block at the header's target
+pub fn block_proof(header: &BlockHeader) -> U256 {
+    let proof: U256 = header.difficulty().into();
+    // We need to compute 2**256 / (bnTarget+1), but we can't represent 2**256
+    // as it's too large for a arith_uint256. However, as 2**256 is at least as
+    // large as bnTarget+1, it is equal to ((2**256 - bnTarget - 1) /
+    // (bnTarget+1)) + 1, or ~bnTarget / (nTarget+1) + 1.
+    (!proof / (proof + U256::one())) + U256::one()
+}
+
 pub struct BarossaProtocol {
     network: Network,
+    max_difficulty: U256,
+    allow_min_difficulty_blocks: bool,
+    genesis: BlockHeader,
+    fee_burn_percent: u8,
 }
 
 impl BarossaProtocol {
-    pub fn new(network: Network) -> Self {
-        Self { network }
+    pub fn new(network: Network) -> anyhow::Result<Self> {
+        let chainspec = ChainSpec::from_network(network)?;
+        Ok(Self {
+            network,
+            max_difficulty: chainspec.max_difficulty,
+            allow_min_difficulty_blocks: chainspec.allow_min_difficulty_blocks,
+            genesis: chainspec.genesis_header(),
+            fee_burn_percent: chainspec.fee_burn_percent,
+        })
+    }
+
+    pub fn from_chainspec(chainspec: &ChainSpec) -> anyhow::Result<Self> {
+        Ok(Self {
+            network: chainspec.network()?,
+            max_difficulty: chainspec.max_difficulty,
+            allow_min_difficulty_blocks: chainspec.allow_min_difficulty_blocks,
+            genesis: chainspec.genesis_header(),
+            fee_burn_percent: chainspec.fee_burn_percent,
+        })
+    }
+
+    pub fn max_difficulty_compact(&self) -> Compact {
+        Compact::from_u256(self.max_difficulty)
     }
 }
 
 impl BarossaProtocol {
+    /// Returns the median timestamp of the last `MEDIAN_TIME_SPAN` blocks ending at `parent`
+    pub fn median_time_past(
+        &self,
+        parent: IndexedBlockHeader,
+        chain: Arc<dyn ChainHeadReader>,
+    ) -> anyhow::Result<u32> {
+        let mut timestamps = Vec::with_capacity(MEDIAN_TIME_SPAN);
+        let mut header = Some(parent);
+        while let Some(current) = header {
+            timestamps.push(current.raw.time);
+            if timestamps.len() == MEDIAN_TIME_SPAN || current.raw.level == 0 {
+                break;
+            }
+            header = chain.get_header_by_hash(&current.raw.parent_hash)?;
+        }
+        timestamps.sort_unstable();
+        Ok(timestamps[timestamps.len() / 2])
+    }
+
+    /// Returns the ancestor of `header` at `level`. Side chain ancestors are not indexed by
+    /// level, so the parents are walked until one is canonical and the rest is read from the
+    /// canonical level index.
+    pub fn ancestor_at_level(
+        &self,
+        header: &BlockHeader,
+        level: u32,
+        chain: Arc<dyn ChainHeadReader>,
+    ) -> anyhow::Result<IndexedBlockHeader> {
+        let mut parent_hash = header.parent_hash;
+        loop {
+            let parent = chain
+                .get_header_by_hash(&parent_hash)?
+                .ok_or(Error::ParentBlockNotFound)?;
+            if parent.raw.level <= level {
+                return Ok(parent);
+            }
+            let canonical = chain.get_header_by_level(parent.raw.level)?;
+            if canonical.map(|canonical| canonical.hash) == Some(parent.hash) {
+                return Ok(chain
+                    .get_header_by_level(level)?
+                    .ok_or(Error::ParentBlockNotFound)?);
+            }
+            parent_hash = parent.raw.parent_hash;
+        }
+    }
+
     /// Returns work required for given header
     pub fn work_required(
         &self,
@@ -42,7 +127,7 @@ impl BarossaProtocol {
         height: u32,
         chain: Arc<dyn ChainHeadReader>,
     ) -> Compact {
-        let max_bits = self.network.max_difficulty_compact();
+        let max_bits = self.max_difficulty_compact();
         if height == 0 {
             return max_bits;
         }
@@ -62,7 +147,7 @@ impl BarossaProtocol {
             return self.work_required_retarget(parent_header, height, chain, max_bits);
         }
 
-        if self.network == Network::Testnet {
+        if self.allow_min_difficulty_blocks {
             return self.work_required_testnet(parent_hash, time, height, chain);
         }
 
@@ -138,7 +223,7 @@ impl BarossaProtocol {
             .unwrap()
             .expect("height != 0; qed");
         let max_time_gap = parent_header.raw.time + DOUBLE_SPACING_SECONDS;
-        let max_bits = self.network.max_difficulty_compact();
+        let max_bits = self.max_difficulty_compact();
         if time > max_time_gap {
             return max_bits;
         }
@@ -201,16 +286,6 @@ impl BarossaProtocol {
             header1
         }
 
-        /// Get block proof.
-        fn block_proof(header: &IndexedBlockHeader) -> U256 {
-            let proof: U256 = header.raw.difficulty().to_u256().unwrap();
-            // We need to compute 2**256 / (bnTarget+1), but we can't represent 2**256
-            // as it's too large for a arith_uint256. However, as 2**256 is at least as
-            // large as bnTarget+1, it is equal to ((2**256 - bnTarget - 1) /
-            // (bnTarget+1)) + 1, or ~bnTarget / (nTarget+1) + 1.
-            (!proof / (proof + U256::one())) + U256::one()
-        }
-
         /// Compute chain work between two blocks. Last block work is included. First block work is excluded.
         fn compute_work_between_blocks(
             first: H256,
@@ -218,13 +293,13 @@ impl BarossaProtocol {
             chain: Arc<dyn ChainHeadReader>,
         ) -> U256 {
             debug_assert!(last.hash != first);
-            let mut chain_work: U256 = block_proof(last);
+            let mut chain_work: U256 = block_proof(&last.raw);
             let mut prev_hash = last.raw.parent_hash;
             loop {
                 let header = chain.get_header_by_hash(&prev_hash).unwrap()
                     .expect("last header is on main chain; first is at level last.level - 144; it is on main chain; qed");
 
-                chain_work += block_proof(&header);
+                chain_work += block_proof(&header.raw);
                 prev_hash = header.raw.parent_hash;
                 if prev_hash == first {
                     return chain_work;
@@ -270,8 +345,8 @@ impl BarossaProtocol {
         // Special difficulty rule for testnet:
         // If the new block's timestamp is more than 2 * 10 minutes then allow
         // mining of a min-difficulty block.
-        let max_bits: Compact = Compact::from_u256(self.network.max_difficulty());
-        if self.network == Network::Testnet {
+        let max_bits: Compact = self.max_difficulty_compact();
+        if self.allow_min_difficulty_blocks {
             let max_time_gap = parent_header.raw.time + DOUBLE_SPACING_SECONDS;
             if time > max_time_gap {
                 return max_bits;
@@ -295,7 +370,7 @@ impl BarossaProtocol {
 
         // Compute the target based on time and work done during the interval.
         let next_target = compute_target(first_header, last_header, chain);
-        let max_bits = self.network.max_difficulty();
+        let max_bits = self.max_difficulty;
         if next_target > max_bits {
             return max_bits.into();
         }
@@ -311,7 +386,7 @@ impl Consensus for BarossaProtocol {
         header: &BlockHeader,
     ) -> anyhow::Result<()> {
         let current_time = chrono::Utc::now().timestamp();
-        let _ = chain
+        let parent = chain
             .get_header(&header.parent_hash, header.level - 1)?
             .ok_or(Error::ParentBlockNotFound)?;
         // Check timestamp
@@ -319,13 +394,18 @@ impl Consensus for BarossaProtocol {
             (header.time as i64) < BLOCK_MAX_FUTURE + current_time,
             "future block timestamp"
         );
+        let median_time_past = self.median_time_past(parent, chain)?;
+        anyhow::ensure!(
+            header.time > median_time_past,
+            Error::BlockTimeTooOld(header.time, median_time_past)
+        );
         anyhow::ensure!(
             is_valid_proof_of_work(
-                self.network.max_difficulty().into(),
+                self.max_difficulty.into(),
                 header.difficulty(),
                 &header.hash()
             ),
-            Error::BadPow(self.network.max_difficulty().into(), header.difficulty())
+            Error::BadPow(self.max_difficulty.into(), header.difficulty())
         );
         Ok(())
     }
@@ -347,23 +427,28 @@ impl Consensus for BarossaProtocol {
 
     fn finalize<'a>(
         &self,
-        _chain: Arc<dyn ChainHeadReader>,
         header: &mut BlockHeader,
         vm: Arc<dyn WasmVMInstance>,
         state: Arc<dyn StateDB>,
+        reward: &BlockReward,
         txs: &[SignedTransaction],
-    ) -> anyhow::Result<()> {
+    ) -> anyhow::Result<(Vec<Receipt>, StateDiff)> {
         let mut merkle = SparseMerkleTree::default();
         for tx in txs {
             merkle.update(tx.hash(), tx.hash())?;
         }
-        state.apply_txs(vm, txs)?;
-        let _ = state.credit_balance(&header.coinbase, self.miner_reward(header.level))?;
+        let (receipts, diff) = state.apply_txs(vm, reward, txs)?;
         state.commit()?;
 
+        let mut receipts_merkle = SparseMerkleTree::default();
+        for receipt in receipts.iter() {
+            receipts_merkle.update(receipt.tx_hash(), receipt.hash())?;
+        }
+
         header.state_root = state.root();
         header.tx_root = merkle.root();
-        Ok(())
+        header.receipt_hash = receipts_merkle.root();
+        Ok((receipts, diff))
     }
 
     fn finalize_and_assemble(
@@ -374,7 +459,8 @@ impl Consensus for BarossaProtocol {
         state: Arc<dyn StateDB>,
         txs: &[SignedTransaction],
     ) -> anyhow::Result<Option<Block>> {
-        self.finalize(chain, header, vm, state, txs)?;
+        let reward = self.block_reward(chain, header)?;
+        self.finalize(header, vm, state, &reward, txs)?;
         let block = Block::new(*header, txs.into());
         Ok(Some(block))
     }
@@ -399,24 +485,35 @@ impl Consensus for BarossaProtocol {
         miner_reward(block_level as u128) as u64
     }
 
+    fn block_reward(
+        &self,
+        chain: Arc<dyn ChainHeadReader>,
+        header: &BlockHeader,
+    ) -> anyhow::Result<BlockReward> {
+        // The genesis block has no reward to release
+        let matured_coinbase = match header.level.checked_sub(COINBASE_MATURITY) {
+            Some(level) if level > 0 => {
+                Some(self.ancestor_at_level(header, level, chain)?.raw.coinbase)
+            }
+            _ => None,
+        };
+        Ok(BlockReward {
+            level: header.level,
+            coinbase: header.coinbase,
+            amount: self.miner_reward(header.level),
+            unlock_level: header.level + COINBASE_MATURITY,
+            matured_coinbase,
+            fee_burn_percent: self.fee_burn_percent,
+            chain_id: self.network.chain_id(),
+        })
+    }
+
+    fn block_proof(&self, header: &BlockHeader) -> U256 {
+        block_proof(header)
+    }
+
     fn get_genesis_header(&self) -> BlockHeader {
-        BlockHeader::new(
-            [0; 32].into(),
-            [0; 32].into(),
-            [0; 32].into(),
-            [
-                167, 166, 177, 200, 75, 77, 145, 25, 149, 154, 251, 233, 94, 46, 215, 162, 118, 43,
-                119, 114, 196, 232, 42, 88, 209, 4, 27, 184, 193, 138, 143, 109,
-            ]
-            .into(),
-            [0; 32].into(),
-            [0; 44].into(),
-            self.network.max_difficulty_compact().into(),
-            self.network.chain_id(),
-            0,
-            0,
-            0,
-        )
+        self.genesis
     }
 
     fn network(&self) -> Network {
@@ -433,7 +530,7 @@ mod tests {
     use traits::ChainHeadReader;
     use types::block::{BlockHeader, IndexedBlockHeader};
 
-    use crate::barossa::{BarossaProtocol, Network};
+    use crate::barossa::{block_proof, BarossaProtocol, Network};
 
     #[derive(Default)]
     struct MemoryBlockHeaderReader {
@@ -478,14 +575,102 @@ mod tests {
 
     #[test]
     fn test_consensus_protocol() {
-        let _barossa = BarossaProtocol::new(Network::Mainnet);
+        let _barossa = BarossaProtocol::new(Network::Mainnet).unwrap();
+    }
+
+    #[test]
+    fn test_block_proof_increases_with_difficulty() {
+        let max_bits: Compact = Network::Mainnet.max_difficulty().unwrap().into();
+        let harder_bits: Compact = (Network::Mainnet.max_difficulty().unwrap() >> 4).into();
+        let easy = BlockHeader {
+            difficulty: max_bits.into(),
+            ..Default::default()
+        };
+        let hard = BlockHeader {
+            difficulty: harder_bits.into(),
+            ..Default::default()
+        };
+        assert!(block_proof(&easy) > U256::zero());
+        assert!(block_proof(&hard) > block_proof(&easy));
+    }
+
+    #[test]
+    fn test_median_time_past() {
+        let barossa = BarossaProtocol::new(Network::Mainnet).unwrap();
+        let header_provider = Arc::new(MemoryBlockHeaderReader::default());
+        let mut parent = header_provider.insert(BlockHeader {
+            time: 1000,
+            ..Default::default()
+        });
+        assert_eq!(
+            barossa
+                .median_time_past(parent.clone(), header_provider.clone())
+                .unwrap(),
+            1000
+        );
+        for level in 1..20 {
+            parent = header_provider.insert(BlockHeader {
+                parent_hash: parent.hash,
+                level,
+                time: 1000 + level * 120,
+                ..Default::default()
+            });
+        }
+        // median of the last 11 blocks, levels 9 to 19
+        assert_eq!(
+            barossa
+                .median_time_past(parent, header_provider.clone())
+                .unwrap(),
+            1000 + 14 * 120
+        );
+    }
+
+    #[test]
+    fn test_ancestor_at_level() {
+        let barossa = BarossaProtocol::new(Network::Mainnet).unwrap();
+        let header_provider = Arc::new(MemoryBlockHeaderReader::default());
+        let mut canonical = vec![header_provider.insert(BlockHeader::default())];
+        for level in 1..=10 {
+            canonical.push(header_provider.insert(BlockHeader {
+                parent_hash: canonical[level as usize - 1].hash,
+                level,
+                time: level,
+                ..Default::default()
+            }));
+        }
+        // A side chain forked at level 5, its blocks are not in the level index
+        let mut side = vec![canonical[5].clone()];
+        for level in 6..=8 {
+            side.push(header_provider.insert(BlockHeader {
+                parent_hash: side.last().unwrap().hash,
+                level,
+                time: level + 100,
+                ..Default::default()
+            }));
+        }
+
+        let tip = canonical[10].raw;
+        let ancestor = barossa
+            .ancestor_at_level(&tip, 3, header_provider.clone())
+            .unwrap();
+        assert_eq!(ancestor.hash, canonical[3].hash);
+
+        let side_tip = side.last().unwrap().raw;
+        let ancestor = barossa
+            .ancestor_at_level(&side_tip, 6, header_provider.clone())
+            .unwrap();
+        assert_eq!(ancestor.hash, side[1].hash);
+        let ancestor = barossa
+            .ancestor_at_level(&side_tip, 2, header_provider.clone())
+            .unwrap();
+        assert_eq!(ancestor.hash, canonical[2].hash);
     }
 
     #[test]
     fn test_consensus_protocol_adjusted_difficulty() {
-        let barossa = BarossaProtocol::new(Network::Mainnet);
+        let barossa = BarossaProtocol::new(Network::Mainnet).unwrap();
 
-        let limit_bits = barossa.network.max_difficulty();
+        let limit_bits = barossa.max_difficulty;
         let _initial_bits = limit_bits >> 4;
         let initial_bits: Compact = limit_bits.into();
         let header_provider = Arc::new(MemoryBlockHeaderReader::default());
@@ -1,6 +1,8 @@
 //! Consenus constants
 
 pub const BLOCK_MAX_FUTURE: i64 = 2 * 60 * 60;
+// Number of ancestors used to compute the median time past
+pub const MEDIAN_TIME_SPAN: usize = 11;
 // 2 hours
 pub const COINBASE_MATURITY: u32 = 100;
 // 2 hours
@@ -9,4 +9,6 @@ pub enum Error {
     BlockBadTarget,
     #[error("bad proof of work expected {0:?} got {1:?}")]
     BadPow(Compact, Compact),
+    #[error("block timestamp {0} is not after median time past {1}")]
+    BlockTimeTooOld(u32, u32),
 }
@@ -54,6 +54,14 @@ pub fn sha256<B: AsRef<[u8]>>(bytes: B) -> H256 {
     H256::from_slice(hasher.finalize().as_ref())
 }
 
+#[inline]
+pub fn blake2b256<B: AsRef<[u8]>>(bytes: B) -> H256 {
+    let hash = blake2b_simd::Params::new()
+        .hash_length(32)
+        .hash(bytes.as_ref());
+    H256::from_slice(hash.as_bytes())
+}
+
 #[inline]
 pub fn keccak256<B: AsRef<[u8]>>(bytes: B) -> H256 {
     let mut hasher = sha3::Keccak256::default();
@@ -6,7 +6,7 @@ use rune_framework::prelude::RuntimeApplication;
 use std::collections::BTreeMap;
 use std::sync::Arc;
 use traits::{StateDB, WasmVMInstance};
-use types::app::AppStateKey;
+use types::diff::StorageWrites;
 use types::prelude::{
     get_address_from_package_name, AccountState, ApplicationCall, SignedTransaction,
     TransactionData,
@@ -26,7 +26,7 @@ pub fn is_namespace_registered(
     tx: &SignedTransaction,
     state_db: Arc<dyn StateDB>,
 ) -> anyhow::Result<bool> {
-    let app_id = get_address_from_package_name("network.odax.nameregistry", tx.network())?;
+    let app_id = get_address_from_package_name("network.odax.nameregistry", tx.network()?)?;
 
     let descriptor = prost_reflect::DescriptorPool::decode(
         namespace_registry::PackageNameRegistry::descriptor(),
@@ -61,6 +61,7 @@ pub fn is_namespace_registered(
 pub fn register_namespace(
     vm: &dyn WasmVMInstance,
     states: &mut BTreeMap<Address, AccountState>,
+    storage_writes: &mut BTreeMap<Address, StorageWrites>,
     tx: &SignedTransaction,
     state_db: Arc<dyn StateDB>,
 ) -> anyhow::Result<()> {
@@ -70,7 +71,7 @@ pub fn register_namespace(
 
     let pkn = PackageName::parse(&arg.package_name)?;
 
-    let app_id = get_address_from_package_name("network.odax.nameregistry", tx.network())?;
+    let app_id = get_address_from_package_name("network.odax.nameregistry", tx.network()?)?;
 
     let descriptor = prost_reflect::DescriptorPool::decode(
         namespace_registry::PackageNameRegistry::descriptor(),
@@ -90,7 +91,7 @@ pub fn register_namespace(
     let mut message = DynamicMessage::new(input);
     message.transcode_from(&namespace_registry::service::Namespace {
         namespace: Some(pkn.organisation_id),
-        owner: Some(tx.sender()),
+        owner: Some(tx.sender()?),
     })?;
     let call = ApplicationCall {
         app_id,
@@ -116,10 +117,11 @@ pub fn register_namespace(
         .ok_or_else(|| anyhow::anyhow!("app state not found"))?;
     app_state.root_hash = namespace_registry_changes.storage.root();
 
-    state_db.set_app_data(
-        AppStateKey(app_id, namespace_registry_changes.storage.root()),
-        namespace_registry_changes.storage,
-    )?;
+    state_db.set_app_data(&namespace_registry_changes.storage)?;
+    storage_writes
+        .entry(app_id)
+        .or_default()
+        .extend(namespace_registry_changes.storage_writes);
 
     Ok(())
 }
@@ -166,6 +166,7 @@ fn block_template(
     };
 
     consensus.prepare_header(chain_header_reader.clone(), &mut header)?;
-    consensus.finalize(chain_header_reader, &mut header, vm, state.clone(), &txs)?;
+    let reward = consensus.block_reward(chain_header_reader, &header)?;
+    consensus.finalize(&mut header, vm, state.clone(), &reward, &txs)?;
     Ok((header, txs))
 }
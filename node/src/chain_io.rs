@@ -0,0 +1,250 @@
+use std::fs::{File, OpenOptions};
+use std::io::{BufReader, BufWriter, ErrorKind, Read, Write};
+use std::sync::{Arc, RwLock};
+
+use anyhow::{anyhow, ensure, Context, Result};
+use tokio::sync::mpsc::UnboundedSender;
+
+use blockchain::chain_state::ChainState;
+use codec::{Decodable, Encodable};
+use primitive_types::H256;
+use traits::{Blockchain, ChainHeadReader, ChainReader, Consensus};
+use txpool::TxPool;
+use types::block::Block;
+use types::events::LocalEventMessage;
+
+use crate::node::{open_blockchain, setup_chain_environment};
+use crate::{ChainExportArgs, ChainImportArgs};
+
+const EXPORT_PROGRESS_INTERVAL: u32 = 1000;
+const IMPORT_BATCH_SIZE: usize = 500;
+/// Largest encoded block accepted from a file, the largest message a block is gossiped in
+pub(crate) const MAX_BLOCK_RECORD_SIZE: usize = 10_000_000;
+
+/// Writes canonical blocks as a stream of big-endian u32 length prefixed protobuf blocks
+pub(crate) fn export_chain(args: &ChainExportArgs) -> Result<()> {
+    let env = setup_chain_environment(&args.env)?;
+    let (_, blockchain) = open_blockchain(&env, event_sink())?;
+    let chain_state = blockchain.chain_state();
+    let block_storage = chain_state.block_storage();
+    let current_head = chain_state
+        .current_header()?
+        .ok_or_else(|| anyhow!("current head not found"))?;
+    let to = args
+        .to
+        .map_or(current_head.raw.level, |to| to.min(current_head.raw.level));
+    ensure!(args.from <= to, "--from {} is above --to {}", args.from, to);
+
+    let file = OpenOptions::new()
+        .write(true)
+        .create_new(true)
+        .open(&args.out)?;
+    let mut out = BufWriter::new(file);
+    let mut exported = 0;
+    for block in block_storage.get_blocks(&H256::zero(), args.from)? {
+        let block = block?;
+        if block.level() > to {
+            break;
+        }
+        // get_blocks also yields blocks from side chains
+        let is_canonical = block_storage
+            .get_header_by_level(block.level())?
+            .map(|header| header.hash == block.hash())
+            .unwrap_or(false);
+        if !is_canonical {
+            continue;
+        }
+        write_block(&mut out, &block)?;
+        exported += 1;
+        if exported % EXPORT_PROGRESS_INTERVAL == 0 {
+            println!(
+                "Exported {} blocks, level {}/{}",
+                exported,
+                block.level(),
+                to
+            );
+        }
+    }
+    out.flush()?;
+    println!("Exported {} blocks to {:?}", exported, args.out);
+    Ok(())
+}
+
+/// Replays an exported block file through full block validation
+pub(crate) fn import_chain(args: &ChainImportArgs) -> Result<()> {
+    let env = setup_chain_environment(&args.env)?;
+    let (consensus, blockchain) = open_blockchain(&env, event_sink())?;
+    let chain_state = blockchain.chain_state();
+    let block_storage = chain_state.block_storage();
+    let current_head = chain_state
+        .current_header()?
+        .ok_or_else(|| anyhow!("current head not found"))?;
+    ensure!(
+        args.resume || current_head.raw.level == 0,
+        "chain is already at level {}, use --resume to continue a previous import",
+        current_head.raw.level
+    );
+
+    let mut reader = BufReader::new(File::open(&args.file)?);
+    let mut batch = Vec::with_capacity(IMPORT_BATCH_SIZE);
+    let mut imported = 0;
+    let mut skipped = 0;
+    while let Some(block) = read_block(&mut reader)? {
+        if block.level() == 0 {
+            ensure!(
+                block.hash() == chain_state.genesis().hash,
+                "genesis block {:?} does not match the local genesis, check the chainspec",
+                block.hash()
+            );
+            continue;
+        }
+        if block_storage.get_block_by_hash(&block.hash())?.is_some() {
+            skipped += 1;
+            continue;
+        }
+        batch.push(block);
+        if batch.len() >= IMPORT_BATCH_SIZE {
+            imported += import_batch(
+                &chain_state,
+                consensus.clone(),
+                blockchain.txpool(),
+                &mut batch,
+            )?;
+        }
+    }
+    imported += import_batch(&chain_state, consensus, blockchain.txpool(), &mut batch)?;
+    println!(
+        "Imported {} blocks, skipped {} known blocks",
+        imported, skipped
+    );
+    Ok(())
+}
+
+fn import_batch(
+    chain_state: &ChainState,
+    consensus: Arc<dyn Consensus>,
+    txpool: Arc<RwLock<TxPool>>,
+    batch: &mut Vec<Block>,
+) -> Result<usize> {
+    let (Some(first), Some(last)) = (batch.first(), batch.last()) else {
+        return Ok(0);
+    };
+    let (first_level, last_level) = (first.level(), last.level());
+    let count = batch.len();
+    chain_state
+        .put_chain(
+            consensus,
+            Box::new(std::mem::take(batch).into_iter()),
+            txpool,
+        )
+        .with_context(|| {
+            format!(
+                "failed to import blocks {}..={}, rerun with --resume after fixing the cause",
+                first_level, last_level
+            )
+        })?;
+    println!("Imported blocks up to level {}", last_level);
+    Ok(count)
+}
+
+fn write_block<W: Write>(out: &mut W, block: &Block) -> Result<()> {
+    write_record(out, &block.encode()?)
+}
+
+fn read_block<R: Read>(reader: &mut R) -> Result<Option<Block>> {
+    match read_record(reader, MAX_BLOCK_RECORD_SIZE).context("invalid block in import file")? {
+        None => Ok(None),
+        Some(buf) => Ok(Some(Block::decode(&buf)?)),
+    }
+}
+
+/// Writes `data` prefixed with its big-endian u32 length
+pub(crate) fn write_record<W: Write>(out: &mut W, data: &[u8]) -> Result<()> {
+    out.write_all(&(data.len() as u32).to_be_bytes())?;
+    out.write_all(data)?;
+    Ok(())
+}
+
+/// Reads a record written by [`write_record`], returns `None` at the end of the input. Fails
+/// if the record is longer than `max_len` or truncated, the buffer grows with the data read so a
+/// corrupt length does not allocate up front.
+pub(crate) fn read_record<R: Read>(reader: &mut R, max_len: usize) -> Result<Option<Vec<u8>>> {
+    let mut len = [0; 4];
+    match reader.read_exact(&mut len) {
+        Ok(_) => {}
+        Err(error) if error.kind() == ErrorKind::UnexpectedEof => return Ok(None),
+        Err(error) => return Err(error.into()),
+    }
+    let len = u32::from_be_bytes(len) as usize;
+    ensure!(
+        len <= max_len,
+        "record of {} bytes exceeds the limit of {} bytes",
+        len,
+        max_len
+    );
+    let mut buf = Vec::new();
+    reader.take(len as u64).read_to_end(&mut buf)?;
+    ensure!(
+        buf.len() == len,
+        "truncated record, expected {} bytes, read {}",
+        len,
+        buf.len()
+    );
+    Ok(Some(buf))
+}
+
+/// The chain emits local events while importing, there is no node loop to consume them
+pub(crate) fn event_sink() -> UnboundedSender<LocalEventMessage> {
+    let (sender, mut receiver) = tokio::sync::mpsc::unbounded_channel();
+    std::thread::spawn(move || while receiver.blocking_recv().is_some() {});
+    sender
+}
+
+#[cfg(test)]
+mod tests {
+    use types::block::{Block, BlockHeader};
+
+    use super::{read_block, read_record, write_block, write_record, MAX_BLOCK_RECORD_SIZE};
+
+    #[test]
+    fn test_block_file_round_trip() {
+        let blocks: Vec<_> = (0..3)
+            .map(|level| {
+                Block::new(
+                    BlockHeader {
+                        level,
+                        ..Default::default()
+                    },
+                    Vec::new(),
+                )
+            })
+            .collect();
+        let mut buf = Vec::new();
+        for block in blocks.iter() {
+            write_block(&mut buf, block).unwrap();
+        }
+        let mut reader = buf.as_slice();
+        for block in blocks.iter() {
+            let read = read_block(&mut reader).unwrap().unwrap();
+            assert_eq!(read.hash(), block.hash());
+        }
+        assert!(read_block(&mut reader).unwrap().is_none());
+
+        let mut truncated = &buf[..buf.len() - 1];
+        for _ in 0..2 {
+            read_block(&mut truncated).unwrap();
+        }
+        assert!(read_block(&mut truncated).is_err());
+
+        // A length above the limit is rejected before the record is read
+        let oversized = (MAX_BLOCK_RECORD_SIZE as u32 + 1).to_be_bytes();
+        assert!(read_block(&mut oversized.as_slice()).is_err());
+        let mut small = Vec::new();
+        write_record(&mut small, &[0; 8]).unwrap();
+        assert!(read_record(&mut small.as_slice(), 4).is_err());
+        assert_eq!(
+            read_record(&mut small.as_slice(), 8).unwrap(),
+            Some(vec![0; 8])
+        );
+    }
+}
@@ -0,0 +1,38 @@
+use anyhow::{bail, Result};
+
+use types::config::StatePruning;
+
+use crate::chain_io::event_sink;
+use crate::node::{open_blockchain, setup_chain_environment};
+use crate::{DbCleanupArgs, DbPruneArgs};
+
+/// Deletes the state of old blocks, the node must not be running
+pub(crate) fn prune_state(args: &DbPruneArgs) -> Result<()> {
+    let env = setup_chain_environment(&args.env)?;
+    let keep_recent = match (args.keep_recent, env.state_pruning) {
+        (Some(keep_recent), _) | (None, StatePruning::KeepRecent(keep_recent)) => keep_recent,
+        (None, StatePruning::Archive) => {
+            bail!("state pruning is configured as archive, pass --keep-recent to prune")
+        }
+    };
+    let (_, blockchain) = open_blockchain(&env, event_sink())?;
+    let pruned = blockchain.chain_state().prune_state(keep_recent)?;
+    println!(
+        "Pruned {} state roots, kept the last {} levels",
+        pruned, keep_recent
+    );
+    Ok(())
+}
+
+/// Deletes the legacy app storage migrated when the state was opened, the node must not be
+/// running
+pub(crate) fn cleanup_appdata(args: &DbCleanupArgs) -> Result<()> {
+    let env = setup_chain_environment(&args.env)?;
+    let (_, blockchain) = open_blockchain(&env, event_sink())?;
+    if blockchain.chain_state().state().remove_legacy_appdata()? {
+        println!("Deleted the migrated legacy app storage");
+    } else {
+        println!("No legacy app storage to delete");
+    }
+    Ok(())
+}
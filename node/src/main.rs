@@ -14,9 +14,13 @@ use tracing::Level;
 use types::config::{EnvironmentConfig, DEFAULT_DIR_NAME};
 use types::network::Network;
 
+mod chain_io;
+mod db;
 pub mod environment;
 mod error;
 mod node;
+mod orphan_pool;
+mod snapshot;
 pub mod sync;
 
 #[derive(Copy, Clone, PartialEq, Eq, PartialOrd, Ord, Debug, ArgEnum)]
@@ -55,6 +59,8 @@ enum Commands {
     Config(ConfigArgs),
     Account(AccountArgs),
     Client(ClientArgsCommands),
+    Chain(ChainArgs),
+    Db(DbArgs),
 }
 
 #[derive(Args, Debug)]
@@ -81,6 +87,110 @@ struct RunArgs {
     p2p_port: Option<u16>,
     #[clap(long)]
     rpc_port: Option<u16>,
+    #[clap(long)]
+    chainspec: Option<PathBuf>,
+}
+
+#[derive(Args, Debug)]
+struct ChainArgs {
+    #[clap(subcommand)]
+    command: ChainCommands,
+}
+
+#[derive(Subcommand, Debug)]
+enum ChainCommands {
+    /// Export canonical blocks to a file
+    Export(ChainExportArgs),
+    /// Import blocks from a file exported with `chain export`
+    Import(ChainImportArgs),
+    /// Export the state at a block to a snapshot file
+    ExportSnapshot(SnapshotExportArgs),
+    /// Start an empty data directory from a snapshot file
+    ImportSnapshot(SnapshotImportArgs),
+}
+
+#[derive(Args, Debug)]
+struct ChainEnvArgs {
+    #[clap(short, long)]
+    datadir: Option<PathBuf>,
+    #[clap(short, long)]
+    config_file: Option<PathBuf>,
+    #[clap(long)]
+    chainspec: Option<PathBuf>,
+}
+
+#[derive(Args, Debug)]
+struct ChainExportArgs {
+    #[clap(long, default_value_t = 0)]
+    from: u32,
+    #[clap(long)]
+    to: Option<u32>,
+    #[clap(long)]
+    out: PathBuf,
+    #[clap(flatten)]
+    env: ChainEnvArgs,
+}
+
+#[derive(Args, Debug)]
+struct ChainImportArgs {
+    file: PathBuf,
+    /// Skip blocks that are already in the local chain
+    #[clap(long)]
+    resume: bool,
+    #[clap(flatten)]
+    env: ChainEnvArgs,
+}
+
+#[derive(Args, Debug)]
+struct SnapshotExportArgs {
+    /// Level of the snapshot block, defaults to the current head
+    #[clap(long)]
+    level: Option<u32>,
+    #[clap(long)]
+    out: PathBuf,
+    #[clap(flatten)]
+    env: ChainEnvArgs,
+}
+
+#[derive(Args, Debug)]
+struct SnapshotImportArgs {
+    file: PathBuf,
+    /// Total work of the first block after genesis of a snapshot that does not reach back to
+    /// genesis. It can not be recomputed from the snapshot and must come from a trusted node.
+    #[clap(long)]
+    checkpoint_work: Option<String>,
+    #[clap(flatten)]
+    env: ChainEnvArgs,
+}
+
+#[derive(Args, Debug)]
+struct DbArgs {
+    #[clap(subcommand)]
+    command: DbCommands,
+}
+
+#[derive(Subcommand, Debug)]
+enum DbCommands {
+    /// Delete the state of old blocks
+    Prune(DbPruneArgs),
+    /// Delete the app storage left in the legacy layout once it was migrated
+    CleanupAppdata(DbCleanupArgs),
+}
+
+#[derive(Args, Debug)]
+struct DbPruneArgs {
+    /// Number of levels below the head whose state is kept, defaults to the configured
+    /// state pruning
+    #[clap(long)]
+    keep_recent: Option<u32>,
+    #[clap(flatten)]
+    env: ChainEnvArgs,
+}
+
+#[derive(Args, Debug)]
+struct DbCleanupArgs {
+    #[clap(flatten)]
+    env: ChainEnvArgs,
 }
 
 #[derive(Args, Debug)]
@@ -137,6 +247,8 @@ struct SetConfigArgs {
     rpc_port: Option<u16>,
     #[clap(arg_enum, long)]
     network: Option<Network>,
+    #[clap(long)]
+    chainspec: Option<PathBuf>,
 }
 
 #[derive(Args, Debug)]
@@ -172,7 +284,7 @@ fn main() -> Result<()> {
         }
         Commands::Account(args) => match &args.command {
             AccountCommands::Create(args) => {
-                let account = account::create_account(args.network);
+                let account = account::create_account(args.network)?;
                 println!("{}", serde_json::to_string_pretty(&account)?);
             }
         },
@@ -181,6 +293,28 @@ fn main() -> Result<()> {
             let resp = rt.block_on(async { handle_client_command(args).await })?;
             println!("{}", serde_json::to_string_pretty(&resp)?);
         }
+        Commands::Chain(args) => match &args.command {
+            ChainCommands::Export(args) => {
+                chain_io::export_chain(args)?;
+            }
+            ChainCommands::Import(args) => {
+                chain_io::import_chain(args)?;
+            }
+            ChainCommands::ExportSnapshot(args) => {
+                snapshot::export_snapshot(args)?;
+            }
+            ChainCommands::ImportSnapshot(args) => {
+                snapshot::import_snapshot(args)?;
+            }
+        },
+        Commands::Db(args) => match &args.command {
+            DbCommands::Prune(args) => {
+                db::prune_state(args)?;
+            }
+            DbCommands::CleanupAppdata(args) => {
+                db::cleanup_appdata(args)?;
+            }
+        },
     }
 
     Ok(())
@@ -246,6 +380,10 @@ fn handle_config_commands(args: &ConfigCommands) -> Result<()> {
                 config.identity_file = Some(identity_file.clone())
             }
 
+            if let Some(chainspec) = &args.chainspec {
+                config.chainspec = Some(chainspec.clone())
+            }
+
             let config_file_path = create_file_path(args.datadir.clone(), "config.json")?;
             let config_file = OpenOptions::new()
                 .write(true)
@@ -322,6 +460,10 @@ fn sanitize_config_args(
     if let Some(rpc_port) = args.rpc_port {
         config.rpc_port = rpc_port
     }
+
+    if let Some(chainspec) = &args.chainspec {
+        config.chainspec = Some(chainspec.clone())
+    }
     Ok(config)
 }
 
@@ -337,7 +479,9 @@ pub(crate) fn parse_miner_address(s: &str) -> Result<Address, String> {
         || s.eq_ignore_ascii_case("kofi")
         || s.eq_ignore_ascii_case("kwame")
     {
-        return Ok(account::create_account_from_uri(Network::Testnet, s).address);
+        return account::create_account_from_uri(Network::Testnet, s)
+            .map(|account| account.address)
+            .map_err(|e| format!("{}", e));
     }
     match Address::from_str(s) {
         Ok(s) => Ok(s),
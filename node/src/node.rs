@@ -1,4 +1,5 @@
 use std::fs::{File, OpenOptions};
+use std::path::PathBuf;
 use std::sync::atomic::AtomicI8;
 use std::sync::Arc;
 
@@ -27,11 +28,11 @@ use types::events::LocalEventMessage;
 
 use crate::environment::default_db_opts;
 use crate::sync::{SyncMode, SyncService};
-use crate::{Level, RunArgs};
+use crate::{ChainEnvArgs, Level, RunArgs};
 
 enum Event {
     LocalMessage(LocalEventMessage),
-    PeerMessage(Msg),
+    PeerMessage(PeerToNodeMessage),
     Unhandled,
 }
 
@@ -92,26 +93,7 @@ async fn _start_node(args: &RunArgs) -> Result<()> {
     )
     .expect("failed to read identity file");
 
-    let database = Arc::new(rocksdb::DB::open_cf_descriptors(
-        &default_db_opts(),
-        env.datadir.join("main"),
-        column_families(),
-    )?);
-    let storage = Arc::new(PersistentStorage::new(PersistentStorageBackend::RocksDB(
-        database,
-    )));
-    let consensus = Arc::new(BarossaProtocol::new(env.network));
-    let blockchain = Arc::new(
-        Chain::initialize(
-            env.datadir.clone(),
-            consensus.clone(),
-            storage,
-            local_mpsc_sender.clone(),
-            build_in_apps(),
-        )
-        .unwrap(),
-    )
-    .clone();
+    let (consensus, blockchain) = open_blockchain(&env, local_mpsc_sender.clone())?;
 
     let network_state = Arc::new(NetworkState::new(local_mpsc_sender.clone()));
     let handler = Arc::new(RequestHandler::new(
@@ -129,6 +111,7 @@ async fn _start_node(args: &RunArgs) -> Result<()> {
             consensus,
             blockchain.chain_state().block_storage(),
             Arc::new(SyncMode::Normal),
+            network_state.clone(),
         )
     };
 
@@ -200,8 +183,8 @@ async fn _start_node(args: &RunArgs) -> Result<()> {
 
         if let Some(event) = event {
             match event {
-                Event::PeerMessage(msg) => {
-                    match msg {
+                Event::PeerMessage(PeerToNodeMessage { peer_id, message }) => {
+                    match message {
                         Msg::BroadcastTransaction(msg) => {
                             let txpool = blockchain.txpool();
                             let mut txpool = txpool.write().unwrap();
@@ -209,13 +192,27 @@ async fn _start_node(args: &RunArgs) -> Result<()> {
                         }
                         Msg::BroadcastBlock(msg) => {
                             if let Some(block) = msg.block {
-                                // TODO: validate block
-                                // TODO: Check if future block is not further than 3 days
-                                blockchain.chain_state().block_storage().put(block)?;
+                                let hash = block.hash();
+                                match sync_service
+                                    .import_broadcast_block(peer_id.clone(), block.clone())
+                                {
+                                    Ok(true) => {
+                                        broadcast_message(
+                                            &node_to_peer_sender,
+                                            Msg::BroadcastBlock(BroadcastBlockMessage::new(block)),
+                                        )
+                                        .unwrap();
+                                    }
+                                    Ok(false) => {}
+                                    Err(error) => {
+                                        warn!(peer = ?peer_id, block = ?hash, error = ?error, "Rejected invalid block");
+                                        sync_service.report_rejected_block(&peer_id, &error);
+                                    }
+                                }
                             }
                         }
-                        msg => {
-                            sync_service.handle(msg);
+                        message => {
+                            sync_service.handle(PeerToNodeMessage { peer_id, message });
                         }
                     };
                 }
@@ -240,6 +237,24 @@ async fn _start_node(args: &RunArgs) -> Result<()> {
                             Msg::CurrentHead(CurrentHeadMessage::new(current_head)),
                         )
                         .unwrap();
+                        if let Err(error) = sync_service.connect_orphans(current_head.hash()) {
+                            warn!(error = ?error, "Failed to connect orphan blocks");
+                        }
+                    }
+                    LocalEventMessage::ChainReorg {
+                        old_tip,
+                        new_tip,
+                        depth,
+                    } => {
+                        warn!(old_tip = ?old_tip.hash(), new_tip = ?new_tip.hash(), depth = depth, "Chain reorganised");
+                        broadcast_message(
+                            &node_to_peer_sender,
+                            Msg::CurrentHead(CurrentHeadMessage::new(new_tip)),
+                        )
+                        .unwrap();
+                        if let Err(error) = sync_service.connect_orphans(new_tip.hash()) {
+                            warn!(error = ?error, "Failed to connect orphan blocks");
+                        }
                     }
                     LocalEventMessage::NetworkNewPeerConnection { stats, .. } => {
                         info!(pending = ?stats.0, connected = ?stats.1, "Peers");
@@ -254,36 +269,46 @@ async fn _start_node(args: &RunArgs) -> Result<()> {
     }
 }
 
-pub(crate) fn setup_environment(args: &RunArgs) -> Result<Arc<EnvironmentConfig>> {
-    let mut config = EnvironmentConfig::default();
-
-    if let Some(datadir) = &args.datadir {
-        config.datadir = datadir.clone();
-    }
+pub(crate) fn open_main_storage(env: &EnvironmentConfig) -> Result<Arc<PersistentStorage>> {
+    let database = Arc::new(rocksdb::DB::open_cf_descriptors(
+        &default_db_opts(),
+        env.datadir.join("main"),
+        column_families(),
+    )?);
+    Ok(Arc::new(PersistentStorage::new(
+        PersistentStorageBackend::RocksDB(database),
+    )))
+}
 
-    if let Some(config_file_path) = &args.config_file {
-        let config_file = OpenOptions::new()
-            .read(true)
-            .open(config_file_path.as_path())?;
-        config = serde_json::from_reader(config_file)?;
+pub(crate) fn open_blockchain(
+    env: &EnvironmentConfig,
+    sender: UnboundedSender<LocalEventMessage>,
+) -> Result<(Arc<BarossaProtocol>, Arc<Chain>)> {
+    let storage = open_main_storage(env)?;
+    let chainspec = env.chainspec()?;
+    let built_in_apps = if chainspec.built_in_apps.is_empty() {
+        build_in_apps()
+            .into_iter()
+            .map(|(package_name, binary)| (package_name.to_string(), binary.to_vec()))
+            .collect()
     } else {
-        let res: Result<EnvironmentConfig, _> = OpenOptions::new()
-            .read(true)
-            .open(config.datadir.join("config.json"))
-            .map_err(|e| anyhow::anyhow!("{}", e))
-            .and_then(|config_file| {
-                serde_json::from_reader(config_file).map_err(|e| anyhow::anyhow!("{}", e))
-            });
+        chainspec.load_built_in_apps()?
+    };
+    let consensus = Arc::new(BarossaProtocol::from_chainspec(&chainspec)?);
+    let blockchain = Arc::new(Chain::initialize(
+        env.datadir.clone(),
+        consensus.clone(),
+        storage,
+        sender,
+        &chainspec,
+        built_in_apps,
+        env.state_pruning,
+    )?);
+    Ok((consensus, blockchain))
+}
 
-        match res {
-            Ok(c) => {
-                config = c;
-            }
-            Err(error) => {
-                warn!(error = ?error, "failed to read config file, reverting to application default");
-            }
-        }
-    }
+pub(crate) fn setup_environment(args: &RunArgs) -> Result<Arc<EnvironmentConfig>> {
+    let mut config = read_config(&args.datadir, &args.config_file)?;
 
     if let Some(network) = args.network {
         config.network = network;
@@ -321,7 +346,75 @@ pub(crate) fn setup_environment(args: &RunArgs) -> Result<Arc<EnvironmentConfig>
         config.identity_file = Some(identity_file.clone())
     }
 
+    if let Some(chainspec) = &args.chainspec {
+        config.chainspec = Some(chainspec.clone())
+    }
+
+    resolve_network(&mut config)?;
+    config.sanitize();
+
+    Ok(Arc::new(config))
+}
+
+pub(crate) fn setup_chain_environment(args: &ChainEnvArgs) -> Result<Arc<EnvironmentConfig>> {
+    let mut config = read_config(&args.datadir, &args.config_file)?;
+
+    if let Some(chainspec) = &args.chainspec {
+        config.chainspec = Some(chainspec.clone())
+    }
+
+    resolve_network(&mut config)?;
     config.sanitize();
 
     Ok(Arc::new(config))
 }
+
+fn resolve_network(config: &mut EnvironmentConfig) -> Result<()> {
+    if config.chainspec.is_some() {
+        config.network = config.chainspec()?.network()?;
+    }
+
+    anyhow::ensure!(
+        config.network.is_registered(),
+        "custom network {} requires a chainspec",
+        config.network.chain_id()
+    );
+    Ok(())
+}
+
+fn read_config(
+    datadir: &Option<PathBuf>,
+    config_file: &Option<PathBuf>,
+) -> Result<EnvironmentConfig> {
+    let mut config = EnvironmentConfig::default();
+
+    if let Some(datadir) = datadir {
+        config.datadir = datadir.clone();
+    }
+
+    if let Some(config_file_path) = config_file {
+        let config_file = OpenOptions::new()
+            .read(true)
+            .open(config_file_path.as_path())?;
+        config = serde_json::from_reader(config_file)?;
+    } else {
+        let res: Result<EnvironmentConfig, _> = OpenOptions::new()
+            .read(true)
+            .open(config.datadir.join("config.json"))
+            .map_err(|e| anyhow::anyhow!("{}", e))
+            .and_then(|config_file| {
+                serde_json::from_reader(config_file).map_err(|e| anyhow::anyhow!("{}", e))
+            });
+
+        match res {
+            Ok(c) => {
+                config = c;
+            }
+            Err(error) => {
+                warn!(error = ?error, "failed to read config file, reverting to application default");
+            }
+        }
+    }
+
+    Ok(config)
+}
@@ -0,0 +1,235 @@
+use std::collections::{BTreeSet, HashMap};
+use std::time::{Duration, Instant};
+
+use primitive_types::H256;
+use types::block::Block;
+
+pub const MAX_ORPHAN_BLOCKS: usize = 256;
+pub const MAX_ORPHAN_BLOCKS_PER_PEER: usize = 32;
+pub const ORPHAN_BLOCK_EXPIRY: Duration = Duration::from_secs(10 * 60);
+
+struct OrphanBlock {
+    block: Block,
+    peer_id: String,
+    received_at: Instant,
+}
+
+/// Holds blocks whose parent is not yet known, keyed by parent hash, until the parent is
+/// accepted or the block expires.
+pub struct OrphanBlockPool {
+    blocks: HashMap<H256, OrphanBlock>,
+    by_parent: HashMap<H256, BTreeSet<H256>>,
+    by_peer: HashMap<String, usize>,
+    requested: HashMap<H256, Instant>,
+    max_blocks: usize,
+    max_blocks_per_peer: usize,
+    expiry: Duration,
+}
+
+impl Default for OrphanBlockPool {
+    fn default() -> Self {
+        Self::new(
+            MAX_ORPHAN_BLOCKS,
+            MAX_ORPHAN_BLOCKS_PER_PEER,
+            ORPHAN_BLOCK_EXPIRY,
+        )
+    }
+}
+
+impl OrphanBlockPool {
+    pub fn new(max_blocks: usize, max_blocks_per_peer: usize, expiry: Duration) -> Self {
+        Self {
+            blocks: Default::default(),
+            by_parent: Default::default(),
+            by_peer: Default::default(),
+            requested: Default::default(),
+            max_blocks,
+            max_blocks_per_peer,
+            expiry,
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.blocks.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.blocks.is_empty()
+    }
+
+    pub fn contains(&self, hash: &H256) -> bool {
+        self.blocks.contains_key(hash)
+    }
+
+    /// Adds an orphan announced by `peer_id`, returns false if the block was already pooled or
+    /// the peer has reached its limit. When the pool is full the oldest orphan is evicted.
+    pub fn insert(&mut self, peer_id: String, block: Block) -> bool {
+        self.expire();
+        let hash = block.hash();
+        if self.blocks.contains_key(&hash) || self.max_blocks == 0 {
+            return false;
+        }
+        if self.by_peer.get(&peer_id).copied().unwrap_or_default() >= self.max_blocks_per_peer {
+            return false;
+        }
+        if self.blocks.len() >= self.max_blocks {
+            let oldest = self
+                .blocks
+                .iter()
+                .min_by_key(|(_, orphan)| orphan.received_at)
+                .map(|(hash, _)| *hash);
+            if let Some(oldest) = oldest {
+                self.remove(&oldest);
+            }
+        }
+        self.by_parent
+            .entry(*block.parent_hash())
+            .or_default()
+            .insert(hash);
+        *self.by_peer.entry(peer_id.clone()).or_default() += 1;
+        self.blocks.insert(
+            hash,
+            OrphanBlock {
+                block,
+                peer_id,
+                received_at: Instant::now(),
+            },
+        );
+        true
+    }
+
+    pub fn remove(&mut self, hash: &H256) -> Option<(String, Block)> {
+        let orphan = self.blocks.remove(hash)?;
+        let parent_hash = *orphan.block.parent_hash();
+        if let Some(children) = self.by_parent.get_mut(&parent_hash) {
+            children.remove(hash);
+            if children.is_empty() {
+                self.by_parent.remove(&parent_hash);
+            }
+        }
+        if let Some(count) = self.by_peer.get_mut(&orphan.peer_id) {
+            *count = count.saturating_sub(1);
+            if *count == 0 {
+                self.by_peer.remove(&orphan.peer_id);
+            }
+        }
+        Some((orphan.peer_id, orphan.block))
+    }
+
+    /// Removes and returns the orphans whose parent is `parent_hash`.
+    pub fn take_children(&mut self, parent_hash: &H256) -> Vec<(String, Block)> {
+        self.requested.remove(parent_hash);
+        let children = match self.by_parent.get(parent_hash) {
+            None => return Vec::new(),
+            Some(children) => children.iter().copied().collect::<Vec<_>>(),
+        };
+        children
+            .iter()
+            .filter_map(|hash| self.remove(hash))
+            .collect()
+    }
+
+    /// Follows the parents of `hash` through the pool and returns the first ancestor that is
+    /// not an orphan, this is the block that must be requested to connect the branch.
+    pub fn missing_ancestor(&self, hash: &H256) -> H256 {
+        let mut hash = *hash;
+        while let Some(orphan) = self.blocks.get(&hash) {
+            hash = *orphan.block.parent_hash();
+        }
+        hash
+    }
+
+    /// Records a request for `hash`, returns false if one is already in flight.
+    pub fn mark_requested(&mut self, hash: H256) -> bool {
+        let now = Instant::now();
+        match self.requested.get(&hash) {
+            Some(requested_at) if now.duration_since(*requested_at) < self.expiry => false,
+            _ => {
+                self.requested.insert(hash, now);
+                true
+            }
+        }
+    }
+
+    pub fn is_requested(&self, hash: &H256) -> bool {
+        self.requested.contains_key(hash)
+    }
+
+    pub fn clear_request(&mut self, hash: &H256) {
+        self.requested.remove(hash);
+    }
+
+    pub fn expire(&mut self) {
+        let now = Instant::now();
+        let expiry = self.expiry;
+        let expired: Vec<_> = self
+            .blocks
+            .iter()
+            .filter(|(_, orphan)| now.duration_since(orphan.received_at) >= expiry)
+            .map(|(hash, _)| *hash)
+            .collect();
+        for hash in expired.iter() {
+            self.remove(hash);
+        }
+        self.requested
+            .retain(|_, requested_at| now.duration_since(*requested_at) < expiry);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use types::block::BlockHeader;
+
+    use super::*;
+
+    fn make_block(parent_hash: H256, level: u32) -> Block {
+        let header = BlockHeader::new(
+            parent_hash,
+            H256::zero(),
+            H256::zero(),
+            H256::zero(),
+            Default::default(),
+            Default::default(),
+            0,
+            0,
+            level,
+            0,
+            0,
+        );
+        Block::new(header, Vec::new())
+    }
+
+    #[test]
+    fn test_take_children_and_missing_ancestor() {
+        let mut pool = OrphanBlockPool::default();
+        let missing = H256::from_low_u64_be(1);
+        let a = make_block(missing, 1);
+        let b = make_block(a.hash(), 2);
+        assert!(pool.insert("peer".to_string(), b.clone()));
+        assert!(pool.insert("peer".to_string(), a.clone()));
+        assert!(!pool.insert("peer".to_string(), a.clone()));
+        assert_eq!(pool.missing_ancestor(&b.hash()), missing);
+
+        let children = pool.take_children(&missing);
+        assert_eq!(children.len(), 1);
+        assert_eq!(children[0].1.hash(), a.hash());
+        assert_eq!(pool.take_children(&a.hash())[0].1.hash(), b.hash());
+        assert!(pool.is_empty());
+    }
+
+    #[test]
+    fn test_limits_and_expiry() {
+        let mut pool = OrphanBlockPool::new(2, 1, ORPHAN_BLOCK_EXPIRY);
+        let parent = H256::from_low_u64_be(1);
+        assert!(pool.insert("a".to_string(), make_block(parent, 1)));
+        assert!(!pool.insert("a".to_string(), make_block(parent, 2)));
+        assert!(pool.insert("b".to_string(), make_block(parent, 3)));
+        assert!(pool.insert("c".to_string(), make_block(parent, 4)));
+        assert_eq!(pool.len(), 2);
+
+        let mut pool = OrphanBlockPool::new(2, 2, Duration::ZERO);
+        assert!(pool.insert("a".to_string(), make_block(parent, 1)));
+        pool.expire();
+        assert!(pool.is_empty());
+    }
+}
@@ -0,0 +1,334 @@
+use std::collections::HashMap;
+use std::fs::{File, OpenOptions};
+use std::io::{BufReader, BufWriter, Read, Write};
+use std::sync::Arc;
+
+use anyhow::{anyhow, ensure, Context, Result};
+
+use blockchain::blockchain::Chain;
+use codec::{Decodable, Encodable};
+use consensus::barossa::BarossaProtocol;
+use consensus::constants::RETARGETING_INTERVAL;
+use primitive_types::{H256, U256};
+use state::snapshot::StateSnapshot;
+use traits::{ChainHeadReader, ChainReader, Consensus};
+use types::block::{Block, IndexedBlockHeader};
+
+use crate::chain_io::{event_sink, read_record, write_record, MAX_BLOCK_RECORD_SIZE};
+use crate::node::{open_blockchain, open_main_storage, setup_chain_environment};
+use crate::{SnapshotExportArgs, SnapshotImportArgs};
+
+const SNAPSHOT_MAGIC: &[u8; 8] = b"ODNSNAP2";
+/// Size of an encoded total work
+const U256_SIZE: usize = 32;
+/// Largest encoded state accepted from a snapshot
+const MAX_STATE_RECORD_SIZE: usize = 1 << 30;
+
+/// Identifies the block a snapshot was taken at, written after the magic bytes
+struct SnapshotHeader {
+    chain_id: u32,
+    level: u32,
+    block_hash: H256,
+    state_root: H256,
+    block_count: u32,
+}
+
+impl SnapshotHeader {
+    fn write<W: Write>(&self, out: &mut W) -> Result<()> {
+        out.write_all(&self.chain_id.to_be_bytes())?;
+        out.write_all(&self.level.to_be_bytes())?;
+        out.write_all(self.block_hash.as_bytes())?;
+        out.write_all(self.state_root.as_bytes())?;
+        out.write_all(&self.block_count.to_be_bytes())?;
+        Ok(())
+    }
+
+    fn read<R: Read>(reader: &mut R) -> Result<Self> {
+        let mut buf = [0; 4 + 4 + 32 + 32 + 4];
+        reader
+            .read_exact(&mut buf)
+            .context("truncated snapshot header")?;
+        let u32_at =
+            |at: usize| u32::from_be_bytes([buf[at], buf[at + 1], buf[at + 2], buf[at + 3]]);
+        Ok(Self {
+            chain_id: u32_at(0),
+            level: u32_at(4),
+            block_hash: H256::from_slice(&buf[8..40]),
+            state_root: H256::from_slice(&buf[40..72]),
+            block_count: u32_at(72),
+        })
+    }
+}
+
+/// Writes the state at a canonical block with genesis and the ancestors consensus reads when
+/// validating the following blocks
+pub(crate) fn export_snapshot(args: &SnapshotExportArgs) -> Result<()> {
+    let env = setup_chain_environment(&args.env)?;
+    let (_, blockchain) = open_blockchain(&env, event_sink())?;
+    let chain_state = blockchain.chain_state();
+    let block_storage = chain_state.block_storage();
+    let current_head = chain_state
+        .current_header()?
+        .ok_or_else(|| anyhow!("current head not found"))?;
+    let level = args.level.map_or(current_head.raw.level, |level| {
+        level.min(current_head.raw.level)
+    });
+    let head = block_storage
+        .get_header_by_level(level)?
+        .ok_or_else(|| anyhow!("block at level {} not found", level))?;
+    let state = chain_state
+        .state()
+        .snapshot_at(head.raw.state_root)
+        .with_context(|| {
+            format!(
+                "state at level {} is not available, it may be pruned",
+                level
+            )
+        })?;
+
+    let first_level = level.saturating_sub(RETARGETING_INTERVAL).max(1);
+    let levels: Vec<u32> = std::iter::once(0).chain(first_level..=level).collect();
+    let header = SnapshotHeader {
+        chain_id: head.raw.chain_id,
+        level,
+        block_hash: head.hash,
+        state_root: head.raw.state_root,
+        block_count: levels.len() as u32,
+    };
+
+    let file = OpenOptions::new()
+        .write(true)
+        .create_new(true)
+        .open(&args.out)?;
+    let mut out = BufWriter::new(file);
+    out.write_all(SNAPSHOT_MAGIC)?;
+    header.write(&mut out)?;
+    let mut checkpoint_work = None;
+    for level in levels {
+        let block = block_storage
+            .get_block_by_level(level)?
+            .ok_or_else(|| anyhow!("block at level {} not found", level))?;
+        let total_work = block_storage
+            .get_total_work(&block.hash())?
+            .unwrap_or_default();
+        // The total work of the first block after a gap is trusted by the importing node
+        if level == first_level && first_level > 1 {
+            checkpoint_work = Some(total_work);
+        }
+        write_record(&mut out, &block.encode()?)?;
+        write_record(&mut out, &total_work.encode()?)?;
+    }
+    write_record(&mut out, &state.encode()?)?;
+    out.flush()?;
+    println!(
+        "Exported snapshot at level {} block {:?} to {:?}",
+        level, head.hash, args.out
+    );
+    if let Some(checkpoint_work) = checkpoint_work {
+        println!("Pass --checkpoint-work {} to import it", checkpoint_work);
+    }
+    Ok(())
+}
+
+/// Boots an empty data directory from a snapshot written by [`export_snapshot`]
+pub(crate) fn import_snapshot(args: &SnapshotImportArgs) -> Result<()> {
+    let env = setup_chain_environment(&args.env)?;
+    let chainspec = env.chainspec()?;
+    let mut reader = BufReader::new(File::open(&args.file)?);
+    let mut magic = [0; 8];
+    reader.read_exact(&mut magic)?;
+    ensure!(
+        &magic == SNAPSHOT_MAGIC,
+        "{:?} is not a snapshot",
+        args.file
+    );
+    let header = SnapshotHeader::read(&mut reader)?;
+    ensure!(
+        header.chain_id == chainspec.chain_id,
+        "snapshot chain id {} does not match chainspec chain id {}",
+        header.chain_id,
+        chainspec.chain_id
+    );
+
+    let mut blocks = Vec::new();
+    for _ in 0..header.block_count {
+        let block =
+            read_record(&mut reader, MAX_BLOCK_RECORD_SIZE)?.context("truncated snapshot block")?;
+        let total_work =
+            read_record(&mut reader, U256_SIZE)?.context("truncated snapshot block")?;
+        blocks.push((Block::decode(&block)?, U256::decode(&total_work)?));
+    }
+    let state =
+        read_record(&mut reader, MAX_STATE_RECORD_SIZE)?.context("truncated snapshot state")?;
+    let state = StateSnapshot::decode(&state)?;
+
+    // The genesis state can not be recomputed from the snapshot, the rest of the header must
+    // match the chainspec
+    let (genesis, _) = blocks
+        .first()
+        .ok_or_else(|| anyhow!("snapshot contains no blocks"))?;
+    let mut expected_genesis = chainspec.genesis_header();
+    expected_genesis.state_root = genesis.header().state_root;
+    ensure!(
+        genesis.hash() == expected_genesis.hash(),
+        "snapshot genesis {:?} does not match the chainspec",
+        genesis.hash()
+    );
+    let (head, _) = &blocks[blocks.len() - 1];
+    ensure!(
+        head.hash() == header.block_hash
+            && head.level() == header.level
+            && state.root == header.state_root,
+        "snapshot content does not match its header"
+    );
+    let consensus = BarossaProtocol::from_chainspec(&chainspec)?;
+    if let Some((hash, total_work)) = verify_headers(&consensus, &blocks)? {
+        let checkpoint_work = args.checkpoint_work.as_deref().ok_or_else(|| {
+            anyhow!(
+                "total work {} of snapshot block {:?} can not be verified, pass it with \
+                 --checkpoint-work if it is trusted",
+                total_work,
+                hash
+            )
+        })?;
+        let checkpoint_work = U256::from_dec_str(checkpoint_work)
+            .map_err(|e| anyhow!("invalid checkpoint work: {:?}", e))?;
+        ensure!(
+            checkpoint_work == total_work,
+            "snapshot block {:?} has total work {}, expected checkpoint work {}",
+            hash,
+            total_work,
+            checkpoint_work
+        );
+    }
+
+    let head = Chain::import_snapshot(
+        env.datadir.clone(),
+        open_main_storage(&env)?,
+        blocks,
+        state,
+        chainspec.tree_hasher,
+    )?;
+    println!(
+        "Imported snapshot at level {} block {:?}",
+        head.level,
+        head.hash()
+    );
+    Ok(())
+}
+
+/// Headers of the blocks of a snapshot, read by consensus when checking them
+struct SnapshotHeaders {
+    by_hash: HashMap<H256, IndexedBlockHeader>,
+    by_level: HashMap<u32, H256>,
+}
+
+impl SnapshotHeaders {
+    fn new(blocks: &[(Block, U256)]) -> Self {
+        let mut by_hash = HashMap::new();
+        let mut by_level = HashMap::new();
+        for (block, _) in blocks {
+            let header: IndexedBlockHeader = (*block.header()).into();
+            by_level.insert(header.raw.level, header.hash);
+            by_hash.insert(header.hash, header);
+        }
+        Self { by_hash, by_level }
+    }
+}
+
+impl ChainHeadReader for SnapshotHeaders {
+    fn get_header(&self, hash: &H256, level: u32) -> Result<Option<IndexedBlockHeader>> {
+        Ok(self
+            .by_hash
+            .get(hash)
+            .filter(|header| header.raw.level == level)
+            .cloned())
+    }
+
+    fn get_header_by_hash(&self, hash: &H256) -> Result<Option<IndexedBlockHeader>> {
+        Ok(self.by_hash.get(hash).cloned())
+    }
+
+    fn get_header_by_level(&self, level: u32) -> Result<Option<IndexedBlockHeader>> {
+        Ok(self
+            .by_level
+            .get(&level)
+            .and_then(|hash| self.by_hash.get(hash))
+            .cloned())
+    }
+}
+
+/// Checks the headers of the snapshot blocks after genesis the way block import does, without
+/// executing their transactions. The difficulty, timestamp and proof of work of a block are
+/// verified against the snapshot blocks before it, and its total work is its parent's plus its
+/// own proof. The first block of a snapshot that does not reach back to genesis has no parent to
+/// be checked against, only its proof of work is. It is returned with its total work, which the
+/// caller has to trust as a checkpoint.
+fn verify_headers(
+    consensus: &BarossaProtocol,
+    blocks: &[(Block, U256)],
+) -> Result<Option<(H256, U256)>> {
+    let headers = Arc::new(SnapshotHeaders::new(blocks));
+    let total_works: HashMap<H256, U256> = blocks
+        .iter()
+        .map(|(block, total_work)| (block.hash(), *total_work))
+        .collect();
+    let (genesis, genesis_work) = &blocks[0];
+    ensure!(
+        *genesis_work == consensus.block_proof(genesis.header()),
+        "snapshot genesis has total work {}, expected its proof",
+        genesis_work
+    );
+    let mut checkpoint = None;
+    for (block, total_work) in blocks.iter().skip(1) {
+        let header = block.header();
+        let hash = block.hash();
+        ensure!(
+            header.chain_id == consensus.network().chain_id(),
+            "snapshot block {:?} belongs to chain {}",
+            hash,
+            header.chain_id
+        );
+        let Some(parent_work) = total_works.get(&header.parent_hash) else {
+            ensure!(
+                checkpoint.is_none(),
+                "snapshot block {:?} does not extend the block before it",
+                hash
+            );
+            ensure!(
+                crypto::is_valid_proof_of_work(
+                    consensus.max_difficulty_compact(),
+                    header.difficulty(),
+                    &hash
+                ),
+                "snapshot block {:?} has an invalid proof of work",
+                hash
+            );
+            checkpoint = Some((hash, *total_work));
+            continue;
+        };
+        ensure!(
+            parent_work.checked_add(consensus.block_proof(header)) == Some(*total_work),
+            "snapshot block {:?} has an invalid total work {}",
+            hash,
+            total_work
+        );
+        // A retarget reads the block an interval back, which can be older than the snapshot
+        let retarget_level = (header.level + 1).checked_sub(RETARGETING_INTERVAL);
+        if !consensus.is_retarget_height(header.level + 1)
+            || retarget_level.is_some_and(|level| headers.by_level.contains_key(&level))
+        {
+            let mut expected = *header;
+            consensus.prepare_header(headers.clone(), &mut expected)?;
+            ensure!(
+                expected.difficulty == header.difficulty,
+                "snapshot block {:?} has an invalid difficulty",
+                hash
+            );
+        }
+        consensus
+            .verify_header(headers.clone(), header)
+            .with_context(|| format!("snapshot block {:?} is invalid", hash))?;
+    }
+    Ok(checkpoint)
+}
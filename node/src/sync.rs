@@ -1,6 +1,7 @@
 use std::cmp::Ordering;
 use std::collections::{BTreeMap, BTreeSet, HashMap};
 use std::fmt::Debug;
+use std::str::FromStr;
 use std::sync::{Arc, RwLock};
 
 use anyhow::{anyhow, ensure, Result};
@@ -8,7 +9,12 @@ use tokio::sync::mpsc::UnboundedSender;
 
 use blockchain::block_storage::BlockStorage;
 use blockchain::chain_state::ChainState;
-use p2p::message::{BlocksMessage, FindBlocksMessage, Msg, NodeToPeerMessage};
+use blockchain::errors::BlockChainError;
+use p2p::message::{
+    BlocksMessage, FindBlocksMessage, GetBlocksMessage, Msg, NodeToPeerMessage, PeerToNodeMessage,
+};
+use p2p::peer_manager::NetworkState;
+use p2p::PeerId;
 use primitive_types::H256;
 use tracing::{debug, warn};
 use traits::{Blockchain, ChainReader, Consensus, Handler};
@@ -17,6 +23,7 @@ use types::block::{Block, BlockHeader};
 use types::events::LocalEventMessage;
 
 use crate::error::NodeError;
+use crate::orphan_pool::OrphanBlockPool;
 
 #[derive(Debug, Eq, PartialEq, Clone)]
 pub enum SyncMode {
@@ -75,19 +82,156 @@ pub struct SyncService {
     highest_peer: String,
     sender: Arc<UnboundedSender<NodeToPeerMessage>>,
     tip_before_sync: Option<(String, BlockHeader)>,
+    orphan_pool: OrphanBlockPool,
+    network_state: Arc<NetworkState>,
 }
 
 impl SyncService {
-    pub fn handle_remote_message(&mut self, msg: Msg) -> Result<()> {
+    pub fn handle_remote_message(&mut self, peer_id: String, msg: Msg) -> Result<()> {
         match msg {
-            Msg::Blocks(msg) => self.handle_import_blocks(&msg),
+            Msg::Blocks(msg) if self.is_orphan_parents_response(&msg) => {
+                self.handle_orphan_parents(peer_id, msg.blocks)
+            }
+            Msg::Blocks(msg) => self.handle_import_blocks(&peer_id, &msg),
             Msg::BroadcastTransaction(_) => Ok(()),
             Msg::BroadcastBlock(_) => Ok(()),
             _ => Ok(()),
         }
     }
 
-    fn handle_import_blocks(&mut self, msg: &BlocksMessage) -> Result<()> {
+    /// Pools a block whose parent is unknown and asks the announcing peer for the missing
+    /// ancestor.
+    pub fn add_orphan(&mut self, peer_id: String, block: Block) -> Result<()> {
+        let hash = block.hash();
+        if !self.orphan_pool.insert(peer_id.clone(), block) {
+            return Ok(());
+        }
+        debug!(target: "sync", block = ?hash, peer = ?peer_id, orphans = self.orphan_pool.len(), "Added orphan block");
+        let missing = self.orphan_pool.missing_ancestor(&hash);
+        if self.orphan_pool.mark_requested(missing) {
+            let sent = self.sender.send(NodeToPeerMessage {
+                peer_id: Some(peer_id),
+                message: Msg::GetBlocks(GetBlocksMessage::new(vec![missing])),
+            });
+            if sent.is_err() {
+                // Let the next orphan with the same missing ancestor request it again
+                self.orphan_pool.clear_request(&missing);
+                return Err(anyhow!("failed to request missing ancestor {:?}", missing));
+            }
+        }
+        Ok(())
+    }
+
+    /// Validates and imports a gossiped block, pooling it if its parent is unknown. Returns true
+    /// if the block became our new tip.
+    pub fn import_broadcast_block(&mut self, peer_id: String, block: Block) -> Result<bool> {
+        let hash = block.hash();
+        if self.orphan_pool.contains(&hash)
+            || self.block_storage.get_block_by_hash(&hash)?.is_some()
+        {
+            return Ok(false);
+        }
+        if self
+            .block_storage
+            .get_header_by_hash(block.parent_hash())?
+            .is_none()
+        {
+            self.add_orphan(peer_id, block)?;
+            return Ok(false);
+        }
+        self.chain.put_chain(
+            self.consensus.clone(),
+            Box::new(std::iter::once(block)),
+            self.txpool.clone(),
+        )?;
+        let extends_tip = self
+            .chain
+            .current_header()?
+            .map(|header| header.hash == hash)
+            .unwrap_or(false);
+        self.connect_orphans(hash)?;
+        Ok(extends_tip)
+    }
+
+    /// Holds a block rejected as invalid against the peer that sent it, a block that could not
+    /// be processed, e.g. on a storage error, is not the peer's fault
+    pub fn report_rejected_block(&self, peer_id: &str, error: &anyhow::Error) {
+        if !BlockChainError::is_invalid_block(error) {
+            return;
+        }
+        let Ok(peer) = PeerId::from_str(peer_id) else {
+            return;
+        };
+        if self.network_state.report_invalid_block(&peer) {
+            warn!(target: "sync", peer = ?peer_id, "Banned peer for sending invalid blocks");
+        }
+    }
+
+    /// Connects pooled orphans descending from `parent_hash` now that it has been accepted.
+    pub fn connect_orphans(&mut self, parent_hash: H256) -> Result<()> {
+        let mut parents = vec![parent_hash];
+        while let Some(parent_hash) = parents.pop() {
+            for (peer_id, block) in self.orphan_pool.take_children(&parent_hash) {
+                let hash = block.hash();
+                match self.chain.put_chain(
+                    self.consensus.clone(),
+                    Box::new(std::iter::once(block)),
+                    self.txpool.clone(),
+                ) {
+                    Ok(_) => {
+                        debug!(target: "sync", block = ?hash, "Connected orphan block");
+                        parents.push(hash);
+                    }
+                    Err(error) => {
+                        warn!(target: "sync", block = ?hash, peer = ?peer_id, error = ?error, "Rejected orphan block");
+                        self.report_rejected_block(&peer_id, &error);
+                    }
+                }
+            }
+        }
+        Ok(())
+    }
+
+    fn is_orphan_parents_response(&self, msg: &BlocksMessage) -> bool {
+        !msg.blocks.is_empty()
+            && msg
+                .blocks
+                .iter()
+                .all(|block| self.orphan_pool.is_requested(&block.hash()))
+    }
+
+    fn handle_orphan_parents(&mut self, peer_id: String, blocks: Vec<Block>) -> Result<()> {
+        for block in blocks {
+            let hash = block.hash();
+            self.orphan_pool.clear_request(&hash);
+            if self.block_storage.get_block_by_hash(&hash)?.is_some() {
+                self.connect_orphans(hash)?;
+                continue;
+            }
+            if self
+                .block_storage
+                .get_header_by_hash(block.parent_hash())?
+                .is_none()
+            {
+                self.add_orphan(peer_id.clone(), block)?;
+                continue;
+            }
+            match self.chain.put_chain(
+                self.consensus.clone(),
+                Box::new(std::iter::once(block)),
+                self.txpool.clone(),
+            ) {
+                Ok(_) => self.connect_orphans(hash)?,
+                Err(error) => {
+                    warn!(target: "sync", block = ?hash, peer = ?peer_id, error = ?error, "Rejected orphan parent block");
+                    self.report_rejected_block(&peer_id, &error);
+                }
+            }
+        }
+        Ok(())
+    }
+
+    fn handle_import_blocks(&mut self, peer_id: &str, msg: &BlocksMessage) -> Result<()> {
         let blocks_to_import = &msg.blocks;
         let node_head = self.chain.current_header_blocking().unwrap();
         let node_level = node_head.map(|block| block.raw.level).unwrap();
@@ -136,11 +280,18 @@ impl SyncService {
         };
         if has_common_ancestor {
             self.finder_multiplier = 1;
-            self.chain.put_chain(
+            let imported: Vec<_> = ordered_blocks.iter().map(|ob| ob.0.hash()).collect();
+            if let Err(error) = self.chain.put_chain(
                 self.consensus.clone(),
                 Box::new(ordered_blocks.into_iter().map(|ob| ob.0)),
                 self.txpool.clone(),
-            )?;
+            ) {
+                self.report_rejected_block(peer_id, &error);
+                return Err(error);
+            }
+            for hash in imported {
+                self.connect_orphans(hash)?;
+            }
             self.sync_mode = Arc::new(SyncMode::Forward);
             let node_head = self.chain.current_header().unwrap();
             let node_level = node_head.map(|block| block.raw.level).unwrap();
@@ -200,9 +351,9 @@ impl Handler<LocalEventMessage> for SyncService {
     }
 }
 
-impl Handler<Msg> for SyncService {
-    fn handle(&mut self, msg: Msg) {
-        match self.handle_remote_message(msg) {
+impl Handler<PeerToNodeMessage> for SyncService {
+    fn handle(&mut self, msg: PeerToNodeMessage) {
+        match self.handle_remote_message(msg.peer_id, msg.message) {
             Ok(_) => {}
             Err(error) => {
                 warn!(target: "sync", error = ?error, "failed to handle remote message");
@@ -251,6 +402,7 @@ impl SyncService {
         consensus: Arc<dyn Consensus>,
         block_storage: Arc<BlockStorage>,
         sync_mode: Arc<SyncMode>,
+        network_state: Arc<NetworkState>,
     ) -> Self {
         let node_height = chain.current_header().unwrap();
         let node_height = node_height.map(|block| block.raw.level).unwrap();
@@ -267,6 +419,8 @@ impl SyncService {
             highest_peer: "".to_string(),
             sender,
             tip_before_sync: None,
+            orphan_pool: Default::default(),
+            network_state,
         }
     }
 
@@ -53,8 +53,8 @@ pub mod util;
 
 trait P2pEnvironment {
     fn p2p_address(&self) -> Multiaddr;
-    fn topic(&self) -> Sha256Topic;
-    fn p2p_pow_target(&self) -> Compact;
+    fn topic(&self) -> Result<Sha256Topic>;
+    fn p2p_pow_target(&self) -> Result<Compact>;
 }
 
 impl P2pEnvironment for EnvironmentConfig {
@@ -64,18 +64,19 @@ impl P2pEnvironment for EnvironmentConfig {
             .with(Protocol::Tcp(self.p2p_port()))
     }
 
-    fn topic(&self) -> Sha256Topic {
-        Sha256Topic::new(self.network())
+    fn topic(&self) -> Result<Sha256Topic> {
+        self.network().topic().map(Sha256Topic::new)
     }
 
-    fn p2p_pow_target(&self) -> Compact {
+    fn p2p_pow_target(&self) -> Result<Compact> {
         self.network().max_difficulty_compact()
     }
 }
 
 async fn config_network(
+    network_topic: Sha256Topic,
     node_identity: NodeIdentity,
-    p2p_to_node: UnboundedSender<Msg>,
+    p2p_to_node: UnboundedSender<PeerToNodeMessage>,
     network_state: Arc<NetworkState>,
 ) -> Result<Swarm<ChainNetworkBehavior>> {
     let auth_keys = libp2p::noise::Keypair::<X25519Spec>::new()
@@ -87,8 +88,6 @@ async fn config_network(
         .authenticate(NoiseConfig::xx(auth_keys).into_authenticated())
         .multiplex(libp2p::mplex::MplexConfig::new())
         .boxed();
-    //TODO get topic from config or network info
-    let network_topic = Sha256Topic::new("testnet");
     let mdns = Mdns::new(Default::default())
         .await
         .expect("Cannot create mdns");
@@ -159,13 +158,18 @@ pub async fn start_p2p_server(
     config: Arc<EnvironmentConfig>,
     node_identity: NodeIdentity,
     mut node_to_p2p: UnboundedReceiver<NodeToPeerMessage>,
-    p2p_to_node: UnboundedSender<Msg>,
+    p2p_to_node: UnboundedSender<PeerToNodeMessage>,
     peer_arg: Vec<String>,
     network_state: Arc<NetworkState>,
     request_handler: Arc<RequestHandler>,
 ) -> Result<()> {
-    let mut swarm =
-        config_network(node_identity.clone(), p2p_to_node, network_state.clone()).await?;
+    let mut swarm = config_network(
+        config.topic()?,
+        node_identity.clone(),
+        p2p_to_node,
+        network_state.clone(),
+    )
+    .await?;
 
     Swarm::listen_on(
         &mut swarm,
@@ -268,12 +272,21 @@ async fn handle_swam_event<T: std::fmt::Debug>(
             message,
             ..
         })) => {
+            if network_state.is_banned(&propagation_source) {
+                if swarm.disconnect_peer_id(propagation_source).is_err() {
+                    debug!(peer_id = ?propagation_source, "Failed to disconnect banned peer");
+                }
+                return Ok(());
+            }
             if let Some(peer_message) = PeerMessage::decode(&message.data)?.msg {
                 if let Msg::CurrentHead(msg) = &peer_message {
                     network_state
                         .update_peer_current_head(&propagation_source, *msg.block_header()?)?;
                 }
-                swarm.behaviour_mut().p2p_to_node.send(peer_message)?;
+                swarm.behaviour_mut().p2p_to_node.send(PeerToNodeMessage {
+                    peer_id: propagation_source.to_string(),
+                    message: peer_message,
+                })?;
             }
         }
 
@@ -330,6 +343,15 @@ async fn handle_swam_event<T: std::fmt::Debug>(
             }
         }
 
+        SwarmEvent::Behaviour(OutEvent::RequestResponse(RequestResponseEvent::Message {
+            peer,
+            ..
+        })) if network_state.is_banned(&peer) => {
+            if swarm.disconnect_peer_id(peer).is_err() {
+                debug!(peer_id = ?peer, "Failed to disconnect banned peer");
+            }
+        }
+
         SwarmEvent::Behaviour(OutEvent::RequestResponse(RequestResponseEvent::Message {
             peer,
             message,
@@ -351,7 +373,10 @@ async fn handle_swam_event<T: std::fmt::Debug>(
             }
 
             RequestResponseMessage::Response { response, .. } => {
-                swarm.behaviour_mut().p2p_to_node.send(response)?;
+                swarm.behaviour_mut().p2p_to_node.send(PeerToNodeMessage {
+                    peer_id: peer.to_string(),
+                    message: response,
+                })?;
             }
         },
 
@@ -405,7 +430,7 @@ struct ChainNetworkBehavior {
     kad: Kademlia<MemoryStore>,
     requestresponse: RequestResponse<ChainP2pExchangeCodec>,
     #[behaviour(ignore)]
-    p2p_to_node: UnboundedSender<Msg>,
+    p2p_to_node: UnboundedSender<PeerToNodeMessage>,
     #[behaviour(ignore)]
     topic: Sha256Topic,
     #[behaviour(ignore)]
@@ -198,6 +198,12 @@ pub struct NodeToPeerMessage {
     pub message: Msg,
 }
 
+#[derive(Debug)]
+pub struct PeerToNodeMessage {
+    pub peer_id: String,
+    pub message: Msg,
+}
+
 impl Encodable for PeerMessage {
     fn encode(&self) -> anyhow::Result<Vec<u8>> {
         Ok(prost::Message::encode_to_vec(self))
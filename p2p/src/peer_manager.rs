@@ -1,5 +1,6 @@
 use std::collections::HashMap;
 use std::sync::{Arc, RwLock};
+use std::time::{Duration, Instant};
 
 use anyhow::Result;
 use dashmap::DashMap;
@@ -9,6 +10,22 @@ use tokio::sync::mpsc::UnboundedSender;
 use types::block::BlockHeader;
 use types::events::LocalEventMessage;
 
+/// Number of invalid blocks a peer may send within [`INVALID_BLOCK_WINDOW`] before it is banned
+pub const MAX_INVALID_BLOCKS_PER_PEER: u32 = 3;
+/// Time after the last invalid block of a peer at which its count starts over
+pub const INVALID_BLOCK_WINDOW: Duration = Duration::from_secs(10 * 60);
+/// Time a peer stays banned, blocks can also be rejected because of a local state error so a
+/// ban is not permanent
+pub const PEER_BAN_DURATION: Duration = Duration::from_secs(60 * 60);
+
+/// Invalid blocks recently received from a peer
+#[derive(Debug, Clone, Copy)]
+struct InvalidBlocks {
+    count: u32,
+    last: Instant,
+    banned_until: Option<Instant>,
+}
+
 #[derive(Debug, Clone)]
 pub struct PeerList {
     addrs: DashMap<Arc<PeerId>, Multiaddr>,
@@ -53,6 +70,7 @@ pub struct NetworkState {
     peer_list: Arc<PeerList>,
     peer_state: Arc<RwLock<HashMap<Arc<PeerId>, BlockHeader>>>,
     highest_know_head: RwLock<Option<Arc<PeerId>>>,
+    invalid_blocks: DashMap<PeerId, InvalidBlocks>,
     sender: UnboundedSender<LocalEventMessage>,
 }
 
@@ -62,6 +80,7 @@ impl NetworkState {
             peer_list: Arc::new(PeerList::new()),
             peer_state: Default::default(),
             highest_know_head: RwLock::default(),
+            invalid_blocks: Default::default(),
             sender,
         }
     }
@@ -149,6 +168,57 @@ impl NetworkState {
             .map_err(|e| anyhow::anyhow!("{}", e))
     }
 
+    /// Records an invalid block received from `peer_id`, returns true if the peer is now banned
+    pub fn report_invalid_block(&self, peer_id: &PeerId) -> bool {
+        self.report_invalid_block_at(peer_id, Instant::now())
+    }
+
+    /// Whether `peer_id` sent [`MAX_INVALID_BLOCKS_PER_PEER`] invalid blocks within
+    /// [`INVALID_BLOCK_WINDOW`] less than [`PEER_BAN_DURATION`] ago
+    pub fn is_banned(&self, peer_id: &PeerId) -> bool {
+        self.is_banned_at(peer_id, Instant::now())
+    }
+
+    fn report_invalid_block_at(&self, peer_id: &PeerId, now: Instant) -> bool {
+        let mut entry = self
+            .invalid_blocks
+            .entry(*peer_id)
+            .or_insert(InvalidBlocks {
+                count: 0,
+                last: now,
+                banned_until: None,
+            });
+        let expired = match entry.banned_until {
+            Some(banned_until) => now >= banned_until,
+            None => now.duration_since(entry.last) >= INVALID_BLOCK_WINDOW,
+        };
+        if expired {
+            entry.count = 0;
+            entry.banned_until = None;
+        }
+        entry.count += 1;
+        entry.last = now;
+        if entry.count >= MAX_INVALID_BLOCKS_PER_PEER && entry.banned_until.is_none() {
+            entry.banned_until = Some(now + PEER_BAN_DURATION);
+        }
+        entry.banned_until.is_some()
+    }
+
+    fn is_banned_at(&self, peer_id: &PeerId, now: Instant) -> bool {
+        let banned = match self.invalid_blocks.get(peer_id) {
+            Some(entry) => entry.banned_until.map(|banned_until| now < banned_until),
+            None => return false,
+        };
+        if banned == Some(false) {
+            // The ban expired, the peer starts over with no invalid blocks
+            self.invalid_blocks.remove_if(
+                peer_id,
+                |_, entry| matches!(entry.banned_until, Some(banned_until) if now >= banned_until),
+            );
+        }
+        banned.unwrap_or(false)
+    }
+
     pub fn highest_peer(&self) -> Option<String> {
         let highest_know_head = self.highest_know_head.read().unwrap();
         highest_know_head
@@ -176,11 +246,48 @@ impl NetworkState {
 
 #[cfg(test)]
 mod test {
+    use std::time::Instant;
+
+    use libp2p::PeerId;
+
     use crate::identity::NodeIdentity;
+    use crate::peer_manager::{
+        NetworkState, INVALID_BLOCK_WINDOW, MAX_INVALID_BLOCKS_PER_PEER, PEER_BAN_DURATION,
+    };
 
     #[test]
     fn check_pow() {
         let node_identity = NodeIdentity::generate();
         println!("Stramp {:#?}", node_identity.to_p2p_node());
     }
+
+    #[test]
+    fn test_peer_ban_expires() {
+        let (sender, _receiver) = tokio::sync::mpsc::unbounded_channel();
+        let network_state = NetworkState::new(sender);
+        let peer = PeerId::random();
+        let now = Instant::now();
+        for _ in 1..MAX_INVALID_BLOCKS_PER_PEER {
+            assert!(!network_state.report_invalid_block_at(&peer, now));
+        }
+        assert!(network_state.report_invalid_block_at(&peer, now));
+        assert!(network_state.is_banned_at(&peer, now + PEER_BAN_DURATION / 2));
+
+        let expired = now + PEER_BAN_DURATION;
+        assert!(!network_state.is_banned_at(&peer, expired));
+        assert!(!network_state.report_invalid_block_at(&peer, expired));
+    }
+
+    #[test]
+    fn test_invalid_blocks_decay() {
+        let (sender, _receiver) = tokio::sync::mpsc::unbounded_channel();
+        let network_state = NetworkState::new(sender);
+        let peer = PeerId::random();
+        let mut now = Instant::now();
+        for _ in 0..MAX_INVALID_BLOCKS_PER_PEER * 2 {
+            assert!(!network_state.report_invalid_block_at(&peer, now));
+            now += INVALID_BLOCK_WINDOW;
+        }
+        assert!(!network_state.is_banned_at(&peer, now));
+    }
 }
@@ -5,6 +5,7 @@ fn main() -> Result<(), Box<dyn std::error::Error>> {
         .extern_path(".odana.primitive_types", "::primitive_types")
         .compile(
             &[
+                &"../proto/rpc_common.proto".to_string(),
                 &"../proto/rpc_txs.proto".to_string(),
                 &"../proto/rpc_account.proto".to_string(),
                 &"../proto/rpc_chain.proto".to_string(),
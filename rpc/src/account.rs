@@ -3,19 +3,33 @@ use std::sync::{Arc, RwLock};
 use tonic::{Request, Response, Status};
 
 use crate::rpc::account_service_server::AccountService;
-use crate::rpc::{GetAccountBalanceResponse, GetAccountNonceResponse, GetAccountRequest};
-use traits::StateDB;
+use crate::rpc::{
+    GetAccountBalanceResponse, GetAccountNonceResponse, GetAccountProofRequest,
+    GetAccountProofResponse, GetAccountRequest,
+};
+use crate::{select_state, state_at_block};
+use codec::Encodable;
+use traits::{Blockchain, StateDB};
 use txpool::TxPool;
 use types::account::AccountState;
 
 pub(crate) struct AccountServiceImpl {
     state: Arc<dyn StateDB>,
     txpool: Arc<RwLock<TxPool>>,
+    blockchain: Arc<dyn Blockchain>,
 }
 
 impl AccountServiceImpl {
-    pub(crate) fn new(state: Arc<dyn StateDB>, txpool: Arc<RwLock<TxPool>>) -> Self {
-        Self { state, txpool }
+    pub(crate) fn new(
+        state: Arc<dyn StateDB>,
+        txpool: Arc<RwLock<TxPool>>,
+        blockchain: Arc<dyn Blockchain>,
+    ) -> Self {
+        Self {
+            state,
+            txpool,
+            blockchain,
+        }
     }
 }
 
@@ -25,11 +39,12 @@ impl AccountService for AccountServiceImpl {
         &self,
         request: Request<GetAccountRequest>,
     ) -> Result<Response<GetAccountBalanceResponse>, Status> {
-        let req = request.get_ref();
+        let req = request.into_inner();
         let address = req
             .address
             .ok_or_else(|| Status::unknown("failed to parse address"))?;
-        let balance = self.state.balance(&address);
+        let state = select_state(self.blockchain.as_ref(), &self.state, req.block)?;
+        let balance = state.balance(&address);
         Ok(Response::new(GetAccountBalanceResponse { balance }))
     }
 
@@ -41,8 +56,15 @@ impl AccountService for AccountServiceImpl {
         let address = req
             .address
             .ok_or_else(|| Status::unknown("failed to parse address"))?;
-        let txpool = self.txpool.read().unwrap();
-        let nonce = txpool.nonce(&address);
+        // Without a block selector the nonce includes pending transactions in the pool
+        let nonce = match req.block {
+            Some(block) if block.block.is_some() => {
+                state_at_block(self.blockchain.as_ref(), Some(block))?
+                    .1
+                    .nonce(&address)
+            }
+            _ => self.txpool.read().unwrap().nonce(&address),
+        };
         Ok(Response::new(GetAccountNonceResponse { nonce }))
     }
 
@@ -54,7 +76,31 @@ impl AccountService for AccountServiceImpl {
         let address = req
             .address
             .ok_or_else(|| Status::unknown("failed to parse address"))?;
-        let account_state = self.state.account_state(&address);
+        let state = select_state(self.blockchain.as_ref(), &self.state, req.block)?;
+        let account_state = state.account_state(&address);
         Ok(Response::new(account_state))
     }
+
+    async fn get_account_proof(
+        &self,
+        request: Request<GetAccountProofRequest>,
+    ) -> Result<Response<GetAccountProofResponse>, Status> {
+        let req = request.into_inner();
+        let address = req
+            .address
+            .ok_or_else(|| Status::invalid_argument("address not present in message"))?;
+        let (header, state) = state_at_block(self.blockchain.as_ref(), req.block)?;
+        let (account_state, proof) = state
+            .account_state_with_proof(&address)
+            .map_err(|e| Status::internal(e.to_string()))?;
+        let proof = proof
+            .encode()
+            .map_err(|e| Status::internal(e.to_string()))?;
+        Ok(Response::new(GetAccountProofResponse {
+            block_hash: Some(header.hash()),
+            state_root: Some(header.state_root),
+            account_state,
+            proof,
+        }))
+    }
 }
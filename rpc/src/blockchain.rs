@@ -2,13 +2,15 @@ use std::sync::Arc;
 
 use tonic::{Code, Request, Response, Status};
 
+use crate::header_at_block;
 use crate::rpc::chain_service_server::ChainService;
 use crate::rpc::{
     ChainInfo, CurrentHeadResponse, GetBlockByHashRequest, GetBlockByLevelRequest,
-    GetBlockNumberResponse, GetBlocksRequest, GetBlocksResponse,
+    GetBlockNumberResponse, GetBlocksRequest, GetBlocksResponse, GetStateDiffRequest,
 };
 use traits::Blockchain;
 use types::block::Block;
+use types::diff::StateDiff;
 
 pub(crate) struct ChainServiceImpl {
     blockchain: Arc<dyn Blockchain>,
@@ -91,13 +93,32 @@ impl ChainService for ChainServiceImpl {
     ) -> Result<Response<ChainInfo>, Status> {
         let current_head = self.current_head(request).await?;
         let current_head = current_head.get_ref().header.unwrap();
+        let total_work = self
+            .blockchain
+            .get_total_work(&current_head.hash())
+            .map_err(|_| Status::internal(""))?
+            .unwrap_or_default();
         let chain = ChainInfo {
             chain: self.blockchain.network().into(),
             genesis_hash: Some(self.blockchain.genesis().hash),
             difficulty: current_head.difficulty().into(),
             network_difficulty: 26,
             blocks: current_head.level,
+            total_work: Some(total_work),
         };
         Ok(Response::new(chain))
     }
+
+    async fn get_state_diff(
+        &self,
+        request: Request<GetStateDiffRequest>,
+    ) -> Result<Response<StateDiff>, Status> {
+        let header = header_at_block(self.blockchain.as_ref(), request.into_inner().block)?;
+        let diff = self
+            .blockchain
+            .get_block_state_diff(&header.hash(), header.level)
+            .map_err(|e| Status::internal(e.to_string()))?
+            .ok_or_else(|| Status::not_found(format!("State diff of block {}", header.hash())))?;
+        Ok(Response::new(diff))
+    }
 }
@@ -4,6 +4,7 @@ use std::sync::{Arc, RwLock};
 use anyhow::Result;
 use tokio::sync::mpsc::UnboundedSender;
 use tonic::transport::Server;
+use tonic::Status;
 
 use crate::rpc::account_service_server::AccountServiceServer;
 use crate::rpc::chain_service_server::ChainServiceServer;
@@ -11,12 +12,15 @@ use crate::rpc::transactions_service_server::TransactionsServiceServer;
 use tracing::info;
 use traits::{Blockchain, StateDB, WasmVMInstance};
 use txpool::TxPool;
+use types::block::BlockHeader;
 use types::config::EnvironmentConfig;
 use types::events::LocalEventMessage;
 
 use crate::account::AccountServiceImpl;
 use crate::blockchain::ChainServiceImpl;
+use crate::rpc::block_selector::Block;
 use crate::rpc::runtime_api_service_server::RuntimeApiServiceServer;
+use crate::rpc::BlockSelector;
 use crate::runtime::RuntimeApiServiceImpl;
 use crate::txs::TransactionsServiceImpl;
 
@@ -42,10 +46,11 @@ pub async fn start_rpc_server(
     let host = env.rpc_host();
     let port = env.rpc_port();
     let addr = SocketAddr::new(host.parse()?, port);
-    let chain_service = ChainServiceImpl::new(blockchain);
-    let account_service = AccountServiceImpl::new(state.clone(), txpool.clone());
-    let transaction_service = TransactionsServiceImpl::new(txpool, n2p_sender);
-    let rt_api_service = RuntimeApiServiceImpl::new(state, vm);
+    let chain_service = ChainServiceImpl::new(blockchain.clone());
+    let account_service =
+        AccountServiceImpl::new(state.clone(), txpool.clone(), blockchain.clone());
+    let rt_api_service = RuntimeApiServiceImpl::new(state, vm, blockchain.clone());
+    let transaction_service = TransactionsServiceImpl::new(blockchain, txpool, n2p_sender);
     info!(addr = ?addr, "RPC server running at");
     Server::builder()
         .add_service(ChainServiceServer::new(chain_service))
@@ -56,3 +61,55 @@ pub async fn start_rpc_server(
         .await?;
     Ok(())
 }
+
+/// Returns the selected header, or the current head when no block is selected
+pub(crate) fn header_at_block(
+    blockchain: &dyn Blockchain,
+    block: Option<BlockSelector>,
+) -> Result<BlockHeader, Status> {
+    let header = match block.and_then(|selector| selector.block) {
+        Some(Block::BlockHash(block_hash)) => blockchain
+            .get_block_by_hash(&block_hash)
+            .map_err(|e| Status::internal(e.to_string()))?
+            .map(|block| *block.header())
+            .ok_or_else(|| Status::not_found(format!("Block hash {}", block_hash)))?,
+        Some(Block::BlockLevel(level)) => blockchain
+            .get_block_by_level(level)
+            .map_err(|e| Status::internal(e.to_string()))?
+            .map(|block| *block.header())
+            .ok_or_else(|| Status::not_found(format!("Block level {}", level)))?,
+        None => blockchain
+            .current_header()
+            .map_err(|e| Status::internal(e.to_string()))?
+            .map(|header| header.raw)
+            .ok_or_else(|| Status::not_found("head not available"))?,
+    };
+    Ok(header)
+}
+
+/// Returns the selected header, or the current head when no block is selected, together with the
+/// state at that block
+pub(crate) fn state_at_block(
+    blockchain: &dyn Blockchain,
+    block: Option<BlockSelector>,
+) -> Result<(BlockHeader, Arc<dyn StateDB>), Status> {
+    let header = header_at_block(blockchain, block)?;
+    let state = blockchain
+        .get_state_at(&header.state_root)
+        .map_err(|e| Status::internal(e.to_string()))?;
+    Ok((header, state))
+}
+
+/// Returns the state at the selected block, or `live` when no block is selected
+pub(crate) fn select_state(
+    blockchain: &dyn Blockchain,
+    live: &Arc<dyn StateDB>,
+    block: Option<BlockSelector>,
+) -> Result<Arc<dyn StateDB>, Status> {
+    match block {
+        Some(selector) if selector.block.is_some() => {
+            state_at_block(blockchain, Some(selector)).map(|(_, state)| state)
+        }
+        _ => Ok(live.clone()),
+    }
+}
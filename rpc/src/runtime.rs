@@ -15,20 +15,34 @@
  */
 
 use crate::rpc::runtime_api_service_server::RuntimeApiService;
-use crate::rpc::{GetDescriptorRequest, GetDescriptorResponse, QueryResponse, QueryStorage};
+use crate::rpc::{
+    GetDescriptorRequest, GetDescriptorResponse, GetStorageProofRequest, GetStorageProofResponse,
+    Query, QueryResponse, QueryStorage,
+};
+use crate::{select_state, state_at_block};
+use codec::Encodable;
 use std::sync::Arc;
 use tonic::{Code, Request, Response, Status};
-use traits::{StateDB, WasmVMInstance};
+use traits::{Blockchain, StateDB, WasmVMInstance};
 use types::prelude::ApplicationCall;
 
 pub(crate) struct RuntimeApiServiceImpl {
     state: Arc<dyn StateDB>,
     vm: Arc<dyn WasmVMInstance>,
+    blockchain: Arc<dyn Blockchain>,
 }
 
 impl RuntimeApiServiceImpl {
-    pub(crate) fn new(state: Arc<dyn StateDB>, vm: Arc<dyn WasmVMInstance>) -> Self {
-        Self { state, vm }
+    pub(crate) fn new(
+        state: Arc<dyn StateDB>,
+        vm: Arc<dyn WasmVMInstance>,
+        blockchain: Arc<dyn Blockchain>,
+    ) -> Self {
+        Self {
+            state,
+            vm,
+            blockchain,
+        }
     }
 }
 
@@ -36,11 +50,20 @@ impl RuntimeApiServiceImpl {
 impl RuntimeApiService for RuntimeApiServiceImpl {
     async fn query_runtime(
         &self,
-        request: Request<ApplicationCall>,
+        request: Request<Query>,
     ) -> Result<Response<QueryResponse>, Status> {
-        let call = request.get_ref();
+        let query = request.into_inner();
+        let call = ApplicationCall {
+            app_id: query
+                .app_id
+                .ok_or_else(|| Status::invalid_argument("app_id not present in message"))?,
+            service: query.service,
+            method: query.method,
+            args: query.query,
+        };
+        let state = select_state(self.blockchain.as_ref(), &self.state, query.block)?;
         self.vm
-            .execute_app_query(self.state.clone(), call)
+            .execute_app_query(state, &call)
             .map(|data| Response::new(QueryResponse { data }))
             .map_err(|e| Status::new(Code::Unknown, format!("failed to execute query: {}", e)))
     }
@@ -56,13 +79,53 @@ impl RuntimeApiService for RuntimeApiServiceImpl {
         &self,
         request: Request<GetDescriptorRequest>,
     ) -> Result<Response<GetDescriptorResponse>, Status> {
-        let app_id = request
-            .get_ref()
+        let req = request.into_inner();
+        let app_id = req
             .app_id
             .ok_or_else(|| Status::new(Code::Unknown, "failed to obtain app address"))?;
+        let state = select_state(self.blockchain.as_ref(), &self.state, req.block)?;
         self.vm
-            .execute_get_descriptor(self.state.clone(), app_id)
+            .execute_get_descriptor(state, app_id)
             .map(|descriptor| Response::new(GetDescriptorResponse { descriptor }))
             .map_err(|e| Status::new(Code::Unknown, e.to_string()))
     }
+
+    async fn get_storage_proof(
+        &self,
+        request: Request<GetStorageProofRequest>,
+    ) -> Result<Response<GetStorageProofResponse>, Status> {
+        let req = request.into_inner();
+        let app_id = req
+            .app_id
+            .ok_or_else(|| Status::invalid_argument("app_id not present in message"))?;
+        let (header, state) = state_at_block(self.blockchain.as_ref(), req.block)?;
+        let (app_account_state, account_proof) = state
+            .account_state_with_proof(&app_id)
+            .map_err(|e| Status::internal(e.to_string()))?;
+        let root_hash = app_account_state
+            .as_ref()
+            .and_then(|account_state| account_state.app_state.as_ref())
+            .map(|app_state| app_state.root_hash)
+            .ok_or_else(|| Status::not_found(format!("app {} not found", app_id)))?;
+        let (value, storage_proof) = state
+            .get_app_data(app_id)
+            .and_then(|storage| {
+                storage
+                    .get_with_proof_for_root(&req.storage_key, &root_hash)
+                    .map_err(anyhow::Error::from)
+            })
+            .map_err(|e| Status::internal(e.to_string()))?;
+        Ok(Response::new(GetStorageProofResponse {
+            block_hash: Some(header.hash()),
+            state_root: Some(header.state_root),
+            app_account_state,
+            account_proof: account_proof
+                .encode()
+                .map_err(|e| Status::internal(e.to_string()))?,
+            value,
+            storage_proof: storage_proof
+                .encode()
+                .map_err(|e| Status::internal(e.to_string()))?,
+        }))
+    }
 }
@@ -5,29 +5,50 @@ use tonic::{Request, Response, Status};
 
 use crate::rpc::transactions_service_server::TransactionsService;
 use crate::rpc::{
-    AddressTransactionList, GetTransactionStatusResponse, PendingTransactionsResponse,
-    SignedTransactionResponse, TransactionHash, TransactionHashes, TxpoolContentResponse,
-    UnsignedTransactionRequest,
+    AddressTransactionList, GetTransactionByHashResponse, GetTransactionReceiptResponse,
+    GetTransactionStatusResponse, PendingTransactionsResponse, SignedTransactionResponse,
+    TransactionHash, TransactionHashes, TxpoolContentResponse, UnsignedTransactionRequest,
 };
 use primitive_types::H256;
 use tracing::warn;
+use traits::Blockchain;
 use txpool::TxPool;
 use types::account::get_address_from_secret_key;
+use types::block::TransactionLocation;
 use types::events::LocalEventMessage;
 use types::network::Network;
-use types::tx::SignedTransaction;
+use types::tx::{SignedTransaction, TransactionStatus};
 
 pub(crate) struct TransactionsServiceImpl {
+    blockchain: Arc<dyn Blockchain>,
     txpool: Arc<RwLock<TxPool>>,
     sender: UnboundedSender<LocalEventMessage>,
 }
 
 impl TransactionsServiceImpl {
     pub(crate) fn new(
+        blockchain: Arc<dyn Blockchain>,
         txpool: Arc<RwLock<TxPool>>,
         sender: UnboundedSender<LocalEventMessage>,
     ) -> Self {
-        Self { txpool, sender }
+        Self {
+            blockchain,
+            txpool,
+            sender,
+        }
+    }
+
+    fn transaction_location(
+        &self,
+        request: TransactionHash,
+    ) -> Result<TransactionLocation, Status> {
+        let tx_hash = request
+            .hash
+            .ok_or_else(|| Status::invalid_argument("hash not present in message"))?;
+        self.blockchain
+            .get_transaction_location(&tx_hash)
+            .map_err(|_| Status::internal(""))?
+            .ok_or_else(|| Status::not_found(format!("Transaction hash {}", tx_hash)))
     }
 }
 
@@ -43,11 +64,10 @@ impl TransactionsService for TransactionsServiceImpl {
         let mut tx = request
             .tx
             .ok_or_else(|| Status::invalid_argument("tx arg not found or failed to decode"))?;
-        let address = get_address_from_secret_key(
-            H256::from_slice(&secret_key),
-            Network::from_chain_id(tx.chain_id),
-        )
-        .map_err(|e| Status::internal(e.to_string()))?;
+        let network = Network::from_chain_id(tx.chain_id)
+            .map_err(|e| Status::invalid_argument(e.to_string()))?;
+        let address = get_address_from_secret_key(H256::from_slice(&secret_key), network)
+            .map_err(|e| Status::internal(e.to_string()))?;
         if tx.nonce == 0 {
             tx.nonce = txpool.nonce(&address);
         }
@@ -124,14 +144,73 @@ impl TransactionsService for TransactionsServiceImpl {
             .map(|tx_hash| H256::from_slice(tx_hash))
             .collect();
         let status: Vec<_> = txpool
-            .status(txs)
+            .status(txs.clone())
             .iter()
             .copied()
+            .zip(txs.iter())
+            .map(|(tx_status, tx_hash)| match tx_status {
+                TransactionStatus::NotFound
+                    if matches!(
+                        self.blockchain.get_transaction_location(tx_hash),
+                        Ok(Some(_))
+                    ) =>
+                {
+                    TransactionStatus::Confirmed
+                }
+                tx_status => tx_status,
+            })
             .map(|tx_status| tx_status as i32)
             .collect();
         Ok(Response::new(GetTransactionStatusResponse { status }))
     }
 
+    async fn get_transaction_by_hash(
+        &self,
+        request: Request<TransactionHash>,
+    ) -> Result<Response<GetTransactionByHashResponse>, Status> {
+        let location = self.transaction_location(request.into_inner())?;
+        let block = self
+            .blockchain
+            .get_block(&location.block_hash, location.level)
+            .map_err(|_| Status::internal(""))?
+            .ok_or_else(|| Status::not_found(format!("Block hash {}", location.block_hash)))?;
+        let tx = block
+            .transactions()
+            .get(location.index as usize)
+            .cloned()
+            .ok_or_else(|| Status::internal("transaction index out of range"))?;
+        Ok(Response::new(GetTransactionByHashResponse {
+            tx: Some(tx),
+            block_hash: Some(location.block_hash),
+            block_level: location.level,
+            index: location.index,
+        }))
+    }
+
+    async fn get_transaction_receipt(
+        &self,
+        request: Request<TransactionHash>,
+    ) -> Result<Response<GetTransactionReceiptResponse>, Status> {
+        let location = self.transaction_location(request.into_inner())?;
+        let receipts = self
+            .blockchain
+            .get_block_receipts(&location.block_hash, location.level)
+            .map_err(|_| Status::internal(""))?
+            .ok_or_else(|| {
+                Status::not_found(format!("Receipts for block {}", location.block_hash))
+            })?;
+        let receipt = receipts
+            .get(location.index as usize)
+            .cloned()
+            .ok_or_else(|| Status::internal("receipt index out of range"))?;
+        Ok(Response::new(GetTransactionReceiptResponse {
+            receipt: Some(receipt),
+            block_hash: Some(location.block_hash),
+            block_level: location.level,
+            index: location.index,
+        }))
+    }
+
     async fn get_pending_transactions(
         &self,
         _: Request<()>,
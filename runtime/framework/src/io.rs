@@ -55,6 +55,11 @@ pub trait StorageApi {
     fn delete(key: &[u8]) -> bool {
         internal::storage::remove(key)
     }
+    /// Up to `limit` entries whose key starts with `prefix`, sorted by key and starting at
+    /// `start` if set
+    fn scan(prefix: &[u8], start: Option<&[u8]>, limit: u32) -> Vec<(Vec<u8>, Vec<u8>)> {
+        internal::storage::scan(prefix, start, limit)
+    }
 }
 
 pub struct RawStorage;
@@ -65,6 +70,36 @@ pub trait StorageKeyPrefix {
     fn key_prefix() -> &'static [u8];
 }
 
+/// Entries a [`StorageMapIter`] reads from storage at once
+const SCAN_PAGE_SIZE: u32 = 64;
+
+fn put_entry<V: prost::Message>(storage_key: &[u8], value: V) {
+    let value = value.encode_to_vec();
+    internal::storage::insert(storage_key, value.as_slice())
+}
+
+fn get_entry<V: prost::Message + Default>(storage_key: &[u8]) -> Result<Option<V>> {
+    let Some(raw_value) = internal::storage::get(storage_key) else {
+        return Ok(None);
+    };
+    if raw_value.is_empty() {
+        return Ok(None);
+    }
+    let value = V::decode(raw_value.as_slice()).map_err(|e| anyhow::anyhow!("{}", e))?;
+    Ok(Some(value))
+}
+
+fn remove_entry(storage_key: &[u8]) -> Result<()> {
+    if !internal::storage::remove(storage_key) {
+        bail!("failed to delete key")
+    }
+    Ok(())
+}
+
+/// Map stored under the hash of its prefix and encoded key. Entries are spread over the storage
+/// and cannot be listed, use an [`IterableStorageMap`] for that.
+///
+/// The layout is part of the state of deployed apps, changing it would hide their entries.
 pub struct StorageMap<
     Prefix,
     H: StorageKeyHasher,
@@ -81,27 +116,17 @@ where
     K: prost::Message + Default,
     V: prost::Message + Default,
 {
-    pub fn put(key: K, value: V) {
-        let prefix = Prefix::key_prefix();
+    fn storage_key(key: &K) -> Box<[u8]> {
         let key = key.encode_to_vec();
-        let value = value.encode_to_vec();
-        let storage_key = [prefix, key.as_slice()].concat();
-        internal::storage::insert(&H::hash(storage_key.as_slice()), value.as_slice())
+        H::hash([Prefix::key_prefix(), key.as_slice()].concat().as_slice())
+    }
+
+    pub fn put(key: K, value: V) {
+        put_entry(&Self::storage_key(&key), value)
     }
 
     pub fn get(key: K) -> Result<Option<V>> {
-        let prefix = Prefix::key_prefix();
-        let key = key.encode_to_vec();
-        let storage_key = [prefix, key.as_slice()].concat();
-        let storage_key = H::hash(storage_key.as_slice());
-        let Some(raw_value) = internal::storage::get(storage_key.as_ref()) else {
-            return Ok(None)
-        };
-        if raw_value.is_empty() {
-            return Ok(None);
-        }
-        let value = V::decode(raw_value.as_slice()).map_err(|e| anyhow::anyhow!("{}", e))?;
-        Ok(Some(value))
+        get_entry(&Self::storage_key(&key))
     }
 
     pub fn contains(key: K) -> bool {
@@ -112,13 +137,114 @@ where
     }
 
     pub fn remove(key: K) -> Result<()> {
-        let prefix = Prefix::key_prefix();
+        remove_entry(&Self::storage_key(&key))
+    }
+}
+
+/// Map stored under the hash of its prefix followed by the encoded key, so its entries can be
+/// listed in the order of their encoded keys. Entries written before the chain recorded storage
+/// keys are not listed.
+///
+/// Its layout differs from the one of [`StorageMap`], a map of a deployed app cannot be turned
+/// into an iterable one without moving its entries.
+pub struct IterableStorageMap<
+    Prefix,
+    H: StorageKeyHasher,
+    K: prost::Message + Default,
+    V: prost::Message + Default,
+> {
+    inner: PhantomData<(Prefix, H, K, V)>,
+}
+
+impl<Prefix, H, K, V> IterableStorageMap<Prefix, H, K, V>
+where
+    Prefix: StorageKeyPrefix,
+    H: StorageKeyHasher,
+    K: prost::Message + Default,
+    V: prost::Message + Default,
+{
+    fn storage_key(key: &K) -> Vec<u8> {
         let key = key.encode_to_vec();
-        let storage_key = [prefix, key.as_slice()].concat();
-        if !internal::storage::remove(&H::hash(storage_key.as_slice())) {
-            bail!("failed to delete key")
+        [&*H::hash(Prefix::key_prefix()), key.as_slice()].concat()
+    }
+
+    pub fn put(key: K, value: V) {
+        put_entry(&Self::storage_key(&key), value)
+    }
+
+    pub fn get(key: K) -> Result<Option<V>> {
+        get_entry(&Self::storage_key(&key))
+    }
+
+    pub fn contains(key: K) -> bool {
+        if let Ok(res) = Self::get(key) {
+            return res.is_some();
+        }
+        false
+    }
+
+    pub fn remove(key: K) -> Result<()> {
+        remove_entry(&Self::storage_key(&key))
+    }
+
+    /// Every entry of the map sorted by encoded key
+    pub fn iter() -> StorageMapIter<K, V> {
+        StorageMapIter::new(H::hash(Prefix::key_prefix()), None)
+    }
+
+    /// Entries of the map starting at `start`, sorted by encoded key
+    pub fn iter_from(start: K) -> StorageMapIter<K, V> {
+        let start = Self::storage_key(&start);
+        StorageMapIter::new(H::hash(Prefix::key_prefix()), Some(start))
+    }
+}
+
+/// Entries of an [`IterableStorageMap`], read from storage a page at a time
+pub struct StorageMapIter<K, V> {
+    prefix: Box<[u8]>,
+    cursor: Option<Vec<u8>>,
+    page: alloc::vec::IntoIter<(Vec<u8>, Vec<u8>)>,
+    done: bool,
+    inner: PhantomData<(K, V)>,
+}
+
+impl<K, V> StorageMapIter<K, V> {
+    fn new(prefix: Box<[u8]>, cursor: Option<Vec<u8>>) -> Self {
+        Self {
+            prefix,
+            cursor,
+            page: Vec::new().into_iter(),
+            done: false,
+            inner: PhantomData,
+        }
+    }
+}
+
+impl<K, V> Iterator for StorageMapIter<K, V>
+where
+    K: prost::Message + Default,
+    V: prost::Message + Default,
+{
+    type Item = Result<(K, V)>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            if let Some((storage_key, raw_value)) = self.page.next() {
+                let entry = K::decode(&storage_key[self.prefix.len()..])
+                    .and_then(|key| Ok((key, V::decode(raw_value.as_slice())?)))
+                    .map_err(|e| anyhow::anyhow!("{}", e));
+                return Some(entry);
+            }
+            if self.done {
+                return None;
+            }
+            let page =
+                internal::storage::scan(&self.prefix, self.cursor.as_deref(), SCAN_PAGE_SIZE);
+            self.done = page.len() < SCAN_PAGE_SIZE as usize;
+            // The next page starts at the first key after the last one read
+            self.cursor = page.last().map(|(key, _)| [key.as_slice(), &[0]].concat());
+            self.page = page.into_iter();
         }
-        Ok(())
     }
 }
 
@@ -21,45 +21,23 @@ fn get_module_path(visibility: &syn::Visibility) -> String {
 
 #[proc_macro_attribute]
 pub fn storage_map(_attr: TokenStream, item: TokenStream) -> TokenStream {
-    if let Item::Type(type_item) = parse_macro_input!(item as Item) {
-        let ident = &type_item.ident;
-        let prefix = format!("{}::{}", get_module_path(&type_item.vis), ident);
-
-        // Generate a new struct name for the StorageKeyPrefix
-        let storage_key_prefix_ident = format_ident!("{}StorageKeyPrefix", ident);
-
-        //Extract the generics from the original type
-        let (replace_first_generic, generics) =
-            extract_generics(&type_item, &storage_key_prefix_ident);
-
-        // Only generate the new struct and the redefinition if the first generic is an underscore
-        if replace_first_generic {
-            let output = quote! {
-                pub struct #storage_key_prefix_ident;
-
-                impl StorageKeyPrefix for #storage_key_prefix_ident {
-                    fn key_prefix() -> &'static [u8] {
-                        #prefix.as_bytes()
-                    }
-                }
-
-                pub type #ident = ::rune_framework::prelude::StorageMap<#(#generics),*>;
-            };
+    storage_type(item, quote!(::rune_framework::prelude::StorageMap))
+}
 
-            output.into()
-        } else {
-            // If the first generic is not an underscore, just return the input
-            TokenStream::from(quote! {
-                #type_item
-            })
-        }
-    } else {
-        panic!("This macro can only be used with type alias");
-    }
+/// Like [`storage_map`] for a map whose entries can be listed in order, see
+/// `IterableStorageMap`
+#[proc_macro_attribute]
+pub fn iterable_storage_map(_attr: TokenStream, item: TokenStream) -> TokenStream {
+    storage_type(item, quote!(::rune_framework::prelude::IterableStorageMap))
 }
 
 #[proc_macro_attribute]
 pub fn storage_value(_attr: TokenStream, item: TokenStream) -> TokenStream {
+    storage_type(item, quote!(::rune_framework::prelude::StorageValue))
+}
+
+/// Redefines the type alias `item` as `storage` keyed by the path of the alias
+fn storage_type(item: TokenStream, storage: proc_macro2::TokenStream) -> TokenStream {
     if let Item::Type(type_item) = parse_macro_input!(item as Item) {
         let ident = &type_item.ident;
         let prefix = format!("{}::{}", get_module_path(&type_item.vis), ident);
@@ -82,7 +60,7 @@ pub fn storage_value(_attr: TokenStream, item: TokenStream) -> TokenStream {
                     }
                 }
 
-                pub type #ident = ::rune_framework::prelude::StorageValue<#(#generics),*>;
+                pub type #ident = #storage<#(#generics),*>;
             };
 
             output.into()
@@ -6,20 +6,25 @@ use crate::internal::syscall::Syscall;
 use anyhow::{anyhow, bail};
 use crypto::ecdsa::{PublicKey, SecretKey};
 use primitive_types::address::Address;
-use smt::SparseMerkleTree;
 use std::collections::HashMap;
 use std::sync::Arc;
 use traits::{ChainHeadReader, StateDB};
 use types::account::{get_address_from_pub_key, get_address_from_seed, AccountState};
+use types::app::AppStorage;
+use types::diff::StorageWrites;
 use types::network::Network;
 use types::{Addressing, Changelist};
 
+/// Most entries a single storage scan returns, apps page through larger ranges
+const MAX_SCAN_LIMIT: u32 = 256;
+
 pub struct ExecutionEnvironment {
     network: Network,
     sender: Address,
     app_address: Address,
     value: u64,
-    storage: SparseMerkleTree,
+    storage: AppStorage,
+    storage_writes: StorageWrites,
     state_db: Arc<dyn StateDB>,
     blockchain: Arc<dyn ChainHeadReader>,
     accounts: HashMap<Address, AccountState>,
@@ -31,13 +36,13 @@ impl ExecutionEnvironment {
         origin: Address,
         app_id: Address,
         value: u64,
-        storage: SparseMerkleTree,
+        storage: AppStorage,
         state_db: Arc<dyn StateDB>,
         blockchain: Arc<dyn ChainHeadReader>,
     ) -> anyhow::Result<ExecutionEnvironment> {
         let mut accounts = HashMap::new();
         let mut account_state = state_db.account_state(&app_id);
-        account_state.free_balance += value;
+        credit(&mut account_state.free_balance, value)?;
         accounts.insert(app_id, account_state);
 
         Ok(Self {
@@ -48,6 +53,7 @@ impl ExecutionEnvironment {
             app_address: app_id,
             value,
             storage,
+            storage_writes: StorageWrites::new(),
             state_db,
             blockchain,
             accounts,
@@ -64,6 +70,20 @@ impl ExecutionEnvironment {
     }
 }
 
+fn credit(balance: &mut u64, amount: u64) -> anyhow::Result<()> {
+    *balance = balance
+        .checked_add(amount)
+        .ok_or_else(|| anyhow!("balance overflow"))?;
+    Ok(())
+}
+
+fn debit(balance: &mut u64, amount: u64) -> anyhow::Result<()> {
+    *balance = balance
+        .checked_sub(amount)
+        .ok_or_else(|| anyhow!("balance underflow"))?;
+    Ok(())
+}
+
 impl Syscall for ExecutionEnvironment {
     fn block_hash(&mut self, level: u32) -> anyhow::Result<Vec<u8>> {
         Ok(self
@@ -76,13 +96,13 @@ impl Syscall for ExecutionEnvironment {
 
     fn address_from_pk(&mut self, pk: Vec<u8>) -> anyhow::Result<Vec<u8>> {
         PublicKey::from_bytes(&pk)
-            .map(|pk| get_address_from_pub_key(pk, self.network))
-            .map(|add| add.as_bytes().to_vec())
             .map_err(|e| anyhow!(e))
+            .and_then(|pk| get_address_from_pub_key(pk, self.network))
+            .map(|add| add.as_bytes().to_vec())
     }
 
     fn generate_keypair(&mut self) -> anyhow::Result<(Vec<u8>, Vec<u8>)> {
-        let account = account::create_account(self.network);
+        let account = account::create_account(self.network)?;
         Ok((
             account.secrete_key().as_bytes().to_vec(),
             account.public_key().as_bytes().to_vec(),
@@ -104,28 +124,25 @@ impl Syscall for ExecutionEnvironment {
         //TODO: very unsafe
         let to = Address::from_slice(&to);
         let from_acc = self.get_account_state(self.app_address);
-        anyhow::ensure!((from_acc.free_balance as i128 - amount as i128) > 0);
-        from_acc.free_balance -= amount;
+        debit(&mut from_acc.free_balance, amount)?;
         let to_acc = self.get_account_state(to);
-        to_acc.free_balance += amount;
+        credit(&mut to_acc.free_balance, amount)?;
         Ok(true)
     }
 
     fn reserve(&mut self, amount: u64) -> anyhow::Result<bool> {
         //TODO: very unsafe
         let from_acc = self.get_account_state(self.sender);
-        anyhow::ensure!((from_acc.free_balance as i128 - amount as i128) > 0);
-        from_acc.free_balance -= amount;
-        from_acc.reserve_balance += amount;
+        debit(&mut from_acc.free_balance, amount)?;
+        credit(&mut from_acc.reserve_balance, amount)?;
         Ok(true)
     }
 
     fn unreserve(&mut self, amount: u64) -> anyhow::Result<bool> {
         //TODO: very unsafe
         let from_acc = self.get_account_state(self.sender);
-        anyhow::ensure!((from_acc.reserve_balance as i128 - amount as i128) > 0);
-        from_acc.reserve_balance -= amount;
-        from_acc.free_balance += amount;
+        debit(&mut from_acc.reserve_balance, amount)?;
+        credit(&mut from_acc.free_balance, amount)?;
         Ok(true)
     }
 
@@ -150,19 +167,34 @@ impl Syscall for ExecutionEnvironment {
 
 impl Storage for ExecutionEnvironment {
     fn insert(&mut self, key: Vec<u8>, value: Vec<u8>) -> anyhow::Result<()> {
-        let _ = self.storage.update(key, value)?;
+        let _ = self.storage.update(&key, &value)?;
+        self.storage_writes.insert(key, value);
         Ok(())
     }
 
     fn get(&mut self, key: Vec<u8>) -> anyhow::Result<Option<Vec<u8>>> {
-        let Ok(data) = self.storage.get(key) else {
-            return Ok(None)
-        };
-        Ok(Some(data))
+        Ok(self.storage.get(key)?)
     }
 
     fn remove(&mut self, key: Vec<u8>) -> anyhow::Result<bool> {
-        Ok(self.storage.update(key, Vec::new()).is_ok())
+        match self.storage.delete(&key) {
+            Ok(_) => {
+                self.storage_writes.insert(key, Vec::new());
+                Ok(true)
+            }
+            Err(smt::error::Error::KeyAlreadyEmpty) => Ok(false),
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    fn scan(
+        &mut self,
+        prefix: Vec<u8>,
+        start: Option<Vec<u8>>,
+        limit: u32,
+    ) -> anyhow::Result<Vec<(Vec<u8>, Vec<u8>)>> {
+        let limit = limit.min(MAX_SCAN_LIMIT) as usize;
+        Ok(self.storage.scan(&prefix, start.as_deref(), limit)?)
     }
 }
 
@@ -208,17 +240,19 @@ impl From<&ExecutionEnvironment> for Changelist {
             account_changes: value.accounts.clone(),
             logs: value.events.clone(),
             storage: value.storage.clone(),
+            storage_writes: value.storage_writes.clone(),
+            fuel_used: 0,
         }
     }
 }
 
 pub struct QueryEnvironment {
-    storage: SparseMerkleTree,
+    storage: AppStorage,
     state_db: Arc<dyn StateDB>,
 }
 
 impl QueryEnvironment {
-    pub fn new(storage: SparseMerkleTree, state_db: Arc<dyn StateDB>) -> anyhow::Result<Self> {
+    pub fn new(storage: AppStorage, state_db: Arc<dyn StateDB>) -> anyhow::Result<Self> {
         Ok(Self { storage, state_db })
     }
 
@@ -267,15 +301,22 @@ impl Storage for QueryEnvironment {
     }
 
     fn get(&mut self, key: Vec<u8>) -> anyhow::Result<Option<Vec<u8>>> {
-        let Ok(data) = self.storage.get(key) else {
-            return Ok(None)
-        };
-        Ok(Some(data))
+        Ok(self.storage.get(key)?)
     }
 
     fn remove(&mut self, _: Vec<u8>) -> anyhow::Result<bool> {
         bail!("cannot mutate state in query environment")
     }
+
+    fn scan(
+        &mut self,
+        prefix: Vec<u8>,
+        start: Option<Vec<u8>>,
+        limit: u32,
+    ) -> anyhow::Result<Vec<(Vec<u8>, Vec<u8>)>> {
+        let limit = limit.min(MAX_SCAN_LIMIT) as usize;
+        Ok(self.storage.scan(&prefix, start.as_deref(), limit)?)
+    }
 }
 
 impl Syscall for QueryEnvironment {
@@ -1,9 +1,8 @@
 use crate::env::{ExecutionEnvironment, QueryEnvironment};
 use anyhow::anyhow;
 use internal::Runtime;
-use parking_lot::RwLock;
+use parking_lot::{Mutex, RwLock};
 use primitive_types::address::Address;
-use smt::SparseMerkleTree;
 use std::collections::BTreeMap;
 use std::sync::Arc;
 use traits::{ChainHeadReader, StateDB, WasmVMInstance};
@@ -12,6 +11,7 @@ use wasmtime::{AsContextMut, Config, Engine, Store};
 mod env;
 
 use types::account::get_address_from_seed;
+use types::app::AppStorage;
 use types::prelude::{ApplicationCall, CreateApplication};
 use types::{Addressing, Changelist};
 
@@ -22,7 +22,12 @@ mod internal {
     include!(concat!(env!("OUT_DIR"), "/io.rs"));
     include!(concat!(env!("OUT_DIR"), "/runtime.rs"));
 }
-type AppStateStore = BTreeMap<Address, (Runtime, Store<ExecutionEnvironment>)>;
+
+/// Fuel given to a store for each call, an app that burns all of it traps
+const CALL_FUEL: u64 = 10_000_000_000;
+
+/// Loaded apps, each behind its own lock so calls to different apps can run concurrently
+type AppStateStore = BTreeMap<Address, Arc<Mutex<(Runtime, Store<ExecutionEnvironment>)>>>;
 pub struct WasmVM {
     engine: Arc<Engine>,
     blockchain: Arc<dyn ChainHeadReader>,
@@ -31,7 +36,7 @@ pub struct WasmVM {
 
 impl WasmVM {
     pub fn new(blockchain: Arc<dyn ChainHeadReader>) -> anyhow::Result<Self> {
-        Engine::new(Config::new().consume_fuel(false).wasm_component_model(true)).map(|engine| {
+        Engine::new(Config::new().consume_fuel(true).wasm_component_model(true)).map(|engine| {
             Self {
                 engine: Arc::new(engine),
                 blockchain,
@@ -49,7 +54,8 @@ impl WasmVM {
         binary: &[u8],
     ) -> anyhow::Result<(Vec<u8>, Changelist)> {
         let engine = &self.engine;
-        let storage = SparseMerkleTree::new();
+        let mut storage = AppStorage::default();
+        storage.set_hasher(state_db.tree_hasher());
         let mut store = Store::new(
             engine,
             ExecutionEnvironment::new(
@@ -61,6 +67,7 @@ impl WasmVM {
                 self.blockchain.clone(),
             )?,
         );
+        store.add_fuel(CALL_FUEL)?;
 
         let mut linker = Linker::<ExecutionEnvironment>::new(engine);
         internal::syscall::add_to_linker(&mut linker, |env| env)?;
@@ -72,8 +79,9 @@ impl WasmVM {
         let app = app.runtime_app();
         app.genesis(&mut store)?;
         let descriptor = app.descriptor(&mut store)?;
-        let env = store.data();
-        Ok((descriptor, env.into()))
+        let mut changelist = Changelist::from(store.data());
+        changelist.fuel_used = store.fuel_consumed().unwrap_or_default();
+        Ok((descriptor, changelist))
     }
 
     pub fn load_application(
@@ -101,6 +109,7 @@ impl WasmVM {
                 self.blockchain.clone(),
             )?,
         );
+        store.add_fuel(CALL_FUEL)?;
 
         let mut linker = Linker::<ExecutionEnvironment>::new(engine);
         internal::syscall::add_to_linker(&mut linker, |env| env)?;
@@ -112,7 +121,7 @@ impl WasmVM {
 
         let app = Runtime::new(&mut store, &instance)?;
         let mut apps = self.apps.write();
-        apps.insert(app_id, (app, store));
+        apps.insert(app_id, Arc::new(Mutex::new((app, store))));
         Ok(())
     }
 
@@ -126,10 +135,9 @@ impl WasmVM {
         self.load_application(state_db.clone(), call.app_id)?;
 
         let storage = state_db.get_app_data(call.app_id)?;
-        let mut apps = self.apps.write();
-        let (app, store) = apps
-            .get_mut(&call.app_id)
-            .ok_or_else(|| anyhow::anyhow!("app not found"))?;
+        let loaded = self.loaded_application(&call.app_id)?;
+        let mut loaded = loaded.lock();
+        let (app, store) = &mut *loaded;
 
         *store.data_mut() = ExecutionEnvironment::new(
             origin,
@@ -139,14 +147,17 @@ impl WasmVM {
             state_db.clone(),
             self.blockchain.clone(),
         )?;
-        app.runtime_app().call(
-            store.as_context_mut(),
-            call.service,
-            call.method,
-            call.args.as_slice(),
-        )?;
-        let env = store.data();
-        Ok(Changelist::from(env))
+        let ((), fuel_used) = metered(store, |store| {
+            app.runtime_app().call(
+                store.as_context_mut(),
+                call.service,
+                call.method,
+                call.args.as_slice(),
+            )
+        })?;
+        let mut changelist = Changelist::from(store.data());
+        changelist.fuel_used = fuel_used;
+        Ok(changelist)
     }
 
     pub fn execute_query(
@@ -158,6 +169,7 @@ impl WasmVM {
         let engine = &self.engine;
         let storage = state_db.get_app_data(call.app_id)?;
         let mut store = Store::new(engine, QueryEnvironment::new(storage, state_db.clone())?);
+        store.add_fuel(CALL_FUEL)?;
 
         let mut linker = Linker::<QueryEnvironment>::new(engine);
         internal::syscall::add_to_linker(&mut linker, |env| env)?;
@@ -178,13 +190,36 @@ impl WasmVM {
         app_id: Address,
     ) -> anyhow::Result<Vec<u8>> {
         self.load_application(state_db.clone(), app_id)?;
-        let mut apps = self.apps.write();
-        let (app, store) = apps
-            .get_mut(&app_id)
-            .ok_or_else(|| anyhow::anyhow!("app not loaded"))?;
+        let loaded = self.loaded_application(&app_id)?;
+        let mut loaded = loaded.lock();
+        let (app, store) = &mut *loaded;
         let app = app.runtime_app();
-        app.descriptor(store)
+        metered(store, |store| app.descriptor(store)).map(|(descriptor, _)| descriptor)
     }
+
+    fn loaded_application(
+        &self,
+        app_id: &Address,
+    ) -> anyhow::Result<Arc<Mutex<(Runtime, Store<ExecutionEnvironment>)>>> {
+        self.apps
+            .read()
+            .get(app_id)
+            .cloned()
+            .ok_or_else(|| anyhow::anyhow!("app not loaded"))
+    }
+}
+
+/// Runs `call` on a loaded app's store and gives back the fuel it burnt, so every call starts
+/// with `CALL_FUEL`. Returns the result of the call with the fuel it used.
+fn metered<T, F>(store: &mut Store<ExecutionEnvironment>, call: F) -> anyhow::Result<(T, u64)>
+where
+    F: FnOnce(&mut Store<ExecutionEnvironment>) -> anyhow::Result<T>,
+{
+    let before = store.fuel_consumed().unwrap_or_default();
+    let result = call(store);
+    let fuel_used = store.fuel_consumed().unwrap_or_default() - before;
+    store.add_fuel(fuel_used)?;
+    Ok((result?, fuel_used))
 }
 
 impl WasmVMInstance for WasmVM {
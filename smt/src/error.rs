@@ -5,11 +5,13 @@ use core::fmt::Formatter;
 #[derive(Debug)]
 pub enum Error {
     KeyAlreadyEmpty,
+    KeyNotEmpty,
     NonMembershipPathError(Vec<u8>, Vec<u8>),
     StorageError,
     CustomError(String),
     StorageErrorKeyNotFound,
     BadProof(Vec<Vec<Vec<u8>>>),
+    BadBatchProof,
 }
 
 impl core::fmt::Display for Error {
@@ -18,6 +20,9 @@ impl core::fmt::Display for Error {
             Error::KeyAlreadyEmpty => {
                 writeln!(f, "KeyAlreadyEmpty")
             }
+            Error::KeyNotEmpty => {
+                writeln!(f, "KeyNotEmpty")
+            }
             Error::NonMembershipPathError(left, right) => {
                 writeln!(f, "NonMembershipPathError {:?} {:?}", left, right)
             }
@@ -30,6 +35,9 @@ impl core::fmt::Display for Error {
             Error::BadProof(proof) => {
                 writeln!(f, "BadProof {:?}", proof)
             }
+            Error::BadBatchProof => {
+                writeln!(f, "BadBatchProof")
+            }
             Error::CustomError(error) => {
                 writeln!(f, "CustomError {:?}", error)
             }
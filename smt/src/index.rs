@@ -0,0 +1,337 @@
+//! Crit-bit tree over the keys of a [`SparseMerkleTree`](crate::SparseMerkleTree), ordered by
+//! key so a range of keys is read without walking the whole tree.
+//!
+//! Nodes are stored by their hash and never modified, an update writes the nodes on the path to
+//! the changed leaf. The tree is canonical for a set of keys, so two trees holding the same keys
+//! have the same index root.
+
+use alloc::vec::Vec;
+
+use primitive_types::H256;
+
+use crate::error::Error;
+use crate::treehasher::TreeHasher;
+use crate::{Result, StorageBackend};
+
+const LEAF_PREFIX: u8 = 0;
+const BRANCH_PREFIX: u8 = 1;
+
+pub enum Node {
+    Leaf(Vec<u8>),
+    /// Keys on the left have a zero at bit `crit` and keys on the right a one, all keys under the
+    /// branch have the same bits before `crit`
+    Branch {
+        crit: u32,
+        left: H256,
+        right: H256,
+    },
+}
+
+impl Node {
+    pub fn encode(&self) -> Vec<u8> {
+        match self {
+            Node::Leaf(key) => [&[LEAF_PREFIX][..], key].concat(),
+            Node::Branch { crit, left, right } => [
+                &[BRANCH_PREFIX][..],
+                &crit.to_be_bytes(),
+                left.as_bytes(),
+                right.as_bytes(),
+            ]
+            .concat(),
+        }
+    }
+
+    pub fn decode(data: &[u8]) -> Result<Self> {
+        match data.split_first() {
+            Some((&LEAF_PREFIX, key)) => Ok(Node::Leaf(key.to_vec())),
+            Some((&BRANCH_PREFIX, branch)) if branch.len() == 68 => Ok(Node::Branch {
+                crit: u32::from_be_bytes([branch[0], branch[1], branch[2], branch[3]]),
+                left: H256::from_slice(&branch[4..36]),
+                right: H256::from_slice(&branch[36..]),
+            }),
+            _ => Err(Error::CustomError("invalid key index node".into())),
+        }
+    }
+}
+
+/// Bit `index` of `key` encoded so that the bits sort like the keys: every byte is a one
+/// followed by the eight bits of the byte, the end of the key is a zero
+fn bit(key: &[u8], index: u32) -> u8 {
+    let offset = index % 9;
+    match key.get((index / 9) as usize) {
+        None => 0,
+        Some(_) if offset == 0 => 1,
+        Some(byte) => (byte >> (8 - offset)) & 1,
+    }
+}
+
+/// First bit at which two different keys differ
+fn crit_bit(a: &[u8], b: &[u8]) -> u32 {
+    let common = a.iter().zip(b).take_while(|(a, b)| a == b).count();
+    match (a.get(common), b.get(common)) {
+        (Some(a), Some(b)) => common as u32 * 9 + 1 + (a ^ b).leading_zeros(),
+        // One key is a prefix of the other, they differ where the shorter one ends
+        _ => common as u32 * 9,
+    }
+}
+
+fn read<S: StorageBackend>(nodes: &S, hash: &H256) -> Result<Node> {
+    Node::decode(&nodes.get(hash.as_bytes())?)
+}
+
+fn write<S: StorageBackend, H: TreeHasher>(nodes: &mut S, hasher: &H, node: Node) -> Result<H256> {
+    let data = node.encode();
+    let hash = hasher.digest(&data);
+    nodes.put(hash.as_bytes(), &data)?;
+    Ok(hash)
+}
+
+/// The key whose bits match `key` at every branch from `root` to its leaf
+fn nearest<S: StorageBackend>(nodes: &S, root: H256, key: &[u8]) -> Result<Vec<u8>> {
+    let mut hash = root;
+    loop {
+        match read(nodes, &hash)? {
+            Node::Leaf(leaf) => return Ok(leaf),
+            Node::Branch { crit, left, right } => {
+                hash = if bit(key, crit) == 1 { right } else { left };
+            }
+        }
+    }
+}
+
+/// Branches on the way from the root to a node, with the side that was taken
+type Path = Vec<(u32, H256, H256, bool)>;
+
+fn rebuild<S: StorageBackend, H: TreeHasher>(
+    nodes: &mut S,
+    hasher: &H,
+    mut path: Path,
+    mut child: H256,
+) -> Result<H256> {
+    while let Some((crit, left, right, went_right)) = path.pop() {
+        let (left, right) = if went_right {
+            (left, child)
+        } else {
+            (child, right)
+        };
+        child = write(nodes, hasher, Node::Branch { crit, left, right })?;
+    }
+    Ok(child)
+}
+
+/// Adds `key` to the index at `root` and returns the new root
+pub(crate) fn insert<S: StorageBackend, H: TreeHasher>(
+    nodes: &mut S,
+    hasher: &H,
+    root: H256,
+    key: &[u8],
+) -> Result<H256> {
+    if root.is_zero() {
+        return write(nodes, hasher, Node::Leaf(key.to_vec()));
+    }
+    let nearest = nearest(nodes, root, key)?;
+    if nearest == key {
+        return Ok(root);
+    }
+    let new_crit = crit_bit(key, &nearest);
+    let mut path = Path::new();
+    let mut hash = root;
+    while let Node::Branch { crit, left, right } = read(nodes, &hash)? {
+        if crit > new_crit {
+            break;
+        }
+        let went_right = bit(key, crit) == 1;
+        path.push((crit, left, right, went_right));
+        hash = if went_right { right } else { left };
+    }
+    let leaf = write(nodes, hasher, Node::Leaf(key.to_vec()))?;
+    let (left, right) = if bit(key, new_crit) == 1 {
+        (hash, leaf)
+    } else {
+        (leaf, hash)
+    };
+    let branch = write(
+        nodes,
+        hasher,
+        Node::Branch {
+            crit: new_crit,
+            left,
+            right,
+        },
+    )?;
+    rebuild(nodes, hasher, path, branch)
+}
+
+/// Removes `key` from the index at `root` and returns the new root, the root is unchanged if the
+/// key is not indexed
+pub(crate) fn remove<S: StorageBackend, H: TreeHasher>(
+    nodes: &mut S,
+    hasher: &H,
+    root: H256,
+    key: &[u8],
+) -> Result<H256> {
+    if root.is_zero() {
+        return Ok(root);
+    }
+    let mut path = Path::new();
+    let mut hash = root;
+    while let Node::Branch { crit, left, right } = read(nodes, &hash)? {
+        let went_right = bit(key, crit) == 1;
+        path.push((crit, left, right, went_right));
+        hash = if went_right { right } else { left };
+    }
+    if nearest(nodes, hash, key)? != key {
+        return Ok(root);
+    }
+    // The sibling of the leaf takes the place of their parent
+    let Some((_, left, right, went_right)) = path.pop() else {
+        return Ok(H256::zero());
+    };
+    let sibling = if went_right { left } else { right };
+    rebuild(nodes, hasher, path, sibling)
+}
+
+/// Keys of an index in order, starting at a lower bound
+pub(crate) struct Cursor<'a, S> {
+    nodes: &'a S,
+    /// Subtrees left to visit, the next one is last
+    pending: Vec<H256>,
+}
+
+impl<'a, S: StorageBackend> Cursor<'a, S> {
+    /// Positions the cursor before the first key that is not lower than `from`
+    pub(crate) fn seek(nodes: &'a S, root: H256, from: &[u8]) -> Result<Self> {
+        let mut pending = Vec::new();
+        if root.is_zero() {
+            return Ok(Self { nodes, pending });
+        }
+        // Every key met on the way down has the same bits as `from` before `diff`, if `from`
+        // is not indexed
+        let nearest = nearest(nodes, root, from)?;
+        let diff = (nearest != from).then(|| crit_bit(from, &nearest));
+        let mut hash = root;
+        while let Node::Branch { crit, left, right } = read(nodes, &hash)? {
+            if diff.is_some_and(|diff| crit > diff) {
+                break;
+            }
+            if bit(from, crit) == 1 {
+                hash = right;
+            } else {
+                pending.push(right);
+                hash = left;
+            }
+        }
+        // All keys under `hash` are on the same side of `from`, the side of `nearest`
+        if diff.is_none_or(|diff| bit(from, diff) == 0) {
+            pending.push(hash);
+        }
+        Ok(Self { nodes, pending })
+    }
+
+    pub(crate) fn next_key(&mut self) -> Result<Option<Vec<u8>>> {
+        while let Some(hash) = self.pending.pop() {
+            match read(self.nodes, &hash)? {
+                Node::Leaf(key) => return Ok(Some(key)),
+                Node::Branch { left, right, .. } => {
+                    self.pending.push(right);
+                    self.pending.push(left);
+                }
+            }
+        }
+        Ok(None)
+    }
+}
+
+/// Hashes of the nodes of the index at `root`, read with `read_node`
+pub fn reachable<F>(root: H256, mut read_node: F) -> Result<Vec<H256>>
+where
+    F: FnMut(&H256) -> Result<Vec<u8>>,
+{
+    let mut hashes = Vec::new();
+    let mut pending = Vec::new();
+    if !root.is_zero() {
+        pending.push(root);
+    }
+    while let Some(hash) = pending.pop() {
+        if let Node::Branch { left, right, .. } = Node::decode(&read_node(&hash)?)? {
+            pending.push(left);
+            pending.push(right);
+        }
+        hashes.push(hash);
+    }
+    Ok(hashes)
+}
+
+#[cfg(test)]
+mod tests {
+    use alloc::collections::BTreeSet;
+    use alloc::vec::Vec;
+
+    use primitive_types::H256;
+
+    use crate::index::{insert, remove, Cursor};
+    use crate::{DefaultTreeHasher, MemoryStorage, StorageBackend};
+
+    fn keys_from(nodes: &MemoryStorage, root: H256, from: &[u8]) -> Vec<Vec<u8>> {
+        let mut cursor = Cursor::seek(nodes, root, from).unwrap();
+        let mut keys = Vec::new();
+        while let Some(key) = cursor.next_key().unwrap() {
+            keys.push(key);
+        }
+        keys
+    }
+
+    #[test]
+    fn test_seek_matches_sorted_keys() {
+        let keys: BTreeSet<Vec<u8>> = [
+            &b""[..],
+            b"a",
+            b"ab",
+            b"abc",
+            b"b",
+            b"ba",
+            &[0],
+            &[0, 0],
+            &[0xff],
+            &[0x7f, 0x80],
+        ]
+        .iter()
+        .map(|key| key.to_vec())
+        .collect();
+        let mut nodes = MemoryStorage::new();
+        let mut root = H256::zero();
+        for key in keys.iter().rev() {
+            root = insert(&mut nodes, &DefaultTreeHasher, root, key).unwrap();
+        }
+
+        let mut bounds = keys.clone();
+        bounds.extend([b"aa".to_vec(), b"abd".to_vec(), b"c".to_vec(), vec![0x7f]]);
+        for from in bounds {
+            let expected: Vec<_> = keys.range(from.clone()..).cloned().collect();
+            assert_eq!(keys_from(&nodes, root, &from), expected);
+        }
+
+        // The index is the same whatever the order the keys were added in
+        let mut other = H256::zero();
+        for key in keys.iter() {
+            other = insert(&mut nodes, &DefaultTreeHasher, other, key).unwrap();
+        }
+        assert_eq!(other, root);
+
+        let mut removed = root;
+        for key in [&b"ab"[..], b"missing", b"", &[0xff]] {
+            removed = remove(&mut nodes, &DefaultTreeHasher, removed, key).unwrap();
+        }
+        let mut expected = H256::zero();
+        for key in keys.iter() {
+            if ![&b"ab"[..], b"", &[0xff]].contains(&key.as_slice()) {
+                expected = insert(&mut nodes, &DefaultTreeHasher, expected, key).unwrap();
+            }
+        }
+        assert_eq!(removed, expected);
+        for key in keys.iter() {
+            removed = remove(&mut nodes, &DefaultTreeHasher, removed, key).unwrap();
+        }
+        assert!(removed.is_zero());
+    }
+}
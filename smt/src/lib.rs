@@ -8,11 +8,16 @@ use alloc::vec::Vec;
 
 use crate::error::Error;
 use crate::treehasher::TreeHasher;
+use bincode::de::Decoder;
+use bincode::enc::Encoder;
+use bincode::error::{DecodeError, EncodeError};
 use bincode::{Decode, Encode};
 use primitive_types::H256;
 
 mod constants;
 pub mod error;
+pub mod index;
+pub mod overlay;
 pub mod proof;
 pub mod smt;
 pub mod treehasher;
@@ -93,7 +98,8 @@ impl StorageBackend for MemoryStorage {
     }
 }
 
-#[derive(Debug, Clone, Encode, Decode)]
+/// Keccak-256 tree hasher
+#[derive(Debug, Clone, Default, Encode, Decode)]
 pub struct DefaultTreeHasher;
 
 impl TreeHasher for DefaultTreeHasher {
@@ -101,3 +107,77 @@ impl TreeHasher for DefaultTreeHasher {
         crypto::keccak256(data)
     }
 }
+
+/// Blake2b-256 tree hasher
+#[derive(Debug, Clone, Default, Encode, Decode)]
+pub struct Blake2bTreeHasher;
+
+impl TreeHasher for Blake2bTreeHasher {
+    fn digest(&self, data: &[u8]) -> H256 {
+        crypto::blake2b256(data)
+    }
+}
+
+/// SHA-256 tree hasher
+#[derive(Debug, Clone, Default, Encode, Decode)]
+pub struct Sha256TreeHasher;
+
+impl TreeHasher for Sha256TreeHasher {
+    fn digest(&self, data: &[u8]) -> H256 {
+        crypto::sha256(data)
+    }
+}
+
+/// Tree hasher selected at runtime, by a chain for its state.
+///
+/// Like [`DefaultTreeHasher`] it is encoded as nothing so trees written before the hasher was
+/// selectable still decode. A decoded tree hashes with Keccak-256 until its owner restores the
+/// hasher with [`SparseMerkleTree::set_hasher`].
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum TreeHasherKind {
+    #[default]
+    Keccak256,
+    Blake2b256,
+    Sha256,
+}
+
+impl TreeHasherKind {
+    pub fn name(&self) -> &'static str {
+        match self {
+            TreeHasherKind::Keccak256 => "keccak256",
+            TreeHasherKind::Blake2b256 => "blake2b256",
+            TreeHasherKind::Sha256 => "sha256",
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "keccak256" => Some(TreeHasherKind::Keccak256),
+            "blake2b256" => Some(TreeHasherKind::Blake2b256),
+            "sha256" => Some(TreeHasherKind::Sha256),
+            _ => None,
+        }
+    }
+}
+
+impl TreeHasher for TreeHasherKind {
+    fn digest(&self, data: &[u8]) -> H256 {
+        match self {
+            TreeHasherKind::Keccak256 => DefaultTreeHasher.digest(data),
+            TreeHasherKind::Blake2b256 => Blake2bTreeHasher.digest(data),
+            TreeHasherKind::Sha256 => Sha256TreeHasher.digest(data),
+        }
+    }
+}
+
+impl Encode for TreeHasherKind {
+    fn encode<E: Encoder>(&self, _: &mut E) -> core::result::Result<(), EncodeError> {
+        Ok(())
+    }
+}
+
+impl Decode for TreeHasherKind {
+    fn decode<D: Decoder>(_: &mut D) -> core::result::Result<Self, DecodeError> {
+        Ok(Self::default())
+    }
+}
@@ -0,0 +1,345 @@
+use alloc::collections::BTreeMap;
+use alloc::sync::Arc;
+use alloc::vec::Vec;
+
+use primitive_types::H256;
+
+use crate::error::Error;
+use crate::index;
+use crate::treehasher::TreeHasher;
+use crate::utils::get_bits_at_from_msb;
+use crate::{
+    DefaultTreeHasher, MemoryStorage, Result, SparseMerkleTree, StorageBackend,
+    StorageBackendSnapshot,
+};
+
+/// Persistent nodes and values shared by every root of a tree. Nodes are keyed by their hash,
+/// values by the hash of the value and keys by their path, so identical content is stored once.
+pub trait NodeStore: Send + Sync {
+    fn node(&self, hash: &H256) -> Result<Option<Vec<u8>>>;
+    fn value(&self, value_hash: &H256) -> Result<Option<Vec<u8>>>;
+    fn key(&self, path: &H256) -> Result<Option<Vec<u8>>>;
+    /// Node of a key [`index`] by hash
+    fn index_node(&self, hash: &H256) -> Result<Option<Vec<u8>>>;
+    /// Root of the key index of the tree at `root`, `None` for trees written before their keys
+    /// were indexed
+    fn index_root(&self, root: &H256) -> Result<Option<H256>>;
+}
+
+#[derive(Clone)]
+enum Base {
+    None,
+    /// Nodes are read from the store by hash
+    Nodes(Arc<dyn NodeStore>),
+    /// Values are read from the leaf of their path under the root the tree was opened at
+    Values(Arc<dyn NodeStore>, H256),
+    /// Keys are read from the store by path
+    Keys(Arc<dyn NodeStore>),
+    /// Index nodes are read from the store by hash
+    Index(Arc<dyn NodeStore>),
+}
+
+/// Storage that keeps writes in memory and reads everything else from a [`NodeStore`] when it
+/// is first touched. Deletes only hide entries, the store is never modified.
+#[derive(Clone)]
+pub struct OverlayStorage {
+    base: Base,
+    overlay: BTreeMap<Vec<u8>, Option<Vec<u8>>>,
+}
+
+impl OverlayStorage {
+    fn read(&self, key: &[u8]) -> Result<Option<Vec<u8>>> {
+        if let Some(value) = self.overlay.get(key) {
+            return Ok(value.clone());
+        }
+        match &self.base {
+            Base::None => Ok(None),
+            Base::Nodes(store) => store.node(&H256::from_slice(key)),
+            Base::Values(store, root) => value_at(store.as_ref(), root, key),
+            Base::Keys(store) => store.key(&H256::from_slice(key)),
+            Base::Index(store) => store.index_node(&H256::from_slice(key)),
+        }
+    }
+}
+
+impl StorageBackend for OverlayStorage {
+    fn put(&mut self, key: &[u8], value: &[u8]) -> Result<()> {
+        self.overlay.insert(key.to_vec(), Some(value.to_vec()));
+        Ok(())
+    }
+
+    fn get(&self, key: &[u8]) -> Result<Vec<u8>> {
+        self.read(key)?.ok_or(Error::StorageErrorKeyNotFound)
+    }
+
+    fn delete(&mut self, key: &[u8]) -> Result<()> {
+        if self.read(key)?.is_none() {
+            return Err(Error::StorageError);
+        }
+        self.overlay.insert(key.to_vec(), None);
+        Ok(())
+    }
+
+    fn get_or_default(&self, key: &[u8], default: Vec<u8>) -> Result<Vec<u8>> {
+        Ok(self.read(key)?.unwrap_or(default))
+    }
+
+    /// Only the entries written to the overlay, not the content of the store
+    fn snapshot(&self) -> Result<StorageBackendSnapshot> {
+        Ok(self
+            .overlay
+            .iter()
+            .filter_map(|(key, value)| Some((key.clone(), value.clone()?)))
+            .collect())
+    }
+
+    fn from_snapshot(snapshot: StorageBackendSnapshot) -> Result<Self> {
+        Ok(Self {
+            base: Base::None,
+            overlay: snapshot
+                .into_iter()
+                .map(|(key, value)| (key, Some(value)))
+                .collect(),
+        })
+    }
+
+    fn new() -> Self {
+        Self {
+            base: Base::None,
+            overlay: BTreeMap::new(),
+        }
+    }
+}
+
+/// Walks from `root` along `path` and reads the value of the leaf if it is at `path`
+fn value_at(store: &dyn NodeStore, root: &H256, path: &[u8]) -> Result<Option<Vec<u8>>> {
+    if root.is_zero() {
+        return Ok(None);
+    }
+    let hasher = DefaultTreeHasher;
+    let mut data = store.node(root)?.ok_or(Error::StorageErrorKeyNotFound)?;
+    let mut depth = 0;
+    while !hasher.is_leaf(&data) {
+        let (left, right) = hasher.parse_node(&data);
+        let child = if get_bits_at_from_msb(path, depth) == 1 {
+            H256::from_slice(right)
+        } else {
+            H256::from_slice(left)
+        };
+        if child.is_zero() {
+            return Ok(None);
+        }
+        data = store.node(&child)?.ok_or(Error::StorageErrorKeyNotFound)?;
+        depth += 1;
+    }
+    let (leaf_path, value_hash) = hasher.parse_leaf(&data);
+    if leaf_path != path {
+        return Ok(None);
+    }
+    store
+        .value(&H256::from_slice(value_hash))?
+        .map(Some)
+        .ok_or(Error::StorageErrorKeyNotFound)
+}
+
+impl SparseMerkleTree<OverlayStorage, DefaultTreeHasher> {
+    /// Opens the tree at `root` without loading it, nodes and values are read from `store` as
+    /// they are touched
+    pub fn open(store: Arc<dyn NodeStore>, root: H256) -> Result<Self> {
+        Self::open_with_hasher(DefaultTreeHasher, store, root)
+    }
+}
+
+impl<H: TreeHasher> SparseMerkleTree<OverlayStorage, H> {
+    /// Opens the tree at `root` hashed with `hasher`, see [`SparseMerkleTree::open`]
+    pub fn open_with_hasher(hasher: H, store: Arc<dyn NodeStore>, root: H256) -> Result<Self> {
+        let mut tree = Self::new_with_hasher(
+            hasher,
+            OverlayStorage {
+                base: Base::Nodes(store.clone()),
+                overlay: BTreeMap::new(),
+            },
+            OverlayStorage {
+                base: Base::Values(store.clone(), root),
+                overlay: BTreeMap::new(),
+            },
+        );
+        tree.keys = OverlayStorage {
+            base: Base::Keys(store.clone()),
+            overlay: BTreeMap::new(),
+        };
+        tree.index = OverlayStorage {
+            base: Base::Index(store.clone()),
+            overlay: BTreeMap::new(),
+        };
+        // The leaves of a tree without an index are skipped by scans, keys written from now on
+        // are indexed
+        if !root.is_zero() {
+            tree.index_root = store.index_root(&root)?.unwrap_or_default();
+        }
+        tree.root = root;
+        tree.parent = root;
+        Ok(tree)
+    }
+
+    /// Copies every node, value, key and index node reachable from the root into a tree that
+    /// does not depend on the store
+    pub fn detach(&self) -> Result<SparseMerkleTree<MemoryStorage, H>> {
+        let mut nodes = MemoryStorage::new();
+        let mut values = MemoryStorage::new();
+        let mut keys = MemoryStorage::new();
+        let mut pending = Vec::new();
+        if !self.root.is_zero() {
+            pending.push(self.root);
+        }
+        while let Some(hash) = pending.pop() {
+            let data = self.nodes.get(hash.as_bytes())?;
+            if self.hasher.is_leaf(&data) {
+                let (path, _) = self.hasher.parse_leaf(&data);
+                values.put(path, &self.values.get(path)?)?;
+                if let Some(key) = self.keys.read(path)? {
+                    keys.put(path, &key)?;
+                }
+            } else {
+                let (left, right) = self.hasher.parse_node(&data);
+                for child in [left, right] {
+                    let child = H256::from_slice(child);
+                    if !child.is_zero() {
+                        pending.push(child);
+                    }
+                }
+            }
+            nodes.put(hash.as_bytes(), &data)?;
+        }
+        let mut index = MemoryStorage::new();
+        for hash in index::reachable(self.index_root, |hash| self.index.get(hash.as_bytes()))? {
+            index.put(hash.as_bytes(), &self.index.get(hash.as_bytes())?)?;
+        }
+        let mut tree = SparseMerkleTree::new_with_hasher(self.hasher.clone(), nodes, values);
+        tree.keys = keys;
+        tree.index = index;
+        tree.root = self.root;
+        tree.parent = self.root;
+        tree.index_root = self.index_root;
+        Ok(tree)
+    }
+}
+
+impl<H: TreeHasher + Default> Default for SparseMerkleTree<OverlayStorage, H> {
+    fn default() -> Self {
+        Self::new_with_hasher(H::default(), OverlayStorage::new(), OverlayStorage::new())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use alloc::collections::BTreeMap;
+    use alloc::sync::Arc;
+    use alloc::vec::Vec;
+
+    use primitive_types::H256;
+
+    use crate::overlay::{NodeStore, OverlayStorage};
+    use crate::treehasher::TreeHasher;
+    use crate::{DefaultTreeHasher, Result, SparseMerkleTree, StorageBackend};
+
+    #[derive(Default)]
+    struct TestStore {
+        nodes: BTreeMap<H256, Vec<u8>>,
+        values: BTreeMap<H256, Vec<u8>>,
+        keys: BTreeMap<H256, Vec<u8>>,
+        index: BTreeMap<H256, Vec<u8>>,
+        index_roots: BTreeMap<H256, H256>,
+    }
+
+    impl TestStore {
+        fn write<S: StorageBackend>(&mut self, tree: &SparseMerkleTree<S>) {
+            for (hash, data) in tree.nodes().unwrap() {
+                self.nodes.insert(H256::from_slice(&hash), data);
+            }
+            for (_, value) in tree.values().unwrap() {
+                self.values.insert(DefaultTreeHasher.digest(&value), value);
+            }
+            for (path, key) in tree.keys().unwrap() {
+                self.keys.insert(H256::from_slice(&path), key);
+            }
+            for (hash, data) in tree.index().unwrap() {
+                self.index.insert(H256::from_slice(&hash), data);
+            }
+            self.index_roots.insert(tree.root(), tree.index_root());
+        }
+    }
+
+    impl NodeStore for TestStore {
+        fn node(&self, hash: &H256) -> Result<Option<Vec<u8>>> {
+            Ok(self.nodes.get(hash).cloned())
+        }
+
+        fn value(&self, value_hash: &H256) -> Result<Option<Vec<u8>>> {
+            Ok(self.values.get(value_hash).cloned())
+        }
+
+        fn key(&self, path: &H256) -> Result<Option<Vec<u8>>> {
+            Ok(self.keys.get(path).cloned())
+        }
+
+        fn index_node(&self, hash: &H256) -> Result<Option<Vec<u8>>> {
+            Ok(self.index.get(hash).cloned())
+        }
+
+        fn index_root(&self, root: &H256) -> Result<Option<H256>> {
+            Ok(self.index_roots.get(root).copied())
+        }
+    }
+
+    #[test]
+    fn test_open_reads_lazily_from_store() {
+        let mut store = TestStore::default();
+        let mut tree = SparseMerkleTree::<OverlayStorage>::default();
+        for i in 0u8..16 {
+            tree.update([i], [i, i]).unwrap();
+        }
+        store.write(&tree);
+        let first_root = tree.root();
+
+        let store = Arc::new(store);
+        let mut opened = SparseMerkleTree::open(store.clone(), first_root).unwrap();
+        assert_eq!(opened.get([3]).unwrap(), Some(vec![3, 3]));
+        assert!(opened.get([100]).unwrap().is_none());
+        assert!(opened.nodes().unwrap().is_empty());
+
+        opened.update([3], [30]).unwrap();
+        opened.update([5], []).unwrap();
+        tree.update([3], [30]).unwrap();
+        tree.update([5], []).unwrap();
+        assert_eq!(opened.root(), tree.root());
+        assert_eq!(opened.get([3]).unwrap(), Some(vec![30]));
+        assert!(opened.get([5]).unwrap().is_none());
+        assert_eq!(opened.get([7]).unwrap(), Some(vec![7, 7]));
+        assert_eq!(
+            opened.scan(&[], Some(&[4]), 2).unwrap(),
+            vec![(vec![4], vec![4, 4]), (vec![6], vec![6, 6])]
+        );
+
+        let (value, proof) = opened.get_with_proof([7]).unwrap();
+        assert!(crate::proof::verify_proof(
+            &DefaultTreeHasher,
+            &proof,
+            opened.root(),
+            &[7],
+            &value
+        ));
+
+        // The first root is still readable, nothing was removed from the store
+        let old = SparseMerkleTree::open(store, first_root).unwrap();
+        assert_eq!(old.get([5]).unwrap(), Some(vec![5, 5]));
+        let detached = old.detach().unwrap();
+        assert_eq!(detached.root(), first_root);
+        assert_eq!(detached.get([5]).unwrap(), Some(vec![5, 5]));
+        assert_eq!(
+            detached.scan(&[], Some(&[5]), 1).unwrap(),
+            vec![(vec![5], vec![5, 5])]
+        );
+        detached.verify_index().unwrap();
+    }
+}
@@ -1,3 +1,4 @@
+use alloc::collections::BTreeMap;
 use alloc::vec;
 use alloc::vec::Vec;
 
@@ -5,9 +6,10 @@ use bincode::{Decode, Encode};
 use codec::{Decodable, Encodable};
 use primitive_types::H256;
 
+use crate::constants::{HASH_LEN, LEAF_PREFIX};
 use crate::error::Error;
 use crate::treehasher::TreeHasher;
-use crate::utils::get_bits_at_from_msb;
+use crate::utils::{get_bits_at_from_msb, path_prefix, set_bit_at_from_msb};
 
 #[derive(Clone, Debug, Encode, Decode)]
 pub struct Proof {
@@ -30,6 +32,47 @@ impl Decodable for Proof {
     }
 }
 
+/// Proof of several keys under one root. Siblings shared by the paths of the keys or computed
+/// from the keys themselves are not included.
+#[derive(Clone, Debug, Default, Encode, Decode)]
+pub struct BatchProof {
+    /// Depth of the leaf or empty subtree the path of each key ends at, in key order
+    pub depths: Vec<u16>,
+    /// Leaf of another key found at the end of the path of each absent key, in key order
+    pub non_membership_leaf_data: Vec<Option<Vec<u8>>>,
+    /// Siblings in the order they are consumed during verification, placeholders omitted
+    pub side_nodes: Vec<H256>,
+    /// Bit `i` is set when the `i`-th sibling consumed during verification is a placeholder
+    pub placeholders: Vec<u8>,
+}
+
+impl BatchProof {
+    pub(crate) fn push_side_node<T: TreeHasher>(&mut self, hasher: &T, index: usize, node: H256) {
+        if index / 8 == self.placeholders.len() {
+            self.placeholders.push(0);
+        }
+        if node == hasher.placeholder() {
+            set_bit_at_from_msb(&mut self.placeholders, index);
+        } else {
+            self.side_nodes.push(node);
+        }
+    }
+}
+
+impl Encodable for BatchProof {
+    fn encode(&self) -> anyhow::Result<Vec<u8>> {
+        bincode::encode_to_vec(self, codec::config()).map_err(|e| e.into())
+    }
+}
+
+impl Decodable for BatchProof {
+    fn decode(buf: &[u8]) -> anyhow::Result<Self> {
+        bincode::decode_from_slice(buf, codec::config())
+            .map(|(output, _)| output)
+            .map_err(|e| e.into())
+    }
+}
+
 pub struct CompatProof {
     pub side_nodes: Vec<H256>,
     pub non_membership_leaf_data: Vec<u8>,
@@ -48,6 +91,16 @@ pub fn verify_proof<T: TreeHasher>(
     verify_proof_with_updates(hasher, proof, root, key, value).is_ok()
 }
 
+/// Verifies that `key` is absent under `root`
+pub fn verify_non_membership<T: TreeHasher>(
+    hasher: &T,
+    proof: &Proof,
+    root: H256,
+    key: &[u8],
+) -> bool {
+    verify_proof(hasher, proof, root, key, &[])
+}
+
 pub fn verify_proof_with_updates<T: TreeHasher>(
     hasher: &T,
     proof: &Proof,
@@ -110,11 +163,182 @@ pub fn verify_proof_with_updates<T: TreeHasher>(
     Ok(updates)
 }
 
+/// Verifies the values of every key in `entries` against `root`, an empty value proves that the
+/// key is absent
+pub fn verify_batch_proof<T, K, V>(
+    hasher: &T,
+    proof: &BatchProof,
+    root: H256,
+    entries: &[(K, V)],
+) -> bool
+where
+    T: TreeHasher,
+    K: AsRef<[u8]>,
+    V: AsRef<[u8]>,
+{
+    batch_proof_root(hasher, proof, entries).is_ok_and(|computed| computed == root)
+}
+
+/// Computes the root `proof` commits to with the values in `entries`
+pub fn batch_proof_root<T, K, V>(
+    hasher: &T,
+    proof: &BatchProof,
+    entries: &[(K, V)],
+) -> Result<H256, Error>
+where
+    T: TreeHasher,
+    K: AsRef<[u8]>,
+    V: AsRef<[u8]>,
+{
+    if proof.depths.len() != entries.len() || proof.non_membership_leaf_data.len() != entries.len()
+    {
+        return Err(Error::BadBatchProof);
+    }
+    let mut leaves = Vec::with_capacity(entries.len());
+    for (((key, value), depth), leaf_data) in entries
+        .iter()
+        .zip(proof.depths.iter())
+        .zip(proof.non_membership_leaf_data.iter())
+    {
+        let depth = *depth as usize;
+        if depth > hasher.path_size() * 8 {
+            return Err(Error::BadBatchProof);
+        }
+        let path = hasher.path(key.as_ref());
+        let leaf = batch_leaf(hasher, &path, value.as_ref(), leaf_data.as_deref())?;
+        leaves.push((depth, path, leaf));
+    }
+
+    let mut consumed = 0;
+    let mut side_nodes = proof.side_nodes.iter();
+    let root = fold_batch(hasher, &leaves, |_, _| {
+        if consumed / 8 >= proof.placeholders.len() {
+            return Err(Error::BadBatchProof);
+        }
+        let is_placeholder = get_bits_at_from_msb(&proof.placeholders, consumed) == 1;
+        consumed += 1;
+        if is_placeholder {
+            Ok(hasher.placeholder())
+        } else {
+            side_nodes.next().copied().ok_or(Error::BadBatchProof)
+        }
+    })?;
+    if side_nodes.next().is_some() || proof.placeholders.len() != consumed.div_ceil(8) {
+        return Err(Error::BadBatchProof);
+    }
+    Ok(root)
+}
+
+/// Hash of the leaf or empty subtree at the end of `path`
+fn batch_leaf<T: TreeHasher>(
+    hasher: &T,
+    path: &H256,
+    value: &[u8],
+    non_membership_leaf_data: Option<&[u8]>,
+) -> Result<H256, Error> {
+    match non_membership_leaf_data {
+        None if value.is_empty() => Ok(hasher.placeholder()),
+        None => Ok(hasher
+            .digest_leaf(path.as_bytes(), hasher.digest(value).as_bytes())
+            .0),
+        Some(data) if value.is_empty() => {
+            if data.len() != LEAF_PREFIX.len() + 2 * HASH_LEN || !hasher.is_leaf(data) {
+                return Err(Error::BadBatchProof);
+            }
+            let (actual_path, _) = hasher.parse_leaf(data);
+            if actual_path.eq(path.as_bytes()) {
+                return Err(Error::NonMembershipPathError(
+                    actual_path.to_vec(),
+                    path.as_bytes().to_vec(),
+                ));
+            }
+            Ok(hasher.digest(data))
+        }
+        Some(_) => Err(Error::BadBatchProof),
+    }
+}
+
+/// Nodes by depth and path prefix, with the index of a leaf below each node
+type Levels = BTreeMap<usize, BTreeMap<H256, (H256, usize)>>;
+
+fn insert_node(
+    levels: &mut Levels,
+    depth: usize,
+    prefix: H256,
+    node: (H256, usize),
+) -> Result<(), Error> {
+    match levels.entry(depth).or_default().insert(prefix, node) {
+        Some((hash, _)) if hash != node.0 => Err(Error::BadBatchProof),
+        _ => Ok(()),
+    }
+}
+
+/// Hashes `leaves`, given as depth, path and hash, up to the root. Nodes are combined from the
+/// deepest level up and by path within a level, `side_node` is called with the depth and leaf
+/// index of a node whose sibling is not computed from the leaves.
+pub(crate) fn fold_batch<T, F>(
+    hasher: &T,
+    leaves: &[(usize, H256, H256)],
+    mut side_node: F,
+) -> Result<H256, Error>
+where
+    T: TreeHasher,
+    F: FnMut(usize, usize) -> Result<H256, Error>,
+{
+    let mut levels = Levels::new();
+    for (index, (depth, path, hash)) in leaves.iter().enumerate() {
+        insert_node(
+            &mut levels,
+            *depth,
+            path_prefix(path, *depth),
+            (*hash, index),
+        )?;
+    }
+
+    while let Some((depth, nodes)) = levels.pop_last() {
+        if depth == 0 {
+            return nodes
+                .into_values()
+                .next()
+                .map(|(root, _)| root)
+                .ok_or(Error::BadBatchProof);
+        }
+        let mut nodes = nodes.into_iter().peekable();
+        while let Some((prefix, (hash, index))) = nodes.next() {
+            let (left, right) = if get_bits_at_from_msb(prefix.as_bytes(), depth - 1) == 1 {
+                (side_node(depth, index)?, hash)
+            } else {
+                // The right sibling immediately follows in path order when it is computed
+                let mut sibling_prefix = prefix;
+                set_bit_at_from_msb(sibling_prefix.as_bytes_mut(), depth - 1);
+                match nodes.peek() {
+                    Some((next, _)) if *next == sibling_prefix => {
+                        let (_, (right, _)) = nodes.next().ok_or(Error::BadBatchProof)?;
+                        (hash, right)
+                    }
+                    _ => (hash, side_node(depth, index)?),
+                }
+            };
+            let (parent, _) = hasher.digest_node(left.as_bytes(), right.as_bytes());
+            insert_node(
+                &mut levels,
+                depth - 1,
+                path_prefix(&prefix, depth - 1),
+                (parent, index),
+            )?;
+        }
+    }
+    Err(Error::BadBatchProof)
+}
+
 #[cfg(test)]
 mod tests {
-    use crate::proof::verify_proof;
+    use crate::error::Error;
+    use crate::proof::{verify_batch_proof, verify_non_membership, verify_proof, BatchProof};
     use crate::smt::SparseMerkleTree;
+    use alloc::vec;
     use alloc::vec::Vec;
+    use codec::{Decodable, Encodable};
 
     use primitive_types::H256;
 
@@ -147,4 +371,80 @@ mod tests {
         let result = verify_proof(&hasher, &proof, root, b"testKey", b"badValue");
         assert!(!result, "invalid proof verification returned true");
     }
+
+    #[test]
+    fn test_batch_proof() {
+        let mut smt = SparseMerkleTree::new();
+        let hasher = smt.hasher.clone();
+
+        let absent: [&[u8]; 2] = [b"absent", b"missing"];
+        let proof = smt.batch_proof(&absent).unwrap();
+        let entries = absent.map(|key| (key, Vec::<u8>::new()));
+        assert!(verify_batch_proof(&hasher, &proof, smt.root(), &entries));
+
+        for i in 0u32..64 {
+            smt.update(i.to_be_bytes(), (i * 7).to_be_bytes()).unwrap();
+        }
+        let root = smt.root();
+        let keys: Vec<Vec<u8>> = [3u32, 4, 17, 63, 1000, 3]
+            .iter()
+            .map(|i| i.to_be_bytes().to_vec())
+            .collect();
+        let mut entries: Vec<(Vec<u8>, Vec<u8>)> = keys
+            .iter()
+            .map(|key| (key.clone(), smt.get(key).unwrap().unwrap_or_default()))
+            .collect();
+        assert!(entries[4].1.is_empty());
+
+        let proof = smt.batch_proof(&keys).unwrap();
+        let proof = BatchProof::decode(&proof.encode().unwrap()).unwrap();
+        assert!(verify_batch_proof(&hasher, &proof, root, &entries));
+        let single_side_nodes: usize = keys
+            .iter()
+            .map(|key| smt.proof(key).unwrap().side_nodes.len())
+            .sum();
+        assert!(proof.side_nodes.len() < single_side_nodes);
+
+        let mut bad_value = entries.clone();
+        bad_value[2].1 = vec![1];
+        assert!(!verify_batch_proof(&hasher, &proof, root, &bad_value));
+        let mut bad_absent = entries.clone();
+        bad_absent[1].1 = Vec::new();
+        assert!(!verify_batch_proof(&hasher, &proof, root, &bad_absent));
+        entries.pop();
+        assert!(!verify_batch_proof(&hasher, &proof, root, &entries));
+    }
+
+    #[test]
+    fn test_delete_and_non_membership_proof() {
+        let mut smt = SparseMerkleTree::new();
+        let hasher = smt.hasher.clone();
+        assert!(matches!(smt.delete(b"a"), Err(Error::KeyAlreadyEmpty)));
+
+        let mut expected = SparseMerkleTree::new();
+        for key in [b"a", b"b", b"c"] {
+            smt.update(key, key).unwrap();
+            if key != b"b" {
+                expected.update(key, key).unwrap();
+            }
+        }
+        let root = smt.delete(b"b").unwrap();
+        assert_eq!(root, expected.root());
+        assert_eq!(smt.get(b"b").unwrap(), None);
+        assert_eq!(smt.get(b"a").unwrap(), Some(b"a".to_vec()));
+        assert!(matches!(smt.delete(b"b"), Err(Error::KeyAlreadyEmpty)));
+
+        let proof = smt.non_membership_proof(b"b").unwrap();
+        assert!(verify_non_membership(&hasher, &proof, root, b"b"));
+        assert!(!verify_non_membership(&hasher, &proof, root, b"a"));
+        assert!(matches!(
+            smt.non_membership_proof(b"a"),
+            Err(Error::KeyNotEmpty)
+        ));
+
+        smt.delete(b"a").unwrap();
+        assert_eq!(smt.delete(b"c").unwrap(), H256::zero());
+        let proof = smt.non_membership_proof(b"c").unwrap();
+        assert!(verify_non_membership(&hasher, &proof, H256::zero(), b"c"));
+    }
 }
@@ -5,12 +5,16 @@ use codec::{Decodable, Encodable};
 use primitive_types::H256;
 
 use crate::error::Error;
-use crate::proof::{verify_proof_with_updates, Proof};
+use crate::index::{self, Cursor};
+use crate::proof::{
+    fold_batch, verify_non_membership, verify_proof_with_updates, BatchProof, Proof,
+};
 use crate::treehasher::TreeHasher;
 use crate::utils::{count_common_prefix, get_bits_at_from_msb};
 use crate::{
     CopyStrategy, DefaultTreeHasher, MemoryStorage, StorageBackend, StorageBackendSnapshot,
 };
+use bincode::de::read::Reader;
 use bincode::de::Decoder;
 use bincode::enc::Encoder;
 use bincode::error::{DecodeError, EncodeError};
@@ -22,54 +26,90 @@ pub(crate) struct SideNodesForRootResult(Vec<H256>, Vec<H256>, Vec<u8>, Option<V
 pub struct SparseMerkleTree<Storage = MemoryStorage, Hasher = DefaultTreeHasher> {
     pub(crate) nodes: Storage,
     pub(crate) values: Storage,
+    /// Keys of the leaves by path
+    pub(crate) keys: Storage,
+    /// Nodes of the [`index`] of the keys by hash
+    pub(crate) index: Storage,
     pub(crate) hasher: Hasher,
     pub(crate) root: H256,
     pub(crate) parent: H256,
+    pub(crate) index_root: H256,
+}
+
+/// Marks an encoding that starts with its version. Trees encoded before the encoding was
+/// versioned start with the length of their nodes, which as a varint never starts with this
+/// byte.
+const VERSIONED: u8 = 0xff;
+const VERSION: u8 = 1;
+
+fn encode_snapshot<S: StorageBackend, E: Encoder>(
+    storage: &S,
+    encoder: &mut E,
+) -> core::result::Result<(), EncodeError> {
+    let snapshot = storage
+        .snapshot()
+        .map_err(|e| EncodeError::OtherString(format!("{}", e)))?;
+    Encode::encode(&snapshot, encoder)
+}
+
+fn decode_snapshot<S: StorageBackend, D: Decoder>(
+    decoder: &mut D,
+) -> core::result::Result<S, DecodeError> {
+    let snapshot: StorageBackendSnapshot = Decode::decode(decoder)?;
+    S::from_snapshot(snapshot).map_err(|e| DecodeError::OtherString(format!("{}", e)))
 }
 
 impl<Storage: StorageBackend, Hasher: TreeHasher> Encode for SparseMerkleTree<Storage, Hasher> {
     fn encode<E: Encoder>(&self, encoder: &mut E) -> core::result::Result<(), EncodeError> {
-        Encode::encode(
-            &self
-                .nodes
-                .snapshot()
-                .map_err(|e| EncodeError::OtherString(format!("{}", e)))?,
-            encoder,
-        )?;
-        Encode::encode(
-            &self
-                .values
-                .snapshot()
-                .map_err(|e| EncodeError::OtherString(format!("{}", e)))?,
-            encoder,
-        )?;
+        Encode::encode(&VERSIONED, encoder)?;
+        Encode::encode(&VERSION, encoder)?;
+        encode_snapshot(&self.nodes, encoder)?;
+        encode_snapshot(&self.values, encoder)?;
         Encode::encode(&self.hasher, encoder)?;
         Encode::encode(&self.root, encoder)?;
         Encode::encode(&self.parent, encoder)?;
+        encode_snapshot(&self.keys, encoder)?;
+        encode_snapshot(&self.index, encoder)?;
+        Encode::encode(&self.index_root, encoder)?;
         Ok(())
     }
 }
 
 impl<Storage: StorageBackend, Hasher: TreeHasher> Decode for SparseMerkleTree<Storage, Hasher> {
+    /// Trees encoded before the encoding was versioned are only recognized when decoding from a
+    /// slice, they have no keys and no index
     fn decode<D: Decoder>(decoder: &mut D) -> core::result::Result<Self, DecodeError> {
-        let nodes_snapshot: StorageBackendSnapshot = bincode::Decode::decode(decoder)
-            .map_err(|e| DecodeError::OtherString(format!("{}", e)))?;
-        let values_snapshot: StorageBackendSnapshot = bincode::Decode::decode(decoder)
-            .map_err(|e| DecodeError::OtherString(format!("{}", e)))?;
-        let hasher: Hasher = Decode::decode(decoder)?;
-        let root: H256 = bincode::Decode::decode(decoder)?;
-        let parent: H256 = bincode::Decode::decode(decoder)?;
-        let nodes = Storage::from_snapshot(nodes_snapshot)
-            .map_err(|e| DecodeError::OtherString(format!("{}", e)))?;
-        let values = Storage::from_snapshot(values_snapshot)
-            .map_err(|e| DecodeError::OtherString(format!("{}", e)))?;
+        let versioned = match decoder.reader().peek_read(1) {
+            Some(&[first]) => first == VERSIONED,
+            _ => true,
+        };
+        if !versioned {
+            let nodes = decode_snapshot(decoder)?;
+            let values = decode_snapshot(decoder)?;
+            let hasher = Decode::decode(decoder)?;
+            let mut tree = Self::new_with_hasher(hasher, nodes, values);
+            tree.root = Decode::decode(decoder)?;
+            tree.parent = Decode::decode(decoder)?;
+            return Ok(tree);
+        }
 
+        let marker: u8 = Decode::decode(decoder)?;
+        let version: u8 = Decode::decode(decoder)?;
+        if marker != VERSIONED || version != VERSION {
+            return Err(DecodeError::OtherString(format!(
+                "unsupported tree encoding {} version {}",
+                marker, version
+            )));
+        }
         Ok(Self {
-            hasher,
-            nodes,
-            values,
-            root,
-            parent,
+            nodes: decode_snapshot(decoder)?,
+            values: decode_snapshot(decoder)?,
+            hasher: Decode::decode(decoder)?,
+            root: Decode::decode(decoder)?,
+            parent: Decode::decode(decoder)?,
+            keys: decode_snapshot(decoder)?,
+            index: decode_snapshot(decoder)?,
+            index_root: Decode::decode(decoder)?,
         })
     }
 }
@@ -93,9 +133,12 @@ impl SparseMerkleTree<MemoryStorage, DefaultTreeHasher> {
         Self {
             nodes: MemoryStorage::new(),
             values: MemoryStorage::new(),
+            keys: MemoryStorage::new(),
+            index: MemoryStorage::new(),
             hasher: DefaultTreeHasher,
             root: Default::default(),
             parent: Default::default(),
+            index_root: Default::default(),
         }
     }
 }
@@ -111,9 +154,12 @@ impl<Storage: StorageBackend, Hasher: TreeHasher> SparseMerkleTree<Storage, Hash
         Self {
             nodes,
             values,
+            keys: Storage::new(),
+            index: Storage::new(),
             hasher,
             root: Default::default(),
             parent: Default::default(),
+            index_root: Default::default(),
         }
     }
 
@@ -121,15 +167,25 @@ impl<Storage: StorageBackend, Hasher: TreeHasher> SparseMerkleTree<Storage, Hash
         self.root = new_root
     }
 
+    pub fn hasher(&self) -> &Hasher {
+        &self.hasher
+    }
+
+    pub fn set_hasher(&mut self, hasher: Hasher) {
+        self.hasher = hasher
+    }
+
     pub fn subtree(&self, strategy: CopyStrategy, import_keys: Vec<Vec<u8>>) -> Result<Self> {
         let mut subtree =
             SparseMerkleTree::new_with_hasher(self.hasher.clone(), Storage::new(), Storage::new());
         subtree.parent = self.root;
         subtree.root = self.root;
+        subtree.index_root = self.index_root;
 
         match strategy {
             CopyStrategy::Partial => {
                 subtree.nodes = self.nodes.clone();
+                subtree.index = self.index.clone();
                 for key in import_keys {
                     let (value, proof) = self.get_with_proof_updatable(&key)?;
                     subtree.add_branch(&proof, self.root(), &key, &value)?;
@@ -153,7 +209,9 @@ impl<Storage: StorageBackend, Hasher: TreeHasher> SparseMerkleTree<Storage, Hash
     ) -> Result<()> {
         let updates = verify_proof_with_updates(&self.hasher, proof, root, key, value)?;
         if !value.is_empty() {
-            self.values.put(self.hasher.path(key).as_bytes(), value)?;
+            let path = self.hasher.path(key);
+            self.values.put(path.as_bytes(), value)?;
+            self.keys.put(path.as_bytes(), key)?;
         }
 
         for update in updates {
@@ -170,16 +228,35 @@ impl<Storage: StorageBackend, Hasher: TreeHasher> SparseMerkleTree<Storage, Hash
     }
 
     fn update_for_root(&mut self, key: &[u8], value: &[u8], root: H256) -> Result<H256> {
+        if value.is_empty() {
+            return self.delete_for_root(key, root);
+        }
         let path = self.hasher.path(key);
         let SideNodesForRootResult(side_nodes, path_nodes, old_lead_data, _) =
             self.side_nodes_for_root(&path, &root, false)?;
+        let new_root =
+            self.update_with_sides_nodes(&path, value, &side_nodes, &path_nodes, &old_lead_data)?;
+        self.keys.put(path.as_bytes(), key)?;
+        self.index_root = index::insert(&mut self.index, &self.hasher, self.index_root, key)?;
+        Ok(new_root)
+    }
 
-        if value.is_empty() {
-            self.values.delete(path.as_bytes())?;
-            self.delete_with_sides_nodes(&path, &side_nodes, &path_nodes, &old_lead_data)
-        } else {
-            self.update_with_sides_nodes(&path, value, &side_nodes, &path_nodes, &old_lead_data)
+    fn delete_for_root(&mut self, key: &[u8], root: H256) -> Result<H256> {
+        let path = self.hasher.path(key);
+        let SideNodesForRootResult(side_nodes, path_nodes, old_leaf_data, _) =
+            self.side_nodes_for_root(&path, &root, false)?;
+        let new_root =
+            self.delete_with_sides_nodes(&path, &side_nodes, &path_nodes, &old_leaf_data)?;
+        self.values.delete(path.as_bytes())?;
+        if !self
+            .keys
+            .get_or_default(path.as_bytes(), Vec::new())?
+            .is_empty()
+        {
+            self.keys.delete(path.as_bytes())?;
         }
+        self.index_root = index::remove(&mut self.index, &self.hasher, self.index_root, key)?;
+        Ok(new_root)
     }
 
     fn depth(&self) -> usize {
@@ -415,7 +492,7 @@ impl<Storage: StorageBackend, Hasher: TreeHasher> SparseMerkleTree<Storage, Hash
     }
 
     pub fn get_with_proof_for_root(&self, key: &[u8], root: &H256) -> Result<(Vec<u8>, Proof)> {
-        let value = self.get(key)?;
+        let value = self.get(key)?.unwrap_or_default();
         let proof = self.do_proof_for_root(key, root, false)?;
         Ok((value, proof))
     }
@@ -425,7 +502,7 @@ impl<Storage: StorageBackend, Hasher: TreeHasher> SparseMerkleTree<Storage, Hash
         key: &[u8],
         root: &H256,
     ) -> Result<(Vec<u8>, Proof)> {
-        let value = self.get(key)?;
+        let value = self.get(key)?.unwrap_or_default();
         let proof = self.do_proof_for_root(key, root, true)?;
         Ok((value, proof))
     }
@@ -454,17 +531,81 @@ impl<Storage: StorageBackend, Hasher: TreeHasher> SparseMerkleTree<Storage, Hash
         })
     }
 
-    pub fn get<K>(&self, key: K) -> Result<Vec<u8>>
+    pub fn non_membership_proof<K: AsRef<[u8]>>(&self, key: K) -> Result<Proof> {
+        self.non_membership_proof_for_root(key.as_ref(), &self.root)
+    }
+
+    /// Proves that `key` is absent under `root`, fails with [`Error::KeyNotEmpty`] if it is
+    /// present
+    pub fn non_membership_proof_for_root(&self, key: &[u8], root: &H256) -> Result<Proof> {
+        let proof = self.do_proof_for_root(key, root, false)?;
+        if !verify_non_membership(&self.hasher, &proof, *root, key) {
+            return Err(Error::KeyNotEmpty);
+        }
+        Ok(proof)
+    }
+
+    pub fn batch_proof<K: AsRef<[u8]>>(&self, keys: &[K]) -> Result<BatchProof> {
+        self.batch_proof_for_root(keys, &self.root)
+    }
+
+    /// Proves the values of all `keys` under `root`, siblings needed by several keys are included
+    /// once
+    pub fn batch_proof_for_root<K: AsRef<[u8]>>(
+        &self,
+        keys: &[K],
+        root: &H256,
+    ) -> Result<BatchProof> {
+        let mut proof = BatchProof::default();
+        let mut leaves = Vec::with_capacity(keys.len());
+        let mut key_side_nodes = Vec::with_capacity(keys.len());
+        for key in keys {
+            let path = self.hasher.path(key.as_ref());
+            let SideNodesForRootResult(side_nodes, path_nodes, leaf_data, _) =
+                self.side_nodes_for_root(&path, root, false)?;
+            let mut non_membership_leaf_data = None;
+            if !path_nodes[0].is_zero() {
+                let (actual_path, _) = self.hasher.parse_leaf(&leaf_data);
+                if !actual_path.eq(path.as_bytes()) {
+                    non_membership_leaf_data = Some(leaf_data)
+                }
+            }
+            proof.depths.push(side_nodes.len() as u16);
+            proof
+                .non_membership_leaf_data
+                .push(non_membership_leaf_data);
+            leaves.push((side_nodes.len(), path, path_nodes[0]));
+            key_side_nodes.push(side_nodes);
+        }
+
+        // Side nodes are ordered from the leaf up
+        let mut count = 0;
+        let computed = fold_batch(&self.hasher, &leaves, |depth, index| {
+            let side_nodes = &key_side_nodes[index];
+            let node = side_nodes[side_nodes.len() - depth];
+            proof.push_side_node(&self.hasher, count, node);
+            count += 1;
+            Ok(node)
+        })?;
+        if computed.ne(root) {
+            return Err(Error::BadBatchProof);
+        }
+        Ok(proof)
+    }
+
+    /// The value of `key`, `None` if the key is absent
+    pub fn get<K>(&self, key: K) -> Result<Option<Vec<u8>>>
     where
         K: AsRef<[u8]>,
     {
         let root = self.root();
         if root.is_zero() {
-            return Ok(Vec::new());
+            return Ok(None);
         }
 
         let path = self.hasher.path(key.as_ref());
-        self.values.get_or_default(path.as_bytes(), Vec::new())
+        let value = self.values.get_or_default(path.as_bytes(), Vec::new())?;
+        Ok((!value.is_empty()).then_some(value))
     }
 
     pub fn get_with_proof<K>(&self, key: K) -> Result<(Vec<u8>, Proof)>
@@ -491,24 +632,152 @@ impl<Storage: StorageBackend, Hasher: TreeHasher> SparseMerkleTree<Storage, Hash
         Ok(new_root)
     }
 
+    /// Removes `key` and collapses the branches left with a single leaf, fails with
+    /// [`Error::KeyAlreadyEmpty`] if the key is absent
+    pub fn delete<K>(&mut self, key: K) -> Result<H256>
+    where
+        K: AsRef<[u8]>,
+    {
+        let new_root = self.delete_for_root(key.as_ref(), self.root())?;
+        self.set_root(new_root);
+        Ok(new_root)
+    }
+
     pub fn root(&self) -> H256 {
         self.root
     }
     pub fn parent(&self) -> H256 {
         self.parent
     }
+    /// A tree that is its own parent has no ancestors to read values from
+    pub fn set_parent(&mut self, parent: H256) {
+        self.parent = parent
+    }
+    /// Copies the values and keys of `ancestor` that this tree does not override, so the
+    /// ancestor can be dropped from a chain of subtrees
+    pub fn inherit_values(&mut self, ancestor: &Self) -> Result<()> {
+        for (path, value) in ancestor.values.snapshot()? {
+            if self.values.get_or_default(&path, Vec::new())?.is_empty() {
+                self.values.put(&path, &value)?;
+            }
+        }
+        for (path, key) in ancestor.keys.snapshot()? {
+            if self.keys.get_or_default(&path, Vec::new())?.is_empty() {
+                self.keys.put(&path, &key)?;
+            }
+        }
+        Ok(())
+    }
     pub fn values(&self) -> Result<StorageBackendSnapshot> {
         self.values.snapshot()
     }
     pub fn nodes(&self) -> Result<StorageBackendSnapshot> {
         self.nodes.snapshot()
     }
+    pub fn keys(&self) -> Result<StorageBackendSnapshot> {
+        self.keys.snapshot()
+    }
+    pub fn index(&self) -> Result<StorageBackendSnapshot> {
+        self.index.snapshot()
+    }
+    pub fn index_root(&self) -> H256 {
+        self.index_root
+    }
+
+    /// Up to `limit` entries whose key starts with `prefix`, sorted by key and starting at
+    /// `start` if set. Leaves written before keys were recorded are skipped.
+    pub fn scan(
+        &self,
+        prefix: &[u8],
+        start: Option<&[u8]>,
+        limit: usize,
+    ) -> Result<Vec<(Vec<u8>, Vec<u8>)>> {
+        let from = match start {
+            Some(start) if start > prefix => start,
+            _ => prefix,
+        };
+        let mut cursor = Cursor::seek(&self.index, self.index_root, from)?;
+        let mut entries = Vec::new();
+        while entries.len() < limit {
+            // Keys with the prefix are next to each other
+            let Some(key) = cursor.next_key()? else {
+                break;
+            };
+            if !key.starts_with(prefix) {
+                break;
+            }
+            let value = self.values.get(self.hasher.path(&key).as_bytes())?;
+            entries.push((key, value));
+        }
+        Ok(entries)
+    }
+
+    /// Checks that every index node matches its hash and that the index holds exactly the keys
+    /// recorded for the leaves under the root
+    pub fn verify_index(&self) -> Result<()> {
+        for (hash, data) in self.index.snapshot()? {
+            if self.hasher.digest(&data).as_bytes() != hash.as_slice() {
+                return Err(Error::CustomError(format!(
+                    "key index node {:?} does not match its hash",
+                    H256::from_slice(&hash)
+                )));
+            }
+        }
+        let mut index = Storage::new();
+        let mut index_root = H256::zero();
+        for path in self.leaf_paths()? {
+            let key = self.keys.get_or_default(&path, Vec::new())?;
+            if key.is_empty() {
+                continue;
+            }
+            if self.hasher.path(&key).as_bytes() != path.as_slice() {
+                return Err(Error::CustomError(format!(
+                    "key {:?} is recorded under another path",
+                    key
+                )));
+            }
+            index_root = index::insert(&mut index, &self.hasher, index_root, &key)?;
+        }
+        if index_root != self.index_root {
+            return Err(Error::CustomError(format!(
+                "key index root {:?} does not match the keys, expected {:?}",
+                self.index_root, index_root
+            )));
+        }
+        Ok(())
+    }
+
+    /// Paths of every leaf under the root
+    pub(crate) fn leaf_paths(&self) -> Result<Vec<Vec<u8>>> {
+        let mut paths = Vec::new();
+        let mut pending = Vec::new();
+        if !self.root.is_zero() {
+            pending.push(self.root);
+        }
+        while let Some(hash) = pending.pop() {
+            let data = self.nodes.get(hash.as_bytes())?;
+            if self.hasher.is_leaf(&data) {
+                let (path, _) = self.hasher.parse_leaf(&data);
+                paths.push(path.to_vec());
+                continue;
+            }
+            let (left, right) = self.hasher.parse_node(&data);
+            for child in [left, right] {
+                let child = H256::from_slice(child);
+                if !child.is_zero() {
+                    pending.push(child);
+                }
+            }
+        }
+        Ok(paths)
+    }
 }
 
 #[cfg(test)]
 mod tests {
+    use crate::proof::verify_proof;
     use crate::smt::SparseMerkleTree;
-    use crate::CopyStrategy;
+    use crate::{CopyStrategy, MemoryStorage, StorageBackend, TreeHasherKind};
     use alloc::vec;
     use codec::{Decodable, Encodable};
 
@@ -535,4 +804,104 @@ mod tests {
             smt_decoded.get([1, 2, 3]).unwrap()
         )
     }
+
+    #[test]
+    fn test_scan_sorted_by_key() {
+        let mut smt = SparseMerkleTree::new();
+        for key in [&b"user/carol"[..], b"user/alice", b"token/x", b"user/bob"] {
+            smt.update(key, key.len().to_be_bytes()).unwrap();
+        }
+        let keys = |entries: Vec<(Vec<u8>, Vec<u8>)>| {
+            entries.into_iter().map(|(key, _)| key).collect::<Vec<_>>()
+        };
+        assert_eq!(
+            keys(smt.scan(b"user/", None, 10).unwrap()),
+            vec![
+                b"user/alice".to_vec(),
+                b"user/bob".to_vec(),
+                b"user/carol".to_vec()
+            ]
+        );
+        assert_eq!(
+            keys(smt.scan(b"user/", Some(b"user/b"), 1).unwrap()),
+            vec![b"user/bob".to_vec()]
+        );
+
+        smt.delete(b"user/bob").unwrap();
+        let decoded: SparseMerkleTree = SparseMerkleTree::decode(&smt.encode().unwrap()).unwrap();
+        assert_eq!(
+            decoded.scan(b"user/", Some(b"user/b"), 10).unwrap(),
+            vec![(b"user/carol".to_vec(), 10usize.to_be_bytes().to_vec())]
+        );
+    }
+
+    #[test]
+    fn test_decode_unversioned_tree() {
+        let mut smt = SparseMerkleTree::new();
+        for i in 0u8..4 {
+            smt.update([i], [i]).unwrap();
+        }
+        // Layout of the trees encoded before the encoding was versioned
+        let legacy = bincode::encode_to_vec(
+            (
+                smt.nodes().unwrap(),
+                smt.values().unwrap(),
+                smt.root,
+                smt.parent,
+            ),
+            bincode::config::standard(),
+        )
+        .unwrap();
+        let decoded: SparseMerkleTree = SparseMerkleTree::decode(&legacy).unwrap();
+        assert_eq!(decoded.root(), smt.root());
+        assert_eq!(decoded.get([2]).unwrap(), Some(vec![2]));
+        // Keys written before they were indexed are not scanned
+        assert!(decoded.scan(&[], None, 10).unwrap().is_empty());
+
+        let mut encoded = smt.encode().unwrap();
+        encoded[1] += 1;
+        assert!(SparseMerkleTree::<MemoryStorage>::decode(&encoded).is_err());
+    }
+
+    #[test]
+    fn test_tree_hasher_kinds() {
+        let root = |kind: TreeHasherKind| {
+            let mut smt =
+                SparseMerkleTree::new_with_hasher(kind, MemoryStorage::new(), MemoryStorage::new());
+            smt.update(b"key", b"value").unwrap();
+            smt.update(b"other", b"value").unwrap();
+            let proof = smt.proof(b"key").unwrap();
+            assert!(verify_proof(&kind, &proof, smt.root(), b"key", b"value"));
+            smt
+        };
+        let keccak = root(TreeHasherKind::Keccak256);
+        let mut default = SparseMerkleTree::new();
+        default.update(b"key", b"value").unwrap();
+        default.update(b"other", b"value").unwrap();
+        assert_eq!(keccak.root(), default.root());
+        // Encoded the same as the default hasher
+        assert_eq!(keccak.encode().unwrap(), default.encode().unwrap());
+
+        let blake2b = root(TreeHasherKind::Blake2b256);
+        let sha256 = root(TreeHasherKind::Sha256);
+        assert_ne!(blake2b.root(), keccak.root());
+        assert_ne!(sha256.root(), keccak.root());
+        assert_ne!(sha256.root(), blake2b.root());
+
+        let mut decoded: SparseMerkleTree<MemoryStorage, TreeHasherKind> =
+            SparseMerkleTree::decode(&blake2b.encode().unwrap()).unwrap();
+        decoded.set_hasher(TreeHasherKind::Blake2b256);
+        let mut updated = blake2b.clone();
+        updated.update(b"key", b"new").unwrap();
+        decoded.update(b"key", b"new").unwrap();
+        assert_eq!(decoded.root(), updated.root());
+
+        for kind in [
+            TreeHasherKind::Keccak256,
+            TreeHasherKind::Blake2b256,
+            TreeHasherKind::Sha256,
+        ] {
+            assert_eq!(TreeHasherKind::from_name(kind.name()), Some(kind));
+        }
+    }
 }
@@ -1,3 +1,5 @@
+use primitive_types::H256;
+
 pub(crate) fn get_bits_at_from_msb(data: &[u8], position: usize) -> i32 {
     let position = position as i32;
     let t = (data[(position / 8) as usize] as i32) & (1 << (8 - 1 - ((position as u32) % 8)));
@@ -18,3 +20,20 @@ pub(crate) fn count_common_prefix(lhs: &[u8], rhs: &[u8]) -> i32 {
     }
     count
 }
+
+pub(crate) fn set_bit_at_from_msb(data: &mut [u8], position: usize) {
+    data[position / 8] |= 1 << (8 - 1 - position % 8);
+}
+
+/// Keeps the first `len` bits of `path` and clears the rest
+pub(crate) fn path_prefix(path: &H256, len: usize) -> H256 {
+    let mut prefix = H256::zero();
+    let full_bytes = len / 8;
+    prefix.as_bytes_mut()[..full_bytes].copy_from_slice(&path.as_bytes()[..full_bytes]);
+    let partial_bits = len % 8;
+    if partial_bits > 0 {
+        prefix.as_bytes_mut()[full_bytes] =
+            path.as_bytes()[full_bytes] & (0xff << (8 - partial_bits));
+    }
+    prefix
+}
@@ -32,6 +32,14 @@ pub enum StateError {
     GenesisAlreadyInitialized,
     #[error("NonceIsLessThanCurrent")]
     NonceIsLessThanCurrent,
+    #[error("InvalidNonce expected {expected} got {got}")]
+    InvalidNonce { expected: u64, got: u64 },
+    #[error("InvalidChainId expected {expected} got {got}")]
+    InvalidChainId { expected: u32, got: u32 },
+    #[error("UnsupportedTransaction {0}")]
+    UnsupportedTransaction(&'static str),
+    #[error("TreeHasherMismatch expected {expected} found {found}")]
+    TreeHasherMismatch { expected: String, found: String },
     #[error("LogIndexNoFound")]
     LogIndexNoFound,
     #[error("ColumnFamilyMissing {0}")]
@@ -44,6 +52,14 @@ pub enum StateError {
     CodecErrorEncoding,
     #[error("InsufficientFunds")]
     InsufficientFunds,
+    #[error("ImmatureReward")]
+    ImmatureReward,
+    #[error("Overflow")]
+    Overflow,
+    #[error("Underflow")]
+    Underflow,
+    #[error("ExecutionWorkerPanicked {0}")]
+    ExecutionWorkerPanicked(String),
 
     #[error("Invalid Key {0}")]
     InvalidKey(String),
@@ -62,6 +62,20 @@ where
         V::decode(&raw)
     }
 
+    /// Returns every persisted entry, staged entries of a read only store are not included
+    pub(crate) fn entries(&self) -> Result<Vec<(K, V)>> {
+        self.inner
+            .keys()?
+            .iter()
+            .map(|key| Ok((K::decode(key)?, V::decode(&self.inner.get(key)?)?)))
+            .collect()
+    }
+
+    pub(crate) fn checkpoint<P: AsRef<Path>>(&self, path: P) -> Result<()> {
+        self.inner.checkpoint(path.as_ref().to_path_buf())?;
+        Ok(())
+    }
+
     pub(crate) fn delete(&self, key: &K) -> Result<()> {
         let key = key.encode()?;
         if self.read_only {
@@ -1,39 +1,71 @@
 #![allow(dead_code)]
-use std::collections::BTreeMap;
+use std::collections::{BTreeMap, BTreeSet};
+use std::fs;
 use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicUsize, Ordering};
 use std::sync::Arc;
 
 use crate::error::StateError;
 use crate::kvdb::KvDB;
-use crate::tree::{Op, TreeDB};
-use anyhow::{bail, Result};
+use crate::snapshot::{app_roots, StateSnapshot};
+use crate::store::{AppStorageDatabase, LiveEntries};
+use crate::tree::{Op, Options, TreeDB};
+use anyhow::{bail, ensure, Result};
 use primitive_types::address::Address;
 use primitive_types::H256;
 use schema::ReadProof;
-use smt::SparseMerkleTree;
+use smt::overlay::NodeStore;
+use smt::proof::Proof;
+use smt::treehasher::TreeHasher;
+use smt::{SparseMerkleTree, TreeHasherKind};
+use tracing::debug;
 use traits::{StateDB, WasmVMInstance};
-use transaction::{NoncePricedTransaction, TransactionsByNonceAndPrice};
-use types::account::AccountState;
-use types::app::{AppMetadata, AppStateKey};
+use types::account::{AccountState, BlockReward};
+use types::app::{AppMetadata, AppStateKey, AppStorage};
+use types::diff::{StateDiff, StorageWrites};
 use types::prelude::{AppState, TransactionData};
+use types::receipt::{Log, Receipt};
 use types::tx::SignedTransaction;
 use types::Hash;
 
 pub mod error;
 pub mod kvdb;
 pub mod persistent;
+mod schedule;
 pub mod schema;
+pub mod snapshot;
 pub mod store;
 pub mod tree;
 
 const ACCOUNT_DB_NAME: &str = "accounts";
+const APP_STORAGE_DB_NAME: &str = "appstorage";
+/// App storage trees serialized whole per root, migrated to [`APP_STORAGE_DB_NAME`] on open
 const APPDATA_DB_NAME: &str = "appdata";
+/// Written once every tree of [`APPDATA_DB_NAME`] was read back from [`APP_STORAGE_DB_NAME`]
+const APPDATA_MIGRATED_MARKER: &str = "appdata.migrated";
 const METADATA_DB_NAME: &str = "metadata";
 
+/// State roots and app storage entries a prune deletes, read by [`State::plan_prune`]
+pub struct PrunePlan {
+    /// Account trie roots persisted when the plan was made
+    roots: BTreeSet<H256>,
+    keep: BTreeSet<H256>,
+    live: LiveEntries,
+    unmarked: Vec<(&'static str, Vec<u8>)>,
+}
+
+/// Account states, receipts, app storage writes and collected fees of executed transactions
+struct Execution {
+    states: BTreeMap<Address, AccountState>,
+    receipts: BTreeMap<H256, Receipt>,
+    storage_writes: BTreeMap<Address, StorageWrites>,
+    fees: u64,
+}
+
 #[derive(Clone)]
 pub struct State {
-    trie: Arc<TreeDB<Address, AccountState>>,
-    appdata: Arc<KvDB<AppStateKey, SparseMerkleTree>>,
+    trie: Arc<TreeDB<Address, AccountState, TreeHasherKind>>,
+    app_storage: Arc<AppStorageDatabase>,
     metadata: Arc<KvDB<H256, AppMetadata>>,
     path: PathBuf,
     read_only: bool,
@@ -66,14 +98,20 @@ impl StateDB for State {
 
     fn credit_balance(&self, address: &Address, amount: u64) -> Result<H256> {
         let mut account_state = self.get_account_state(address)?;
-        account_state.free_balance += amount;
+        account_state.free_balance = account_state
+            .free_balance
+            .checked_add(amount)
+            .ok_or(StateError::Overflow)?;
         self.trie.put(*address, account_state)?;
         self.root_hash()
     }
 
     fn debit_balance(&self, address: &Address, amount: u64) -> Result<H256> {
         let mut account_state = self.get_account_state(address)?;
-        account_state.free_balance -= amount;
+        account_state.free_balance = account_state
+            .free_balance
+            .checked_sub(amount)
+            .ok_or(StateError::Underflow)?;
         self.trie.put(*address, account_state)?;
         self.root_hash()
     }
@@ -82,9 +120,13 @@ impl StateDB for State {
         self.trie.reset(root)
     }
 
-    fn apply_txs(&self, vm: Arc<dyn WasmVMInstance>, txs: &[SignedTransaction]) -> Result<H256> {
-        self.apply_txs(vm, txs)?;
-        self.root_hash().map(H256::from)
+    fn apply_txs(
+        &self,
+        vm: Arc<dyn WasmVMInstance>,
+        reward: &BlockReward,
+        txs: &[SignedTransaction],
+    ) -> Result<(Vec<Receipt>, StateDiff)> {
+        self.apply_txs(vm, reward, txs)
     }
 
     fn root(&self) -> H256 {
@@ -103,7 +145,12 @@ impl StateDB for State {
         Ok(self.get_sate_at(root)?)
     }
 
-    fn get_app_data(&self, app_id: Address) -> Result<SparseMerkleTree> {
+    fn account_state_with_proof(&self, address: &Address) -> Result<(Option<AccountState>, Proof)> {
+        self.get_account_state_with_proof(address)
+            .map(|(account_state, read_proof)| (account_state, read_proof.proof))
+    }
+
+    fn get_app_data(&self, app_id: Address) -> Result<AppStorage> {
         let Ok(Some(app_account_state)) = self.trie.get(&app_id) else {
             bail!("app not found")
         };
@@ -112,14 +159,15 @@ impl StateDB for State {
             bail!("app not initialized")
         };
 
-        Ok(self
-            .appdata
-            .get(&AppStateKey(app_id, app_root))
-            .unwrap_or_else(|_| SparseMerkleTree::new()))
+        Ok(SparseMerkleTree::open_with_hasher(
+            self.tree_hasher(),
+            self.app_storage.clone(),
+            app_root,
+        )?)
     }
 
-    fn set_app_data(&self, app_state_key: AppStateKey, app_data: SparseMerkleTree) -> Result<()> {
-        self.appdata.put(app_state_key, app_data)
+    fn set_app_data(&self, app_data: &AppStorage) -> Result<()> {
+        self.app_storage.put(app_data)
     }
 
     fn get_app_source(&self, app_id: Address) -> Result<Vec<u8>> {
@@ -148,6 +196,10 @@ impl StateDB for State {
             .map(|bins| bins.descriptor)
     }
 
+    fn tree_hasher(&self) -> TreeHasherKind {
+        *self.trie.hasher()
+    }
+
     fn set_app_metadata(&self, binary: &[u8], descriptor: Vec<u8>) -> Result<()> {
         let code_hash = crypto::keccak256(binary);
         self.metadata.put(
@@ -162,53 +214,348 @@ impl StateDB for State {
 
 impl State {
     pub fn new<P: AsRef<Path>>(path: P) -> Result<Self> {
-        let trie = TreeDB::open(path.as_ref().join(ACCOUNT_DB_NAME).as_path())?;
-        let appdata = KvDB::open(path.as_ref().join(APPDATA_DB_NAME).as_path())?;
+        Self::with_hasher(path, TreeHasherKind::default())
+    }
+
+    /// Opens the state at `path` with the trees hashed by `hasher`, fails if the state was
+    /// created with another hasher
+    pub fn with_hasher<P: AsRef<Path>>(path: P, hasher: TreeHasherKind) -> Result<Self> {
+        let trie = TreeDB::open_with_options(
+            hasher,
+            path.as_ref().join(ACCOUNT_DB_NAME).as_path(),
+            Options::default(),
+        )?;
+        let found = match trie.hasher_name()? {
+            Some(name) => name,
+            // States created before the hasher was recorded are hashed with Keccak-256
+            None if !trie.root()?.is_zero() => TreeHasherKind::Keccak256.name().to_string(),
+            None => hasher.name().to_string(),
+        };
+        if found != hasher.name() {
+            return Err(StateError::TreeHasherMismatch {
+                expected: hasher.name().to_string(),
+                found,
+            }
+            .into());
+        }
+        trie.set_hasher_name(hasher.name())?;
+        let app_storage = Arc::new(AppStorageDatabase::open(
+            path.as_ref().join(APP_STORAGE_DB_NAME),
+        )?);
+        Self::migrate_appdata(path.as_ref(), &app_storage)?;
         let metadata = KvDB::open(path.as_ref().join(METADATA_DB_NAME).as_path())?;
         Ok(Self {
             trie: Arc::new(trie),
-            appdata: Arc::new(appdata),
+            app_storage,
             metadata: Arc::new(metadata),
             path: path.as_ref().to_path_buf(),
             read_only: false,
         })
     }
 
-    pub fn apply_txs(&self, vm: Arc<dyn WasmVMInstance>, txs: &[SignedTransaction]) -> Result<()> {
-        let mut accounts: BTreeMap<Address, TransactionsByNonceAndPrice> = BTreeMap::new();
-        let mut states: BTreeMap<Address, AccountState> = BTreeMap::new();
-
-        for tx in txs {
-            if let std::collections::btree_map::Entry::Vacant(e) = states.entry(tx.from()) {
-                let current_state = self.get_account_state(&tx.from())?;
-                e.insert(current_state);
+    /// Copies app storage written as whole trees per root into the shared node store. Every
+    /// tree is then reopened from the node store and compared with the legacy one, the legacy
+    /// trees are kept until [`State::remove_legacy_appdata`] deletes them.
+    fn migrate_appdata(path: &Path, app_storage: &Arc<AppStorageDatabase>) -> Result<()> {
+        let legacy_path = path.join(APPDATA_DB_NAME);
+        if !legacy_path.exists() || path.join(APPDATA_MIGRATED_MARKER).exists() {
+            return Ok(());
+        }
+        let appdata: KvDB<AppStateKey, SparseMerkleTree> = KvDB::open(&legacy_path)?;
+        let entries = appdata.entries()?;
+        for (_, storage) in entries.iter() {
+            app_storage.put(storage)?;
+        }
+        for (key, storage) in entries.iter() {
+            ensure!(
+                storage.root() == key.1,
+                "legacy app storage of {} has root {:?}, expected {:?}",
+                key.0,
+                storage.root(),
+                key.1
+            );
+            let migrated = SparseMerkleTree::open(app_storage.clone(), key.1)?;
+            for (_, app_key) in storage.keys()? {
+                ensure!(
+                    migrated.get(&app_key)? == storage.get(&app_key)?,
+                    "migrated app storage of {} at {:?} differs",
+                    key.0,
+                    key.1
+                );
             }
-            if let std::collections::btree_map::Entry::Vacant(e) = states.entry(tx.to()) {
-                let current_state = self.get_account_state(&tx.to())?;
-                e.insert(current_state);
+            // Leaves written before keys were recorded are only checked to be stored
+            for (_, value) in storage.values()? {
+                let value_hash = storage.hasher().digest(&value);
+                ensure!(
+                    app_storage.value(&value_hash)?.as_ref() == Some(&value),
+                    "migrated app storage of {} at {:?} misses a value",
+                    key.0,
+                    key.1
+                );
             }
-            let txs = accounts.entry(tx.from()).or_default();
-            txs.insert(NoncePricedTransaction(tx));
         }
+        fs::write(path.join(APPDATA_MIGRATED_MARKER), b"")?;
+        Ok(())
+    }
 
-        for (_, txs) in accounts {
-            for tx in txs.into_iter().map(|tx| tx.0) {
-                self.apply_transaction(vm.as_ref(), &mut states, tx)?;
-            }
+    /// Deletes the app storage trees migrated to the shared node store on open. Returns false
+    /// if there are none, fails if they were not migrated.
+    pub fn remove_legacy_appdata(&self) -> Result<bool> {
+        let legacy_path = self.path.join(APPDATA_DB_NAME);
+        if !legacy_path.exists() {
+            return Ok(false);
         }
-        //TODO; Check accounts for negative balances
+        let marker = self.path.join(APPDATA_MIGRATED_MARKER);
+        ensure!(marker.exists(), "app storage has not been migrated");
+        fs::remove_dir_all(legacy_path)?;
+        fs::remove_file(marker)?;
+        Ok(true)
+    }
+
+    pub fn apply_txs(
+        &self,
+        vm: Arc<dyn WasmVMInstance>,
+        reward: &BlockReward,
+        txs: &[SignedTransaction],
+    ) -> Result<(Vec<Receipt>, StateDiff)> {
+        let load = |address: &Address| self.get_account_state(address);
+        let Execution {
+            mut states,
+            receipts,
+            storage_writes,
+            fees,
+        } = self.execute_txs(vm.as_ref(), reward.chain_id, txs, &load)?;
+        Self::apply_reward(&mut states, reward, fees, load)?;
+        let before = states
+            .keys()
+            .filter_map(|address| Some((*address, self.trie.get(address).ok()??)))
+            .collect();
+        let diff = StateDiff::new(&before, &states, storage_writes);
         for (acc, state) in states {
             self.trie.put(acc, state)?;
         }
-        Ok(())
+        Ok((Self::receipts_in_block_order(txs, receipts), diff))
+    }
+
+    /// Executes `txs` in block execution order. Clusters of transactions that declare no common
+    /// account run concurrently, if their executions touched a common account or one of them
+    /// failed, all transactions are executed again sequentially.
+    fn execute_txs<F>(
+        &self,
+        vm: &dyn WasmVMInstance,
+        chain_id: u32,
+        txs: &[SignedTransaction],
+        load: &F,
+    ) -> Result<Execution>
+    where
+        F: Fn(&Address) -> Result<AccountState> + Sync,
+    {
+        let ordered = schedule::execution_order(txs)?;
+        let clusters = schedule::clusters(&ordered)?;
+        if clusters.len() > 1 {
+            if let Some(execution) = self.execute_clusters(vm, chain_id, &clusters, load)? {
+                return Ok(execution);
+            }
+            debug!(
+                clusters = clusters.len(),
+                "Parallel execution conflicted, executing sequentially"
+            );
+        }
+        self.execute_sequential(vm, chain_id, &ordered, load)
+    }
+
+    fn execute_sequential<F>(
+        &self,
+        vm: &dyn WasmVMInstance,
+        chain_id: u32,
+        ordered: &[&SignedTransaction],
+        load: &F,
+    ) -> Result<Execution>
+    where
+        F: Fn(&Address) -> Result<AccountState>,
+    {
+        let mut states: BTreeMap<Address, AccountState> = BTreeMap::new();
+        for tx in ordered {
+            for address in [tx.sender()?, tx.to()] {
+                if let std::collections::btree_map::Entry::Vacant(e) = states.entry(address) {
+                    e.insert(load(&address)?);
+                }
+            }
+        }
+
+        let mut receipts = BTreeMap::new();
+        let mut storage_writes = BTreeMap::new();
+        let mut fees = 0;
+        for tx in ordered {
+            let receipt =
+                self.apply_transaction(vm, &mut states, &mut storage_writes, chain_id, tx)?;
+            receipts.insert(tx.hash(), receipt);
+            fees = u64::checked_add(fees, tx.fees()).ok_or(StateError::Overflow)?;
+        }
+        Ok(Execution {
+            states,
+            receipts,
+            storage_writes,
+            fees,
+        })
+    }
+
+    /// Executes the clusters on worker threads and merges the results, returns
+    /// `None` when the result could differ from sequential execution. A panic on a worker
+    /// thread is returned as an error.
+    fn execute_clusters<F>(
+        &self,
+        vm: &dyn WasmVMInstance,
+        chain_id: u32,
+        clusters: &[Vec<&SignedTransaction>],
+        load: &F,
+    ) -> Result<Option<Execution>>
+    where
+        F: Fn(&Address) -> Result<AccountState> + Sync,
+    {
+        let next = AtomicUsize::new(0);
+        let workers = num_cpus::get().clamp(1, clusters.len());
+        let mut executions: Vec<Option<Result<Execution>>> = std::iter::repeat_with(|| None)
+            .take(clusters.len())
+            .collect();
+        std::thread::scope(|scope| {
+            let handles: Vec<_> = (0..workers)
+                .map(|_| {
+                    scope.spawn(|| {
+                        let mut done = Vec::new();
+                        loop {
+                            let index = next.fetch_add(1, Ordering::Relaxed);
+                            let Some(cluster) = clusters.get(index) else {
+                                return done;
+                            };
+                            done.push((
+                                index,
+                                self.execute_sequential(vm, chain_id, cluster, load),
+                            ));
+                        }
+                    })
+                })
+                .collect();
+            for handle in handles {
+                let done = handle.join().map_err(|payload| {
+                    let message = payload
+                        .downcast_ref::<&str>()
+                        .map(|message| message.to_string())
+                        .or_else(|| payload.downcast_ref::<String>().cloned())
+                        .unwrap_or_default();
+                    StateError::ExecutionWorkerPanicked(message)
+                })?;
+                for (index, execution) in done {
+                    executions[index] = Some(execution);
+                }
+            }
+            Ok::<_, StateError>(())
+        })?;
+
+        // Each account must have been touched by a single cluster, then the order in which
+        // clusters ran does not change the result
+        let mut merged = Execution {
+            states: BTreeMap::new(),
+            receipts: BTreeMap::new(),
+            storage_writes: BTreeMap::new(),
+            fees: 0,
+        };
+        for execution in executions {
+            let Some(Ok(execution)) = execution else {
+                return Ok(None);
+            };
+            for (address, state) in execution.states {
+                if merged.states.insert(address, state).is_some() {
+                    return Ok(None);
+                }
+            }
+            merged.receipts.extend(execution.receipts);
+            merged.storage_writes.extend(execution.storage_writes);
+            let Some(fees) = merged.fees.checked_add(execution.fees) else {
+                return Ok(None);
+            };
+            merged.fees = fees;
+        }
+        Ok(Some(merged))
     }
 
+    /// Applies `tx` to `states` and records the app storage keys it wrote in `storage_writes`.
+    /// If execution fails its changes are discarded and a failed receipt is returned, the sender
+    /// is still charged the fee and its nonce is bumped, its receipt reports no fuel used. Only a nonce other than the sender's
+    /// current nonce, a sender that can not pay the fee or a transaction signed for another chain
+    /// makes the block invalid.
     fn apply_transaction(
         &self,
         vm: &dyn WasmVMInstance,
         states: &mut BTreeMap<Address, AccountState>,
+        storage_writes: &mut BTreeMap<Address, StorageWrites>,
+        chain_id: u32,
         tx: &SignedTransaction,
-    ) -> Result<()> {
+    ) -> Result<Receipt> {
+        if tx.chain_id() != chain_id {
+            return Err(StateError::InvalidChainId {
+                expected: chain_id,
+                got: tx.chain_id(),
+            }
+            .into());
+        }
+        let from = tx.from()?;
+        let expected = states.get(&from).ok_or(StateError::AccountNotFound)?.nonce;
+        if tx.nonce() != expected {
+            return Err(StateError::InvalidNonce {
+                expected,
+                got: tx.nonce(),
+            }
+            .into());
+        }
+        let checkpoint = states.clone();
+        let mut tx_writes = BTreeMap::new();
+        let (logs, fuel_used, post_state, status) =
+            match self.execute_transaction(vm, states, &mut tx_writes, tx) {
+                Ok((logs, fuel_used, post_state)) => {
+                    for (app_id, writes) in tx_writes {
+                        storage_writes.entry(app_id).or_default().extend(writes);
+                    }
+                    (logs, fuel_used, post_state, true)
+                }
+                Err(error) => {
+                    debug!(tx = ?tx.hash(), error = ?error, "Transaction failed");
+                    *states = checkpoint;
+                    (Vec::new(), 0, H256::zero(), false)
+                }
+            };
+
+        // Update transaction origin nonce and charge the fee, fees are credited to the coinbase
+        // once all transactions of the block are applied
+        let mut from_account_state = states.get_mut(&from).ok_or(StateError::AccountNotFound)?;
+        from_account_state.free_balance = from_account_state
+            .free_balance
+            .checked_sub(tx.fees())
+            .ok_or(StateError::InsufficientFunds)?;
+        from_account_state.nonce = from_account_state
+            .nonce
+            .checked_add(1)
+            .ok_or(StateError::Overflow)?;
+        Ok(Receipt::new(
+            tx.to(),
+            tx.hash(),
+            logs,
+            fuel_used,
+            post_state,
+            status,
+        ))
+    }
+
+    fn execute_transaction(
+        &self,
+        vm: &dyn WasmVMInstance,
+        states: &mut BTreeMap<Address, AccountState>,
+        storage_writes: &mut BTreeMap<Address, StorageWrites>,
+        tx: &SignedTransaction,
+    ) -> Result<(Vec<Log>, u64, H256)> {
+        let mut logs = Vec::new();
+        let mut fuel_used = 0;
+        let mut post_state = H256::zero();
         match tx.data() {
             TransactionData::Payment(_) => {
                 self.execute_payment_tx(tx, states)?;
@@ -216,7 +563,7 @@ impl State {
             TransactionData::Call(arg) => {
                 let app_address = tx.to();
                 let state_db = Arc::new(self.clone());
-                let changelist = vm.execute_app_tx(state_db, tx.sender(), tx.price(), arg)?;
+                let changelist = vm.execute_app_tx(state_db, tx.sender()?, tx.price(), arg)?;
 
                 // Apply Account Changes
                 for (addr, state) in changelist.account_changes {
@@ -228,10 +575,14 @@ impl State {
                     .and_then(|account_state| account_state.app_state.as_mut())
                     .ok_or_else(|| anyhow::anyhow!("app state not found"))?;
                 app_state.root_hash = changelist.storage.root();
-                self.appdata.put(
-                    AppStateKey(app_address, changelist.storage.root()),
-                    changelist.storage,
-                )?;
+                post_state = changelist.storage.root();
+                fuel_used = changelist.fuel_used;
+                logs = Self::encode_logs(changelist.logs)?;
+                self.app_storage.put(&changelist.storage)?;
+                storage_writes
+                    .entry(app_address)
+                    .or_default()
+                    .extend(changelist.storage_writes);
             }
             TransactionData::Create(arg) => {
                 let state_db = Arc::new(self.clone());
@@ -241,11 +592,14 @@ impl State {
                     bail!("app address already exists")
                 }
 
-                builtin::register_namespace(vm, states, tx, state_db.clone())?;
+                builtin::register_namespace(vm, states, storage_writes, tx, state_db.clone())?;
 
                 let code_hash = crypto::keccak256(&arg.binary);
                 let (descriptor, changelist) =
-                    vm.execute_app_create(state_db, tx.sender(), tx.price(), arg)?;
+                    vm.execute_app_create(state_db, tx.sender()?, tx.price(), arg)?;
+                post_state = changelist.storage.root();
+                fuel_used = changelist.fuel_used;
+                logs = Self::encode_logs(changelist.logs)?;
                 for (addr, state) in changelist.account_changes {
                     states.insert(addr, state);
                 }
@@ -255,7 +609,7 @@ impl State {
                 app_state.app_state = Some(AppState::new(
                     changelist.storage.root(),
                     code_hash,
-                    tx.from(),
+                    tx.sender()?,
                     1,
                 ));
                 self.metadata.put(
@@ -265,76 +619,92 @@ impl State {
                         descriptor,
                     },
                 )?;
-                self.appdata.put(
-                    AppStateKey(app_address, changelist.storage.root()),
-                    changelist.storage,
-                )?;
+                self.app_storage.put(&changelist.storage)?;
+                storage_writes
+                    .entry(app_address)
+                    .or_default()
+                    .extend(changelist.storage_writes);
             }
             TransactionData::Update(_) => {
-                unimplemented!("update app transaction not implemented")
-            }
-            TransactionData::RawData(raw) => {
-                println!("[NOT AVAILABLE] Raw DATA: {:?}", hex::encode_raw(raw))
+                return Err(StateError::UnsupportedTransaction("update app").into());
             }
+            // Raw data is only recorded in the block
+            TransactionData::RawData(_) => {}
         }
 
-        // Update transaction origin nonce
-        let mut from_account_state = states
-            .get_mut(&tx.from())
-            .ok_or(StateError::AccountNotFound)?;
+        Ok((logs, fuel_used, post_state))
+    }
 
-        let next_nonce = if tx.nonce() > from_account_state.nonce {
-            tx.nonce() + 1
-        } else {
-            from_account_state.nonce + 1
-        };
-        from_account_state.nonce = next_nonce;
-        Ok(())
+    fn encode_logs(logs: Vec<(String, Vec<u8>)>) -> Result<Vec<Log>> {
+        logs.into_iter()
+            .map(|log| bincode::encode_to_vec(log, codec::config()).map_err(|e| e.into()))
+            .collect()
+    }
+
+    /// Transactions are executed grouped by sender, receipts are returned in the order
+    /// the transactions appear in the block
+    fn receipts_in_block_order(
+        txs: &[SignedTransaction],
+        mut receipts: BTreeMap<H256, Receipt>,
+    ) -> Vec<Receipt> {
+        txs.iter()
+            .filter_map(|tx| receipts.remove(&tx.hash()))
+            .collect()
     }
 
     pub fn apply_txs_no_commit(
         &self,
         vm: Arc<dyn WasmVMInstance>,
         at_root: H256,
-        reward: u64,
-        coinbase: Address,
+        reward: &BlockReward,
         txs: &[SignedTransaction],
-    ) -> Result<Hash> {
-        let mut accounts: BTreeMap<Address, TransactionsByNonceAndPrice> = BTreeMap::new();
-        let mut states: BTreeMap<Address, AccountState> = BTreeMap::new();
+    ) -> Result<(Hash, Vec<Receipt>)> {
+        let load = |address: &Address| self.get_account_state_at_root(&at_root, address);
+        let Execution {
+            mut states,
+            receipts,
+            fees,
+            ..
+        } = self.execute_txs(vm.as_ref(), reward.chain_id, txs, &load)?;
+        Self::apply_reward(&mut states, reward, fees, load)?;
+        let batch: Vec<_> = states.into_iter().map(|(k, v)| Op::Put(k, v)).collect();
 
-        for tx in txs {
-            if let std::collections::btree_map::Entry::Vacant(e) = states.entry(tx.from()) {
-                let current_state = self.get_account_state_at_root(&at_root, &tx.from())?;
-                e.insert(current_state);
-            }
-            if let std::collections::btree_map::Entry::Vacant(e) = states.entry(tx.to()) {
-                let current_state = self.get_account_state_at_root(&at_root, &tx.to())?;
-                e.insert(current_state);
-            }
-            let txs = accounts.entry(tx.from()).or_default();
-            txs.insert(NoncePricedTransaction(tx));
-        }
+        let root = self.trie.apply_non_commit(&at_root, batch)?;
+        Ok((
+            root.to_fixed_bytes(),
+            Self::receipts_in_block_order(txs, receipts),
+        ))
+    }
 
-        for (_, txs) in accounts {
-            for tx in txs.iter().map(|tx| tx.0) {
-                self.apply_transaction(vm.as_ref(), &mut states, tx)?;
+    /// Releases the rewards of `matured_coinbase` and locks the block reward and collected `fees`
+    /// until the unlock level, accounts missing from `states` are loaded with `load`
+    fn apply_reward<F>(
+        states: &mut BTreeMap<Address, AccountState>,
+        reward: &BlockReward,
+        fees: u64,
+        load: F,
+    ) -> Result<()>
+    where
+        F: Fn(&Address) -> Result<AccountState>,
+    {
+        for address in std::iter::once(reward.coinbase).chain(reward.matured_coinbase) {
+            if let std::collections::btree_map::Entry::Vacant(e) = states.entry(address) {
+                e.insert(load(&address)?);
             }
         }
-
-        let mut batch: Vec<_> = states.into_iter().map(|(k, v)| Op::Put(k, v)).collect();
-
-        let mut coinbase_account_state = self
-            .trie
-            .get_at_root(&at_root, &coinbase)
-            .unwrap_or_default()
-            .unwrap_or_default();
-        coinbase_account_state.free_balance += reward;
-        batch.push(Op::Put(coinbase, coinbase_account_state));
-
-        self.trie
-            .apply_non_commit(&at_root, batch)
-            .map(|hash| hash.to_fixed_bytes())
+        if let Some(matured_coinbase) = reward.matured_coinbase {
+            states
+                .get_mut(&matured_coinbase)
+                .ok_or(StateError::AccountNotFound)?
+                .unlock_rewards(reward.level)
+                .ok_or(StateError::Overflow)?;
+        }
+        let total = reward.total(fees).ok_or(StateError::Overflow)?;
+        states
+            .get_mut(&reward.coinbase)
+            .ok_or(StateError::AccountNotFound)?
+            .lock_reward(total, reward.unlock_level);
+        Ok(())
     }
 
     fn execute_payment_tx(
@@ -343,17 +713,32 @@ impl State {
         states: &mut BTreeMap<Address, AccountState>,
     ) -> Result<()> {
         let mut from_account_state = states
-            .get_mut(&transaction.from())
+            .get_mut(&transaction.sender()?)
             .ok_or(StateError::AccountNotFound)?;
-        let amount = transaction.price() + transaction.fees();
+        let amount = transaction
+            .price()
+            .checked_add(transaction.fees())
+            .ok_or(StateError::Overflow)?;
         if from_account_state.free_balance < amount {
+            let spendable = from_account_state
+                .free_balance
+                .saturating_add(from_account_state.locked_balance());
+            if spendable >= amount {
+                return Err(StateError::ImmatureReward.into());
+            }
             return Err(StateError::InsufficientFunds.into());
         }
-        from_account_state.free_balance -= amount;
+        from_account_state.free_balance = from_account_state
+            .free_balance
+            .checked_sub(transaction.price())
+            .ok_or(StateError::Underflow)?;
         let mut to_account_state = states
             .get_mut(&transaction.to())
             .ok_or(StateError::AccountNotFound)?;
-        to_account_state.free_balance += transaction.price();
+        to_account_state.free_balance = to_account_state
+            .free_balance
+            .checked_add(transaction.price())
+            .ok_or(StateError::Overflow)?;
         Ok(())
     }
 
@@ -381,30 +766,697 @@ impl State {
     }
 
     pub fn get_sate_at(&self, root: H256) -> Result<Arc<Self>> {
-        let trie =
-            TreeDB::open_read_only_at_root(self.path.join(ACCOUNT_DB_NAME).as_path(), &root)?;
-        let appdata = KvDB::open_read_only_at_root(self.path.join(APPDATA_DB_NAME).as_path())?;
+        let trie = TreeDB::open_read_only_at_root_with_hasher(
+            self.tree_hasher(),
+            self.path.join(ACCOUNT_DB_NAME).as_path(),
+            &root,
+        )?;
+        let app_storage = AppStorageDatabase::open_read_only(self.path.join(APP_STORAGE_DB_NAME))?;
         let appsource = KvDB::open_read_only_at_root(self.path.join(METADATA_DB_NAME).as_path())?;
         Ok(Arc::new(State {
             trie: Arc::new(trie),
-            appdata: Arc::new(appdata),
+            app_storage: Arc::new(app_storage),
             metadata: Arc::new(appsource),
             path: self.path.clone(),
             read_only: true,
         }))
     }
 
-    fn get_account_state_with_proof(&self, address: &Address) -> Result<(AccountState, ReadProof)> {
-        let (account_state, proof) = self.trie.get_with_proof(address)?;
+    pub fn get_account_state_with_proof(
+        &self,
+        address: &Address,
+    ) -> Result<(Option<AccountState>, ReadProof)> {
+        let (account_state, proof) = self.trie.prove(address)?;
         let root = self.trie.root()?;
         Ok((account_state, ReadProof { proof, root }))
     }
 
-    pub fn checkpoint<P: AsRef<Path>>(&self, _path: P) -> Result<Self> {
-        unimplemented!()
+    /// Copies the committed state to `path` and opens it at the current root
+    pub fn checkpoint<P: AsRef<Path>>(&self, path: P) -> Result<Self> {
+        let path = path.as_ref();
+        fs::create_dir_all(path)?;
+        self.trie.checkpoint(path.join(ACCOUNT_DB_NAME))?;
+        self.app_storage
+            .checkpoint(path.join(APP_STORAGE_DB_NAME))?;
+        self.metadata.checkpoint(path.join(METADATA_DB_NAME))?;
+        let state = Self::with_hasher(path, self.tree_hasher())?;
+        state.reset(self.root_hash()?)?;
+        Ok(state)
+    }
+
+    /// Exports the committed state at `root` with the storage of the apps initialized at `root`
+    /// and the metadata of every app
+    pub fn snapshot_at(&self, root: H256) -> Result<StateSnapshot> {
+        let accounts = self.trie.flatten(&root)?;
+        let app_storage = app_roots(&accounts)?
+            .into_iter()
+            .map(|app_root| {
+                SparseMerkleTree::open_with_hasher(
+                    self.tree_hasher(),
+                    self.app_storage.clone(),
+                    app_root,
+                )?
+                .detach()
+                .map_err(|e| e.into())
+            })
+            .collect::<Result<_>>()?;
+        Ok(StateSnapshot {
+            root,
+            accounts,
+            app_storage,
+            metadata: self.metadata.entries()?,
+        })
+    }
+
+    /// Creates a state at `path` from a snapshot hashed with `hasher`, `path` must not contain a
+    /// state yet
+    pub fn from_snapshot<P: AsRef<Path>>(
+        path: P,
+        mut snapshot: StateSnapshot,
+        hasher: TreeHasherKind,
+    ) -> Result<Self> {
+        let path = path.as_ref();
+        ensure!(!path.exists(), "state directory {:?} already exists", path);
+        snapshot.set_hasher(hasher);
+        snapshot.verify()?;
+        let state = Self::with_hasher(path, hasher)?;
+        let root = state.trie.import(snapshot.accounts)?;
+        for storage in snapshot.app_storage.iter() {
+            state.app_storage.put(storage)?;
+        }
+        for (code_hash, metadata) in snapshot.metadata {
+            state.metadata.put(code_hash, metadata)?;
+        }
+        state.reset(root)?;
+        Ok(state)
+    }
+
+    /// Deletes the account trie roots that are not in `keep`, the current root is always kept.
+    /// App storage nodes that are no longer reachable from an app of a kept root are deleted
+    /// too.
+    pub fn prune(&self, keep: &BTreeSet<H256>) -> Result<usize> {
+        let plan = self.plan_prune(keep)?;
+        self.apply_prune(plan)
+    }
+
+    /// Reads what pruning to `keep` deletes without writing anything, so state can still be
+    /// committed meanwhile. [`State::apply_prune`] deletes it once commits are stopped.
+    pub fn plan_prune(&self, keep: &BTreeSet<H256>) -> Result<PrunePlan> {
+        let roots: BTreeSet<H256> = self.trie.roots()?.into_iter().collect();
+        let keep: BTreeSet<H256> = keep
+            .iter()
+            .copied()
+            .chain(std::iter::once(self.trie.root()?))
+            .filter(|root| roots.contains(root))
+            .collect();
+        let mut live = LiveEntries::default();
+        self.mark_live_app_storage(&keep, &keep, &mut live)?;
+        let unmarked = self.app_storage.unmarked(&live)?;
+        Ok(PrunePlan {
+            roots,
+            keep,
+            live,
+            unmarked,
+        })
+    }
+
+    /// Deletes what `plan` found prunable. Roots committed since the plan was made are kept
+    /// along with the app storage they reach, no state may be committed while this runs.
+    pub fn apply_prune(&self, plan: PrunePlan) -> Result<usize> {
+        let PrunePlan {
+            roots,
+            mut keep,
+            mut live,
+            unmarked,
+        } = plan;
+        let committed: BTreeSet<H256> = self
+            .trie
+            .roots()?
+            .into_iter()
+            .filter(|root| !roots.contains(root))
+            .collect();
+        keep.extend(committed.iter().copied());
+        self.mark_live_app_storage(&committed, &keep, &mut live)?;
+        let pruned = self.trie.prune(&keep)?;
+        let pruned_app_entries = self.app_storage.sweep(unmarked, &live)?;
+        debug!(pruned_app_entries, "Pruned app storage");
+        Ok(pruned)
+    }
+
+    /// Marks the app storage reachable from the apps of `roots` as live. The values of the
+    /// ancestors pruning to `keep` folds into a root are read too, they can hold its apps.
+    fn mark_live_app_storage(
+        &self,
+        roots: &BTreeSet<H256>,
+        keep: &BTreeSet<H256>,
+        live: &mut LiveEntries,
+    ) -> Result<()> {
+        let mut live_app_roots = BTreeSet::new();
+        for root in roots.iter() {
+            let (tree, ancestors, _) = self.trie.folded(root, keep)?;
+            live_app_roots.extend(app_roots(&tree)?);
+            for ancestor in ancestors.iter() {
+                live_app_roots.extend(app_roots(ancestor)?);
+            }
+        }
+        self.app_storage
+            .mark(&self.tree_hasher(), &live_app_roots, live)
     }
 
     pub fn root_hash(&self) -> Result<H256> {
         self.trie.root()
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use std::collections::{BTreeMap, BTreeSet};
+    use std::sync::Arc;
+
+    use anyhow::{bail, Result};
+    use tempdir::TempDir;
+
+    use account::create_account_from_uri;
+    use codec::{Decodable, Encodable};
+    use primitive_types::address::Address;
+    use primitive_types::H256;
+    use smt::{SparseMerkleTree, TreeHasherKind};
+    use traits::{StateDB, WasmVMInstance};
+    use transaction::{make_payment_sign_transaction, make_signed_transaction};
+    use types::account::{Account, AccountState, BlockReward};
+    use types::app::AppStateKey;
+    use types::network::Network;
+    use types::prelude::{AppState, TransactionData};
+    use types::receipt::Receipt;
+    use types::tx::{ApplicationCall, CreateApplication, UpdateApplication};
+    use types::Changelist;
+
+    use crate::error::StateError;
+    use crate::kvdb::KvDB;
+    use crate::snapshot::StateSnapshot;
+    use crate::{schedule, State, APPDATA_DB_NAME};
+
+    struct FailingVM;
+
+    impl WasmVMInstance for FailingVM {
+        fn execute_app_create(
+            &self,
+            _: Arc<dyn StateDB>,
+            _: Address,
+            _: u64,
+            _: &CreateApplication,
+        ) -> Result<(Vec<u8>, Changelist)> {
+            bail!("app execution failed")
+        }
+
+        fn execute_app_tx(
+            &self,
+            _: Arc<dyn StateDB>,
+            _: Address,
+            _: u64,
+            _: &ApplicationCall,
+        ) -> Result<Changelist> {
+            bail!("app execution failed")
+        }
+
+        fn execute_app_query(&self, _: Arc<dyn StateDB>, _: &ApplicationCall) -> Result<Vec<u8>> {
+            bail!("app execution failed")
+        }
+
+        fn execute_get_descriptor(&self, _: Arc<dyn StateDB>, _: Address) -> Result<Vec<u8>> {
+            bail!("app execution failed")
+        }
+    }
+
+    #[test]
+    fn test_failed_transaction_pays_fee() {
+        let tmp_dir = TempDir::new("state").unwrap();
+        let state = State::new(tmp_dir.path()).unwrap();
+        let alice = create_account_from_uri(Network::Testnet, "alice").unwrap();
+        let bob = create_account_from_uri(Network::Testnet, "bob").unwrap();
+        let mut alice_state = AccountState::new();
+        alice_state.free_balance = 100;
+        state.set_account_state(alice.address, alice_state).unwrap();
+
+        let overspend =
+            make_payment_sign_transaction(alice.secret, bob.address, 1, 500, 10, Network::Testnet)
+                .unwrap();
+        let payment =
+            make_payment_sign_transaction(alice.secret, bob.address, 2, 50, 10, Network::Testnet)
+                .unwrap();
+        let miner = Address::default();
+        let reward = BlockReward {
+            level: 1,
+            coinbase: miner,
+            amount: 0,
+            unlock_level: 101,
+            matured_coinbase: None,
+            fee_burn_percent: 0,
+            chain_id: Network::Testnet.chain_id(),
+        };
+        let (receipts, diff) = state
+            .apply_txs(Arc::new(FailingVM), &reward, &[overspend, payment])
+            .unwrap();
+        assert!(!receipts[0].status());
+        assert!(receipts[1].status());
+        assert_eq!(state.balance(&alice.address), 30);
+        assert_eq!(state.nonce(&alice.address), 3);
+        assert_eq!(state.balance(&bob.address), 50);
+        assert_eq!(state.account_state(&miner).locked_balance(), 20);
+
+        assert_eq!(diff.accounts.len(), 3);
+        assert!(diff.app_storage.is_empty());
+        let account_diff = |address| {
+            diff.accounts
+                .iter()
+                .find(|account| account.address == address)
+                .unwrap()
+        };
+        let alice_diff = account_diff(alice.address);
+        assert_eq!(alice_diff.before.as_ref().unwrap().free_balance, 100);
+        assert_eq!(alice_diff.after.as_ref().unwrap().free_balance, 30);
+        let bob_diff = account_diff(bob.address);
+        assert!(bob_diff.before.is_none());
+        assert_eq!(bob_diff.after.as_ref().unwrap().free_balance, 50);
+    }
+
+    #[test]
+    fn test_update_transaction_fails() {
+        let tmp_dir = TempDir::new("state").unwrap();
+        let state = State::new(tmp_dir.path()).unwrap();
+        let alice = create_account_from_uri(Network::Testnet, "alice").unwrap();
+        let mut alice_state = AccountState::new();
+        alice_state.free_balance = 100;
+        state.set_account_state(alice.address, alice_state).unwrap();
+
+        let update = make_signed_transaction(
+            alice.secret,
+            1,
+            0,
+            10,
+            Network::Testnet,
+            TransactionData::Update(UpdateApplication::default()),
+        )
+        .unwrap();
+        let reward = BlockReward {
+            level: 1,
+            coinbase: Address::default(),
+            amount: 0,
+            unlock_level: 101,
+            matured_coinbase: None,
+            fee_burn_percent: 0,
+            chain_id: Network::Testnet.chain_id(),
+        };
+        let (receipts, _) = state
+            .apply_txs(Arc::new(FailingVM), &reward, &[update])
+            .unwrap();
+        assert!(!receipts[0].status());
+        assert_eq!(state.balance(&alice.address), 90);
+    }
+
+    #[test]
+    fn test_invalid_nonce_rejected() {
+        let tmp_dir = TempDir::new("state").unwrap();
+        let state = State::new(tmp_dir.path()).unwrap();
+        let alice = create_account_from_uri(Network::Testnet, "alice").unwrap();
+        let bob = create_account_from_uri(Network::Testnet, "bob").unwrap();
+        let mut alice_state = AccountState::new();
+        alice_state.free_balance = 100;
+        state.set_account_state(alice.address, alice_state).unwrap();
+        let root = state.root();
+
+        let pay = |nonce, amount| {
+            make_payment_sign_transaction(
+                alice.secret,
+                bob.address,
+                nonce,
+                amount,
+                1,
+                Network::Testnet,
+            )
+            .unwrap()
+        };
+        let reward = BlockReward {
+            level: 1,
+            coinbase: Address::default(),
+            amount: 0,
+            unlock_level: 101,
+            matured_coinbase: None,
+            fee_burn_percent: 0,
+            chain_id: Network::Testnet.chain_id(),
+        };
+        // A gapped nonce, then a nonce replayed within the block
+        for (txs, expected, got) in [
+            (vec![pay(2, 10)], 1, 2),
+            (vec![pay(1, 10), pay(1, 20)], 2, 1),
+        ] {
+            let error = state
+                .apply_txs(Arc::new(FailingVM), &reward, &txs)
+                .unwrap_err();
+            match error.downcast_ref::<StateError>() {
+                Some(StateError::InvalidNonce {
+                    expected: e,
+                    got: g,
+                }) => {
+                    assert_eq!((*e, *g), (expected, got))
+                }
+                _ => panic!("unexpected error {}", error),
+            }
+            assert_eq!(state.root(), root);
+        }
+    }
+
+    #[test]
+    fn test_foreign_chain_transaction_rejected() {
+        let tmp_dir = TempDir::new("state").unwrap();
+        let state = State::new(tmp_dir.path()).unwrap();
+        let alice = create_account_from_uri(Network::Testnet, "alice").unwrap();
+        let bob = create_account_from_uri(Network::Testnet, "bob").unwrap();
+        let mut alice_state = AccountState::new();
+        alice_state.free_balance = 100;
+        state.set_account_state(alice.address, alice_state).unwrap();
+        let root = state.root();
+
+        let reward = BlockReward {
+            level: 1,
+            coinbase: Address::default(),
+            amount: 0,
+            unlock_level: 101,
+            matured_coinbase: None,
+            fee_burn_percent: 0,
+            chain_id: Network::Testnet.chain_id(),
+        };
+        let mainnet =
+            make_payment_sign_transaction(alice.secret, bob.address, 1, 10, 1, Network::Mainnet)
+                .unwrap();
+        let error = state
+            .apply_txs(Arc::new(FailingVM), &reward, &[mainnet])
+            .unwrap_err();
+        assert!(matches!(
+            error.downcast_ref::<StateError>(),
+            Some(StateError::InvalidChainId {
+                expected: 1,
+                got: 0
+            })
+        ));
+
+        // The sender of a transaction signed for an unknown chain can not be recovered
+        let unknown = make_payment_sign_transaction(
+            alice.secret,
+            bob.address,
+            1,
+            10,
+            1,
+            Network::Custom(4242),
+        )
+        .unwrap();
+        assert!(unknown.from().is_err());
+        let reward = BlockReward {
+            chain_id: 4242,
+            ..reward
+        };
+        assert!(state
+            .apply_txs(Arc::new(FailingVM), &reward, &[unknown])
+            .is_err());
+        assert_eq!(state.root(), root);
+    }
+
+    #[test]
+    fn test_parallel_execution_matches_sequential() {
+        let tmp_dir = TempDir::new("state").unwrap();
+        let accounts: Vec<_> = ["alice", "bob", "carol", "dave", "eve", "app"]
+            .into_iter()
+            .map(|uri| create_account_from_uri(Network::Testnet, uri).unwrap())
+            .collect();
+        let [alice, bob, carol, dave, eve, app] = &accounts[..] else {
+            unreachable!()
+        };
+        let call = make_signed_transaction(
+            eve.secret,
+            1,
+            0,
+            10,
+            Network::Testnet,
+            TransactionData::Call(ApplicationCall {
+                app_id: app.address,
+                ..Default::default()
+            }),
+        )
+        .unwrap();
+        let pay = |from: &Account, to: &Account, nonce, amount| {
+            make_payment_sign_transaction(
+                from.secret,
+                to.address,
+                nonce,
+                amount,
+                1,
+                Network::Testnet,
+            )
+            .unwrap()
+        };
+        let txs = vec![
+            pay(alice, bob, 1, 10),
+            pay(bob, carol, 1, 5),
+            pay(dave, dave, 2, 1000),
+            pay(carol, alice, 1, 3),
+            call,
+            pay(dave, dave, 1, 7),
+        ];
+        let reward = BlockReward {
+            level: 1,
+            coinbase: Address::default(),
+            amount: 50,
+            unlock_level: 101,
+            matured_coinbase: None,
+            fee_burn_percent: 0,
+            chain_id: Network::Testnet.chain_id(),
+        };
+
+        let states: Vec<_> = ["parallel", "sequential"]
+            .map(|name| {
+                let state = State::new(tmp_dir.path().join(name)).unwrap();
+                for account in accounts.iter() {
+                    let mut account_state = AccountState::new();
+                    account_state.free_balance = 100;
+                    state
+                        .set_account_state(account.address, account_state)
+                        .unwrap();
+                }
+                state.commit().unwrap();
+                state
+            })
+            .into();
+        let (receipts, _) = states[0]
+            .apply_txs(Arc::new(FailingVM), &reward, &txs)
+            .unwrap();
+
+        let sequential = &states[1];
+        let load = |address: &Address| sequential.get_account_state(address);
+        let ordered = schedule::execution_order(&txs).unwrap();
+        assert_eq!(schedule::clusters(&ordered).unwrap().len(), 3);
+        let mut execution = sequential
+            .execute_sequential(&FailingVM, reward.chain_id, &ordered, &load)
+            .unwrap();
+        State::apply_reward(&mut execution.states, &reward, execution.fees, load).unwrap();
+        for (address, account_state) in execution.states {
+            sequential.trie.put(address, account_state).unwrap();
+        }
+        assert_eq!(states[0].root(), sequential.root());
+        let hashes = |receipts: Vec<Receipt>| -> Vec<_> {
+            receipts.iter().map(|receipt| receipt.hash()).collect()
+        };
+        assert_eq!(
+            hashes(receipts),
+            hashes(State::receipts_in_block_order(&txs, execution.receipts))
+        );
+        assert_eq!(states[0].balance(&carol.address), 100 + 5 - 3 - 1);
+    }
+
+    #[test]
+    fn test_block_reward_unlocks_at_maturity() {
+        let miner = Address::default();
+        let mut states = BTreeMap::new();
+        let reward = BlockReward {
+            level: 1,
+            coinbase: miner,
+            amount: 50,
+            unlock_level: 101,
+            matured_coinbase: None,
+            fee_burn_percent: 50,
+            chain_id: Network::Testnet.chain_id(),
+        };
+        State::apply_reward(&mut states, &reward, 10, |_| Ok(AccountState::new())).unwrap();
+        assert_eq!(states[&miner].free_balance, 0);
+        assert_eq!(states[&miner].locked_balance(), 55);
+
+        let reward = BlockReward {
+            level: 101,
+            unlock_level: 201,
+            matured_coinbase: Some(miner),
+            fee_burn_percent: 0,
+            ..reward
+        };
+        State::apply_reward(&mut states, &reward, 10, |_| Ok(AccountState::new())).unwrap();
+        assert_eq!(states[&miner].free_balance, 55);
+        assert_eq!(states[&miner].locked_balance(), 60);
+    }
+
+    #[test]
+    fn test_snapshot_and_checkpoint() {
+        let tmp_dir = TempDir::new("state").unwrap();
+        let state = State::new(tmp_dir.path().join("source")).unwrap();
+        let alice = create_account_from_uri(Network::Testnet, "alice").unwrap();
+        let bob = create_account_from_uri(Network::Testnet, "bob").unwrap();
+        for (address, free_balance) in [(alice.address, 100), (bob.address, 200)] {
+            let mut account_state = AccountState::new();
+            account_state.free_balance = free_balance;
+            state.set_account_state(address, account_state).unwrap();
+            state.commit().unwrap();
+        }
+        let root = state.root();
+
+        let encoded = state.snapshot_at(root).unwrap().encode().unwrap();
+        let snapshot = StateSnapshot::decode(&encoded).unwrap();
+        let imported = State::from_snapshot(
+            tmp_dir.path().join("imported"),
+            snapshot,
+            TreeHasherKind::Keccak256,
+        )
+        .unwrap();
+        assert_eq!(imported.root(), root);
+        assert_eq!(imported.balance(&alice.address), 100);
+        assert_eq!(imported.balance(&bob.address), 200);
+
+        let checkpoint = state.checkpoint(tmp_dir.path().join("checkpoint")).unwrap();
+        assert_eq!(checkpoint.root(), root);
+        assert_eq!(checkpoint.balance(&alice.address), 100);
+    }
+
+    #[test]
+    fn test_tree_hasher_is_recorded() {
+        let tmp_dir = TempDir::new("state").unwrap();
+        let alice = create_account_from_uri(Network::Testnet, "alice").unwrap();
+        let mut account_state = AccountState::new();
+        account_state.free_balance = 100;
+        let mut roots = Vec::new();
+        for (name, hasher) in [
+            ("keccak", TreeHasherKind::Keccak256),
+            ("blake2b", TreeHasherKind::Blake2b256),
+        ] {
+            let state = State::with_hasher(tmp_dir.path().join(name), hasher).unwrap();
+            state
+                .set_account_state(alice.address, account_state.clone())
+                .unwrap();
+            state.commit().unwrap();
+            assert_eq!(state.tree_hasher(), hasher);
+            roots.push(state.root());
+        }
+        assert_ne!(roots[0], roots[1]);
+
+        let reopened =
+            State::with_hasher(tmp_dir.path().join("blake2b"), TreeHasherKind::Blake2b256).unwrap();
+        assert_eq!(reopened.root(), roots[1]);
+        assert_eq!(reopened.balance(&alice.address), 100);
+        drop(reopened);
+        assert!(matches!(
+            State::new(tmp_dir.path().join("blake2b"))
+                .unwrap_err()
+                .downcast_ref::<StateError>(),
+            Some(StateError::TreeHasherMismatch { .. })
+        ));
+    }
+
+    #[test]
+    fn test_prune_deletes_unreachable_app_storage() {
+        let tmp_dir = TempDir::new("state").unwrap();
+        let state = State::new(tmp_dir.path()).unwrap();
+        let app = create_account_from_uri(Network::Testnet, "app").unwrap();
+        let mut storage = SparseMerkleTree::new();
+        let mut roots = Vec::new();
+        let mut storage_roots = Vec::new();
+        for value in [&b"first"[..], &b"second"[..]] {
+            storage.update(b"key", value).unwrap();
+            state.set_app_data(&storage).unwrap();
+            let mut app_state = AccountState::new();
+            app_state.app_state = Some(AppState::new(storage.root(), H256::zero(), app.address, 1));
+            state.set_account_state(app.address, app_state).unwrap();
+            state.commit().unwrap();
+            roots.push(state.root());
+            storage_roots.push(storage.root());
+        }
+
+        state.prune(&BTreeSet::from([roots[1]])).unwrap();
+        let kept = SparseMerkleTree::open(state.app_storage.clone(), storage_roots[1]).unwrap();
+        assert_eq!(kept.get(b"key").unwrap(), Some(b"second".to_vec()));
+        assert_eq!(
+            kept.scan(b"", None, 10).unwrap(),
+            vec![(b"key".to_vec(), b"second".to_vec())]
+        );
+        let pruned = SparseMerkleTree::open(state.app_storage.clone(), storage_roots[0]).unwrap();
+        assert!(pruned.get(b"key").is_err());
+    }
+
+    #[test]
+    fn test_app_storage_migration_and_snapshot() {
+        let tmp_dir = TempDir::new("state").unwrap();
+        let path = tmp_dir.path().join("source");
+        let app = create_account_from_uri(Network::Testnet, "app").unwrap();
+        let mut legacy_storage = SparseMerkleTree::new();
+        legacy_storage.update(b"key", b"value").unwrap();
+        {
+            let appdata: KvDB<AppStateKey, SparseMerkleTree> =
+                KvDB::open(path.join(APPDATA_DB_NAME)).unwrap();
+            appdata
+                .put(
+                    AppStateKey(app.address, legacy_storage.root()),
+                    legacy_storage.clone(),
+                )
+                .unwrap();
+        }
+
+        // The legacy trees are kept until they are explicitly removed
+        drop(State::new(&path).unwrap());
+        assert!(path.join(APPDATA_DB_NAME).exists());
+        let state = State::new(&path).unwrap();
+        assert!(state.remove_legacy_appdata().unwrap());
+        assert!(!path.join(APPDATA_DB_NAME).exists());
+        assert!(!state.remove_legacy_appdata().unwrap());
+        let mut app_state = AccountState::new();
+        app_state.app_state = Some(AppState::new(
+            legacy_storage.root(),
+            H256::zero(),
+            app.address,
+            1,
+        ));
+        state.set_account_state(app.address, app_state).unwrap();
+        state.commit().unwrap();
+
+        let mut storage = state.get_app_data(app.address).unwrap();
+        assert_eq!(storage.get(b"key").unwrap(), Some(b"value".to_vec()));
+        storage.update(b"other", b"value").unwrap();
+        state.set_app_data(&storage).unwrap();
+        let reopened = SparseMerkleTree::open(state.app_storage.clone(), storage.root()).unwrap();
+        assert_eq!(reopened.get(b"key").unwrap(), Some(b"value".to_vec()));
+        assert_eq!(reopened.get(b"other").unwrap(), Some(b"value".to_vec()));
+        assert_eq!(
+            reopened.scan(b"", None, 10).unwrap(),
+            vec![
+                (b"key".to_vec(), b"value".to_vec()),
+                (b"other".to_vec(), b"value".to_vec())
+            ]
+        );
+
+        let snapshot = state.snapshot_at(state.root()).unwrap();
+        assert_eq!(snapshot.app_storage.len(), 1);
+        let imported = State::from_snapshot(
+            tmp_dir.path().join("imported"),
+            snapshot,
+            TreeHasherKind::Keccak256,
+        )
+        .unwrap();
+        let storage = imported.get_app_data(app.address).unwrap();
+        assert_eq!(storage.get(b"key").unwrap(), Some(b"value".to_vec()));
+        assert!(storage.get(b"other").unwrap().is_none());
+        assert_eq!(storage.scan(b"k", None, 10).unwrap().len(), 1);
+    }
+}
@@ -7,7 +7,7 @@ use rocksdb::checkpoint::Checkpoint;
 use rocksdb::DB;
 
 use crate::error::StateError as Error;
-use crate::store::{cfs, DatabaseBackend};
+use crate::store::DatabaseBackend;
 
 pub fn default_db_opts() -> rocksdb::Options {
     let mut opts = rocksdb::Options::default();
@@ -78,6 +78,18 @@ impl DatabaseBackend for RocksDB {
             .map_err(|e| e.into())
     }
 
+    fn keys_cn(&self, column_name: &'static str) -> Result<Vec<Vec<u8>>> {
+        let cf = self
+            .inner
+            .cf_handle(column_name)
+            .ok_or(Error::ColumnFamilyMissing(column_name))?;
+        Ok(self
+            .inner
+            .iterator_cf(&cf, rocksdb::IteratorMode::Start)
+            .map(|(key, _)| key.to_vec())
+            .collect())
+    }
+
     fn put(&self, key: &[u8], value: &[u8]) -> Result<()> {
         self.inner
             .put_opt(key, value, &default_write_opts())
@@ -95,12 +107,22 @@ impl DatabaseBackend for RocksDB {
             .map_err(|e| e.into())
     }
 
+    fn keys(&self) -> Result<Vec<Vec<u8>>> {
+        Ok(self
+            .inner
+            .iterator(rocksdb::IteratorMode::Start)
+            .map(|(key, _)| key.to_vec())
+            .collect())
+    }
+
     fn checkpoint(&self, path: PathBuf) -> Result<Arc<dyn DatabaseBackend + Send + Sync>> {
         Checkpoint::new(self.inner.as_ref())?.create_checkpoint(path.as_path())?;
-        let db = Arc::new(rocksdb::DB::open_cf_descriptors(
+        // Reopen with the column families of the source, not every database uses the trie columns
+        let column_families = rocksdb::DB::list_cf(&default_db_opts(), path.as_path())?;
+        let db = Arc::new(rocksdb::DB::open_cf(
             &default_db_opts(),
             path.as_path(),
-            cfs(),
+            column_families,
         )?);
         Ok(Arc::new(RocksDB::new(db)))
     }
@@ -162,6 +184,11 @@ impl DatabaseBackend for MemoryStore {
         Ok(())
     }
 
+    fn keys_cn(&self, column_name: &'static str) -> Result<Vec<Vec<u8>>> {
+        let column = self.inner.entry(column_name).or_default();
+        Ok(column.iter().map(|entry| entry.key().clone()).collect())
+    }
+
     fn put(&self, key: &[u8], value: &[u8]) -> Result<()> {
         self.put_cn("_", key, value)
     }
@@ -174,6 +201,10 @@ impl DatabaseBackend for MemoryStore {
         self.delete_cn("_", key)
     }
 
+    fn keys(&self) -> Result<Vec<Vec<u8>>> {
+        self.keys_cn("_")
+    }
+
     fn checkpoint(&self, _: PathBuf) -> Result<Arc<dyn DatabaseBackend + Send + Sync>> {
         Ok(Arc::new(MemoryStore {
             inner: self.inner.clone(),
@@ -0,0 +1,155 @@
+use std::collections::{BTreeMap, HashMap};
+
+use anyhow::Result;
+
+use primitive_types::address::Address;
+use transaction::{NoncePricedTransaction, TransactionsByNonceAndPrice};
+use types::tx::SignedTransaction;
+
+/// Returns the transactions of a block in the order they are executed, grouped by sender and
+/// sorted by nonce then price within a sender. Fails if the sender of a transaction can not be
+/// recovered.
+pub(crate) fn execution_order(txs: &[SignedTransaction]) -> Result<Vec<&SignedTransaction>> {
+    let mut by_sender: BTreeMap<Address, TransactionsByNonceAndPrice> = BTreeMap::new();
+    for tx in txs {
+        by_sender
+            .entry(tx.sender()?)
+            .or_default()
+            .insert(NoncePricedTransaction(tx));
+    }
+    Ok(by_sender
+        .into_values()
+        .flat_map(|txs| txs.into_iter().map(|tx| tx.0))
+        .collect())
+}
+
+/// Splits transactions in execution order into clusters that share no declared account, the
+/// sender and the recipient or app of a transaction. Transactions keep their relative order
+/// within a cluster, clusters are ordered by their first transaction.
+pub(crate) fn clusters<'a>(
+    ordered: &[&'a SignedTransaction],
+) -> Result<Vec<Vec<&'a SignedTransaction>>> {
+    let mut sets = AccountSets::default();
+    for tx in ordered {
+        sets.union(tx.sender()?, tx.to());
+    }
+
+    let mut clusters: Vec<Vec<&SignedTransaction>> = Vec::new();
+    let mut cluster_of_set: HashMap<usize, usize> = HashMap::new();
+    for tx in ordered {
+        let set = sets.find(tx.sender()?);
+        let cluster = *cluster_of_set.entry(set).or_insert_with(|| {
+            clusters.push(Vec::new());
+            clusters.len() - 1
+        });
+        clusters[cluster].push(*tx);
+    }
+    Ok(clusters)
+}
+
+/// Disjoint sets of accounts
+#[derive(Default)]
+struct AccountSets {
+    slots: HashMap<Address, usize>,
+    parents: Vec<usize>,
+}
+
+impl AccountSets {
+    fn slot(&mut self, address: Address) -> usize {
+        let parents = &mut self.parents;
+        *self.slots.entry(address).or_insert_with(|| {
+            parents.push(parents.len());
+            parents.len() - 1
+        })
+    }
+
+    fn root(&mut self, mut slot: usize) -> usize {
+        while self.parents[slot] != slot {
+            self.parents[slot] = self.parents[self.parents[slot]];
+            slot = self.parents[slot];
+        }
+        slot
+    }
+
+    fn find(&mut self, address: Address) -> usize {
+        let slot = self.slot(address);
+        self.root(slot)
+    }
+
+    fn union(&mut self, a: Address, b: Address) {
+        let a = self.find(a);
+        let b = self.find(b);
+        self.parents[b] = a;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use account::create_account_from_uri;
+    use transaction::{make_payment_sign_transaction, sign_tx};
+    use types::account::Account;
+    use types::network::Network;
+    use types::prelude::TransactionData;
+    use types::tx::{PaymentTx, Transaction};
+
+    use crate::schedule::{clusters, execution_order};
+
+    #[test]
+    fn test_clusters_share_no_account() {
+        let [alice, bob, carol, dave, eve] = ["alice", "bob", "carol", "dave", "eve"]
+            .map(|uri| create_account_from_uri(Network::Testnet, uri).unwrap());
+        let pay = |from: &Account, to: &Account, nonce| {
+            make_payment_sign_transaction(from.secret, to.address, nonce, 10, 1, Network::Testnet)
+                .unwrap()
+        };
+        let txs = vec![
+            pay(&alice, &bob, 2),
+            pay(&carol, &dave, 1),
+            pay(&alice, &bob, 1),
+            pay(&eve, &bob, 1),
+        ];
+        let ordered = execution_order(&txs).unwrap();
+        assert_eq!(ordered.len(), 4);
+        let clusters = clusters(&ordered).unwrap();
+        assert_eq!(clusters.len(), 2);
+
+        let shared = clusters.iter().find(|cluster| cluster.len() == 3).unwrap();
+        let alice_nonces: Vec<_> = shared
+            .iter()
+            .filter(|tx| tx.sender().unwrap() == alice.address)
+            .map(|tx| tx.nonce())
+            .collect();
+        assert_eq!(alice_nonces, vec![1, 2]);
+        assert!(clusters
+            .iter()
+            .any(|cluster| cluster.len() == 1 && cluster[0].sender().unwrap() == carol.address));
+    }
+
+    #[test]
+    fn test_unrecoverable_sender_is_rejected() {
+        let [alice, bob] =
+            ["alice", "bob"].map(|uri| create_account_from_uri(Network::Testnet, uri).unwrap());
+        let payment =
+            make_payment_sign_transaction(alice.secret, bob.address, 1, 10, 1, Network::Testnet)
+                .unwrap();
+        // The sender address can not be derived for a chain id that is not a known network
+        let unknown_chain = sign_tx(
+            alice.secret,
+            Transaction {
+                nonce: 1,
+                chain_id: u32::MAX,
+                genesis_hash: Default::default(),
+                fee: 1,
+                value: 10,
+                data: Some(TransactionData::Payment(PaymentTx { to: bob.address })),
+            },
+        )
+        .unwrap();
+        assert!(unknown_chain.sender().is_err());
+
+        let txs = vec![payment, unknown_chain];
+        assert!(execution_order(&txs).is_err());
+        let ordered: Vec<_> = txs.iter().collect();
+        assert!(clusters(&ordered).is_err());
+    }
+}
@@ -20,6 +20,6 @@ use smt::proof::Proof;
 
 #[derive(Encode, Decode, Clone, Debug)]
 pub struct ReadProof {
-    pub(crate) proof: Proof,
-    pub(crate) root: H256,
+    pub proof: Proof,
+    pub root: H256,
 }
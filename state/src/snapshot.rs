@@ -0,0 +1,102 @@
+use std::collections::BTreeSet;
+
+use anyhow::{anyhow, ensure, Result};
+use bincode::{Decode, Encode};
+
+use codec::{Decodable, Encodable};
+use primitive_types::H256;
+use smt::treehasher::TreeHasher;
+use smt::{MemoryStorage, SparseMerkleTree, TreeHasherKind};
+use types::account::AccountState;
+use types::app::AppMetadata;
+use types::proof::IValue;
+
+/// A copy of the state at one root that does not depend on any other persisted root
+#[derive(Encode, Decode)]
+pub struct StateSnapshot {
+    pub root: H256,
+    /// Account trie at `root` with the values of all its ancestors
+    pub accounts: SparseMerkleTree<MemoryStorage, TreeHasherKind>,
+    /// Storage trees of the apps that are initialized at `root`
+    pub app_storage: Vec<SparseMerkleTree<MemoryStorage, TreeHasherKind>>,
+    pub metadata: Vec<(H256, AppMetadata)>,
+}
+
+impl StateSnapshot {
+    /// The hasher is not part of the encoding, it is set by the reader of a decoded snapshot
+    pub fn set_hasher(&mut self, hasher: TreeHasherKind) {
+        self.accounts.set_hasher(hasher);
+        for storage in self.app_storage.iter_mut() {
+            storage.set_hasher(hasher);
+        }
+    }
+
+    /// Checks that every tree hashes to the root it is stored under, that the key index of every
+    /// app storage matches its keys and that the storage of every app is included
+    pub fn verify(&self) -> Result<()> {
+        ensure!(
+            self.accounts.root() == self.root,
+            "account trie root {:?} does not match snapshot root {:?}",
+            self.accounts.root(),
+            self.root
+        );
+        let mut missing = app_roots(&self.accounts)?;
+        for storage in self.app_storage.iter() {
+            for (hash, data) in storage.nodes()? {
+                ensure!(
+                    storage.hasher().digest(&data).as_bytes() == hash.as_slice(),
+                    "app storage node {} does not match its hash",
+                    hex::encode(&hash, false)
+                );
+            }
+            storage.verify_index()?;
+            missing.remove(&storage.root());
+        }
+        ensure!(
+            missing.is_empty(),
+            "storage of app roots {:?} is missing",
+            missing
+        );
+        for (code_hash, metadata) in self.metadata.iter() {
+            ensure!(
+                crypto::keccak256(&metadata.binary) == *code_hash,
+                "app binary does not match code hash {:?}",
+                code_hash
+            );
+        }
+        Ok(())
+    }
+}
+
+/// Storage roots of the initialized apps in a flattened account trie
+pub(crate) fn app_roots(
+    accounts: &SparseMerkleTree<MemoryStorage, TreeHasherKind>,
+) -> Result<BTreeSet<H256>> {
+    let mut roots = BTreeSet::new();
+    for (_, value) in accounts.values()? {
+        let IValue::Value(value) = <IValue as Decodable>::decode(&value)? else {
+            continue;
+        };
+        let account_state = <AccountState as Decodable>::decode(&value)?;
+        if let Some(app_state) = account_state.app_state {
+            if !app_state.root_hash.is_zero() {
+                roots.insert(app_state.root_hash);
+            }
+        }
+    }
+    Ok(roots)
+}
+
+impl Encodable for StateSnapshot {
+    fn encode(&self) -> Result<Vec<u8>> {
+        bincode::encode_to_vec(self, codec::config()).map_err(|e| anyhow!(e))
+    }
+}
+
+impl Decodable for StateSnapshot {
+    fn decode(buf: &[u8]) -> Result<Self> {
+        bincode::decode_from_slice(buf, codec::config())
+            .map(|(out, _)| out)
+            .map_err(|e| anyhow!(e))
+    }
+}
@@ -1,13 +1,16 @@
 #![allow(dead_code)]
 
+use std::collections::BTreeSet;
 use std::path::{Path, PathBuf};
 use std::sync::Arc;
 
-use anyhow::Result;
+use anyhow::{anyhow, Result};
 use rocksdb::{BlockBasedOptions, ColumnFamilyDescriptor, Options};
 
 use codec::{Decodable, Encodable};
 use primitive_types::H256;
+use smt::index::Node as IndexNode;
+use smt::overlay::NodeStore;
 use smt::treehasher::TreeHasher;
 use smt::{SparseMerkleTree, StorageBackend};
 
@@ -15,6 +18,12 @@ use crate::persistent::{default_db_opts, MemoryStore, RocksDB};
 
 const COLUMN_TREES: &str = "t";
 const COLUMN_ROOT: &str = "r";
+const COLUMN_APP_NODES: &str = "n";
+const COLUMN_APP_VALUES: &str = "v";
+const COLUMN_APP_KEYS: &str = "k";
+const COLUMN_APP_INDEX: &str = "i";
+/// Key index root by storage root
+const COLUMN_APP_INDEX_ROOTS: &str = "ir";
 
 pub fn cfs() -> Vec<ColumnFamilyDescriptor> {
     vec![
@@ -23,6 +32,16 @@ pub fn cfs() -> Vec<ColumnFamilyDescriptor> {
     ]
 }
 
+pub fn app_storage_cfs() -> Vec<ColumnFamilyDescriptor> {
+    vec![
+        ColumnFamilyDescriptor::new(COLUMN_APP_NODES, default_table_options()),
+        ColumnFamilyDescriptor::new(COLUMN_APP_VALUES, default_table_options()),
+        ColumnFamilyDescriptor::new(COLUMN_APP_KEYS, default_table_options()),
+        ColumnFamilyDescriptor::new(COLUMN_APP_INDEX, default_table_options()),
+        ColumnFamilyDescriptor::new(COLUMN_APP_INDEX_ROOTS, default_table_options()),
+    ]
+}
+
 fn default_table_options() -> Options {
     // default db options
     let mut db_opts = Options::default();
@@ -48,12 +67,16 @@ pub trait DatabaseBackend {
 
     fn delete_cn(&self, column_name: &'static str, key: &[u8]) -> Result<()>;
 
+    fn keys_cn(&self, column_name: &'static str) -> Result<Vec<Vec<u8>>>;
+
     fn put(&self, key: &[u8], value: &[u8]) -> Result<()>;
 
     fn get(&self, key: &[u8]) -> Result<Vec<u8>>;
 
     fn delete(&self, key: &[u8]) -> Result<()>;
 
+    fn keys(&self) -> Result<Vec<Vec<u8>>>;
+
     fn checkpoint(&self, path: PathBuf) -> Result<Arc<dyn DatabaseBackend + Send + Sync>>;
 
     fn get_or_default_cn(
@@ -118,6 +141,18 @@ impl TrieCacheDatabase {
             .put_cn(COLUMN_ROOT, b"root", &Encodable::encode(&new_root)?)
     }
 
+    /// Name of the hasher the trees were written with, `None` if it was never recorded
+    pub fn hasher_name(&self) -> Result<Option<String>> {
+        let name = self
+            .inner
+            .get_or_default_cn(COLUMN_ROOT, b"hasher", Vec::new())?;
+        Ok((!name.is_empty()).then(|| String::from_utf8_lossy(&name).into_owned()))
+    }
+
+    pub fn set_hasher_name(&self, name: &str) -> Result<()> {
+        self.inner.put_cn(COLUMN_ROOT, b"hasher", name.as_bytes())
+    }
+
     pub fn load_root<S: StorageBackend, H: TreeHasher>(&self) -> Result<SparseMerkleTree<S, H>> {
         let root = self.inner.get_cn(COLUMN_ROOT, b"root")?;
         let root = <H256 as Decodable>::decode(&root)?;
@@ -133,13 +168,291 @@ impl TrieCacheDatabase {
         Ok(smt)
     }
 
+    /// Like [`TrieCacheDatabase::get`] but returns `None` if no tree is persisted under `key`
+    pub fn try_get<S: StorageBackend, H: TreeHasher>(
+        &self,
+        key: &H256,
+    ) -> Result<Option<SparseMerkleTree<S, H>>> {
+        // An encoded tree is never empty
+        let raw =
+            self.inner
+                .get_or_default_cn(COLUMN_TREES, &Encodable::encode(key)?, Vec::new())?;
+        if raw.is_empty() {
+            return Ok(None);
+        }
+        Ok(Some(<SparseMerkleTree<S, H> as Decodable>::decode(&raw)?))
+    }
+
     pub fn delete(&self, key: &H256) -> Result<()> {
         self.inner.delete_cn(COLUMN_TREES, &Encodable::encode(key)?)
     }
 
+    /// Returns the roots of every persisted tree
+    pub fn roots(&self) -> Result<Vec<H256>> {
+        self.inner
+            .keys_cn(COLUMN_TREES)?
+            .iter()
+            .map(|key| <H256 as Decodable>::decode(key))
+            .collect()
+    }
+
     pub fn checkpoint<P: AsRef<Path>>(&self, path: P) -> Result<TrieCacheDatabase> {
         Ok(TrieCacheDatabase {
             inner: self.inner.checkpoint(PathBuf::new().join(path.as_ref()))?,
         })
     }
 }
+
+/// App storage entries reachable from a set of app roots, see [`AppStorageDatabase::mark`]
+#[derive(Default)]
+pub struct LiveEntries {
+    nodes: BTreeSet<H256>,
+    values: BTreeSet<H256>,
+    keys: BTreeSet<H256>,
+    index: BTreeSet<H256>,
+    roots: BTreeSet<H256>,
+}
+
+impl LiveEntries {
+    fn contains(&self, column_name: &str, key: &[u8]) -> bool {
+        let live = match column_name {
+            COLUMN_APP_NODES => &self.nodes,
+            COLUMN_APP_VALUES => &self.values,
+            COLUMN_APP_KEYS => &self.keys,
+            COLUMN_APP_INDEX => &self.index,
+            _ => &self.roots,
+        };
+        key.len() == 32 && live.contains(&H256::from_slice(key))
+    }
+}
+
+/// Content addressed nodes, values, keys and key index nodes of the app storage trees, shared by
+/// every app and root
+pub struct AppStorageDatabase {
+    inner: Arc<dyn DatabaseBackend + Send + Sync>,
+    /// Writes to a read only database are kept in memory
+    staging: Option<MemoryStore>,
+}
+
+impl AppStorageDatabase {
+    pub fn open<P: AsRef<Path>>(path: P) -> Result<Self> {
+        let db = Arc::new(rocksdb::DB::open_cf_descriptors(
+            &default_db_opts(),
+            path.as_ref(),
+            app_storage_cfs(),
+        )?);
+        Ok(Self {
+            inner: Arc::new(RocksDB::new(db)),
+            staging: None,
+        })
+    }
+
+    pub fn open_read_only<P: AsRef<Path>>(path: P) -> Result<Self> {
+        let db = Arc::new(rocksdb::DB::open_cf_for_read_only(
+            &default_db_opts(),
+            path,
+            vec![
+                COLUMN_APP_NODES,
+                COLUMN_APP_VALUES,
+                COLUMN_APP_KEYS,
+                COLUMN_APP_INDEX,
+                COLUMN_APP_INDEX_ROOTS,
+            ],
+            false,
+        )?);
+        Ok(Self {
+            inner: Arc::new(RocksDB::new(db)),
+            staging: Some(MemoryStore::new()),
+        })
+    }
+
+    pub fn in_memory() -> Self {
+        Self {
+            inner: Arc::new(MemoryStore::new()),
+            staging: None,
+        }
+    }
+
+    /// Stores the nodes, values, keys and index nodes held in memory by `tree` and the index root
+    /// of its root, for a tree opened on this database those are the ones written since it was
+    /// opened
+    pub fn put<S: StorageBackend, H: TreeHasher>(
+        &self,
+        tree: &SparseMerkleTree<S, H>,
+    ) -> Result<()> {
+        for (hash, data) in tree.nodes()? {
+            self.write(COLUMN_APP_NODES, &hash, &data)?;
+        }
+        for (_, value) in tree.values()? {
+            let value_hash = tree.hasher().digest(&value);
+            self.write(COLUMN_APP_VALUES, value_hash.as_bytes(), &value)?;
+        }
+        for (path, key) in tree.keys()? {
+            self.write(COLUMN_APP_KEYS, &path, &key)?;
+        }
+        for (hash, data) in tree.index()? {
+            self.write(COLUMN_APP_INDEX, &hash, &data)?;
+        }
+        if !tree.index_root().is_zero() {
+            self.write(
+                COLUMN_APP_INDEX_ROOTS,
+                tree.root().as_bytes(),
+                tree.index_root().as_bytes(),
+            )?;
+        }
+        Ok(())
+    }
+
+    /// Deletes the nodes, values, keys and index nodes that are not reachable from any of
+    /// `roots`. Every reachable node is read before anything is deleted. Returns the number of
+    /// deleted entries.
+    pub fn prune<H: TreeHasher>(&self, hasher: &H, roots: &BTreeSet<H256>) -> Result<usize> {
+        let mut live = LiveEntries::default();
+        self.mark(hasher, roots, &mut live)?;
+        let unmarked = self.unmarked(&live)?;
+        self.sweep(unmarked, &live)
+    }
+
+    /// Adds the entries reachable from `roots` to `live`, subtrees already in `live` are not
+    /// walked again
+    pub fn mark<H: TreeHasher>(
+        &self,
+        hasher: &H,
+        roots: &BTreeSet<H256>,
+        live: &mut LiveEntries,
+    ) -> Result<()> {
+        for root in roots.iter() {
+            if !live.roots.insert(*root) {
+                continue;
+            }
+            if let Some(index_root) = self.index_root(root)? {
+                let mut pending = vec![index_root];
+                while let Some(hash) = pending.pop() {
+                    if !live.index.insert(hash) {
+                        continue;
+                    }
+                    let data = self
+                        .read(COLUMN_APP_INDEX, hash.as_bytes())?
+                        .ok_or_else(|| anyhow!("app storage index node {:?} is missing", hash))?;
+                    if let IndexNode::Branch { left, right, .. } = IndexNode::decode(&data)? {
+                        pending.push(left);
+                        pending.push(right);
+                    }
+                }
+            }
+            let mut pending = vec![*root];
+            while let Some(hash) = pending.pop() {
+                // Subtrees shared by several roots are walked once
+                if hash.is_zero() || !live.nodes.insert(hash) {
+                    continue;
+                }
+                let data = self
+                    .read(COLUMN_APP_NODES, hash.as_bytes())?
+                    .ok_or_else(|| anyhow!("app storage node {:?} is missing", hash))?;
+                if hasher.is_leaf(&data) {
+                    let (path, value_hash) = hasher.parse_leaf(&data);
+                    live.keys.insert(H256::from_slice(path));
+                    live.values.insert(H256::from_slice(value_hash));
+                } else {
+                    let (left, right) = hasher.parse_node(&data);
+                    pending.push(H256::from_slice(left));
+                    pending.push(H256::from_slice(right));
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Entries that are not in `live`, with their column
+    pub fn unmarked(&self, live: &LiveEntries) -> Result<Vec<(&'static str, Vec<u8>)>> {
+        let mut unmarked = Vec::new();
+        for column_name in [
+            COLUMN_APP_NODES,
+            COLUMN_APP_VALUES,
+            COLUMN_APP_KEYS,
+            COLUMN_APP_INDEX,
+            COLUMN_APP_INDEX_ROOTS,
+        ] {
+            for key in self.inner.keys_cn(column_name)? {
+                if !live.contains(column_name, &key) {
+                    unmarked.push((column_name, key));
+                }
+            }
+        }
+        Ok(unmarked)
+    }
+
+    /// Deletes the `entries` that are still not in `live`, returns the number of deleted entries
+    pub fn sweep(
+        &self,
+        entries: Vec<(&'static str, Vec<u8>)>,
+        live: &LiveEntries,
+    ) -> Result<usize> {
+        let mut pruned = 0;
+        for (column_name, key) in entries {
+            if !live.contains(column_name, &key) {
+                self.inner.delete_cn(column_name, &key)?;
+                pruned += 1;
+            }
+        }
+        Ok(pruned)
+    }
+
+    pub fn checkpoint<P: AsRef<Path>>(&self, path: P) -> Result<()> {
+        self.inner.checkpoint(path.as_ref().to_path_buf())?;
+        Ok(())
+    }
+
+    fn write(&self, column_name: &'static str, key: &[u8], value: &[u8]) -> Result<()> {
+        match &self.staging {
+            Some(staging) => staging.put_cn(column_name, key, value),
+            None => self.inner.put_cn(column_name, key, value),
+        }
+    }
+
+    fn index_root(&self, root: &H256) -> Result<Option<H256>> {
+        Ok(self
+            .read(COLUMN_APP_INDEX_ROOTS, root.as_bytes())?
+            .map(|index_root| H256::from_slice(&index_root)))
+    }
+
+    /// Nodes, values, keys and index roots are never empty, an empty read means the entry is
+    /// missing
+    fn read(&self, column_name: &'static str, key: &[u8]) -> Result<Option<Vec<u8>>> {
+        if let Some(staging) = &self.staging {
+            let value = staging.get_or_default_cn(column_name, key, Vec::new())?;
+            if !value.is_empty() {
+                return Ok(Some(value));
+            }
+        }
+        let value = self.inner.get_or_default_cn(column_name, key, Vec::new())?;
+        Ok((!value.is_empty()).then_some(value))
+    }
+}
+
+impl NodeStore for AppStorageDatabase {
+    fn node(&self, hash: &H256) -> smt::Result<Option<Vec<u8>>> {
+        self.read(COLUMN_APP_NODES, hash.as_bytes())
+            .map_err(|e| smt::error::Error::CustomError(e.to_string()))
+    }
+
+    fn value(&self, value_hash: &H256) -> smt::Result<Option<Vec<u8>>> {
+        self.read(COLUMN_APP_VALUES, value_hash.as_bytes())
+            .map_err(|e| smt::error::Error::CustomError(e.to_string()))
+    }
+
+    fn key(&self, path: &H256) -> smt::Result<Option<Vec<u8>>> {
+        self.read(COLUMN_APP_KEYS, path.as_bytes())
+            .map_err(|e| smt::error::Error::CustomError(e.to_string()))
+    }
+
+    fn index_node(&self, hash: &H256) -> smt::Result<Option<Vec<u8>>> {
+        self.read(COLUMN_APP_INDEX, hash.as_bytes())
+            .map_err(|e| smt::error::Error::CustomError(e.to_string()))
+    }
+
+    fn index_root(&self, root: &H256) -> smt::Result<Option<H256>> {
+        AppStorageDatabase::index_root(self, root)
+            .map_err(|e| smt::error::Error::CustomError(e.to_string()))
+    }
+}
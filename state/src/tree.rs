@@ -1,21 +1,20 @@
-use std::collections::HashMap;
+use std::collections::{BTreeSet, HashMap};
 use std::marker::PhantomData;
 use std::path::Path;
 use std::sync::{Arc, RwLock};
 
 use anyhow::Result;
-use serde::{Deserialize, Serialize};
 
-use bincode::{Decode, Encode};
 use codec::{Codec, Decodable, Encodable};
 use primitive_types::H256;
-use tracing::{debug, error};
+use tracing::debug;
 
 use crate::error::StateError as Error;
 use crate::store::TrieCacheDatabase;
 use smt::proof::{verify_proof_with_updates, Proof};
 use smt::treehasher::TreeHasher;
 use smt::{CopyStrategy, DefaultTreeHasher, MemoryStorage, SparseMerkleTree, StorageBackend};
+pub use types::proof::IValue;
 
 pub struct Options {
     strategy: CopyStrategy,
@@ -29,26 +28,6 @@ impl Default for Options {
     }
 }
 
-#[derive(Serialize, Deserialize, Clone, Encode, Decode)]
-pub enum IValue {
-    Deleted,
-    Value(Vec<u8>),
-}
-
-impl Encodable for IValue {
-    fn encode(&self) -> Result<Vec<u8>> {
-        bincode::encode_to_vec(self, codec::config()).map_err(|e| e.into())
-    }
-}
-
-impl Decodable for IValue {
-    fn decode(buf: &[u8]) -> Result<Self> {
-        bincode::decode_from_slice(buf, codec::config())
-            .map(|(output, _)| output)
-            .map_err(|e| e.into())
-    }
-}
-
 pub enum Op<K: Codec, V: Codec> {
     Delete(K),
     Put(K, V),
@@ -88,17 +67,7 @@ where
     }
 
     pub fn open_read_only_at_root<P: AsRef<Path>>(path: P, root: &H256) -> Result<Self> {
-        let db = TrieCacheDatabase::open_read_only(path)?;
-        let tree = db.get(root)?;
-        let options = Options::default();
-        Ok(Self {
-            db: Arc::new(db),
-            head: Arc::new(RwLock::new(tree.clone())),
-            staging: Arc::new(RwLock::new(tree)),
-            options,
-            hasher: DefaultTreeHasher,
-            _data: Default::default(),
-        })
+        Self::open_read_only_at_root_with_hasher(DefaultTreeHasher, path, root)
     }
 
     pub fn in_memory<P: AsRef<Path>>(options: Options) -> Result<Self> {
@@ -128,8 +97,11 @@ where
 {
     pub fn open_with_options<P: AsRef<Path>>(hasher: H, path: P, options: Options) -> Result<Self> {
         let db = TrieCacheDatabase::open(path)?;
-        let tree = match db.load_root() {
-            Ok(tree) => tree,
+        let tree = match db.load_root::<MemoryStorage, H>() {
+            Ok(mut tree) => {
+                tree.set_hasher(hasher.clone());
+                tree
+            }
             Err(_) => SparseMerkleTree::new_with_hasher(
                 hasher.clone(),
                 MemoryStorage::new(),
@@ -148,14 +120,173 @@ where
         })
     }
 
-    pub fn revert(&self, _root: H256) {
-        todo!()
+    pub fn open_read_only_at_root_with_hasher<P: AsRef<Path>>(
+        hasher: H,
+        path: P,
+        root: &H256,
+    ) -> Result<Self> {
+        let db = TrieCacheDatabase::open_read_only(path)?;
+        let mut tree: SparseMerkleTree<MemoryStorage, H> = db.get(root)?;
+        tree.set_hasher(hasher.clone());
+        let options = Options::default();
+        Ok(Self {
+            db: Arc::new(db),
+            head: Arc::new(RwLock::new(tree.clone())),
+            staging: Arc::new(RwLock::new(tree)),
+            options,
+            hasher,
+            _data: Default::default(),
+        })
+    }
+
+    /// Reads the tree persisted under `root`, the hasher is not part of its encoding
+    pub(crate) fn load(&self, root: &H256) -> Result<SparseMerkleTree<MemoryStorage, H>> {
+        let mut tree: SparseMerkleTree<MemoryStorage, H> = self.db.get(root)?;
+        tree.set_hasher(self.hasher.clone());
+        Ok(tree)
+    }
+
+    /// Like [`TreeDB::load`] but returns `None` if no tree is persisted under `root`, read and
+    /// decode errors are still returned
+    fn try_load(&self, root: &H256) -> Result<Option<SparseMerkleTree<MemoryStorage, H>>> {
+        let Some(mut tree) = self.db.try_get::<MemoryStorage, H>(root)? else {
+            return Ok(None);
+        };
+        tree.set_hasher(self.hasher.clone());
+        Ok(Some(tree))
+    }
+
+    /// Roots of every persisted tree
+    pub(crate) fn roots(&self) -> Result<Vec<H256>> {
+        self.db.roots()
+    }
+
+    /// Name of the hasher the persisted trees were written with, `None` if it was never recorded
+    pub fn hasher_name(&self) -> Result<Option<String>> {
+        self.db.hasher_name()
+    }
+
+    pub fn set_hasher_name(&self, name: &str) -> Result<()> {
+        self.db.set_hasher_name(name)
+    }
+
+    /// Moves the head back to `root`, which must be an ancestor of the current head
+    pub fn revert(&self, root: H256) -> Result<()> {
+        let mut ancestor = self.root()?;
+        while ancestor != root {
+            let tree: SparseMerkleTree<MemoryStorage, H> = self.load(&ancestor)?;
+            if tree.parent() == ancestor {
+                return Err(Error::ValidationFailedRootNotValid.into());
+            }
+            ancestor = tree.parent();
+        }
+        self.reset(root)
+    }
+
+    /// Deletes every persisted root that is not in `keep`, the head root is always kept.
+    ///
+    /// Subtrees only hold the values written since their parent, so before an ancestor is
+    /// deleted its values are copied into its kept descendants. Returns the number of deleted
+    /// roots.
+    pub fn prune(&self, keep: &BTreeSet<H256>) -> Result<usize> {
+        let head_root = self.root()?;
+        let roots: BTreeSet<H256> = self.db.roots()?.into_iter().collect();
+        let keep: BTreeSet<H256> = keep
+            .iter()
+            .chain(std::iter::once(&head_root))
+            .filter(|root| roots.contains(root))
+            .copied()
+            .collect();
+
+        for root in keep.iter() {
+            let (mut tree, ancestors, parent) = self.folded(root, &keep)?;
+            let inherited = !ancestors.is_empty();
+            for ancestor in ancestors.iter() {
+                tree.inherit_values(ancestor)?;
+            }
+            if inherited || parent != tree.parent() {
+                tree.set_parent(parent);
+                self.db.put(*root, tree)?;
+            }
+        }
+
+        let mut pruned = 0;
+        for root in roots.difference(&keep) {
+            self.db.delete(root)?;
+            pruned += 1;
+        }
+        debug!(pruned, kept = keep.len(), "Pruned state roots");
+        Ok(pruned)
+    }
+
+    /// Reads the tree persisted under `root` with the ancestors that pruning to `keep` folds into
+    /// it, and the parent it is left with
+    pub(crate) fn folded(
+        &self,
+        root: &H256,
+        keep: &BTreeSet<H256>,
+    ) -> Result<(
+        SparseMerkleTree<MemoryStorage, H>,
+        Vec<SparseMerkleTree<MemoryStorage, H>>,
+        H256,
+    )> {
+        let tree: SparseMerkleTree<MemoryStorage, H> = self.load(root)?;
+        let mut ancestors = Vec::new();
+        let mut parent = tree.parent();
+        while parent != *root && !keep.contains(&parent) {
+            // The empty tree the chain started from is never persisted
+            let Some(ancestor) = self.try_load(&parent)? else {
+                parent = *root;
+                break;
+            };
+            parent = if ancestor.parent() == ancestor.root() {
+                *root
+            } else {
+                ancestor.parent()
+            };
+            ancestors.push(ancestor);
+        }
+        Ok((tree, ancestors, parent))
+    }
+
+    /// Returns the tree at `root` with the values of all its ancestors, so it can be persisted
+    /// on its own
+    pub fn flatten(&self, root: &H256) -> Result<SparseMerkleTree<MemoryStorage, H>> {
+        let mut tree: SparseMerkleTree<MemoryStorage, H> = self.load(root)?;
+        let mut parent = tree.parent();
+        while parent != *root {
+            // The empty tree the chain started from is never persisted
+            let Some(ancestor) = self.try_load(&parent)? else {
+                break;
+            };
+            tree.inherit_values(&ancestor)?;
+            parent = if ancestor.parent() == ancestor.root() {
+                *root
+            } else {
+                ancestor.parent()
+            };
+        }
+        tree.set_parent(*root);
+        Ok(tree)
+    }
+
+    /// Persists a tree created by [`TreeDB::flatten`] under its root
+    pub fn import(&self, tree: SparseMerkleTree<MemoryStorage, H>) -> Result<H256> {
+        let root = tree.root();
+        self.db.put(root, tree)?;
+        Ok(root)
+    }
+
+    /// Creates a copy of the persisted trees at `path`
+    pub fn checkpoint<P: AsRef<Path>>(&self, path: P) -> Result<()> {
+        self.db.checkpoint(path)?;
+        Ok(())
     }
 
     pub fn reset(&self, root: H256) -> Result<()> {
         let mut head = self.head.write().map_err(|_e| Error::RWPoison)?;
         let mut staging = self.staging.write().map_err(|_e| Error::RWPoison)?;
-        let new_head = self.db.get(&root)?;
+        let new_head = self.load(&root)?;
         *head = new_head;
         *staging = head.subtree(self.options.strategy, vec![])?;
         Ok(())
@@ -202,7 +333,7 @@ where
     }
 
     pub fn apply_non_commit(&self, at_root: &H256, batch: Vec<Op<K, V>>) -> Result<H256> {
-        let mut tree: SparseMerkleTree<MemoryStorage, H> = self.db.get(at_root)?;
+        let mut tree: SparseMerkleTree<MemoryStorage, H> = self.load(at_root)?;
         let res: Result<HashMap<_, _>> = batch
             .into_iter()
             .map(|op| match op {
@@ -232,14 +363,8 @@ where
             return Ok(head.root());
         }
         if persist {
-            match self.db.put(staging.root(), staging.clone()) {
-                Ok(_) => {
-                    debug!(state_root = ?staging.root(), "Persisted State");
-                }
-                Err(e) => {
-                    error!(error = ?e, "Unable to persisted");
-                }
-            }
+            self.db.put(staging.root(), staging.clone())?;
+            debug!(state_root = ?staging.root(), "Persisted State");
         }
         *head = staging.clone();
         *staging = head.subtree(self.options.strategy, vec![])?;
@@ -293,11 +418,24 @@ where
         Ok((value, proof))
     }
 
+    /// Returns the value of `key` in the committed head with a proof against the head root, the
+    /// proof shows non-membership when the key is missing
+    pub fn prove(&self, key: &K) -> Result<(Option<V>, Proof)> {
+        let head = self.head.read().map_err(|_e| Error::RWPoison)?;
+        let root = head.root();
+        let proof = head.proof(&key.encode()?)?;
+        drop(head);
+        if root.is_zero() {
+            return Ok((None, proof));
+        }
+        Ok((self.get_at_root(&root, key)?, proof))
+    }
+
     pub fn get_descend(&self, key: &K, descend: bool) -> Result<Option<V>> {
         let key = key.encode()?;
         let staging = self.staging.read().map_err(|_e| Error::RWPoison)?;
         let head = self.head.read().map_err(|_e| Error::RWPoison)?;
-        let mut value = staging.get(&key)?;
+        let mut value = staging.get(&key)?.unwrap_or_default();
         if value.is_empty() && descend {
             let res = self._get_descend(&key, &head.root())?;
             match res {
@@ -307,7 +445,7 @@ where
                 }
             }
         } else if value.is_empty() && !descend {
-            value = head.get(&key)?;
+            value = head.get(&key)?.unwrap_or_default();
         }
 
         if value.is_empty() {
@@ -322,8 +460,8 @@ where
 
     fn get_descend_from_root(&self, from_root: &H256, key: &K, descend: bool) -> Result<Option<V>> {
         let key = key.encode()?;
-        let head: SparseMerkleTree<MemoryStorage, H> = self.db.get(from_root)?;
-        let mut value = head.get(&key)?;
+        let head: SparseMerkleTree<MemoryStorage, H> = self.load(from_root)?;
+        let mut value = head.get(&key)?.unwrap_or_default();
         if value.is_empty() && descend {
             let res = self._get_descend(&key, &head.root())?;
             match res {
@@ -333,7 +471,7 @@ where
                 }
             }
         } else if value.is_empty() && !descend {
-            value = head.get(&key)?;
+            value = head.get(&key)?.unwrap_or_default();
         }
 
         if value.is_empty() {
@@ -349,8 +487,8 @@ where
     fn _get_descend(&self, key: &[u8], root: &H256) -> Result<Option<Vec<u8>>> {
         let mut root = *root;
         loop {
-            let tree: SparseMerkleTree<MemoryStorage, H> = self.db.get(&root)?;
-            let value = tree.get(key)?;
+            let tree: SparseMerkleTree<MemoryStorage, H> = self.load(&root)?;
+            let value = tree.get(key)?.unwrap_or_default();
             if value.is_empty() && tree.root() != tree.parent() {
                 root = tree.parent();
                 continue;
@@ -396,6 +534,8 @@ impl Verifier {
 
 #[cfg(test)]
 mod tests {
+    use std::collections::BTreeSet;
+
     use tempdir::TempDir;
 
     use crate::tree::{TreeDB, Verifier};
@@ -413,6 +553,7 @@ mod tests {
                 reserve_balance: 3000,
                 nonce: 1,
                 app_state: None,
+                locked_rewards: Vec::new(),
             },
         )
         .unwrap();
@@ -424,6 +565,7 @@ mod tests {
                 reserve_balance: 1000,
                 nonce: 1,
                 app_state: None,
+                locked_rewards: Vec::new(),
             },
         )
         .unwrap();
@@ -435,6 +577,7 @@ mod tests {
                 reserve_balance: 1000,
                 nonce: 1,
                 app_state: None,
+                locked_rewards: Vec::new(),
             },
         )
         .unwrap();
@@ -446,6 +589,7 @@ mod tests {
                 reserve_balance: 1000,
                 nonce: 1,
                 app_state: None,
+                locked_rewards: Vec::new(),
             },
         )
         .unwrap();
@@ -458,6 +602,7 @@ mod tests {
                 reserve_balance: 2000,
                 nonce: 2,
                 app_state: None,
+                locked_rewards: Vec::new(),
             },
         )
         .unwrap();
@@ -469,6 +614,7 @@ mod tests {
                 reserve_balance: 1000,
                 nonce: 1,
                 app_state: None,
+                locked_rewards: Vec::new(),
             },
         )
         .unwrap();
@@ -480,6 +626,7 @@ mod tests {
                 reserve_balance: 1000,
                 nonce: 1,
                 app_state: None,
+                locked_rewards: Vec::new(),
             },
         )
         .unwrap();
@@ -491,6 +638,7 @@ mod tests {
                 reserve_balance: 1000,
                 nonce: 1,
                 app_state: None,
+                locked_rewards: Vec::new(),
             },
         )
         .unwrap();
@@ -502,6 +650,7 @@ mod tests {
                 reserve_balance: 200,
                 nonce: 3,
                 app_state: None,
+                locked_rewards: Vec::new(),
             },
         )
         .unwrap();
@@ -516,6 +665,7 @@ mod tests {
                 reserve_balance: 200,
                 nonce: 3,
                 app_state: None,
+                locked_rewards: Vec::new(),
             })
         );
         tree.reset(root_1).unwrap();
@@ -527,6 +677,7 @@ mod tests {
                 reserve_balance: 1000,
                 nonce: 1,
                 app_state: None,
+                locked_rewards: Vec::new(),
             })
         );
 
@@ -539,6 +690,7 @@ mod tests {
                 reserve_balance: 9000,
                 nonce: 2,
                 app_state: None,
+                locked_rewards: Vec::new(),
             },
         )
         .unwrap();
@@ -553,6 +705,7 @@ mod tests {
                 reserve_balance: 9000,
                 nonce: 2,
                 app_state: None,
+                locked_rewards: Vec::new(),
             })
         );
 
@@ -568,6 +721,7 @@ mod tests {
                 reserve_balance: 3000,
                 nonce: 1,
                 app_state: None,
+                locked_rewards: Vec::new(),
             },)
         );
 
@@ -596,6 +750,7 @@ mod tests {
                 reserve_balance: 0,
                 nonce: 1,
                 app_state: None,
+                locked_rewards: Vec::new(),
             },
         )
         .unwrap();
@@ -603,4 +758,41 @@ mod tests {
         println!("{:#?}", tree.root().unwrap());
         println!("{:?}", tree.root().unwrap().0);
     }
+
+    #[test]
+    fn test_prune_keeps_values_of_deleted_roots() {
+        let tmp_dir = TempDir::new("test").unwrap();
+        let tree = TreeDB::open(tmp_dir.path()).unwrap();
+        let account = |free_balance| AccountState {
+            free_balance,
+            ..AccountState::new()
+        };
+        let mut roots = Vec::new();
+        for i in 1..=4u8 {
+            tree.put(H160::from_slice(&[i; 20]), account(i as u64))
+                .unwrap();
+            roots.push(tree.commit(true).unwrap());
+        }
+
+        let pruned = tree.prune(&BTreeSet::from([roots[2]])).unwrap();
+        assert_eq!(pruned, 2);
+        for i in 1..=4u8 {
+            assert_eq!(
+                tree.get(&H160::from_slice(&[i; 20])).unwrap(),
+                Some(account(i as u64))
+            );
+        }
+        assert_eq!(
+            tree.get_at_root(&roots[2], &H160::from_slice(&[1; 20]))
+                .unwrap(),
+            Some(account(1))
+        );
+        assert!(tree
+            .get_at_root(&roots[0], &H160::from_slice(&[1; 20]))
+            .is_err());
+
+        tree.revert(roots[2]).unwrap();
+        assert_eq!(tree.get(&H160::from_slice(&[4; 20])).unwrap(), None);
+        assert!(tree.revert(roots[3]).is_err());
+    }
 }
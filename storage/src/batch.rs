@@ -0,0 +1,74 @@
+use std::collections::BTreeSet;
+
+use anyhow::Result;
+
+use codec::Encodable;
+
+use crate::Schema;
+
+pub(crate) enum BatchOp {
+    Put {
+        column: &'static str,
+        key: Vec<u8>,
+        value: Vec<u8>,
+    },
+    Delete {
+        column: &'static str,
+        key: Vec<u8>,
+    },
+}
+
+impl BatchOp {
+    pub(crate) fn column(&self) -> &'static str {
+        match self {
+            BatchOp::Put { column, .. } | BatchOp::Delete { column, .. } => column,
+        }
+    }
+}
+
+/// Puts and deletes across any number of columns that are written all at once, operations on
+/// the same key are applied in the order they were added
+#[derive(Default)]
+pub struct WriteBatch {
+    ops: Vec<BatchOp>,
+}
+
+impl WriteBatch {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn put<S: Schema>(&mut self, key: S::Key, value: S::Value) -> Result<()> {
+        self.ops.push(BatchOp::Put {
+            column: S::column(),
+            key: key.encode()?,
+            value: value.encode()?,
+        });
+        Ok(())
+    }
+
+    pub fn delete<S: Schema>(&mut self, key: &S::Key) -> Result<()> {
+        self.ops.push(BatchOp::Delete {
+            column: S::column(),
+            key: key.encode()?,
+        });
+        Ok(())
+    }
+
+    pub fn len(&self) -> usize {
+        self.ops.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.ops.is_empty()
+    }
+
+    /// Columns written by the batch, sorted by name
+    pub(crate) fn columns(&self) -> BTreeSet<&'static str> {
+        self.ops.iter().map(|op| op.column()).collect()
+    }
+
+    pub(crate) fn ops(&self) -> &[BatchOp] {
+        &self.ops
+    }
+}
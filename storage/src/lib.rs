@@ -5,9 +5,11 @@ use rocksdb::{BlockBasedOptions, Options};
 
 use codec::Codec;
 
+pub use crate::batch::WriteBatch;
 use crate::memstore::MemStore;
 use crate::sleddb::SledDB;
 
+mod batch;
 pub mod error;
 pub mod memstore;
 mod rocks;
@@ -68,6 +70,15 @@ impl PersistentStorage {
             PersistentStorageBackend::RocksDB(database) => database.clone(),
         }
     }
+
+    /// Writes every operation of the batch or none of them
+    pub fn write_batch(&self, batch: WriteBatch) -> Result<()> {
+        match &self.backend {
+            PersistentStorageBackend::InMemory(database) => database.write_batch(batch),
+            PersistentStorageBackend::Sled(database) => database.write_batch(batch),
+            PersistentStorageBackend::RocksDB(database) => rocks::write_batch(database, batch),
+        }
+    }
 }
 
 pub trait KVStore<Entry>
@@ -80,6 +91,9 @@ where
     fn contains(&self, key: &Entry::Key) -> Result<bool>;
     fn iter(&self) -> Result<StorageIterator<Entry>>;
     fn prefix_iter(&self, start: &Entry::Key) -> anyhow::Result<StorageIterator<Entry>>;
+    /// Writes every operation of the batch or none of them, the batch may write any column of
+    /// the database, not only the one of `Entry`
+    fn write_batch(&self, batch: WriteBatch) -> Result<()>;
 }
 
 pub type StorageIterator<'a, Entry> = Box<
@@ -106,8 +120,9 @@ mod test {
 
     use tempdir::TempDir;
 
+    use crate::memstore::MemStore;
     use crate::sleddb::SledDB;
-    use crate::{KVStore, PersistentStorage, PersistentStorageBackend, Schema};
+    use crate::{KVStore, PersistentStorage, PersistentStorageBackend, Schema, WriteBatch};
 
     pub type BlockStorageKV = dyn KVStore<BlockStorage> + Send + Sync;
 
@@ -136,6 +151,29 @@ mod test {
         }
     }
 
+    pub struct BlockIndex;
+
+    impl Schema for BlockIndex {
+        type Key = String;
+        type Value = String;
+
+        fn column() -> &'static str {
+            "block_index"
+        }
+    }
+
+    /// Column that no backend is opened with
+    pub struct Unknown;
+
+    impl Schema for Unknown {
+        type Key = String;
+        type Value = String;
+
+        fn column() -> &'static str {
+            "unknown"
+        }
+    }
+
     #[test]
     fn test_backends() {
         let temp = TempDir::new("_test_backends_").unwrap();
@@ -147,4 +185,66 @@ mod test {
             .unwrap();
         println!("{:?}", block_storage.get_block("h").unwrap())
     }
+
+    #[test]
+    fn test_write_batch() {
+        let temp = TempDir::new("_test_write_batch_").unwrap();
+        let columns = vec![BlockStorage::column(), BlockIndex::column()];
+        let mut opts = rocksdb::Options::default();
+        opts.create_if_missing(true);
+        opts.create_missing_column_families(true);
+        let rocks = rocksdb::DB::open_cf(&opts, temp.path().join("rocks"), &columns).unwrap();
+        let backends = [
+            PersistentStorageBackend::RocksDB(Arc::new(rocks)),
+            PersistentStorageBackend::Sled(Arc::new(
+                SledDB::new(temp.path().join("sled")).unwrap(),
+            )),
+            PersistentStorageBackend::InMemory(Arc::new(MemStore::new(columns))),
+        ];
+        for backend in backends {
+            // Sled opens missing trees on write, the other backends reject the batch
+            let rejects_unknown = !matches!(backend, PersistentStorageBackend::Sled(_));
+            let persistent = PersistentStorage::new(backend);
+            let blocks: Arc<dyn KVStore<BlockStorage> + Send + Sync> = persistent.database();
+            let index: Arc<dyn KVStore<BlockIndex> + Send + Sync> = persistent.database();
+            blocks.put("stale".to_string(), "aa".to_string()).unwrap();
+
+            let mut batch = WriteBatch::new();
+            batch
+                .put::<BlockStorage>("h".to_string(), "bb".to_string())
+                .unwrap();
+            batch
+                .put::<BlockIndex>("1".to_string(), "h".to_string())
+                .unwrap();
+            batch.delete::<BlockStorage>(&"stale".to_string()).unwrap();
+            assert_eq!(batch.len(), 3);
+            persistent.write_batch(batch).unwrap();
+            assert_eq!(
+                blocks.get(&"h".to_string()).unwrap(),
+                Some("bb".to_string())
+            );
+            assert_eq!(index.get(&"1".to_string()).unwrap(), Some("h".to_string()));
+            assert!(!blocks.contains(&"stale".to_string()).unwrap());
+
+            let mut batch = WriteBatch::new();
+            batch
+                .put::<BlockIndex>("1".to_string(), "g".to_string())
+                .unwrap();
+            batch.delete::<BlockIndex>(&"1".to_string()).unwrap();
+            blocks.write_batch(batch).unwrap();
+            assert_eq!(index.get(&"1".to_string()).unwrap(), None);
+
+            if rejects_unknown {
+                let mut batch = WriteBatch::new();
+                batch
+                    .put::<BlockIndex>("2".to_string(), "h".to_string())
+                    .unwrap();
+                batch
+                    .put::<Unknown>("2".to_string(), "h".to_string())
+                    .unwrap();
+                assert!(persistent.write_batch(batch).is_err());
+                assert_eq!(index.get(&"2".to_string()).unwrap(), None);
+            }
+        }
+    }
 }
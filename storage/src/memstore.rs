@@ -7,9 +7,10 @@ use anyhow::Result;
 
 use codec::{Decodable, Encodable};
 
+use crate::batch::BatchOp;
 use crate::error::StorageError;
 use crate::Schema;
-use crate::{KVStore, StorageIterator};
+use crate::{KVStore, StorageIterator, WriteBatch};
 
 type KVEntry = Arc<Vec<u8>>;
 
@@ -82,6 +83,37 @@ impl MemStore {
             Some(col) => Ok(col.clone()),
         }
     }
+
+    /// Applies the batch while holding the write lock of every column it writes, locks are
+    /// taken in column name order
+    pub fn write_batch(&self, batch: WriteBatch) -> Result<()> {
+        let names: Vec<&'static str> = batch.columns().into_iter().collect();
+        let columns = names
+            .iter()
+            .map(|name| self.column(name))
+            .collect::<Result<Vec<_>>>()?;
+        let mut stores = columns
+            .iter()
+            .map(|column| {
+                column
+                    .inner
+                    .write()
+                    .map_err(|_| anyhow::Error::from(StorageError::RWPoison))
+            })
+            .collect::<Result<Vec<_>>>()?;
+        for op in batch.ops() {
+            let store = &mut stores[names.partition_point(|name| *name < op.column())];
+            match op {
+                BatchOp::Put { key, value, .. } => {
+                    store.insert(Arc::new(key.clone()), Arc::new(value.clone()));
+                }
+                BatchOp::Delete { key, .. } => {
+                    store.remove(key);
+                }
+            }
+        }
+        Ok(())
+    }
 }
 
 impl<S: Schema> KVStore<S> for MemStore {
@@ -122,6 +154,10 @@ impl<S: Schema> KVStore<S> for MemStore {
     fn prefix_iter(&self, _start: &S::Key) -> Result<StorageIterator<S>> {
         todo!()
     }
+
+    fn write_batch(&self, batch: WriteBatch) -> Result<()> {
+        MemStore::write_batch(self, batch)
+    }
 }
 
 impl ColumnMemStore {
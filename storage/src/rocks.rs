@@ -1,7 +1,8 @@
 use codec::{Decodable, Encodable};
 
+use crate::batch::BatchOp;
 use crate::error::StorageError;
-use crate::{KVStore, Schema, StorageIterator};
+use crate::{KVStore, Schema, StorageIterator, WriteBatch};
 
 pub fn default_write_opts() -> rocksdb::WriteOptions {
     let mut opts = rocksdb::WriteOptions::default();
@@ -9,6 +10,21 @@ pub fn default_write_opts() -> rocksdb::WriteOptions {
     opts
 }
 
+pub(crate) fn write_batch(db: &rocksdb::DB, batch: WriteBatch) -> anyhow::Result<()> {
+    let mut write_batch = rocksdb::WriteBatch::default();
+    for op in batch.ops() {
+        let cf = db
+            .cf_handle(op.column())
+            .ok_or_else(|| StorageError::ColumnFamilyMissing(op.column()))?;
+        match op {
+            BatchOp::Put { key, value, .. } => write_batch.put_cf(&cf, key, value),
+            BatchOp::Delete { key, .. } => write_batch.delete_cf(&cf, key),
+        }
+    }
+    db.write_opt(write_batch, &default_write_opts())
+        .map_err(|e| e.into())
+}
+
 impl<S: Schema> KVStore<S> for rocksdb::DB {
     fn get(&self, key: &S::Key) -> anyhow::Result<Option<S::Value>> {
         let cf = self
@@ -69,4 +85,8 @@ impl<S: Schema> KVStore<S> for rocksdb::DB {
             iter.map(|(k, v)| (S::Key::decode(&k), S::Value::decode(&v))),
         ))
     }
+
+    fn write_batch(&self, batch: WriteBatch) -> anyhow::Result<()> {
+        write_batch(self, batch)
+    }
 }
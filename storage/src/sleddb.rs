@@ -1,11 +1,13 @@
 use std::path::Path;
 
-use anyhow::Result;
-use sled::Tree;
+use anyhow::{anyhow, Result};
+use sled::transaction::{TransactionError, TransactionResult};
+use sled::{Transactional, Tree};
 
 use codec::{Decodable, Encodable};
 
-use crate::{KVStore, Schema, StorageIterator};
+use crate::batch::BatchOp;
+use crate::{KVStore, Schema, StorageIterator, WriteBatch};
 
 pub struct SledDB {
     inner: sled::Db,
@@ -20,6 +22,33 @@ impl SledDB {
     fn column(&self, name: &'static str) -> Result<Tree> {
         self.inner.open_tree(name).map_err(|e| e.into())
     }
+
+    /// Applies the batch in one transaction over the trees of every column it writes
+    pub fn write_batch(&self, batch: WriteBatch) -> Result<()> {
+        let columns: Vec<&'static str> = batch.columns().into_iter().collect();
+        let trees = columns
+            .iter()
+            .map(|name| self.column(name))
+            .collect::<Result<Vec<_>>>()?;
+        let result: TransactionResult<()> = trees.as_slice().transaction(|views| {
+            for op in batch.ops() {
+                let view = &views[columns.partition_point(|column| *column < op.column())];
+                match op {
+                    BatchOp::Put { key, value, .. } => {
+                        view.insert(key.as_slice(), value.as_slice())?;
+                    }
+                    BatchOp::Delete { key, .. } => {
+                        view.remove(key.as_slice())?;
+                    }
+                }
+            }
+            Ok(())
+        });
+        result.map_err(|e| match e {
+            TransactionError::Storage(e) => e.into(),
+            TransactionError::Abort(()) => anyhow!("write batch aborted"),
+        })
+    }
 }
 
 impl<S: Schema> KVStore<S> for SledDB {
@@ -68,4 +97,8 @@ impl<S: Schema> KVStore<S> for SledDB {
             (S::Key::decode(k.as_ref()), S::Value::decode(v.as_ref()))
         })))
     }
+
+    fn write_batch(&self, batch: WriteBatch) -> Result<()> {
+        SledDB::write_batch(self, batch)
+    }
 }
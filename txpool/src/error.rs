@@ -19,8 +19,16 @@ pub enum TxPoolError {
     FeeTooLow,
     #[error("`bad origin")]
     BadOrigin,
+    #[error("`transaction chain id {0} does not match the network chain id {1}`")]
+    InvalidChainId(u32, u32),
+    #[error("`transaction type is not supported`")]
+    UnsupportedTransaction,
     #[error("`insufficient funds for fee: {0} + amount: {1}`")]
     InsufficientFunds(u64, u64),
+    #[error("`fee: {0} + amount: {1} overflows`")]
+    AmountOverflow(u64, u64),
+    #[error("`insufficient funds, {0} is locked until the mining rewards mature`")]
+    ImmatureReward(u64),
     #[error("`Explict coinbase transaction not allowed`")]
     ExplictCoinbase,
     #[error("`transaction in index missing from primary`")]
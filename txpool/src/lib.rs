@@ -16,7 +16,7 @@ use tracing::{debug, error, info, trace, warn};
 use traits::{Blockchain, StateDB};
 use types::block::BlockHeader;
 use types::events::LocalEventMessage;
-use types::tx::{SignedTransaction, TransactionList, TransactionStatus};
+use types::tx::{SignedTransaction, TransactionData, TransactionList, TransactionStatus};
 use types::TxPoolConfig;
 
 use crate::error::TxPoolError;
@@ -147,12 +147,31 @@ impl TxPool {
     }
 
     fn validate_tx(&self, tx: &SignedTransaction, _local: bool) -> Result<()> {
-        let from = tx.sender();
+        let chain_id = self.chain.network().chain_id();
+        if tx.chain_id() != chain_id {
+            return Err(TxPoolError::InvalidChainId(tx.chain_id(), chain_id).into());
+        }
+        // Would only fail when executed
+        if let TransactionData::Update(_) = tx.data() {
+            return Err(TxPoolError::UnsupportedTransaction.into());
+        }
+        let from = tx.from().map_err(|_| TxPoolError::BadOrigin)?;
         if self.current_state.nonce(&from) > tx.nonce() {
             return Err(TxPoolError::NonceTooLow.into());
         }
-        let sender_balance = self.current_state.balance(&from);
-        if sender_balance < tx.fees() + tx.price() {
+        let cost = tx
+            .fees()
+            .checked_add(tx.price())
+            .ok_or(TxPoolError::AmountOverflow(tx.fees(), tx.price()))?;
+        let sender_state = self.current_state.account_state(&from);
+        if sender_state.free_balance < cost {
+            // Locked mining rewards can not be spent before they mature
+            let spendable = sender_state
+                .free_balance
+                .saturating_add(sender_state.locked_balance());
+            if spendable >= cost {
+                return Err(TxPoolError::ImmatureReward(sender_state.locked_balance()).into());
+            }
             return Err(TxPoolError::InsufficientFunds(tx.fees(), tx.price()).into());
         }
         Ok(())
@@ -203,7 +222,7 @@ impl TxPool {
                 self.remove_tx(tx.hash(), false)?;
             }
         }
-        let from = tx.sender();
+        let from = tx.sender()?;
         if let Some(list) = self.pending.get_mut(&from) {
             if list.overlaps(tx.clone()) {
                 let (inserted, old) = list.add(tx.clone(), self.config.price_bump);
@@ -215,7 +234,7 @@ impl TxPool {
 
                 self.all.add(tx.clone(), is_local);
                 self.priced.put(tx.clone(), is_local);
-                self.queue_event(tx.clone());
+                self.queue_event(tx.clone())?;
                 trace!(target : TXPOOL_LOG_TARGET, hash = ?tx.hash(), from = ?from, to = ?tx.to(), "Pooled new executable transaction");
                 return Ok(old.is_some());
             }
@@ -234,13 +253,14 @@ impl TxPool {
         Ok(replaced)
     }
 
-    fn queue_event(&mut self, tx: TransactionRef) {
-        let sender = tx.sender();
+    fn queue_event(&mut self, tx: TransactionRef) -> Result<()> {
+        let sender = tx.sender()?;
         let events = self
             .queued_events
             .entry(sender)
             .or_insert_with(Default::default);
         events.put(tx);
+        Ok(())
     }
 
     fn enqueue_tx(
@@ -250,7 +270,7 @@ impl TxPool {
         local: bool,
         add_all: bool,
     ) -> Result<bool> {
-        let from = tx.sender();
+        let from = tx.sender()?;
         let queue = self.queue.entry(from).or_insert_with(|| TxList::new(false));
         let (inserted, old) = queue.add(tx.clone(), self.config.price_bump);
         anyhow::ensure!(inserted, TxPoolError::ReplaceUnderpriced);
@@ -281,7 +301,7 @@ impl TxPool {
         };
 
         self.all.remove(&hash);
-        let sender = tx.sender();
+        let sender = tx.sender()?;
 
         if outofbound {
             self.priced.remove(tx.clone());
@@ -372,24 +392,30 @@ impl TxPool {
         let mut dirty = AccountSet::new();
         let mut errors = Vec::with_capacity(tsx.len());
         for (i, tx) in tsx.into_iter().enumerate() {
-            match self.add(tx.clone(), local) {
-                Ok(replaced) => {
-                    if !replaced {
-                        dirty.add_tx(tx);
-                    }
+            let added = self.add(tx.clone(), local).and_then(|replaced| {
+                if !replaced {
+                    dirty.add_tx(tx)?;
                 }
-                Err(error) => {
-                    errors.insert(i, format!("{}", error));
-                }
-            };
+                Ok(())
+            });
+            if let Err(error) = added {
+                errors.insert(i, format!("{}", error));
+            }
         }
         (dirty, errors)
     }
 
     #[allow(unused_assignments)]
-    fn reset(&mut self, old_head: Option<BlockHeader>, new_head: BlockHeader) -> Result<()> {
+    fn reset(
+        &mut self,
+        old_head: Option<BlockHeader>,
+        new_head: BlockHeader,
+        discarded: Option<Vec<SignedTransaction>>,
+    ) -> Result<()> {
         let mut reinject = Vec::new();
-        if let Some(old_head) = old_head {
+        if let Some(discarded) = discarded {
+            reinject = discarded.into_iter().map(Arc::new).collect();
+        } else if let Some(old_head) = old_head {
             if old_head.hash().ne(&new_head.parent_hash) {
                 let old_num = old_head.level;
                 let new_num = new_head.level;
@@ -468,7 +494,7 @@ impl TxPool {
                             }
                         }
                         reinject = discarded
-                            .intersection(&included)
+                            .difference(&included)
                             .map(|tx| tx.0.clone())
                             .collect();
                     } else {
@@ -760,7 +786,7 @@ impl TxPool {
         }
 
         if let Some(reset) = &reset {
-            self.reset(reset.old_head, reset.new_head)?;
+            self.reset(reset.old_head, reset.new_head, reset.discarded.clone())?;
             for (addr, list) in events.iter_mut() {
                 list.forward(self.pending_nonce.get(addr));
             }
@@ -787,7 +813,7 @@ impl TxPool {
         self.changes_since_repack = 0;
 
         for tx in promoted {
-            let sorted_map = events.entry(tx.sender()).or_insert_with(TxSortedList::new);
+            let sorted_map = events.entry(tx.sender()?).or_insert_with(TxSortedList::new);
             sorted_map.put(tx);
         }
         let mut txs = Vec::new();
@@ -840,7 +866,9 @@ impl TxPool {
         let mut status = vec![TransactionStatus::NotFound; txs.len()];
         for (i, hash) in txs.iter().enumerate() {
             if let Some(tx) = self.get(hash) {
-                let sender = tx.sender();
+                let Ok(sender) = tx.sender() else {
+                    continue;
+                };
                 if let Some(list) = self.pending.get(&sender) {
                     status[i] = if list.txs.has(tx.nonce()) {
                         TransactionStatus::Pending
@@ -930,6 +958,7 @@ impl TxPool {
 pub struct ResetRequest {
     old_head: Option<BlockHeader>,
     new_head: BlockHeader,
+    discarded: Option<Vec<SignedTransaction>>,
 }
 
 impl ResetRequest {
@@ -937,6 +966,21 @@ impl ResetRequest {
         Self {
             old_head: old,
             new_head: new,
+            discarded: None,
+        }
+    }
+
+    /// Reset after a chain reorganisation where the stale blocks have already been removed,
+    /// `discarded` holds their transactions that were not included on the new chain
+    pub fn with_discarded(
+        old: Option<BlockHeader>,
+        new: BlockHeader,
+        discarded: Vec<SignedTransaction>,
+    ) -> Self {
+        Self {
+            old_head: old,
+            new_head: new,
+            discarded: Some(discarded),
         }
     }
 }
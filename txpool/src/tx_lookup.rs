@@ -1,5 +1,7 @@
 #![allow(dead_code)]
 
+use anyhow::Result;
+
 use primitive_types::address::Address;
 use primitive_types::H256;
 use std::collections::{BTreeMap, BTreeSet};
@@ -42,17 +44,17 @@ impl AccountSet {
     }
 
     pub(crate) fn contains_tx(&self, tx: &SignedTransaction) -> bool {
-        let address = tx.sender();
-        self.contains(&address)
+        tx.sender().is_ok_and(|address| self.contains(&address))
     }
 
     pub(crate) fn add(&mut self, address: Address) {
         self.accounts.insert(address);
     }
 
-    pub(crate) fn add_tx(&mut self, tx: TransactionRef) {
-        let address = tx.sender();
+    pub(crate) fn add_tx(&mut self, tx: TransactionRef) -> Result<()> {
+        let address = tx.sender()?;
         self.add(address);
+        Ok(())
     }
 
     pub(crate) fn flatten(&self) -> Vec<Address> {